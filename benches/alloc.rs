@@ -0,0 +1,51 @@
+//! Cost of one allocation and free through each kind of detector. Stacks are
+//! only captured raw on this path; symbolizing them happens at report time.
+
+#![feature(allocator_api, test)]
+
+extern crate test;
+
+use std::alloc::{Allocator, System};
+
+use mem_leak_detector::LeakDetector;
+use test::{Bencher, black_box};
+
+fn alloc_free(b: &mut Bencher, alloc: impl Allocator) {
+    b.iter(|| black_box(Box::new_in(black_box(0u64), &alloc)));
+}
+
+#[bench]
+fn system(b: &mut Bencher) {
+    alloc_free(b, System);
+}
+
+#[bench]
+fn counters(b: &mut Bencher) {
+    alloc_free(b, LeakDetector::system());
+}
+
+#[bench]
+fn registry(b: &mut Bencher) {
+    alloc_free(b, LeakDetector::builder(System).registry(true).build());
+}
+
+#[cfg(feature = "backtrace")]
+#[bench]
+fn raw_backtraces(b: &mut Bencher) {
+    let detector = LeakDetector::builder(System)
+        .registry(true)
+        .backtraces(true)
+        .build();
+    alloc_free(b, detector);
+}
+
+#[cfg(feature = "backtrace")]
+#[bench]
+fn sampled_backtraces(b: &mut Bencher) {
+    let detector = LeakDetector::builder(System)
+        .registry(true)
+        .backtraces(true)
+        .build();
+    detector.set_backtrace_sampling(100);
+    alloc_free(b, detector);
+}
@@ -0,0 +1,16 @@
+//! Builds the C shim `tests/c_intercept.rs` leaks from, with `c-intercept`
+//! on unix, and links it into the tests only.
+
+fn main() {
+    #[cfg(feature = "c-intercept")]
+    if std::env::var("CARGO_CFG_UNIX").is_ok() {
+        let out = std::env::var("OUT_DIR").unwrap();
+        cc::Build::new()
+            .file("tests/c/shim.c")
+            .cargo_metadata(false)
+            .compile("c_intercept_shim");
+        println!("cargo::rerun-if-changed=tests/c/shim.c");
+        println!("cargo::rustc-link-arg-tests={out}/libc_intercept_shim.a");
+    }
+    println!("cargo::rerun-if-changed=build.rs");
+}
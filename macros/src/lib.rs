@@ -0,0 +1,522 @@
+//! `#[leak_checked]`, re-exported by `mem_leak_detector` with its `macros`
+//! feature. Written against `proc_macro` alone, so it only looks at as much
+//! of the item as it has to: outer attributes, the qualifiers before `fn`,
+//! the body, and how an `impl` block splits into items.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Wraps a function, or every method of an `impl` block, in a scope of the
+/// detector at `detector`, named after it, like `Engine::flush`:
+///
+/// ```
+/// # #![feature(allocator_api)]
+/// use std::alloc::System;
+///
+/// use mem_leak_detector::{LeakDetector, leak_checked};
+///
+/// static DETECTOR: LeakDetector<System> = LeakDetector::system();
+///
+/// struct Engine {
+///     pages: Vec<Box<[u8], &'static LeakDetector<System>>>,
+/// }
+///
+/// #[leak_checked(detector = DETECTOR)]
+/// impl Engine {
+///     fn checksum(&self) -> u32 {
+///         let page = Box::new_in(self.pages.len() as u32, &DETECTOR);
+///         *page
+///     }
+///
+///     #[leak_checked(skip)]
+///     fn grow(&mut self) {
+///         self.pages.push(Box::new_in([0; 4096], &DETECTOR));
+///     }
+/// }
+///
+/// let mut engine = Engine { pages: Vec::new() };
+/// engine.grow();
+/// assert_eq!(engine.checksum(), 1);
+/// ```
+///
+/// Options, separated by commas:
+///
+/// - `detector = PATH`: a `LeakDetector` expression, evaluated on every
+///   call. Required on an `impl` block or a free function; a method may
+///   name another.
+/// - `tolerance = BYTES`: opens the scope `with_max_delta(BYTES)`.
+/// - `name = "..."`: names the scope something else.
+/// - `skip`: on a method of a `#[leak_checked]` impl, leaves it alone.
+///
+/// The scope is opened before the body runs and checked after its value,
+/// early returns and `?` included, so a returned allocation counts as
+/// leaked. An `async fn` scopes its whole future with
+/// `LeakDetectorScope::around`, opened on its first poll. `const fn`s can't
+/// open scopes and are rejected, as are items other than functions and
+/// impl blocks:
+///
+/// ```compile_fail
+/// # use mem_leak_detector::{LeakDetector, leak_checked};
+/// # static DETECTOR: LeakDetector<std::alloc::System> = LeakDetector::system();
+/// #[leak_checked(detector = DETECTOR)]
+/// const fn answer() -> u32 {
+///     42
+/// }
+/// ```
+///
+/// ```compile_fail
+/// # use mem_leak_detector::{LeakDetector, leak_checked};
+/// # static DETECTOR: LeakDetector<std::alloc::System> = LeakDetector::system();
+/// #[leak_checked(detector = DETECTOR)]
+/// struct Engine;
+/// ```
+///
+/// ```compile_fail
+/// # use mem_leak_detector::leak_checked;
+/// #[leak_checked]
+/// fn no_detector() {}
+/// ```
+///
+/// ```compile_fail
+/// # use mem_leak_detector::{LeakDetector, leak_checked};
+/// # static DETECTOR: LeakDetector<std::alloc::System> = LeakDetector::system();
+/// #[leak_checked(detector = DETECTOR, tolerance = "lots")]
+/// fn bad_tolerance() {}
+/// ```
+///
+/// ```compile_fail
+/// # use mem_leak_detector::{LeakDetector, leak_checked};
+/// # static DETECTOR: LeakDetector<std::alloc::System> = LeakDetector::system();
+/// trait Store {
+///     #[leak_checked(detector = DETECTOR)]
+///     fn get(&self) -> u32;
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn leak_checked(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand(attr, item).unwrap_or_else(Error::into_compile_error)
+}
+
+struct Error {
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+
+    fn into_compile_error(self) -> TokenStream {
+        let mut message = Literal::string(&self.message);
+        message.set_span(self.span);
+        let mut group = Group::new(
+            Delimiter::Brace,
+            TokenStream::from(TokenTree::Literal(message)),
+        );
+        group.set_span(self.span);
+        let mut bang = Punct::new('!', Spacing::Alone);
+        bang.set_span(self.span);
+        TokenStream::from_iter([
+            TokenTree::Ident(Ident::new("compile_error", self.span)),
+            TokenTree::Punct(bang),
+            TokenTree::Group(group),
+        ])
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Default)]
+struct Options {
+    detector: Option<TokenStream>,
+    tolerance: Option<Literal>,
+    name: Option<Literal>,
+    skip: bool,
+}
+
+impl Options {
+    fn parse(attr: TokenStream) -> Result<Self> {
+        let mut options = Options::default();
+        let tokens: Vec<TokenTree> = attr.into_iter().collect();
+        for option in tokens.split(|token| is_punct(token, ',')) {
+            let Some((key, rest)) = option.split_first() else {
+                continue;
+            };
+            let TokenTree::Ident(key) = key else {
+                return Err(Error::new(
+                    key.span(),
+                    "expected `detector = ...`, `tolerance = ...`, `name = \"...\"` or `skip`",
+                ));
+            };
+            let key_name = key.to_string();
+            if key_name == "skip" {
+                if let Some(extra) = rest.first() {
+                    return Err(Error::new(extra.span(), "`skip` takes no value"));
+                }
+                options.skip = true;
+                continue;
+            }
+            let value = match rest.split_first() {
+                Some((eq, value)) if is_punct(eq, '=') && !value.is_empty() => value,
+                _ => {
+                    return Err(Error::new(
+                        key.span(),
+                        format!("expected `{key_name} = ...`"),
+                    ));
+                }
+            };
+            match key_name.as_str() {
+                "detector" => options.detector = Some(value.iter().cloned().collect()),
+                "tolerance" => {
+                    options.tolerance = Some(literal(value, "an integer", |text| {
+                        text.chars().all(|c| c.is_ascii_digit() || c == '_')
+                    })?)
+                }
+                "name" => {
+                    options.name = Some(literal(value, "a string", |text| text.starts_with('"'))?)
+                }
+                _ => {
+                    return Err(Error::new(
+                        key.span(),
+                        format!("unknown option `{key_name}`"),
+                    ));
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    /// These options, overridden by a method's own.
+    fn with(&self, method: Options) -> Options {
+        Options {
+            detector: method.detector.or_else(|| self.detector.clone()),
+            tolerance: method.tolerance.or_else(|| self.tolerance.clone()),
+            name: method.name,
+            skip: method.skip,
+        }
+    }
+}
+
+fn literal(value: &[TokenTree], expected: &str, valid: impl Fn(&str) -> bool) -> Result<Literal> {
+    match value {
+        [TokenTree::Literal(literal)] if valid(&literal.to_string()) => Ok(literal.clone()),
+        _ => Err(Error::new(value[0].span(), format!("expected {expected}"))),
+    }
+}
+
+fn is_punct(token: &TokenTree, c: char) -> bool {
+    matches!(token, TokenTree::Punct(punct) if punct.as_char() == c)
+}
+
+fn is_ident(token: &TokenTree, name: &str) -> bool {
+    matches!(token, TokenTree::Ident(ident) if ident.to_string() == name)
+}
+
+fn expand(attr: TokenStream, item: TokenStream) -> Result<TokenStream> {
+    let options = Options::parse(attr)?;
+    if options.skip {
+        return Err(Error::new(
+            Span::call_site(),
+            "`skip` only applies to methods of a `#[leak_checked]` impl",
+        ));
+    }
+    let tokens: Vec<TokenTree> = item.into_iter().collect();
+    let item = Item::new(&tokens);
+    let tokens = match item.keyword() {
+        Some(Keyword::Impl) => expand_impl(tokens, &options)?,
+        Some(Keyword::Fn) => {
+            let name = item.fn_name()?.to_string();
+            expand_fn(tokens, &options, name)?
+        }
+        None => {
+            return Err(Error::new(
+                tokens.first().map_or_else(Span::call_site, TokenTree::span),
+                "#[leak_checked] applies to functions and impl blocks",
+            ));
+        }
+    };
+    Ok(tokens.into_iter().collect())
+}
+
+enum Keyword {
+    Impl,
+    Fn,
+}
+
+/// An item's tokens with its outer attributes told apart.
+struct Item<'a> {
+    tokens: &'a [TokenTree],
+    /// Where the tokens after the outer attributes start.
+    start: usize,
+}
+
+impl<'a> Item<'a> {
+    fn new(tokens: &'a [TokenTree]) -> Self {
+        let mut start = 0;
+        while start + 1 < tokens.len()
+            && is_punct(&tokens[start], '#')
+            && matches!(&tokens[start + 1], TokenTree::Group(group) if group.delimiter() == Delimiter::Bracket)
+        {
+            start += 2;
+        }
+        Self { tokens, start }
+    }
+
+    fn attributes(&self) -> impl Iterator<Item = (usize, &'a Group)> {
+        (0..self.start)
+            .step_by(2)
+            .map(|i| match &self.tokens[i + 1] {
+                TokenTree::Group(group) => (i, group),
+                _ => unreachable!(),
+            })
+    }
+
+    /// `impl` or `fn`, after any visibility and qualifiers.
+    fn keyword(&self) -> Option<Keyword> {
+        for token in &self.tokens[self.start..] {
+            match token {
+                TokenTree::Ident(ident) => match ident.to_string().as_str() {
+                    "impl" => return Some(Keyword::Impl),
+                    "fn" => return Some(Keyword::Fn),
+                    "pub" | "crate" | "const" | "async" | "unsafe" | "extern" | "default" => {}
+                    _ => return None,
+                },
+                // `pub(crate)` and `extern "C"`.
+                TokenTree::Group(group) if group.delimiter() == Delimiter::Parenthesis => {}
+                TokenTree::Literal(_) => {}
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    fn fn_index(&self) -> Option<usize> {
+        (self.start..self.tokens.len()).find(|&i| is_ident(&self.tokens[i], "fn"))
+    }
+
+    fn fn_name(&self) -> Result<&'a Ident> {
+        match self.fn_index().map(|i| &self.tokens[i + 1]) {
+            Some(TokenTree::Ident(name)) => Ok(name),
+            _ => Err(Error::new(Span::call_site(), "expected a function name")),
+        }
+    }
+
+    fn has_qualifier(&self, qualifier: &str) -> bool {
+        self.fn_index().is_some_and(|fn_index| {
+            self.tokens[self.start..fn_index]
+                .iter()
+                .any(|token| is_ident(token, qualifier))
+        })
+    }
+
+    /// The body, if the item ends in one.
+    fn body(&self) -> Option<&'a Group> {
+        match self.tokens.last() {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => Some(group),
+            _ => None,
+        }
+    }
+}
+
+fn expand_fn(
+    mut tokens: Vec<TokenTree>,
+    options: &Options,
+    name: String,
+) -> Result<Vec<TokenTree>> {
+    let item = Item::new(&tokens);
+    let fn_name = item.fn_name()?;
+    if item.has_qualifier("const") {
+        return Err(Error::new(
+            fn_name.span(),
+            "#[leak_checked] can't open a scope in a `const fn`",
+        ));
+    }
+    let Some(detector) = &options.detector else {
+        return Err(Error::new(
+            fn_name.span(),
+            "#[leak_checked] needs `detector = PATH`",
+        ));
+    };
+    let Some(body) = item.body() else {
+        return Err(Error::new(
+            fn_name.span(),
+            "#[leak_checked] needs a function with a body",
+        ));
+    };
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => Literal::string(&name),
+    };
+    let mut scope: TokenStream = detector.clone();
+    scope.extend(parse(".scope()"));
+    scope.extend(parse(".named"));
+    scope.extend([TokenTree::Group(Group::new(
+        Delimiter::Parenthesis,
+        TokenStream::from(TokenTree::Literal(name)),
+    ))]);
+    if let Some(tolerance) = &options.tolerance {
+        scope.extend(parse(".with_max_delta"));
+        scope.extend([TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            TokenStream::from(TokenTree::Literal(tolerance.clone())),
+        ))]);
+    }
+
+    let (inner_attributes, statements) = split_inner_attributes(body.stream());
+    let mut wrapped = inner_attributes;
+    if item.has_qualifier("async") {
+        // `return` and `?` leave the async block with the function's
+        // output type, which the `.await` below infers it from.
+        let mut future = parse("async move");
+        future.extend([TokenTree::Group(Group::new(Delimiter::Brace, statements))]);
+        wrapped.extend(scope);
+        wrapped.extend(parse(".around"));
+        wrapped.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, future))]);
+        wrapped.extend(parse(".await"));
+    } else {
+        wrapped.extend(parse("let __leak_checked_scope ="));
+        wrapped.extend(scope);
+        wrapped.extend(parse(";"));
+        wrapped.extend(statements);
+    }
+    let mut group = Group::new(Delimiter::Brace, wrapped);
+    group.set_span(body.span());
+    let last = tokens.len() - 1;
+    tokens[last] = TokenTree::Group(group);
+    Ok(tokens)
+}
+
+fn expand_impl(mut tokens: Vec<TokenTree>, options: &Options) -> Result<Vec<TokenTree>> {
+    let item = Item::new(&tokens);
+    let Some(body) = item.body() else {
+        return Err(Error::new(Span::call_site(), "expected an impl block"));
+    };
+    let type_name = self_type_name(&tokens[item.start..tokens.len() - 1]);
+    let (inner_attributes, items) = split_inner_attributes(body.stream());
+    let mut expanded = inner_attributes;
+    for item_tokens in split_items(items) {
+        let item = Item::new(&item_tokens);
+        if !matches!(item.keyword(), Some(Keyword::Fn)) {
+            expanded.extend(item_tokens);
+            continue;
+        }
+        let mut method = Options::default();
+        let mut kept = Vec::new();
+        let mut own = None;
+        for (i, attribute) in item.attributes() {
+            match leak_checked_arguments(attribute) {
+                Some(arguments) => own = Some(Options::parse(arguments)?),
+                None => kept.extend(item_tokens[i..i + 2].iter().cloned()),
+            }
+        }
+        if let Some(own) = own {
+            method = own;
+        }
+        kept.extend(item_tokens[item.start..].iter().cloned());
+        let method = options.with(method);
+        if method.skip {
+            expanded.extend(kept);
+            continue;
+        }
+        let name = match &type_name {
+            Some(type_name) => format!("{type_name}::{}", item.fn_name()?),
+            None => item.fn_name()?.to_string(),
+        };
+        expanded.extend(expand_fn(kept, &method, name)?);
+    }
+    let mut group = Group::new(Delimiter::Brace, expanded.into_iter().collect());
+    group.set_span(body.span());
+    let last = tokens.len() - 1;
+    tokens[last] = TokenTree::Group(group);
+    Ok(tokens)
+}
+
+/// The arguments of `#[leak_checked(...)]` or `#[path::leak_checked(...)]`,
+/// or empty ones for a bare `#[leak_checked]`.
+fn leak_checked_arguments(attribute: &Group) -> Option<TokenStream> {
+    let tokens: Vec<TokenTree> = attribute.stream().into_iter().collect();
+    let (path, arguments) = match tokens.last() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+            (&tokens[..tokens.len() - 1], group.stream())
+        }
+        _ => (&tokens[..], TokenStream::new()),
+    };
+    path.last()
+        .is_some_and(|last| is_ident(last, "leak_checked"))
+        .then_some(arguments)
+}
+
+/// The last name in the type an `impl` header is for, like `Engine` in
+/// `impl<T> Store for engine::Engine<T> where T: Send`.
+fn self_type_name(header: &[TokenTree]) -> Option<String> {
+    let mut name = None;
+    let mut depth = 0usize;
+    let mut previous_dash = false;
+    for token in header {
+        match token {
+            TokenTree::Punct(punct) => match punct.as_char() {
+                '<' => depth += 1,
+                '>' if !previous_dash => depth = depth.saturating_sub(1),
+                _ => {}
+            },
+            TokenTree::Ident(ident) if depth == 0 => match ident.to_string().as_str() {
+                "where" => break,
+                "impl" | "unsafe" | "for" | "dyn" | "mut" | "const" => {}
+                other => name = Some(other.to_owned()),
+            },
+            _ => {}
+        }
+        previous_dash = is_punct(token, '-');
+    }
+    name
+}
+
+/// Splits `#![...]` attributes off the start of a block.
+fn split_inner_attributes(stream: TokenStream) -> (TokenStream, TokenStream) {
+    let tokens: Vec<TokenTree> = stream.into_iter().collect();
+    let mut end = 0;
+    while end + 2 < tokens.len()
+        && is_punct(&tokens[end], '#')
+        && is_punct(&tokens[end + 1], '!')
+        && matches!(&tokens[end + 2], TokenTree::Group(group) if group.delimiter() == Delimiter::Bracket)
+    {
+        end += 3;
+    }
+    (
+        tokens[..end].iter().cloned().collect(),
+        tokens[end..].iter().cloned().collect(),
+    )
+}
+
+/// Splits an impl body into items: each ends at a `;`, at the body of a
+/// `fn`, or at a braced macro call.
+fn split_items(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut items = Vec::new();
+    let mut current: Vec<TokenTree> = Vec::new();
+    for token in stream {
+        let ends = match &token {
+            TokenTree::Punct(punct) => punct.as_char() == ';',
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
+                let start = Item::new(&current).start;
+                current[start..].iter().any(|token| is_ident(token, "fn"))
+                    || current.last().is_some_and(|last| is_punct(last, '!'))
+            }
+            _ => false,
+        };
+        current.push(token);
+        if ends {
+            items.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        items.push(current);
+    }
+    items
+}
+
+fn parse(code: &str) -> TokenStream {
+    code.parse().unwrap()
+}
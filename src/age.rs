@@ -0,0 +1,132 @@
+use std::{
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+use crate::{LeakDetector, LeakReport, LeakedAllocation, ledger::Ledger};
+
+/// How many of a report's allocations fall in each age range.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AgeDistribution {
+    pub under_1s: usize,
+    pub under_10s: usize,
+    pub under_60s: usize,
+    pub older: usize,
+}
+
+impl fmt::Display for AgeDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} under 1s, {} 1-10s, {} 10-60s, {} over 60s",
+            self.under_1s, self.under_10s, self.under_60s, self.older
+        )
+    }
+}
+
+impl LeakReport {
+    pub fn age_distribution(&self) -> AgeDistribution {
+        let mut distribution = AgeDistribution::default();
+        for allocation in self.allocations() {
+            let bucket = match allocation.age.as_secs() {
+                0 => &mut distribution.under_1s,
+                1..10 => &mut distribution.under_10s,
+                10..60 => &mut distribution.under_60s,
+                _ => &mut distribution.older,
+            };
+            *bucket += 1;
+        }
+        distribution
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Moves the detector's clock forward by `by`. Blocks only record the
+    /// clock's reading when allocated, so ages are as fine as the steps it
+    /// takes; drive it either with this, say once per frame or from a fake
+    /// clock in tests, or with [`start_clock`], not both.
+    ///
+    /// [`start_clock`]: LeakDetector::start_clock
+    pub fn advance_clock(&self, by: Duration) {
+        self.registry.advance_clock(by.as_millis() as u64);
+    }
+
+    /// How far the detector's clock has moved since it started.
+    pub fn clock(&self) -> Duration {
+        Duration::from_millis(self.registry.clock())
+    }
+
+    /// Live registry blocks allocated at least `age` ago by the detector's
+    /// clock, sorted by address.
+    pub fn allocations_older_than(&self, age: Duration) -> Vec<LeakedAllocation> {
+        let mut allocations = self.allocations_after(0);
+        allocations.retain(|allocation| allocation.age >= age);
+        allocations
+    }
+}
+
+impl<T: Sync> LeakDetector<T> {
+    /// Drives the detector's clock from a background thread waking every
+    /// `resolution`, so allocating never reads the OS clock.
+    pub fn start_clock(&'static self, resolution: Duration) -> thread::JoinHandle<()> {
+        let start = Instant::now();
+        thread::Builder::new()
+            .name("leak detector clock".into())
+            .spawn(move || {
+                loop {
+                    thread::sleep(resolution);
+                    self.registry.set_clock(start.elapsed().as_millis() as u64);
+                }
+            })
+            .expect("failed to spawn the leak detector clock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn ages_by_clock() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let cache = Box::new_in([0u8; 64], &detector);
+        detector.advance_clock(Duration::from_secs(90));
+        let session = Box::new_in([0u8; 32], &detector);
+        detector.advance_clock(Duration::from_secs(5));
+        let request = Box::new_in([0u8; 16], &detector);
+
+        let old = detector.allocations_older_than(Duration::from_secs(60));
+        assert_eq!(old.len(), 1);
+        assert_eq!(old[0].size, 64);
+        assert_eq!(old[0].age, Duration::from_secs(95));
+
+        let report = detector.leak_report();
+        assert_eq!(
+            report.age_distribution(),
+            AgeDistribution {
+                under_1s: 1,
+                under_10s: 1,
+                under_60s: 0,
+                older: 1,
+            }
+        );
+        assert!(
+            report
+                .to_string()
+                .contains("\n  by age: 1 under 1s, 1 1-10s, 0 10-60s, 1 over 60s")
+        );
+        drop((cache, session, request));
+    }
+
+    #[test]
+    fn background_clock() {
+        static DETECTOR: LeakDetector<System> = LeakDetector::system();
+        DETECTOR.start_clock(Duration::from_millis(1));
+        let (started, _) = crate::wait::wait_until(Duration::from_secs(10), || {
+            DETECTOR.clock() > Duration::ZERO
+        });
+        assert!(started);
+    }
+}
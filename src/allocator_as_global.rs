@@ -0,0 +1,146 @@
+use std::{
+    alloc::{Allocator, GlobalAlloc, Layout},
+    ptr::{self, NonNull},
+};
+
+use crate::LeakDetector;
+
+/// An [`Allocator`] as a [`GlobalAlloc`], so a detector over a custom
+/// pool can be the `#[global_allocator]`; see
+/// [`LeakDetector::from_allocator`]. It's still an `Allocator` too.
+///
+/// Failures come back as null, as `GlobalAlloc` expects.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AllocatorAsGlobal<T>(pub T);
+
+fn raw(result: Result<NonNull<[u8]>, std::alloc::AllocError>) -> *mut u8 {
+    result.map_or(ptr::null_mut(), |block| block.cast::<u8>().as_ptr())
+}
+
+unsafe impl<T: Allocator> GlobalAlloc for AllocatorAsGlobal<T> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        raw(self.0.allocate(layout))
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            unsafe { self.0.deallocate(ptr, layout) }
+        }
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        raw(self.0.allocate_zeroed(layout))
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Some(ptr) = NonNull::new(ptr) else {
+            return ptr::null_mut();
+        };
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return ptr::null_mut();
+        };
+        match new_size.cmp(&layout.size()) {
+            std::cmp::Ordering::Greater => raw(unsafe { self.0.grow(ptr, layout, new_layout) }),
+            std::cmp::Ordering::Less => raw(unsafe { self.0.shrink(ptr, layout, new_layout) }),
+            std::cmp::Ordering::Equal => ptr.as_ptr(),
+        }
+    }
+}
+
+unsafe impl<T: Allocator> Allocator for AllocatorAsGlobal<T> {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, std::alloc::AllocError> {
+        self.0.allocate(layout)
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, std::alloc::AllocError> {
+        self.0.allocate_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.0.deallocate(ptr, layout) }
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, std::alloc::AllocError> {
+        unsafe { self.0.grow(ptr, old_layout, new_layout) }
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, std::alloc::AllocError> {
+        unsafe { self.0.grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, std::alloc::AllocError> {
+        unsafe { self.0.shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+impl<T: Allocator> LeakDetector<AllocatorAsGlobal<T>> {
+    /// A detector over an allocator that only implements [`Allocator`],
+    /// usable as the `#[global_allocator]`:
+    ///
+    /// ```ignore
+    /// #[global_allocator]
+    /// static GLOBAL: LeakDetector<AllocatorAsGlobal<MyPool>> =
+    ///     LeakDetector::from_allocator(MyPool::new());
+    /// ```
+    pub const fn from_allocator(inner: T) -> Self {
+        Self::new(AllocatorAsGlobal(inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn resizes_and_failures() {
+        struct Refusing;
+        unsafe impl Allocator for Refusing {
+            fn allocate(&self, _: Layout) -> Result<NonNull<[u8]>, std::alloc::AllocError> {
+                Err(std::alloc::AllocError)
+            }
+            unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
+        }
+        let layout = Layout::new::<[u8; 16]>();
+        assert!(unsafe { AllocatorAsGlobal(Refusing).alloc(layout) }.is_null());
+
+        let detector = LeakDetector::from_allocator(System);
+        unsafe {
+            let ptr = detector.alloc_zeroed(layout);
+            ptr.add(15).write(7);
+            let ptr = detector.realloc(ptr, layout, 64);
+            assert_eq!(ptr.add(15).read(), 7);
+            assert_eq!(detector.get_used(), 64);
+            let ptr = detector.realloc(ptr, Layout::from_size_align(64, 1).unwrap(), 16);
+            assert_eq!(ptr.add(15).read(), 7);
+            assert_eq!(detector.get_used(), 16);
+            detector.dealloc(ptr, layout);
+        }
+        detector.assert();
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{Epoch, LeakDetector};
+
+impl<T> LeakDetector<T> {
+    /// Tells the detector that its inner arena freed every block at once,
+    /// as `reset` on a bump allocator does: blocks still live are counted as
+    /// freed and leave the registry.
+    pub fn notify_reset(&self) {
+        self.notify_reset_since(Epoch::START);
+    }
+
+    /// Tells the detector that its inner arena freed every block allocated
+    /// since `epoch` began, for arenas that rewind to a checkpoint; take the
+    /// epoch with [`advance_epoch`] when the checkpoint is made.
+    ///
+    /// Without the registry the detector can't tell which blocks those were,
+    /// and takes back what `used` grew by since `epoch` instead.
+    ///
+    /// [`advance_epoch`]: LeakDetector::advance_epoch
+    pub fn notify_reset_since(&self, epoch: Epoch) {
+        if self.registry.is_enabled() {
+            let freed = self
+                .registry
+                .lock()
+                .retain(|entry| entry.epoch <= epoch.value());
+            let bytes = freed.iter().map(|entry| entry.size).sum();
+            let underflow = self.counters.release(bytes, freed.len());
+            self.diagnostics.underflow(underflow);
+            for entry in &freed {
+                self.charge_thread(Some(entry), entry.size, 0);
+                self.counters.actual(entry.usable, 0);
+                self.counters.pad(entry.padding, 0);
+            }
+        } else {
+            let live = self
+                .counters
+                .allocations()
+                .saturating_sub(self.counters.deallocations());
+            let bytes = self.get_used().saturating_sub(epoch.used);
+            self.counters
+                .release(bytes, live.saturating_sub(epoch.live));
+            self.charge_thread(None, bytes, 0);
+            if epoch == Epoch::START {
+                self.counters.actual(self.get_used_actual(), 0);
+                self.counters.pad(self.padding_bytes(), 0);
+            }
+        }
+        self.waiters.freed(self.get_used());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{AllocError, Allocator, Layout, System},
+        cell::Cell,
+        ptr::NonNull,
+    };
+
+    use super::*;
+
+    /// Hands out one fixed buffer front to back; frees do nothing.
+    struct Bump {
+        buffer: NonNull<u8>,
+        next: Cell<usize>,
+    }
+
+    const CAPACITY: usize = 4096;
+    const BUFFER: Layout = match Layout::from_size_align(CAPACITY, 64) {
+        Ok(layout) => layout,
+        Err(_) => panic!(),
+    };
+
+    impl Bump {
+        fn new() -> Self {
+            Self {
+                buffer: System.allocate(BUFFER).unwrap().cast(),
+                next: Cell::new(0),
+            }
+        }
+
+        fn reset(&self) {
+            self.next.set(0);
+        }
+    }
+
+    impl Drop for Bump {
+        fn drop(&mut self) {
+            unsafe { System.deallocate(self.buffer, BUFFER) };
+        }
+    }
+
+    unsafe impl Allocator for Bump {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            let start = self.next.get().next_multiple_of(layout.align());
+            let end = start + layout.size();
+            if end > CAPACITY {
+                return Err(AllocError);
+            }
+            self.next.set(end);
+            let ptr = unsafe { self.buffer.add(start) };
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        }
+
+        unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+    }
+
+    fn fill(detector: &LeakDetector<Bump>) {
+        for size in [24, 100, 8] {
+            std::mem::forget(Vec::<u8, _>::with_capacity_in(size, detector));
+        }
+        drop(Box::new_in(0u64, detector));
+    }
+
+    #[test]
+    fn reset_returns_to_baseline() {
+        for registry in [false, true] {
+            let detector = LeakDetector::builder(Bump::new())
+                .registry(registry)
+                .build();
+            let before = detector.snapshot();
+            fill(&detector);
+            assert_eq!(detector.get_used(), 132);
+            assert!(detector.check().is_err());
+
+            detector.inner.reset();
+            detector.notify_reset();
+            let after = detector.snapshot();
+            assert_eq!(after.used, before.used);
+            assert_eq!(after.allocations, after.deallocations);
+            assert_eq!(detector.live_allocations(), 0);
+            detector.assert();
+            assert!(detector.diagnostics().is_empty());
+        }
+    }
+
+    #[test]
+    fn rewinds_to_a_checkpoint() {
+        for registry in [false, true] {
+            let detector = LeakDetector::builder(Bump::new())
+                .registry(registry)
+                .build();
+            let kept = Box::new_in([0u8; 40], &detector);
+            let checkpoint = detector.advance_epoch();
+            fill(&detector);
+            detector.notify_reset_since(checkpoint);
+            assert_eq!(detector.get_used(), 40);
+            let snapshot = detector.snapshot();
+            assert_eq!(snapshot.allocations - snapshot.deallocations, 1);
+            drop(kept);
+            detector.assert();
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{FailureMode, LeakDetector, OnLeak};
+
+/// Runs [`check`](LeakDetector::check) when dropped, see
+/// [`LeakDetector::assert_on_drop`].
+#[must_use = "the check runs when the guard is dropped"]
+pub struct BalanceGuard<'a, T> {
+    detector: &'a LeakDetector<T>,
+    defused: bool,
+}
+
+impl<T> LeakDetector<T> {
+    /// Returns a guard that checks the whole detector when dropped, against
+    /// its baseline as [`check`](LeakDetector::check) does, unlike a scope,
+    /// which compares against its own start. A leak is handled by the
+    /// detector's [`OnLeak`] policy like [`check_on_drop`], with the leak
+    /// report appended when the registry is on.
+    ///
+    /// [`check_on_drop`]: crate::LeakDetectorBuilder::check_on_drop
+    pub fn assert_on_drop(&self) -> BalanceGuard<'_, T> {
+        BalanceGuard {
+            detector: self,
+            defused: false,
+        }
+    }
+}
+
+impl<T> BalanceGuard<'static, T> {
+    /// [`assert_on_drop`](LeakDetector::assert_on_drop) for a detector in a
+    /// `static`, such as the `#[global_allocator]`. The guard can then be
+    /// kept anywhere, or sent to another thread when `T` is `Sync`.
+    pub fn global(detector: &'static LeakDetector<T>) -> Self {
+        detector.assert_on_drop()
+    }
+}
+
+impl<T> BalanceGuard<'_, T> {
+    /// Disarms the guard: it won't check anything when dropped.
+    pub fn defuse(&mut self) {
+        self.defused = true;
+    }
+}
+
+/// `OnLeak::Panic` fails as the detector's [`FailureMode`] says; panicking,
+/// it only logs while the thread is already unwinding. A callback policy
+/// logs, as there is no scope to hand it.
+impl<T> Drop for BalanceGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.defused {
+            return;
+        }
+        let Err(err) = self.detector.check() else {
+            return;
+        };
+        let on_leak = self.detector.on_leak();
+        if let OnLeak::Ignore = on_leak {
+            return;
+        }
+        let _internal = crate::pause::internal();
+        let report = self.detector.registry_enabled().then(|| {
+            #[cfg_attr(not(feature = "backtrace"), allow(unused_mut))]
+            let mut report = self.detector.leak_report();
+            #[cfg(feature = "backtrace")]
+            report.symbolize();
+            report
+        });
+        // A panic message may be caught and compared, so only the log is
+        // dressed up for a terminal.
+        let mode = self.detector.failure_mode();
+        let unwinding = mode == FailureMode::Panic && std::thread::panicking();
+        match (on_leak, report) {
+            (OnLeak::Panic, Some(report)) if !unwinding => {
+                mode.fail(&format_args!("{err}\n{report}"))
+            }
+            (OnLeak::Panic, None) if !unwinding => mode.fail(&err),
+            (_, Some(report)) => eprintln!("{err}\n{}", report.for_stderr()),
+            (_, None) => eprintln!("{err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::System,
+        panic::{AssertUnwindSafe, catch_unwind},
+    };
+
+    use super::*;
+
+    static GLOBAL: LeakDetector<System> = LeakDetector::system();
+
+    #[test]
+    fn balanced_program_passes() {
+        let guard = BalanceGuard::global(&GLOBAL);
+        let mut words = GLOBAL.vec();
+        words.push(GLOBAL.boxed("balanced"));
+        drop(words);
+        drop(guard);
+    }
+
+    #[test]
+    fn leaking_program_panics_with_report() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let payload = catch_unwind(AssertUnwindSafe(|| {
+            let _guard = detector.assert_on_drop();
+            std::mem::forget(detector.vec_with_capacity::<u8>(32));
+        }))
+        .unwrap_err();
+        let message = payload.downcast::<String>().unwrap();
+        assert!(message.starts_with("32 bytes leaked\n"), "{message}");
+        assert!(
+            message.contains("32 bytes leaked in 1 allocation(s)"),
+            "{message}"
+        );
+
+        let mut guard = detector.assert_on_drop();
+        guard.defuse();
+    }
+}
@@ -0,0 +1,198 @@
+//! Budgets of scopes opened with [`LeakDetector::scope_with_budget`]: once
+//! what a scope's thread allocated in it, less what it freed, would exceed
+//! its budget, allocations on the thread fail before reaching the inner
+//! allocator. They are kept by detector address, those of a
+//! [`LocalLeakDetector`](crate::LocalLeakDetector) too.
+
+use std::cell::RefCell;
+
+use crate::{LeakDetector, LeakDetectorScope};
+
+/// Budgeted scopes nested deeper than this on one thread aren't enforced.
+const MAX_BUDGETS: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Budget {
+    detector: usize,
+    id: u64,
+    /// Bytes this thread allocated less those it freed since the budget
+    /// opened, below zero if it freed older blocks.
+    used: isize,
+    limit: usize,
+}
+
+/// Like the scope stack, a fixed array so the allocator can read it without
+/// allocating.
+struct Budgets {
+    len: usize,
+    budgets: [Budget; MAX_BUDGETS],
+}
+
+thread_local! {
+    static BUDGETS: RefCell<Budgets> = const {
+        RefCell::new(Budgets {
+            len: 0,
+            budgets: [Budget {
+                detector: 0,
+                id: 0,
+                used: 0,
+                limit: 0,
+            }; MAX_BUDGETS],
+        })
+    };
+}
+
+fn with_budgets<R>(f: impl FnOnce(&mut Budgets) -> R) -> Option<R> {
+    BUDGETS
+        .try_with(|budgets| {
+            budgets
+                .try_borrow_mut()
+                .ok()
+                .map(|mut budgets| f(&mut budgets))
+        })
+        .ok()
+        .flatten()
+}
+
+pub(crate) fn push(detector: usize, id: u64, limit: usize) {
+    with_budgets(|budgets| {
+        if budgets.len < MAX_BUDGETS {
+            budgets.budgets[budgets.len] = Budget {
+                detector,
+                id,
+                used: 0,
+                limit,
+            };
+            budgets.len += 1;
+        }
+    });
+}
+
+pub(crate) fn pop(detector: usize, id: u64) {
+    with_budgets(|budgets| {
+        if let Some(index) = budgets.budgets[..budgets.len]
+            .iter()
+            .rposition(|budget| budget.detector == detector && budget.id == id)
+        {
+            budgets.budgets.copy_within(index + 1..budgets.len, index);
+            budgets.len -= 1;
+        }
+    });
+}
+
+impl Budgets {
+    fn fits(&self, detector: usize, additional: usize) -> bool {
+        let additional = additional.min(isize::MAX as usize) as isize;
+        self.budgets[..self.len]
+            .iter()
+            .filter(|budget| budget.detector == detector)
+            .all(|budget| budget.used.saturating_add(additional) <= budget.limit as isize)
+    }
+
+    fn charge(&mut self, detector: usize, old: usize, new: usize) {
+        for budget in &mut self.budgets[..self.len] {
+            if budget.detector == detector {
+                budget.used = budget
+                    .used
+                    .saturating_add(new as isize)
+                    .saturating_sub(old as isize);
+            }
+        }
+    }
+}
+
+/// Whether allocating `additional` more bytes keeps every budget of
+/// `detector` open on this thread.
+#[inline]
+pub(crate) fn within(detector: usize, additional: usize) -> bool {
+    with_budgets(|budgets| budgets.fits(detector, additional)).unwrap_or(true)
+}
+
+/// Moves this thread's budgets of `detector` from `old` to `new` bytes.
+#[inline]
+pub(crate) fn charge(detector: usize, old: usize, new: usize) {
+    with_budgets(|budgets| budgets.charge(detector, old, new));
+}
+
+impl<T> LeakDetector<T> {
+    /// A scope whose allocations on this thread fail, as if the inner
+    /// allocator were out of memory, once the thread's allocations in it,
+    /// less its frees, would come to more than `bytes`. Other threads don't
+    /// count against it. Nested budgets all apply, so the tightest one wins.
+    /// The scope still checks its balance when dropped.
+    #[track_caller]
+    pub fn scope_with_budget(&self, bytes: usize) -> LeakDetectorScope<'_, T> {
+        self.scope().with_budget(bytes)
+    }
+
+    /// Whether allocating `additional` more bytes keeps every budget of this
+    /// detector open on this thread.
+    #[inline]
+    pub(crate) fn within_budget(&self, additional: usize) -> bool {
+        let detector = self as *const LeakDetector<T> as usize;
+        with_budgets(|budgets| {
+            budgets.len == 0 || !self.tracks_here() || budgets.fits(detector, additional)
+        })
+        .unwrap_or(true)
+    }
+
+    /// Moves this thread's budgets of this detector from `old` to `new`
+    /// bytes, as it allocates, frees or resizes a tracked block.
+    #[inline]
+    pub(crate) fn charge_budgets(&self, old: usize, new: usize) {
+        charge(self as *const LeakDetector<T> as usize, old, new);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn fails_allocations_past_budget() {
+        let detector = LeakDetector::system();
+        {
+            let _scope = detector.scope_with_budget(1024);
+            let mut buffer = Vec::<u8, _>::with_capacity_in(512, &detector);
+            assert!(buffer.try_reserve_exact(2048).is_err());
+            assert_eq!(buffer.capacity(), 512);
+            assert_eq!(detector.get_used(), 512);
+            buffer.try_reserve_exact(1024).unwrap();
+            assert!(Box::try_new_in(0u8, &detector).is_err());
+        }
+        detector.assert();
+        let unlimited = Vec::<u8, _>::with_capacity_in(4096, &detector);
+        drop(unlimited);
+    }
+
+    #[test]
+    fn nested_budgets_take_the_minimum() {
+        let detector = LeakDetector::builder(System).build();
+        let other = LeakDetector::system();
+        let _outer = detector.scope_with_budget(1024);
+        let _inner = detector.scope_with_budget(4096);
+        assert!(Vec::<u8, _>::try_with_capacity_in(2000, &detector).is_err());
+        assert!(Vec::<u8, _>::try_with_capacity_in(1000, &detector).is_ok());
+        assert!(Vec::<u8, _>::try_with_capacity_in(2000, &other).is_ok());
+    }
+
+    #[test]
+    fn other_threads_dont_count() {
+        let detector = LeakDetector::system();
+        let _scope = detector.scope_with_budget(1024);
+        std::thread::scope(|scope| {
+            let kept = scope
+                .spawn(|| Vec::<u8, _>::with_capacity_in(4096, &detector))
+                .join()
+                .unwrap();
+            let mut buffer = Vec::<u8, _>::try_with_capacity_in(1000, &detector).unwrap();
+            assert!(buffer.try_reserve_exact(1100).is_err());
+            drop(buffer);
+            let mut buffer = Vec::<u8, _>::try_with_capacity_in(1000, &detector).unwrap();
+            buffer.try_reserve_exact(1024).unwrap();
+            drop((buffer, kept));
+        });
+    }
+}
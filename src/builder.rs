@@ -0,0 +1,261 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, atomic::AtomicUsize},
+};
+
+#[cfg(feature = "efence")]
+use crate::GuardPlacement;
+use crate::{
+    FailureMode, LeakDetector, OnLargeAllocation, OnLeak, OnThreadExit, ReportOptions, Sampling,
+    Snapshot,
+    counters::Counters,
+    overhead::{Accounted, Internal},
+    poison::Poison,
+    quarantine::Quarantine,
+    registry::Registry,
+    sampling::Sampler,
+    thread_exit::ThreadExit,
+    thread_limit::ThreadLimits,
+};
+
+/// Configures a [`LeakDetector`] before it is created. Every method is
+/// `const`, so a configured detector can still live in a `static`.
+pub struct LeakDetectorBuilder<T> {
+    inner: T,
+    on_leak: OnLeak,
+    failure_mode: FailureMode,
+    registry: bool,
+    sampling: Option<Sampling>,
+    sampling_seed: u64,
+    backtraces: bool,
+    tolerance: usize,
+    check_on_drop: bool,
+    check_on_thread_exit: bool,
+    on_thread_exit: OnThreadExit,
+    quarantine: (usize, usize),
+    verify_quarantine: bool,
+    #[cfg(feature = "efence")]
+    efence: (usize, GuardPlacement),
+    #[cfg(feature = "usable-size")]
+    usable_size: bool,
+    #[cfg(feature = "tracing-attribution")]
+    span_attribution: bool,
+}
+
+impl<T> LeakDetector<T> {
+    pub const fn builder(inner: T) -> LeakDetectorBuilder<T> {
+        LeakDetectorBuilder {
+            inner,
+            on_leak: OnLeak::Panic,
+            failure_mode: FailureMode::Panic,
+            registry: false,
+            sampling: None,
+            sampling_seed: 0,
+            backtraces: false,
+            tolerance: 0,
+            check_on_drop: false,
+            check_on_thread_exit: false,
+            on_thread_exit: OnThreadExit::Log,
+            quarantine: (0, 0),
+            verify_quarantine: false,
+            #[cfg(feature = "efence")]
+            efence: (usize::MAX, GuardPlacement::After),
+            #[cfg(feature = "usable-size")]
+            usable_size: false,
+            #[cfg(feature = "tracing-attribution")]
+            span_attribution: false,
+        }
+    }
+}
+
+impl<T> LeakDetectorBuilder<T> {
+    pub const fn on_leak(mut self, on_leak: OnLeak) -> Self {
+        self.on_leak = on_leak;
+        self
+    }
+
+    /// See [`LeakDetector::set_failure_mode`].
+    pub const fn failure_mode(mut self, mode: FailureMode) -> Self {
+        self.failure_mode = mode;
+        self
+    }
+
+    /// Keeps an entry for every live allocation, which makes frees exact
+    /// across pauses and lets failing scopes say which blocks they leaked.
+    pub const fn registry(mut self, enabled: bool) -> Self {
+        self.registry = enabled;
+        self
+    }
+
+    /// Registers only the allocations `sampling` picks, with their stacks if
+    /// recorded, for production runs where an entry per block costs too
+    /// much. The counters stay exact. Leak reports scale the sampled blocks
+    /// up and mark their numbers as estimates, and
+    /// [`check`](LeakDetector::check) goes by the growth of `used`, as
+    /// without the registry. Frees of unsampled blocks are counted unless
+    /// tracking is paused. Needs the registry.
+    pub const fn sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = Some(sampling);
+        self
+    }
+
+    /// Starts each thread's sampling countdown from `seed`, 0 by default, so
+    /// that a run repeated with the same allocations samples the same ones.
+    pub const fn sampling_seed(mut self, seed: u64) -> Self {
+        self.sampling_seed = seed;
+        self
+    }
+
+    /// See [`LeakDetector::set_tolerance`].
+    pub const fn tolerance(mut self, bytes: usize) -> Self {
+        self.tolerance = bytes;
+        self
+    }
+
+    /// Runs [`check`](LeakDetector::check) when the detector is dropped and
+    /// handles a leak by its [`OnLeak`] policy, like an unbalanced scope but
+    /// logging instead of calling a callback. Off by default, since dropping
+    /// an allocator with live blocks is sometimes intended.
+    pub const fn check_on_drop(mut self, enabled: bool) -> Self {
+        self.check_on_drop = enabled;
+        self
+    }
+
+    /// Checks each thread as it exits for blocks it allocated and never
+    /// freed, handling them by [`on_thread_exit`](Self::on_thread_exit).
+    /// Needs the registry; a block freed on another thread, as when handed
+    /// over through a channel, no longer counts against the thread that
+    /// allocated it. Threads running when the detector is dropped aren't
+    /// checked against it.
+    pub const fn check_on_thread_exit(mut self, enabled: bool) -> Self {
+        self.check_on_thread_exit = enabled;
+        self
+    }
+
+    /// What [`check_on_thread_exit`](Self::check_on_thread_exit) does with a
+    /// leaking thread; [`OnThreadExit::Log`] by default.
+    pub const fn on_thread_exit(mut self, on_exit: OnThreadExit) -> Self {
+        self.on_thread_exit = on_exit;
+        self
+    }
+
+    /// Holds freed blocks back from the inner allocator, up to `max_bytes` in
+    /// `max_blocks` blocks, overwritten with a pattern, and releases the
+    /// oldest once over either. Freeing a block again while it is held
+    /// panics with where it was first freed; from a
+    /// [`GlobalAlloc`](std::alloc::GlobalAlloc) that aborts. Blocks moved by
+    /// a reallocation are freed by the inner allocator and never held.
+    pub const fn quarantine(mut self, max_bytes: usize, max_blocks: usize) -> Self {
+        self.quarantine = (max_bytes, max_blocks);
+        self
+    }
+
+    /// Checks each quarantined block's pattern when it is released and
+    /// panics on a write after free, naming where the block was allocated
+    /// (with the registry) and freed.
+    pub const fn verify_quarantine(mut self, enabled: bool) -> Self {
+        self.verify_quarantine = enabled;
+        self
+    }
+
+    /// Serves every allocation of at least `min_size` bytes from its own
+    /// pages with `mmap` (`VirtualAlloc` on Windows) and an inaccessible
+    /// guard page on the `placement` side, so an overrun or underrun faults
+    /// at once. They bypass the inner allocator but are counted and
+    /// registered like any other block; growing or shrinking one moves it,
+    /// to another guarded block if it is still large enough.
+    ///
+    /// Each block costs at least two pages and a system call to map and
+    /// unmap, so keep `min_size` well above the common sizes. Blocks aligned
+    /// to more than a page, and every block on platforms other than Linux,
+    /// macOS and Windows, go to the inner allocator. A block already from
+    /// the inner allocator stays there when it grows past `min_size`.
+    #[cfg(feature = "efence")]
+    pub const fn efence(mut self, min_size: usize, placement: GuardPlacement) -> Self {
+        self.efence = (min_size, placement);
+        self
+    }
+
+    /// Records the stack of each registered allocation for
+    /// [`LeakDetector::leak_report`]; needs the registry.
+    #[cfg(feature = "backtrace")]
+    pub const fn backtraces(mut self, enabled: bool) -> Self {
+        self.backtraces = enabled;
+        self
+    }
+
+    /// Also tracks [`get_used_actual`], the bytes the inner allocator really
+    /// reserved, from `malloc_usable_size` on Linux, `malloc_size` on macOS
+    /// and `_msize` on Windows. Does nothing on other platforms.
+    ///
+    /// # Safety
+    ///
+    /// Every block the inner allocator hands out must come from the C
+    /// library's `malloc`, as with [`System`](std::alloc::System) on Unix.
+    ///
+    /// [`get_used_actual`]: LeakDetector::get_used_actual
+    #[cfg(feature = "usable-size")]
+    pub const unsafe fn usable_size(mut self, enabled: bool) -> Self {
+        self.usable_size = enabled;
+        self
+    }
+
+    /// Charges each allocation to the innermost entered `tracing` span, for
+    /// [`LeakDetector::span_usage`]. Needs a
+    /// [`SpanAttributionLayer`](crate::SpanAttributionLayer) installed.
+    #[cfg(feature = "tracing-attribution")]
+    pub const fn span_attribution(mut self, enabled: bool) -> Self {
+        self.span_attribution = enabled;
+        self
+    }
+
+    pub const fn build(self) -> LeakDetector<T> {
+        // Moving `inner` out by destructuring isn't allowed in a `const fn`
+        // for a generic `T` yet.
+        let this = std::mem::ManuallyDrop::new(self);
+        let this: *const Self = (&raw const this).cast();
+        let backtrace_every = unsafe { (*this).backtraces } as usize;
+        LeakDetector {
+            inner: unsafe { std::ptr::read(&raw const (*this).inner) },
+            counters: Counters::new(),
+            poison: Poison::new(),
+            paused: AtomicUsize::new(0),
+            baseline: Mutex::new(Snapshot::ZERO),
+            on_leak: Mutex::new(unsafe { (*this).on_leak }),
+            failure_mode: Mutex::new(unsafe { (*this).failure_mode }),
+            registry: Registry::new(unsafe { (*this).registry }, backtrace_every),
+            sampler: unsafe { Sampler::new((*this).sampling, (*this).sampling_seed) },
+            tolerance: AtomicUsize::new(unsafe { (*this).tolerance }),
+            check_on_drop: unsafe { (*this).check_on_drop },
+            large_threshold: AtomicUsize::new(usize::MAX),
+            on_large: Mutex::new(OnLargeAllocation::Log),
+            report_options: Mutex::new(ReportOptions::DEFAULT),
+            forbid: crate::forbid::Forbid::new(),
+            frames: crate::frame::Frames::new(),
+            checkpoints: Accounted::new(Vec::new_in(Internal)),
+            thread_limits: ThreadLimits::new(),
+            thread_exit: unsafe {
+                ThreadExit::new((*this).check_on_thread_exit, (*this).on_thread_exit)
+            },
+            usage_samples: Accounted::new(VecDeque::new_in(Internal)),
+            diagnostics: crate::diagnostics::Diagnostics::new(),
+            waiters: crate::wait::Waiters::new(),
+            quarantine: unsafe {
+                let (max_bytes, max_blocks) = (*this).quarantine;
+                Quarantine::new(max_bytes, max_blocks, (*this).verify_quarantine)
+            },
+            #[cfg(feature = "efence")]
+            efence: unsafe {
+                let (min_size, placement) = (*this).efence;
+                crate::efence::Efence::new(min_size, placement)
+            },
+            #[cfg(feature = "usable-size")]
+            usable_size: unsafe { (*this).usable_size },
+            #[cfg(feature = "tracing-attribution")]
+            spans: crate::span_attribution::SpanTotals::new(unsafe { (*this).span_attribution }),
+            suppressions: Accounted::new(Vec::new_in(Internal)),
+            #[cfg(any(feature = "env-config", feature = "config"))]
+            report_path: Accounted::new(None),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Folding the [`CiSummary`] files of a whole test run into one verdict.
+
+use std::{
+    cmp::Reverse,
+    fmt::{self, Write as _},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    CiSite, CiSummary,
+    ci_summary::{SUMMARY_EXTENSION, write_string},
+};
+
+/// The [`CiSummary`] files of many processes, such as one per test under
+/// nextest, folded into one verdict. Point `MEM_LEAK_DETECTOR_SUMMARY` at a
+/// directory and each process writes its own file there.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergedSummary {
+    /// Sorted by name.
+    pub processes: Vec<ProcessSummary>,
+    /// Files that couldn't be read or parsed, sorted by path.
+    pub unreadable: Vec<UnreadableSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessSummary {
+    /// The file's name without `.json`.
+    pub name: String,
+    pub summary: CiSummary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnreadableSummary {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl MergedSummary {
+    /// Reads every `.json` file in `dir`. Files still being written, which
+    /// end in `.tmp`, are left out; anything else that isn't a summary is
+    /// listed in [`unreadable`](MergedSummary::unreadable).
+    pub fn from_dir(dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        let mut merged = Self::default();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                merged.unreadable.push(UnreadableSummary {
+                    path: dir.to_owned(),
+                    reason: err.to_string(),
+                });
+                return merged;
+            }
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err) => {
+                    merged.unreadable.push(UnreadableSummary {
+                        path: dir.to_owned(),
+                        reason: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            if path
+                .extension()
+                .is_none_or(|extension| extension != SUMMARY_EXTENSION)
+            {
+                continue;
+            }
+            let summary = fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|json| {
+                    CiSummary::from_json(&json).ok_or_else(|| "not a leak summary".to_owned())
+                });
+            match summary {
+                Ok(summary) => merged.processes.push(ProcessSummary {
+                    name: path
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    summary,
+                }),
+                Err(reason) => merged.unreadable.push(UnreadableSummary { path, reason }),
+            }
+        }
+        merged.processes.sort_by(|a, b| a.name.cmp(&b.name));
+        merged.unreadable.sort_by(|a, b| a.path.cmp(&b.path));
+        merged
+    }
+
+    /// All processes as one: leaks, suppressed bytes and sites summed up,
+    /// the highest peak, and poisoned if any process was.
+    pub fn total(&self) -> CiSummary {
+        let mut total = CiSummary::default();
+        for ProcessSummary { summary, .. } in &self.processes {
+            total.leaked_bytes += summary.leaked_bytes;
+            total.leaked_allocations += summary.leaked_allocations;
+            total.peak = total.peak.max(summary.peak);
+            total.suppressed += summary.suppressed;
+            total.poisoned |= summary.poisoned;
+            for site in &summary.sites {
+                match total
+                    .sites
+                    .iter_mut()
+                    .find(|merged| merged.callsite == site.callsite)
+                {
+                    Some(merged) => {
+                        merged.bytes += site.bytes;
+                        merged.allocations += site.allocations;
+                    }
+                    None => total.sites.push(site.clone()),
+                }
+            }
+        }
+        total.sites.sort_by_key(|site| Reverse(site.bytes));
+        total
+    }
+
+    /// The processes that leaked, most bytes first.
+    pub fn worst(&self) -> Vec<&ProcessSummary> {
+        let mut leaking: Vec<_> = self
+            .processes
+            .iter()
+            .filter(|process| process.summary.leaked_bytes != 0)
+            .collect();
+        leaking.sort_by_key(|process| Reverse(process.summary.leaked_bytes));
+        leaking
+    }
+
+    /// 0 when no process leaked, 1 when one did, past the tolerance it
+    /// checks with. A file that couldn't be read may hide a leak, so
+    /// without one that did, it's 2.
+    pub fn exit_code(&self) -> i32 {
+        if !self.worst().is_empty() {
+            1
+        } else if !self.unreadable.is_empty() {
+            2
+        } else {
+            0
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"processes\":{},\"leaking\":[", self.processes.len());
+        for (i, process) in self.worst().into_iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+            json.push_str("{\"name\":");
+            write_string(&mut json, &process.name);
+            let _ = write!(
+                json,
+                ",\"summary\":{}}}",
+                process.summary.to_json().trim_end()
+            );
+        }
+        json.push_str("],\"unreadable\":[");
+        for (i, unreadable) in self.unreadable.iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+            json.push_str("{\"path\":");
+            write_string(&mut json, &unreadable.path.to_string_lossy());
+            json.push_str(",\"reason\":");
+            write_string(&mut json, &unreadable.reason);
+            json.push('}');
+        }
+        let _ = writeln!(json, "],\"total\":{}}}", self.total().to_json().trim_end());
+        json
+    }
+}
+
+/// The combined verdict, then one line per leaking process with its
+/// biggest site, then the unreadable files.
+impl fmt::Display for MergedSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        let worst = self.worst();
+        write!(
+            f,
+            "{} of {} process(es) leaked {} bytes in {} allocation(s)",
+            worst.len(),
+            self.processes.len(),
+            total.leaked_bytes,
+            total.leaked_allocations
+        )?;
+        let width = worst
+            .iter()
+            .map(|process| process.name.len())
+            .max()
+            .unwrap_or(0);
+        for process in worst {
+            let summary = &process.summary;
+            write!(
+                f,
+                "\n  {:width$}  {} bytes in {} allocation(s)",
+                process.name, summary.leaked_bytes, summary.leaked_allocations
+            )?;
+            if let Some(CiSite { callsite, .. }) = summary.sites.first() {
+                write!(f, ", most at {callsite}")?;
+            }
+        }
+        for unreadable in &self.unreadable {
+            write!(
+                f,
+                "\n  couldn't read {}: {}",
+                unreadable.path.display(),
+                unreadable.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(leaked_bytes: usize, callsite: &str) -> CiSummary {
+        CiSummary {
+            leaked_bytes,
+            leaked_allocations: leaked_bytes / 8,
+            peak: 1000 + leaked_bytes,
+            suppressed: 0,
+            poisoned: false,
+            sites: (leaked_bytes != 0)
+                .then(|| CiSite {
+                    callsite: callsite.to_owned(),
+                    bytes: leaked_bytes,
+                    allocations: leaked_bytes / 8,
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn merges_a_directory() {
+        let dir = std::env::temp_dir().join(format!("merged_summary-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files = [
+            ("parser", summary(64, "src/parse.rs:10:5")),
+            ("clean", summary(0, "")),
+            ("cache", summary(16, "src/parse.rs:10:5")),
+            ("server", summary(128, "src/net.rs:3:1")),
+        ];
+        for (name, summary) in &files {
+            fs::write(dir.join(format!("{name}.json")), summary.to_json()).unwrap();
+        }
+        fs::write(dir.join("truncated.json"), &files[0].1.to_json()[..20]).unwrap();
+        fs::write(dir.join("writing.json.77.tmp"), "{").unwrap();
+        fs::write(dir.join("notes.txt"), "not a summary").unwrap();
+
+        let merged = MergedSummary::from_dir(&dir);
+        let names: Vec<_> = merged.processes.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["cache", "clean", "parser", "server"]);
+        assert_eq!(merged.unreadable.len(), 1);
+        assert_eq!(merged.unreadable[0].path, dir.join("truncated.json"));
+        assert_eq!(merged.exit_code(), 1);
+
+        let total = merged.total();
+        assert_eq!(total.leaked_bytes, 208);
+        assert_eq!(total.leaked_allocations, 26);
+        assert_eq!(total.peak, 1128);
+        assert_eq!(
+            total
+                .sites
+                .iter()
+                .map(|site| (site.callsite.as_str(), site.bytes))
+                .collect::<Vec<_>>(),
+            [("src/net.rs:3:1", 128), ("src/parse.rs:10:5", 80)]
+        );
+        let worst: Vec<_> = merged.worst().iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(worst, ["server", "parser", "cache"]);
+
+        let text = merged.to_string();
+        assert!(
+            text.starts_with(
+                "3 of 4 process(es) leaked 208 bytes in 26 allocation(s)\n  \
+                 server  128 bytes in 16 allocation(s), most at src/net.rs:3:1\n  \
+                 parser  64 bytes in 8 allocation(s)"
+            ),
+            "{text}"
+        );
+        assert!(text.contains("\n  couldn't read "), "{text}");
+        assert!(
+            text.ends_with("truncated.json: not a leak summary"),
+            "{text}"
+        );
+
+        let json = merged.to_json();
+        assert!(
+            json.starts_with("{\"processes\":4,\"leaking\":[{\"name\":\"server\",\"summary\":{")
+        );
+        let total_json = json.split_once(",\"total\":").unwrap().1;
+        assert_eq!(
+            CiSummary::from_json(total_json.trim_end().strip_suffix('}').unwrap()),
+            Some(total)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exit_codes() {
+        let clean = MergedSummary {
+            processes: vec![ProcessSummary {
+                name: "a".to_owned(),
+                summary: summary(0, ""),
+            }],
+            unreadable: Vec::new(),
+        };
+        assert_eq!(clean.exit_code(), 0);
+        let missing = MergedSummary::from_dir("/nonexistent/summaries");
+        assert_eq!(missing.exit_code(), 2);
+        assert!(missing.to_string().starts_with(
+            "0 of 0 process(es) leaked 0 bytes in 0 allocation(s)\n  \
+             couldn't read /nonexistent/summaries: "
+        ));
+    }
+}
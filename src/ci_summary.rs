@@ -0,0 +1,389 @@
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::LeakDetector;
+
+/// The verdict written at exit to `MEM_LEAK_DETECTOR_SUMMARY`, see
+/// [`LeakDetector::report_at_exit`]. The file is one JSON object with these
+/// fields; [`from_json`](CiSummary::from_json) reads it back.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CiSummary {
+    /// What [`check`](LeakDetector::check) counts as leaked.
+    pub leaked_bytes: usize,
+    /// Live allocations since the baseline; 0 without the registry.
+    pub leaked_allocations: usize,
+    pub peak: usize,
+    /// Bytes excused by suppressions.
+    pub suppressed: usize,
+    pub poisoned: bool,
+    /// The biggest sites, as in [`LeakReport::summary`](crate::LeakReport::summary).
+    pub sites: Vec<CiSite>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CiSite {
+    /// `file:line:column`.
+    pub callsite: String,
+    pub bytes: usize,
+    pub allocations: usize,
+}
+
+impl CiSummary {
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"leaked_bytes\":{},\"leaked_allocations\":{},\"peak\":{},\"suppressed\":{},\
+             \"poisoned\":{},\"sites\":[",
+            self.leaked_bytes, self.leaked_allocations, self.peak, self.suppressed, self.poisoned
+        );
+        for (i, site) in self.sites.iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+            json.push_str("{\"callsite\":");
+            write_string(&mut json, &site.callsite);
+            let _ = write!(
+                json,
+                ",\"bytes\":{},\"allocations\":{}}}",
+                site.bytes, site.allocations
+            );
+        }
+        json.push_str("]}\n");
+        json
+    }
+
+    /// Parses what [`to_json`](CiSummary::to_json) wrote. Unknown fields
+    /// are skipped; `None` if a field is missing or has the wrong type.
+    pub fn from_json(json: &str) -> Option<Self> {
+        let mut parser = Parser { rest: json };
+        let value = parser.value()?;
+        parser.skip_blank();
+        if !parser.rest.is_empty() {
+            return None;
+        }
+        let sites = value
+            .get("sites")?
+            .array()?
+            .iter()
+            .map(|site| {
+                Some(CiSite {
+                    callsite: site.get("callsite")?.string()?.to_owned(),
+                    bytes: site.get("bytes")?.number()?,
+                    allocations: site.get("allocations")?.number()?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            leaked_bytes: value.get("leaked_bytes")?.number()?,
+            leaked_allocations: value.get("leaked_allocations")?.number()?,
+            peak: value.get("peak")?.number()?,
+            suppressed: value.get("suppressed")?.number()?,
+            poisoned: value.get("poisoned")?.boolean()?,
+            sites,
+        })
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// The detector's verdict as [`report_at_exit`] writes it, with at most
+    /// `max_sites` sites.
+    ///
+    /// [`report_at_exit`]: LeakDetector::report_at_exit
+    pub fn ci_summary(&self, max_sites: usize) -> CiSummary {
+        let report = self.leak_report();
+        let sites = report
+            .summary(max_sites, 0)
+            .sites
+            .iter()
+            .take(max_sites)
+            .map(|site| CiSite {
+                callsite: site.callsite.to_string(),
+                bytes: site.bytes,
+                allocations: site.allocations,
+            })
+            .collect();
+        CiSummary {
+            leaked_bytes: self.leaked_bytes(),
+            leaked_allocations: report.allocations().len(),
+            peak: self.get_peak(),
+            suppressed: report.suppressed_bytes(),
+            poisoned: self.is_poisoned(),
+            sites,
+        }
+    }
+
+    /// Writes [`ci_summary`](LeakDetector::ci_summary) to the path in
+    /// `MEM_LEAK_DETECTOR_SUMMARY`, if set, warning on stderr if it can't.
+    /// A directory gets a file per process, named after the executable and
+    /// the process id, for [`MergedSummary`](crate::ci::MergedSummary).
+    pub(crate) fn write_ci_summary(&self, max_sites: usize) {
+        let _internal = crate::pause::internal();
+        let Some(path) = std::env::var_os("MEM_LEAK_DETECTOR_SUMMARY") else {
+            return;
+        };
+        let mut path = PathBuf::from(path);
+        if path.is_dir() {
+            let exe = std::env::current_exe().ok();
+            let stem = exe
+                .as_deref()
+                .and_then(Path::file_stem)
+                .map_or("process".into(), |stem| stem.to_string_lossy());
+            path.push(format!("{stem}-{}.{SUMMARY_EXTENSION}", std::process::id()));
+        }
+        if let Err(err) = write_atomically(&path, &self.ci_summary(max_sites).to_json()) {
+            eprintln!(
+                "mem_leak_detector: couldn't write summary to {}: {err}",
+                path.display()
+            );
+        }
+    }
+}
+
+/// What summary files end in; [`MergedSummary`](crate::ci::MergedSummary) reads
+/// only these.
+pub(crate) const SUMMARY_EXTENSION: &str = "json";
+
+/// Writes next to `path` and renames, so readers never see half a file.
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+pub(crate) fn write_string(json: &mut String, string: &str) {
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+enum Value {
+    Null,
+    Boolean(bool),
+    /// Only what fits a `usize`; other numbers are rejected.
+    Number(usize),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn number(&self) -> Option<usize> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn boolean(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    fn string(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn skip_blank(&mut self) {
+        self.rest = self.rest.trim_start_matches([' ', '\t', '\n', '\r']);
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_blank();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_blank();
+        match self.rest.chars().next()? {
+            '{' => self.object(),
+            '[' => self.array(),
+            '"' => self.string().map(Value::String),
+            _ if self.eat("true") => Some(Value::Boolean(true)),
+            _ if self.eat("false") => Some(Value::Boolean(false)),
+            _ if self.eat("null") => Some(Value::Null),
+            _ => {
+                let len = self
+                    .rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(self.rest.len());
+                let n = self.rest[..len].parse().ok()?;
+                self.rest = &self.rest[len..];
+                Some(Value::Number(n))
+            }
+        }
+    }
+
+    fn object(&mut self) -> Option<Value> {
+        self.eat("{");
+        let mut fields = Vec::new();
+        if self.eat("}") {
+            return Some(Value::Object(fields));
+        }
+        loop {
+            self.skip_blank();
+            let key = self.string()?;
+            if !self.eat(":") {
+                return None;
+            }
+            fields.push((key, self.value()?));
+            if self.eat("}") {
+                return Some(Value::Object(fields));
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Value> {
+        self.eat("[");
+        let mut items = Vec::new();
+        if self.eat("]") {
+            return Some(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            if self.eat("]") {
+                return Some(Value::Array(items));
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let mut chars = self.rest.strip_prefix('"')?.char_indices();
+        let mut string = String::new();
+        loop {
+            let (i, c) = chars.next()?;
+            match c {
+                '"' => {
+                    self.rest = &self.rest[1 + i + 1..];
+                    return Some(string);
+                }
+                '\\' => string.push(match chars.next()?.1 {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'u' => {
+                        let hex: String = (0..4)
+                            .filter_map(|_| chars.next())
+                            .map(|(_, c)| c)
+                            .collect();
+                        char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                    }
+                    c => c,
+                }),
+                c => string.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout, System};
+
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        detector.add_size_suppression(8..=8);
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let leaked: Vec<_> = (0..2).map(|_| detector.allocate(layout).unwrap()).collect();
+        let line = line!() - 1;
+        let excused = detector.allocate(Layout::new::<u64>()).unwrap();
+
+        let summary = detector.ci_summary(4);
+        assert_eq!(summary.leaked_bytes, 96);
+        assert_eq!(summary.leaked_allocations, 2);
+        assert_eq!(summary.peak, 104);
+        assert_eq!(summary.suppressed, 8);
+        assert!(!summary.poisoned);
+        assert_eq!(summary.sites.len(), 1);
+        assert_eq!(summary.sites[0].bytes, 96);
+        assert_eq!(summary.sites[0].allocations, 2);
+        assert!(
+            summary.sites[0]
+                .callsite
+                .starts_with(&format!("{}:{line}:", file!()))
+        );
+
+        let json = summary.to_json();
+        assert!(json.starts_with(
+            "{\"leaked_bytes\":96,\"leaked_allocations\":2,\"peak\":104,\"suppressed\":8,\
+             \"poisoned\":false,\"sites\":[{\"callsite\":\""
+        ));
+        assert_eq!(CiSummary::from_json(&json), Some(summary));
+
+        for block in leaked {
+            unsafe { detector.deallocate(block.cast(), layout) };
+        }
+        unsafe { detector.deallocate(excused.cast(), Layout::new::<u64>()) };
+    }
+
+    #[test]
+    fn reads_escapes_and_skips_unknown_fields() {
+        let json = r#" {"version": null, "leaked_bytes": 0, "leaked_allocations": 0,
+            "peak": 10, "suppressed": 0, "poisoned": true,
+            "sites": [{"callsite": "C:\\src\\\"a\".rs:1:2", "bytes": 3, "allocations": 1}]} "#;
+        let summary = CiSummary::from_json(json).unwrap();
+        assert!(summary.poisoned);
+        assert_eq!(summary.sites[0].callsite, "C:\\src\\\"a\".rs:1:2");
+        assert_eq!(CiSummary::from_json(&summary.to_json()), Some(summary));
+        assert_eq!(CiSummary::from_json("{\"leaked_bytes\": -1}"), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Shorthands for std collections allocating from a detector. They're only
+//! sugar for the `new_in` constructors. Called on a `static` detector they
+//! borrow it for `'static`, so the results can be stored anywhere. `String`
+//! has no allocator parameter yet, so there is no helper for it.
+
+use std::{
+    alloc::Allocator,
+    collections::{BTreeMap, VecDeque},
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::LeakDetector;
+
+impl<T: Allocator> LeakDetector<T> {
+    /// `Box::new_in(value, self)`.
+    #[inline]
+    #[track_caller]
+    pub fn boxed<V>(&self, value: V) -> Box<V, &Self> {
+        Box::new_in(value, self)
+    }
+
+    /// `Vec::new_in(self)`.
+    #[inline]
+    pub fn vec<V>(&self) -> Vec<V, &Self> {
+        Vec::new_in(self)
+    }
+
+    /// `Vec::with_capacity_in(capacity, self)`.
+    #[inline]
+    #[track_caller]
+    pub fn vec_with_capacity<V>(&self, capacity: usize) -> Vec<V, &Self> {
+        Vec::with_capacity_in(capacity, self)
+    }
+
+    /// `VecDeque::new_in(self)`.
+    #[inline]
+    pub fn vec_deque<V>(&self) -> VecDeque<V, &Self> {
+        VecDeque::new_in(self)
+    }
+
+    /// `BTreeMap::new_in(self)`.
+    #[inline]
+    pub fn btree_map<K, V>(&self) -> BTreeMap<K, V, &Self> {
+        BTreeMap::new_in(self)
+    }
+
+    /// `Rc::new_in(value, self)`.
+    #[inline]
+    #[track_caller]
+    pub fn rc<V>(&self, value: V) -> Rc<V, &Self> {
+        Rc::new_in(value, self)
+    }
+
+    /// `Arc::new_in(value, self)`.
+    #[inline]
+    #[track_caller]
+    pub fn arc<V>(&self, value: V) -> Arc<V, &Self> {
+        Arc::new_in(value, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    static DETECTOR: LeakDetector<System> = LeakDetector::system();
+
+    #[test]
+    fn nested_structures() {
+        let detector = LeakDetector::system();
+        {
+            let mut words = detector.btree_map();
+            for (i, word) in ["one", "two", "three"].into_iter().enumerate() {
+                let mut bytes = detector.vec_with_capacity::<u8>(word.len());
+                bytes.extend_from_slice(word.as_bytes());
+                words.insert(i, detector.boxed(bytes));
+            }
+            let mut queue = detector.vec_deque();
+            queue.push_back(detector.rc(words));
+            let shared = detector.arc(queue);
+            assert_eq!(shared[0][&2].as_slice(), b"three");
+            assert!(detector.get_used() > 0);
+        }
+        detector.assert();
+    }
+
+    #[test]
+    fn from_a_static() {
+        let mut kept = DETECTOR.vec();
+        for n in 0..4u32 {
+            kept.push(DETECTOR.boxed(n));
+        }
+        let handle = std::thread::spawn(move || kept.iter().map(|n| **n).sum::<u32>());
+        assert_eq!(handle.join().unwrap(), 6);
+        DETECTOR.assert();
+    }
+}
@@ -0,0 +1,677 @@
+//! [`LeakDetector::load_config`] and the subset of TOML it reads: `[section]`
+//! headers, `key = value` lines and `#` comments, with strings, integers,
+//! booleans and arrays of those as values. Dotted keys, tables in arrays,
+//! floats and dates are rejected as parse errors.
+
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    LeakDetector, OnLeak,
+    suppress::{Pattern, Suppression},
+};
+
+/// What [`LeakDetector::load_config`] applied.
+#[derive(Debug, Clone, Default)]
+pub struct LoadedConfig {
+    /// The suppressions added, in order, as
+    /// [`SuppressedAllocation::pattern`](crate::SuppressedAllocation::pattern)
+    /// shows them.
+    pub suppressions: Vec<String>,
+    pub tolerance: Option<usize>,
+    pub policy: Option<OnLeak>,
+    pub report_path: Option<PathBuf>,
+    pub large_allocation: Option<usize>,
+    pub backtrace_sampling: Option<usize>,
+    /// The keys that were skipped and why, also printed to stderr.
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for LoadedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} suppression(s)", self.suppressions.len())?;
+        if let Some(bytes) = self.tolerance {
+            write!(f, ", tolerance {bytes} bytes")?;
+        }
+        if let Some(policy) = self.policy {
+            write!(f, ", policy {}", policy_name(policy))?;
+        }
+        if let Some(path) = &self.report_path {
+            write!(f, ", reports to {}", path.display())?;
+        }
+        if let Some(bytes) = self.large_allocation {
+            write!(f, ", large allocations from {bytes} bytes")?;
+        }
+        if let Some(n) = self.backtrace_sampling {
+            write!(f, ", a stack every {n} allocation(s)")?;
+        }
+        if !self.warnings.is_empty() {
+            write!(f, ", {} warning(s)", self.warnings.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Why [`LeakDetector::load_config`] applied nothing.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    /// `line` counts from 1.
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "couldn't read config: {err}"),
+            ConfigError::Parse { line, message } => write!(f, "config line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            ConfigError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+fn policy_name(policy: OnLeak) -> &'static str {
+    match policy {
+        OnLeak::Panic => "panic",
+        OnLeak::Log => "log",
+        OnLeak::Callback(_) => "callback",
+        OnLeak::Ignore => "silent",
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Reads the config file at `path`, see
+    /// [`load_config_from_reader`](LeakDetector::load_config_from_reader).
+    pub fn load_config(&self, path: impl AsRef<Path>) -> Result<LoadedConfig, ConfigError> {
+        self.load_config_from_reader(fs::File::open(path)?)
+    }
+
+    /// Reads a config like this one and applies it to the detector:
+    ///
+    /// ```toml
+    /// tolerance = 1024
+    /// policy = "log"             # panic, log or silent
+    /// report_path = "target/leaks.txt"
+    ///
+    /// [suppressions]
+    /// symbols = ["once_cell::"]  # needs the backtrace feature
+    /// callsites = ["src/cache.rs"]
+    /// sizes = ["16..=64", 4096]
+    ///
+    /// [thresholds]
+    /// large_allocation = 1_048_576
+    /// backtrace_sampling = 8     # needs the backtrace feature
+    /// ```
+    ///
+    /// Suppressions are added after those already there. Unknown keys, and
+    /// keys needing a feature that is off, are skipped with a warning so
+    /// newer configs still load. A file that doesn't parse, or has a value
+    /// of the wrong type, applies nothing. What the detector keeps is
+    /// allocated untracked.
+    pub fn load_config_from_reader(
+        &self,
+        mut reader: impl Read,
+    ) -> Result<LoadedConfig, ConfigError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let (loaded, suppressions) = plan(Parser::new(&text).entries()?)?;
+        for warning in &loaded.warnings {
+            eprintln!("mem_leak_detector: {warning}");
+        }
+
+        if let Some(bytes) = loaded.tolerance {
+            self.set_tolerance(bytes);
+        }
+        if let Some(policy) = loaded.policy {
+            self.set_on_leak(policy);
+        }
+        if let Some(bytes) = loaded.large_allocation {
+            self.set_large_allocation_threshold(bytes);
+        }
+        #[cfg(feature = "backtrace")]
+        if let Some(n) = loaded.backtrace_sampling {
+            self.set_backtrace_sampling(n);
+        }
+        let _internal = crate::pause::internal();
+        if let Some(path) = &loaded.report_path {
+            self.set_report_path(path);
+        }
+        for suppression in &suppressions {
+            self.add_suppression(suppression.clone());
+        }
+        Ok(loaded)
+    }
+}
+
+enum Value {
+    String(String),
+    Integer(i64),
+    /// No key takes one yet, so only its type is kept.
+    Boolean,
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+struct Entry {
+    line: usize,
+    section: String,
+    key: String,
+    value: Value,
+}
+
+impl Entry {
+    fn name(&self) -> String {
+        if self.section.is_empty() {
+            self.key.clone()
+        } else {
+            format!("{}.{}", self.section, self.key)
+        }
+    }
+
+    fn error(&self, expected: &str, found: &str) -> ConfigError {
+        ConfigError::Parse {
+            line: self.line,
+            message: format!("`{}` should be {expected}, not {found}", self.name()),
+        }
+    }
+
+    fn bytes(&self) -> Result<usize, ConfigError> {
+        match self.value {
+            Value::Integer(n) => usize::try_from(n).map_err(|_| self.error("a size", "negative")),
+            ref other => Err(self.error("an integer", other.kind())),
+        }
+    }
+
+    fn string(&self) -> Result<&str, ConfigError> {
+        match &self.value {
+            Value::String(string) => Ok(string),
+            other => Err(self.error("a string", other.kind())),
+        }
+    }
+
+    fn items(&self) -> Result<&[Value], ConfigError> {
+        match &self.value {
+            Value::Array(items) => Ok(items),
+            other => Err(self.error("an array", other.kind())),
+        }
+    }
+
+    fn strings(&self) -> Result<Vec<&str>, ConfigError> {
+        self.items()?
+            .iter()
+            .map(|item| match item {
+                Value::String(string) => Ok(string.as_str()),
+                other => Err(self.error("an array of strings", other.kind())),
+            })
+            .collect()
+    }
+
+    /// Sizes given as `4096`, `"4096"` or `"16..=64"`.
+    fn size_ranges(&self) -> Result<Vec<Suppression>, ConfigError> {
+        let expected = "an array of sizes like 4096 or \"16..=64\"";
+        self.items()?
+            .iter()
+            .map(|item| {
+                let range = match item {
+                    Value::Integer(n) => usize::try_from(*n).ok().map(|n| n..=n),
+                    Value::String(text) => parse_size_range(text),
+                    other => return Err(self.error(expected, other.kind())),
+                };
+                range
+                    .map(Suppression::Size)
+                    .ok_or_else(|| self.error(expected, "that"))
+            })
+            .collect()
+    }
+}
+
+fn parse_size_range(text: &str) -> Option<std::ops::RangeInclusive<usize>> {
+    let bytes = |text: &str| text.trim().replace('_', "").parse::<usize>().ok();
+    match text.split_once("..=") {
+        Some((start, end)) => Some(bytes(start)?..=bytes(end)?),
+        None => bytes(text).map(|n| n..=n),
+    }
+}
+
+fn plan(entries: Vec<Entry>) -> Result<(LoadedConfig, Vec<Suppression>), ConfigError> {
+    let mut loaded = LoadedConfig::default();
+    let mut suppressions = Vec::new();
+    for entry in &entries {
+        match (entry.section.as_str(), entry.key.as_str()) {
+            ("", "tolerance") => loaded.tolerance = Some(entry.bytes()?),
+            ("", "policy") => {
+                loaded.policy = Some(match entry.string()? {
+                    "panic" => OnLeak::Panic,
+                    "log" => OnLeak::Log,
+                    "silent" => OnLeak::Ignore,
+                    other => return Err(entry.error("panic, log or silent", other)),
+                })
+            }
+            ("", "report_path") => match entry.string()? {
+                "" => return Err(entry.error("a path", "empty")),
+                path => loaded.report_path = Some(PathBuf::from(path)),
+            },
+            ("suppressions", "symbols") => {
+                let symbols = entry.strings()?;
+                #[cfg(feature = "backtrace")]
+                suppressions.extend(
+                    symbols
+                        .into_iter()
+                        .map(|symbol| Suppression::Symbol(Pattern::new(symbol))),
+                );
+                #[cfg(not(feature = "backtrace"))]
+                if !symbols.is_empty() {
+                    loaded.warnings.push(format!(
+                        "skipping `{}` on line {}, which needs the backtrace feature",
+                        entry.name(),
+                        entry.line
+                    ));
+                }
+            }
+            ("suppressions", "callsites") => suppressions.extend(
+                entry
+                    .strings()?
+                    .into_iter()
+                    .map(|callsite| Suppression::Callsite(Pattern::new(callsite))),
+            ),
+            ("suppressions", "sizes") => suppressions.extend(entry.size_ranges()?),
+            ("thresholds", "large_allocation") => loaded.large_allocation = Some(entry.bytes()?),
+            ("thresholds", "backtrace_sampling") => {
+                let n = entry.bytes()?;
+                if cfg!(feature = "backtrace") {
+                    loaded.backtrace_sampling = Some(n);
+                } else {
+                    loaded.warnings.push(format!(
+                        "skipping `{}` on line {}, which needs the backtrace feature",
+                        entry.name(),
+                        entry.line
+                    ));
+                }
+            }
+            _ => loaded.warnings.push(format!(
+                "skipping unknown key `{}` on line {}",
+                entry.name(),
+                entry.line
+            )),
+        }
+    }
+    loaded.suppressions = suppressions.iter().map(ToString::to_string).collect();
+    Ok((loaded, suppressions))
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            rest: text,
+            line: 1,
+        }
+    }
+
+    fn error<R>(&self, message: impl Into<String>) -> Result<R, ConfigError> {
+        Err(ConfigError::Parse {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            let len = self.rest.find('\n').unwrap_or(self.rest.len());
+            self.rest = &self.rest[len..];
+        }
+    }
+
+    /// Skips spaces, and with `newlines` blank lines and comments too.
+    fn skip_blank(&mut self, newlines: bool) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t') => {}
+                Some('\n' | '\r') if newlines => {}
+                Some('#') if newlines => self.skip_comment(),
+                _ => return,
+            }
+            self.bump();
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), ConfigError> {
+        self.skip_blank(false);
+        self.skip_comment();
+        self.eat('\r');
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("unexpected `{c}` after the value")),
+        }
+    }
+
+    fn bare_key(&mut self) -> Result<&'a str, ConfigError> {
+        let len = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest.len());
+        if len == 0 {
+            return self.error("expected a key");
+        }
+        let (key, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Ok(key)
+    }
+
+    fn entries(mut self) -> Result<Vec<Entry>, ConfigError> {
+        let mut entries = Vec::new();
+        let mut section = String::new();
+        loop {
+            self.skip_blank(true);
+            if self.peek().is_none() {
+                return Ok(entries);
+            }
+            if self.eat('[') {
+                self.skip_blank(false);
+                section = self.bare_key()?.to_owned();
+                self.skip_blank(false);
+                if !self.eat(']') {
+                    return self.error("expected `]` after the section name");
+                }
+            } else {
+                let line = self.line;
+                let key = self.bare_key()?.to_owned();
+                self.skip_blank(false);
+                if !self.eat('=') {
+                    return self.error(format!("expected `=` after `{key}`"));
+                }
+                self.skip_blank(false);
+                let value = self.value()?;
+                entries.push(Entry {
+                    line,
+                    section: section.clone(),
+                    key,
+                    value,
+                });
+            }
+            self.end_of_line()?;
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ConfigError> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some('t' | 'f') => match self.bare_key()? {
+                "true" | "false" => Ok(Value::Boolean),
+                word => self.error(format!("expected a value, found `{word}`")),
+            },
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' => self.integer(),
+            _ => self.error("expected a value"),
+        }
+    }
+
+    fn integer(&mut self) -> Result<Value, ConfigError> {
+        let len = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.')))
+            .unwrap_or(self.rest.len());
+        let text = &self.rest[..len];
+        match text.replace('_', "").parse() {
+            Ok(n) => {
+                self.rest = &self.rest[len..];
+                Ok(Value::Integer(n))
+            }
+            Err(_) => self.error(format!("`{text}` isn't an integer")),
+        }
+    }
+
+    /// The next character of a string on this line.
+    fn string_char(&mut self) -> Result<char, ConfigError> {
+        match self.peek() {
+            None | Some('\n') => self.error("unterminated string"),
+            Some(_) => Ok(self.bump().unwrap()),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, ConfigError> {
+        self.bump();
+        let mut string = String::new();
+        loop {
+            match self.string_char()? {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escaped = match self.string_char()? {
+                        '"' => '"',
+                        '\\' => '\\',
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'u' => {
+                            let Some(c) = self
+                                .rest
+                                .get(..4)
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32)
+                            else {
+                                return self.error("expected 4 hex digits after `\\u`");
+                            };
+                            self.rest = &self.rest[4..];
+                            c
+                        }
+                        c => return self.error(format!("unknown escape `\\{c}`")),
+                    };
+                    string.push(escaped);
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, ConfigError> {
+        self.bump();
+        let mut string = String::new();
+        loop {
+            match self.string_char()? {
+                '\'' => return Ok(string),
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ConfigError> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank(true);
+            if self.eat(']') {
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_blank(true);
+            if !self.eat(',') {
+                if self.eat(']') {
+                    return Ok(Value::Array(items));
+                }
+                return self.error("expected `,` or `]` in the array");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout, System};
+
+    use super::*;
+    use crate::LeakError;
+
+    const SAMPLE: &str = r#"
+# Shared with the rest of the team.
+tolerance = 1_024
+policy = "log"
+report_path = 'target/leaks.txt'
+
+[suppressions]
+callsites = [
+    "src/config.rs",  # the tests below
+]
+sizes = ["100..=200", 4096]
+
+[thresholds]
+large_allocation = 1048576
+"#;
+
+    #[test]
+    fn applies_sample_config() {
+        let path = std::env::temp_dir().join(format!(
+            "mem_leak_detector-config-{}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, SAMPLE).unwrap();
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let loaded = detector.load_config(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            loaded.suppressions,
+            ["src/config.rs", "100..=200", "4096..=4096"]
+        );
+        assert!(loaded.warnings.is_empty());
+        assert_eq!(
+            loaded.to_string(),
+            "3 suppression(s), tolerance 1024 bytes, policy log, reports to \
+             target/leaks.txt, large allocations from 1048576 bytes"
+        );
+        assert_eq!(detector.tolerance(), 1024);
+        assert!(matches!(detector.on_leak(), OnLeak::Log));
+        assert_eq!(detector.large_allocation_threshold(), Some(1048576));
+        assert_eq!(
+            detector.report_path(),
+            Some(PathBuf::from("target/leaks.txt"))
+        );
+
+        let layout = Layout::from_size_align(2048, 8).unwrap();
+        let here = detector.allocate(layout).unwrap();
+        let sized = Vec::<u8, _>::with_capacity_in(4096, &detector);
+        detector.check().unwrap();
+        let leaked = Vec::<u8, _>::with_capacity_in(1500, &detector);
+        assert!(matches!(
+            detector.check(),
+            Err(LeakError::Leaked { bytes: 1500, .. })
+        ));
+        drop((leaked, sized));
+        unsafe { detector.deallocate(here.cast(), layout) };
+    }
+
+    #[test]
+    fn warns_on_unknown_keys() {
+        let detector = LeakDetector::system();
+        let loaded = detector
+            .load_config_from_reader(
+                "colour = true\n[later]\nflag = [1, 2]\n[thresholds]\nlarge_allocation = 64\n"
+                    .as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(
+            loaded.warnings,
+            [
+                "skipping unknown key `colour` on line 1",
+                "skipping unknown key `later.flag` on line 3",
+            ]
+        );
+        assert_eq!(detector.large_allocation_threshold(), Some(64));
+    }
+
+    #[test]
+    fn bad_config_applies_nothing() {
+        let detector = LeakDetector::system();
+        for (text, line, message) in [
+            (
+                "tolerance = 16\npolicy = \"loud\"\n",
+                2,
+                "`policy` should be panic, log or silent, not loud",
+            ),
+            (
+                "tolerance = 16\n\n[suppressions]\nsizes = [\"64..128\"]",
+                4,
+                "`suppressions.sizes` should be an array of sizes like 4096 or \"16..=64\", not that",
+            ),
+            (
+                "tolerance = 16\nreport_path = \"open",
+                2,
+                "unterminated string",
+            ),
+            ("tolerance = 16 16\n", 1, "unexpected `1` after the value"),
+        ] {
+            let err = detector
+                .load_config_from_reader(text.as_bytes())
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("config line {line}: {message}"),
+                "{text}"
+            );
+        }
+        assert_eq!(detector.tolerance(), 0);
+        assert!(matches!(
+            detector.load_config("/nonexistent/leaks.toml"),
+            Err(ConfigError::Io(_))
+        ));
+    }
+}
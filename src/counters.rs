@@ -0,0 +1,427 @@
+use std::{
+    cell::Cell,
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub(crate) trait Counter {
+    fn load(&self, order: Ordering) -> usize;
+    fn fetch_add(&self, val: usize, order: Ordering) -> usize;
+    fn fetch_sub(&self, val: usize, order: Ordering) -> usize;
+    fn fetch_max(&self, val: usize, order: Ordering) -> usize;
+    fn fetch_update(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        f: impl FnMut(usize) -> Option<usize>,
+    ) -> Result<usize, usize>;
+    fn store(&self, val: usize, order: Ordering);
+}
+
+impl Counter for AtomicUsize {
+    fn load(&self, order: Ordering) -> usize {
+        AtomicUsize::load(self, order)
+    }
+    fn fetch_add(&self, val: usize, order: Ordering) -> usize {
+        AtomicUsize::fetch_add(self, val, order)
+    }
+    fn fetch_sub(&self, val: usize, order: Ordering) -> usize {
+        AtomicUsize::fetch_sub(self, val, order)
+    }
+    fn fetch_max(&self, val: usize, order: Ordering) -> usize {
+        AtomicUsize::fetch_max(self, val, order)
+    }
+    fn fetch_update(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        f: impl FnMut(usize) -> Option<usize>,
+    ) -> Result<usize, usize> {
+        AtomicUsize::fetch_update(self, set_order, fetch_order, f)
+    }
+    fn store(&self, val: usize, order: Ordering) {
+        AtomicUsize::store(self, val, order)
+    }
+}
+
+/// For [`LocalLeakDetector`](crate::LocalLeakDetector), which stays on one
+/// thread and so has no ordering to keep.
+impl Counter for Cell<usize> {
+    fn load(&self, _: Ordering) -> usize {
+        self.get()
+    }
+    fn fetch_add(&self, val: usize, _: Ordering) -> usize {
+        self.replace(self.get().wrapping_add(val))
+    }
+    fn fetch_sub(&self, val: usize, _: Ordering) -> usize {
+        self.replace(self.get().wrapping_sub(val))
+    }
+    fn fetch_max(&self, val: usize, _: Ordering) -> usize {
+        self.replace(self.get().max(val))
+    }
+    fn fetch_update(
+        &self,
+        _: Ordering,
+        _: Ordering,
+        mut f: impl FnMut(usize) -> Option<usize>,
+    ) -> Result<usize, usize> {
+        match f(self.get()) {
+            Some(new) => Ok(self.replace(new)),
+            None => Err(self.get()),
+        }
+    }
+    fn store(&self, val: usize, _: Ordering) {
+        self.set(val)
+    }
+}
+
+#[cfg(loom)]
+impl Counter for loom::sync::atomic::AtomicUsize {
+    fn load(&self, order: Ordering) -> usize {
+        loom::sync::atomic::AtomicUsize::load(self, order)
+    }
+    fn fetch_add(&self, val: usize, order: Ordering) -> usize {
+        loom::sync::atomic::AtomicUsize::fetch_add(self, val, order)
+    }
+    fn fetch_sub(&self, val: usize, order: Ordering) -> usize {
+        loom::sync::atomic::AtomicUsize::fetch_sub(self, val, order)
+    }
+    fn fetch_max(&self, val: usize, order: Ordering) -> usize {
+        loom::sync::atomic::AtomicUsize::fetch_max(self, val, order)
+    }
+    fn fetch_update(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        f: impl FnMut(usize) -> Option<usize>,
+    ) -> Result<usize, usize> {
+        loom::sync::atomic::AtomicUsize::fetch_update(self, set_order, fetch_order, f)
+    }
+    fn store(&self, val: usize, order: Ordering) {
+        loom::sync::atomic::AtomicUsize::store(self, val, order)
+    }
+}
+
+/// Keeps a counter on a cache line of its own, so threads bumping different
+/// counters don't contend for it. 128 bytes also covers the pairs of lines
+/// prefetched together on x86_64, and the lines on Apple silicon.
+#[repr(align(128))]
+pub(crate) struct CachePadded<C>(C);
+
+impl<C> CachePadded<C> {
+    pub(crate) const fn new(counter: C) -> Self {
+        Self(counter)
+    }
+}
+
+impl<C> Deref for CachePadded<C> {
+    type Target = C;
+
+    #[inline]
+    fn deref(&self) -> &C {
+        &self.0
+    }
+}
+
+/// The accounting shared by every allocation path, kept apart from the
+/// allocator plumbing so it can be model-checked on its own.
+pub(crate) struct Counters<C = AtomicUsize> {
+    used: CachePadded<C>,
+    peak: CachePadded<C>,
+    allocations: CachePadded<C>,
+    deallocations: CachePadded<C>,
+    reallocations: C,
+    bytes_allocated: C,
+    bytes_deallocated: C,
+    /// Net bytes moved by reallocations, wrapping when they shrank more than
+    /// they grew.
+    bytes_reallocated: C,
+    /// Bytes the inner allocator actually reserved for the blocks in `used`,
+    /// when known.
+    used_actual: C,
+    /// Estimated alignment padding of the blocks in `used`.
+    padding: C,
+    large_allocations: C,
+}
+
+impl Counters {
+    pub(crate) const fn new() -> Self {
+        Self {
+            used: CachePadded::new(AtomicUsize::new(0)),
+            peak: CachePadded::new(AtomicUsize::new(0)),
+            allocations: CachePadded::new(AtomicUsize::new(0)),
+            deallocations: CachePadded::new(AtomicUsize::new(0)),
+            reallocations: AtomicUsize::new(0),
+            bytes_allocated: AtomicUsize::new(0),
+            bytes_deallocated: AtomicUsize::new(0),
+            bytes_reallocated: AtomicUsize::new(0),
+            used_actual: AtomicUsize::new(0),
+            padding: AtomicUsize::new(0),
+            large_allocations: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Counters<Cell<usize>> {
+    pub(crate) const fn local() -> Self {
+        Self {
+            used: CachePadded::new(Cell::new(0)),
+            peak: CachePadded::new(Cell::new(0)),
+            allocations: CachePadded::new(Cell::new(0)),
+            deallocations: CachePadded::new(Cell::new(0)),
+            reallocations: Cell::new(0),
+            bytes_allocated: Cell::new(0),
+            bytes_deallocated: Cell::new(0),
+            bytes_reallocated: Cell::new(0),
+            used_actual: Cell::new(0),
+            padding: Cell::new(0),
+            large_allocations: Cell::new(0),
+        }
+    }
+}
+
+impl<C: Counter> Counters<C> {
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn peak(&self) -> usize {
+        self.peak.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn allocations(&self) -> usize {
+        self.allocations.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn deallocations(&self) -> usize {
+        self.deallocations.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn reallocations(&self) -> usize {
+        self.reallocations.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn bytes_deallocated(&self) -> usize {
+        self.bytes_deallocated.load(Ordering::Acquire)
+    }
+
+    #[cfg_attr(not(feature = "compat-stats-alloc"), allow(dead_code))]
+    pub(crate) fn bytes_reallocated(&self) -> isize {
+        self.bytes_reallocated.load(Ordering::Acquire) as isize
+    }
+
+    pub(crate) fn used_actual(&self) -> usize {
+        self.used_actual.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn padding(&self) -> usize {
+        self.padding.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn large_allocations(&self) -> usize {
+        self.large_allocations.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn large(&self) {
+        self.large_allocations.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Moves `used_actual` from `old` to `new` usable bytes.
+    pub(crate) fn actual(&self, old: usize, new: usize) {
+        Self::adjust(&self.used_actual, old, new);
+    }
+
+    /// Moves `padding` from `old` to `new` padding bytes.
+    pub(crate) fn pad(&self, old: usize, new: usize) {
+        Self::adjust(&self.padding, old, new);
+    }
+
+    fn adjust(counter: &C, old: usize, new: usize) {
+        if new > old {
+            counter.fetch_add(new - old, Ordering::AcqRel);
+        } else if old > new {
+            counter.fetch_sub(old - new, Ordering::AcqRel);
+        }
+    }
+
+    pub(crate) fn reset_peak(&self) {
+        self.peak.store(self.used(), Ordering::Release);
+    }
+
+    fn add(&self, size: usize) {
+        let before = self.used.fetch_add(size, Ordering::AcqRel);
+        self.peak.fetch_max(before + size, Ordering::AcqRel);
+        self.bytes_allocated.fetch_add(size, Ordering::AcqRel);
+    }
+
+    /// Returns how far below zero `used` would have gone; it stops at zero
+    /// instead, never wrapping where another thread could see it.
+    fn sub(&self, size: usize) -> usize {
+        let before = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                Some(used.saturating_sub(size))
+            })
+            .unwrap_or_else(|used| used);
+        self.bytes_deallocated.fetch_add(size, Ordering::AcqRel);
+        size.saturating_sub(before)
+    }
+
+    pub(crate) fn alloc(&self, size: usize) {
+        self.add(size);
+        self.allocations.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Returns the underflow, see `sub`.
+    pub(crate) fn dealloc(&self, size: usize) -> usize {
+        self.deallocations.fetch_add(1, Ordering::AcqRel);
+        self.sub(size)
+    }
+
+    /// Counts `blocks` frees of `bytes` in all, for memory released in
+    /// bulk. Returns the underflow, see `sub`.
+    pub(crate) fn release(&self, bytes: usize, blocks: usize) -> usize {
+        self.deallocations.fetch_add(blocks, Ordering::AcqRel);
+        self.sub(bytes)
+    }
+
+    pub(crate) fn grow(&self, old_size: usize, new_size: usize) {
+        self.add(new_size - old_size);
+        self.reallocations.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Returns the underflow, see `sub`.
+    pub(crate) fn shrink(&self, old_size: usize, new_size: usize) -> usize {
+        self.reallocations.fetch_add(1, Ordering::AcqRel);
+        self.sub(old_size - new_size)
+    }
+
+    /// Returns the underflow, see `sub`.
+    pub(crate) fn realloc(&self, old_size: usize, new_size: usize) -> usize {
+        self.bytes_reallocated
+            .fetch_add(new_size.wrapping_sub(old_size), Ordering::AcqRel);
+        if new_size >= old_size {
+            self.grow(old_size, new_size);
+            0
+        } else {
+            self.shrink(old_size, new_size)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn realloc_both_directions() {
+        let counters = Counters::new();
+        counters.alloc(16);
+        counters.realloc(16, 64);
+        assert_eq!(counters.used(), 64);
+        counters.realloc(64, 8);
+        assert_eq!(counters.used(), 8);
+        counters.dealloc(8);
+        assert_eq!(counters.used(), 0);
+        assert_eq!(counters.peak(), 64);
+        assert_eq!(counters.allocations(), 1);
+        assert_eq!(counters.deallocations(), 1);
+        assert_eq!(counters.reallocations(), 2);
+        assert_eq!(counters.bytes_allocated(), 64);
+        assert_eq!(counters.bytes_deallocated(), 64);
+        assert_eq!(counters.bytes_reallocated(), -8);
+    }
+
+    #[test]
+    fn underflow_stops_at_zero() {
+        let counters = Counters::new();
+        counters.alloc(16);
+        assert_eq!(counters.dealloc(24), 8);
+        assert_eq!(counters.used(), 0);
+        assert_eq!(counters.dealloc(0), 0);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::{Arc, atomic::AtomicUsize};
+
+    use super::*;
+
+    fn counters() -> Counters<AtomicUsize> {
+        Counters {
+            used: CachePadded::new(AtomicUsize::new(0)),
+            peak: CachePadded::new(AtomicUsize::new(0)),
+            allocations: CachePadded::new(AtomicUsize::new(0)),
+            deallocations: CachePadded::new(AtomicUsize::new(0)),
+            reallocations: AtomicUsize::new(0),
+            bytes_allocated: AtomicUsize::new(0),
+            bytes_deallocated: AtomicUsize::new(0),
+            bytes_reallocated: AtomicUsize::new(0),
+            used_actual: AtomicUsize::new(0),
+            padding: AtomicUsize::new(0),
+            large_allocations: AtomicUsize::new(0),
+        }
+    }
+
+    #[test]
+    fn concurrent_alloc_dealloc_realloc() {
+        loom::model(|| {
+            let counters = Arc::new(counters());
+            let other = counters.clone();
+            let handle = loom::thread::spawn(move || {
+                other.alloc(16);
+                other.realloc(16, 48);
+                other.dealloc(48);
+            });
+            counters.alloc(32);
+            counters.realloc(32, 8);
+            counters.dealloc(8);
+            handle.join().unwrap();
+
+            assert_eq!(counters.used(), 0);
+            let peak = counters.peak();
+            assert!(matches!(peak, 48 | 56 | 80), "peak = {peak}");
+        });
+    }
+
+    #[test]
+    fn concurrent_peak_tracks_overlap() {
+        loom::model(|| {
+            let counters = Arc::new(counters());
+            let other = counters.clone();
+            let handle = loom::thread::spawn(move || {
+                other.alloc(8);
+                other.shrink(8, 4);
+            });
+            counters.grow(0, 4);
+            handle.join().unwrap();
+
+            assert_eq!(counters.used(), 8);
+            let peak = counters.peak();
+            assert!(peak == 8 || peak == 12, "peak = {peak}");
+        });
+    }
+
+    #[test]
+    fn underflowing_free_races_alloc() {
+        loom::model(|| {
+            let counters = Arc::new(counters());
+            counters.alloc(8);
+            let other = counters.clone();
+            let handle = loom::thread::spawn(move || other.dealloc(24));
+            counters.alloc(16);
+            let underflow = handle.join().unwrap();
+
+            let (used, peak) = (counters.used(), counters.peak());
+            assert!(
+                matches!((used, peak, underflow), (0, 24, 0) | (16, 16, 16)),
+                "used = {used}, peak = {peak}, underflow = {underflow}"
+            );
+        });
+    }
+}
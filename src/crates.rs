@@ -0,0 +1,190 @@
+use std::fmt;
+
+use crate::{LeakReport, LeakedAllocation};
+
+/// Frames from crates that allocate on someone else's behalf. Symbols with
+/// these prefixes, or inside `<…>` starting with them, are skipped when
+/// looking for the crate that owns an allocation.
+pub const DEFAULT_SKIPPED_PREFIXES: &[&str] = &[
+    "core::",
+    "alloc::",
+    "std::",
+    "mem_leak_detector::",
+    "backtrace::",
+];
+
+/// The live allocations of a [`LeakReport`] owned by one crate, see
+/// [`LeakReport::by_crate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrateAttribution {
+    /// `None` for allocations without a symbolized stack, or whose every
+    /// frame was skipped.
+    pub crate_name: Option<String>,
+    pub bytes: usize,
+    pub allocations: usize,
+}
+
+impl LeakReport {
+    /// The allocations grouped by the crate of the first frame of their stack
+    /// that isn't in [`DEFAULT_SKIPPED_PREFIXES`], biggest first. Everything
+    /// is unattributed until the report is [symbolized].
+    ///
+    /// [symbolized]: LeakReport::symbolize
+    pub fn by_crate(&self) -> Vec<CrateAttribution> {
+        self.by_crate_skipping(&[])
+    }
+
+    /// Like [`by_crate`](LeakReport::by_crate), also skipping frames whose
+    /// symbol starts with one of `prefixes`, such as an application's own
+    /// allocation helpers.
+    pub fn by_crate_skipping(&self, prefixes: &[&str]) -> Vec<CrateAttribution> {
+        let mut rows: Vec<CrateAttribution> = Vec::new();
+        for allocation in self.allocations() {
+            let crate_name = self.owning_crate(allocation, prefixes);
+            match rows
+                .iter_mut()
+                .find(|row| row.crate_name.as_deref() == crate_name)
+            {
+                Some(row) => {
+                    row.bytes += allocation.size;
+                    row.allocations += 1;
+                }
+                None => rows.push(CrateAttribution {
+                    crate_name: crate_name.map(str::to_owned),
+                    bytes: allocation.size,
+                    allocations: 1,
+                }),
+            }
+        }
+        rows.sort_by_key(|row| std::cmp::Reverse(row.bytes));
+        rows
+    }
+
+    fn owning_crate<'r>(
+        &'r self,
+        allocation: &LeakedAllocation,
+        prefixes: &[&str],
+    ) -> Option<&'r str> {
+        self.frames(allocation.stack)
+            .into_iter()
+            .flat_map(|(_, symbols)| symbols)
+            .filter_map(|symbol| symbol.name.as_deref())
+            .map(|name| name.trim_start_matches('<'))
+            .filter(|name| {
+                !DEFAULT_SKIPPED_PREFIXES
+                    .iter()
+                    .chain(prefixes)
+                    .any(|prefix| name.starts_with(prefix))
+            })
+            .find_map(|name| name.split_once("::").map(|(krate, _)| krate))
+    }
+}
+
+/// The compact per-crate line of a report's `Display`: nothing unless some
+/// allocation could be attributed.
+pub(crate) fn write_by_crate(f: &mut fmt::Formatter<'_>, rows: &[CrateAttribution]) -> fmt::Result {
+    if rows.iter().all(|row| row.crate_name.is_none()) {
+        return Ok(());
+    }
+    write!(f, "\n  by crate:")?;
+    for (index, row) in rows.iter().enumerate() {
+        write!(
+            f,
+            "{} {} {} bytes in {} allocation(s)",
+            if index == 0 { "" } else { "," },
+            row.crate_name.as_deref().unwrap_or("<unknown>"),
+            row.bytes,
+            row.allocations
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, panic::Location};
+
+    use super::*;
+    use crate::{StackId, Symbol};
+
+    fn symbol(name: &str) -> Vec<Symbol> {
+        vec![Symbol {
+            name: Some(name.to_owned()),
+            file: None,
+            line: None,
+        }]
+    }
+
+    fn allocation(size: usize, stack: Option<u32>) -> LeakedAllocation {
+        LeakedAllocation {
+            id: size as u64,
+            address: size,
+            size,
+            usable_size: None,
+            padding: 0,
+            callsite: Location::caller(),
+            age: Default::default(),
+            stack: stack.map(StackId::from_index),
+        }
+    }
+
+    /// Two stacks through the standard library into `parser` and, via an
+    /// application helper, into `cache`, plus an allocation without one.
+    fn report() -> LeakReport {
+        let stacks = BTreeMap::from([
+            (StackId::from_index(0), vec![1, 2, 3]),
+            (StackId::from_index(1), vec![1, 4, 5, 6]),
+        ]);
+        let symbols = BTreeMap::from([
+            (1, symbol("alloc::raw_vec::finish_grow")),
+            (
+                2,
+                symbol("<alloc::vec::Vec<T> as core::clone::Clone>::clone"),
+            ),
+            (3, symbol("parser::lexer::next_token")),
+            (4, symbol("mem_leak_detector::LeakDetector::allocate")),
+            (5, symbol("app::util::alloc_buffer")),
+            (6, symbol("<cache::Lru as core::default::Default>::default")),
+        ]);
+        LeakReport::from_parts(
+            vec![
+                allocation(16, Some(0)),
+                allocation(32, Some(0)),
+                allocation(64, Some(1)),
+                allocation(8, None),
+            ],
+            stacks,
+            symbols,
+        )
+    }
+
+    #[test]
+    fn groups_by_first_foreign_frame() {
+        let report = report();
+        let row = |name: Option<&str>, bytes, allocations| CrateAttribution {
+            crate_name: name.map(str::to_owned),
+            bytes,
+            allocations,
+        };
+        assert_eq!(
+            report.by_crate(),
+            [
+                row(Some("app"), 64, 1),
+                row(Some("parser"), 48, 2),
+                row(None, 8, 1)
+            ]
+        );
+        assert_eq!(
+            report.by_crate_skipping(&["app::util::"]),
+            [
+                row(Some("cache"), 64, 1),
+                row(Some("parser"), 48, 2),
+                row(None, 8, 1)
+            ]
+        );
+        assert!(report.to_string().contains(
+            "\n  by crate: app 64 bytes in 1 allocation(s), parser 48 bytes in 2 allocation(s), \
+             <unknown> 8 bytes in 1 allocation(s)"
+        ));
+    }
+}
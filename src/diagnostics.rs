@@ -0,0 +1,238 @@
+use std::{
+    alloc::Layout,
+    fmt,
+    panic::Location,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    LeakDetector, LeakError,
+    overhead::{Account, Accounted, Internal, Locked},
+};
+
+/// How many [`AccountingDiagnostic`]s a detector keeps; later ones are only
+/// counted.
+pub const MAX_DIAGNOSTICS: usize = 64;
+
+/// What was wrong with a free, see [`AccountingDiagnostic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BadFree {
+    /// No live block was registered at the pointer, while the detector had
+    /// never skipped an allocation, so it can't be one made while paused.
+    Unmatched,
+    /// The block was allocated with a different size.
+    SizeMismatch { allocated: usize },
+}
+
+/// A free the registry couldn't match to the block it was given, recorded
+/// by [`LeakDetector::diagnostics`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountingDiagnostic {
+    pub ptr: usize,
+    pub layout: Layout,
+    pub kind: BadFree,
+    pub callsite: &'static Location<'static>,
+}
+
+impl fmt::Display for AccountingDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "free of {} bytes (align {}) at {:#x} at {}",
+            self.layout.size(),
+            self.layout.align(),
+            self.ptr,
+            self.callsite
+        )?;
+        match self.kind {
+            BadFree::Unmatched => write!(f, ", but no live block is registered there"),
+            BadFree::SizeMismatch { allocated } => {
+                write!(f, ", but the block was allocated with {allocated} bytes")
+            }
+        }
+    }
+}
+
+pub(crate) struct Diagnostics {
+    /// Allocations not counted because tracking was paused or the thread was
+    /// doing the detector's own work.
+    skipped: AtomicUsize,
+    underflows: AtomicUsize,
+    underflow_bytes: AtomicUsize,
+    /// Bad frees seen, including those past `MAX_DIAGNOSTICS`.
+    bad_frees: AtomicUsize,
+    recorded: Accounted<Vec<AccountingDiagnostic, Internal>>,
+}
+
+impl Diagnostics {
+    pub(crate) const fn new() -> Self {
+        Self {
+            skipped: AtomicUsize::new(0),
+            underflows: AtomicUsize::new(0),
+            underflow_bytes: AtomicUsize::new(0),
+            bad_frees: AtomicUsize::new(0),
+            recorded: Accounted::new(Vec::new_in(Internal)),
+        }
+    }
+
+    fn lock(&self) -> Locked<'_, Vec<AccountingDiagnostic, Internal>> {
+        self.recorded.lock()
+    }
+
+    pub(crate) fn account(&self) -> &Account {
+        self.recorded.account()
+    }
+
+    pub(crate) fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether every allocation so far was counted, so an unregistered free
+    /// can't be freeing one made while paused.
+    pub(crate) fn none_skipped(&self) -> bool {
+        self.skipped.load(Ordering::Relaxed) == 0
+    }
+
+    /// Notes that a free would have taken `used` below zero by `bytes`.
+    pub(crate) fn underflow(&self, bytes: usize) {
+        if bytes != 0 {
+            self.underflows.fetch_add(1, Ordering::Relaxed);
+            self.underflow_bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    fn is_corrupted(&self) -> bool {
+        self.underflows.load(Ordering::Relaxed) != 0 || self.bad_frees.load(Ordering::Relaxed) != 0
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Frees the registry found wrong: of a pointer it never handed out, or
+    /// with a size other than the block's. At most [`MAX_DIAGNOSTICS`] are
+    /// kept, oldest first. Needs the registry.
+    pub fn diagnostics(&self) -> Vec<AccountingDiagnostic> {
+        self.diagnostics.lock().to_vec()
+    }
+
+    /// How many frees would have taken `used` below zero, which was clamped
+    /// instead. Without the registry, frees of blocks allocated while
+    /// paused end up here.
+    pub fn underflows(&self) -> usize {
+        self.diagnostics.underflows.load(Ordering::Relaxed)
+    }
+
+    /// The bytes `used` would have gone below zero by, over all
+    /// [`underflows`](LeakDetector::underflows).
+    pub fn underflow_bytes(&self) -> usize {
+        self.diagnostics.underflow_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Forgets recorded diagnostics and underflows, so [`check`] can pass
+    /// again.
+    ///
+    /// [`check`]: LeakDetector::check
+    pub fn clear_diagnostics(&self) {
+        let diagnostics = &self.diagnostics;
+        diagnostics.lock().clear();
+        diagnostics.bad_frees.store(0, Ordering::Relaxed);
+        diagnostics.underflows.store(0, Ordering::Relaxed);
+        diagnostics.underflow_bytes.store(0, Ordering::Relaxed);
+    }
+
+    #[cold]
+    #[inline(never)]
+    #[track_caller]
+    pub(crate) fn bad_free(&self, ptr: *mut u8, layout: Layout, kind: BadFree) {
+        self.diagnostics.bad_frees.fetch_add(1, Ordering::Relaxed);
+        let mut recorded = self.diagnostics.lock();
+        if recorded.len() < MAX_DIAGNOSTICS {
+            recorded.push(AccountingDiagnostic {
+                ptr: ptr as usize,
+                layout,
+                kind,
+                callsite: Location::caller(),
+            });
+        }
+    }
+
+    /// The error [`check`](LeakDetector::check) fails with once the counts
+    /// can't be trusted.
+    pub(crate) fn accounting_error(&self) -> Option<LeakError> {
+        let diagnostics = &self.diagnostics;
+        diagnostics
+            .is_corrupted()
+            .then(|| LeakError::AccountingCorrupted {
+                bad_frees: diagnostics.bad_frees.load(Ordering::Relaxed),
+                underflows: self.underflows(),
+                underflow_bytes: self.underflow_bytes(),
+                diagnostics: self.diagnostics(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, GlobalAlloc, System};
+
+    use super::*;
+
+    #[test]
+    fn records_mismatched_frees() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let wrong = Layout::from_size_align(128, 8).unwrap();
+        let block = detector.allocate(layout).unwrap().cast::<u8>();
+        let callsite = Location::caller();
+        unsafe { detector.deallocate(block, wrong) };
+        assert_eq!(detector.get_used(), 0);
+
+        let stranger = unsafe { System.alloc(layout) };
+        unsafe { detector.dealloc(stranger, layout) };
+
+        let diagnostics = detector.diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].ptr, block.as_ptr() as usize);
+        assert_eq!(diagnostics[0].layout, wrong);
+        assert_eq!(diagnostics[0].kind, BadFree::SizeMismatch { allocated: 64 });
+        assert_eq!(diagnostics[0].callsite.file(), callsite.file());
+        assert_eq!(diagnostics[0].callsite.line(), callsite.line() + 1);
+        assert_eq!(diagnostics[1].ptr, stranger as usize);
+        assert_eq!(diagnostics[1].kind, BadFree::Unmatched);
+        assert_eq!(detector.underflows(), 0);
+
+        let err = detector.check().unwrap_err();
+        assert!(matches!(
+            err,
+            LeakError::AccountingCorrupted { bad_frees: 2, .. }
+        ));
+        assert!(
+            err.to_string()
+                .contains("but the block was allocated with 64 bytes")
+        );
+        detector.clear_diagnostics();
+        assert!(detector.check().is_ok());
+    }
+
+    #[test]
+    fn counts_underflows_without_registry() {
+        let detector = LeakDetector::system();
+        let kept = Vec::<u8, _>::with_capacity_in(16, &detector);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let stranger = unsafe { System.alloc(layout) };
+        unsafe { detector.dealloc(stranger, layout) };
+        assert_eq!(detector.get_used(), 0);
+        assert_eq!(detector.underflows(), 1);
+        assert_eq!(detector.underflow_bytes(), 48);
+        assert!(detector.diagnostics().is_empty());
+        assert_eq!(
+            detector.check(),
+            Err(LeakError::AccountingCorrupted {
+                bad_frees: 0,
+                underflows: 1,
+                underflow_bytes: 48,
+                diagnostics: Vec::new(),
+            })
+        );
+        std::mem::forget(kept);
+    }
+}
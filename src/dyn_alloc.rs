@@ -0,0 +1,109 @@
+use std::alloc::{Allocator, GlobalAlloc, Layout};
+
+use crate::LeakDetector;
+
+/// An inner allocator picked at runtime, for [`LeakDetector::new_dyn`].
+pub type DynAllocator = Box<dyn Allocator + Send + Sync>;
+
+/// A `&'static dyn GlobalAlloc` as an inner allocator, see
+/// [`LeakDetector::new_dyn_global`]. Forwarding through the bare reference
+/// would need `GlobalAlloc` for `&dyn GlobalAlloc`, which only `std` could
+/// add. Statics are `Sync`, so anything a static holds fits.
+#[derive(Clone, Copy)]
+pub struct DynGlobalAlloc(pub &'static (dyn GlobalAlloc + Sync));
+
+unsafe impl GlobalAlloc for DynGlobalAlloc {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.0.alloc(layout) }
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.0.dealloc(ptr, layout) }
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.0.alloc_zeroed(layout) }
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        unsafe { self.0.realloc(ptr, layout, new_size) }
+    }
+}
+
+impl LeakDetector<DynAllocator> {
+    pub fn new_dyn(inner: impl Allocator + Send + Sync + 'static) -> Self {
+        Self::new(Box::new(inner))
+    }
+}
+
+impl LeakDetector<DynGlobalAlloc> {
+    pub const fn new_dyn_global(inner: &'static (dyn GlobalAlloc + Sync)) -> Self {
+        Self::new(DynGlobalAlloc(inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+    use crate::mock::{Call, CountingAlloc};
+
+    // One per test, so their calls aren't mixed up.
+    static BOXED: CountingAlloc = CountingAlloc::system();
+    static GLOBAL: CountingAlloc = CountingAlloc::system();
+
+    /// `MEM_LEAK_DETECTOR_TEST_INNER`, or each choice in turn when unset.
+    fn choices() -> Vec<String> {
+        match std::env::var("MEM_LEAK_DETECTOR_TEST_INNER") {
+            Ok(choice) => vec![choice],
+            Err(_) => vec!["system".to_owned(), "counting".to_owned()],
+        }
+    }
+
+    fn calls(counting: &CountingAlloc) -> usize {
+        counting.count(|call| matches!(call, Call::Allocate(_) | Call::Realloc { .. }))
+    }
+
+    #[test]
+    fn boxed_allocator_picked_at_runtime() {
+        for choice in choices() {
+            let detector = match choice.as_str() {
+                "counting" => LeakDetector::new_dyn(&BOXED),
+                _ => LeakDetector::new_dyn(System),
+            };
+            let before = calls(&BOXED);
+            let mut vec = Vec::with_capacity_in(8, &detector);
+            vec.extend([0u64; 4]);
+            assert_eq!(detector.get_used(), 64);
+            drop(vec);
+            detector.assert();
+            assert_eq!(calls(&BOXED) > before, choice == "counting");
+        }
+    }
+
+    #[test]
+    fn global_allocator_picked_at_runtime() {
+        let layout = Layout::new::<[u32; 4]>();
+        for choice in choices() {
+            let inner: &'static (dyn GlobalAlloc + Sync) = match choice.as_str() {
+                "counting" => &GLOBAL,
+                _ => &System,
+            };
+            let detector = LeakDetector::new_dyn_global(inner);
+            let before = calls(&GLOBAL);
+            unsafe {
+                let ptr = detector.alloc(layout);
+                let ptr = detector.realloc(ptr, layout, 32);
+                assert_eq!(detector.get_used(), 32);
+                detector.dealloc(ptr, Layout::from_size_align(32, 4).unwrap());
+            }
+            detector.assert();
+            assert_eq!(calls(&GLOBAL) > before, choice == "counting");
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Guard pages behind (or in front of) large allocations, so an overrun
+//! faults on the spot instead of corrupting a neighbour.
+
+use std::{
+    alloc::{AllocError, Layout, System},
+    collections::BTreeMap,
+    ptr::NonNull,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::LeakDetector;
+
+/// Which side of a guarded block the inaccessible page goes, see
+/// [`LeakDetectorBuilder::efence`](crate::LeakDetectorBuilder::efence).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuardPlacement {
+    /// The block ends where the guard page starts, so overruns fault.
+    /// Only exact for sizes that are a multiple of the alignment: the
+    /// start is rounded down to it, leaving up to `align - 1` bytes of
+    /// slack.
+    After,
+    /// The block starts right after the guard page, so underruns fault
+    /// and overruns land in the slack of the last page.
+    Before,
+}
+
+#[derive(Clone, Copy)]
+struct Mapping {
+    base: usize,
+    len: usize,
+}
+
+pub(crate) struct Efence {
+    min_size: usize,
+    placement: GuardPlacement,
+    /// Keyed by the address handed out.
+    mappings: Mutex<BTreeMap<usize, Mapping, System>>,
+}
+
+impl Efence {
+    pub(crate) const fn new(min_size: usize, placement: GuardPlacement) -> Self {
+        Self {
+            min_size,
+            placement,
+            mappings: Mutex::new(BTreeMap::new_in(System)),
+        }
+    }
+
+    #[inline]
+    fn is_enabled(&self) -> bool {
+        imp::SUPPORTED && self.min_size != usize::MAX
+    }
+
+    fn guards(&self, layout: Layout) -> bool {
+        self.is_enabled()
+            && layout.size() != 0
+            && layout.size() >= self.min_size
+            && layout.align() <= imp::page_size()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<usize, Mapping, System>> {
+        self.mappings.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn map(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let page = imp::page_size();
+        let data = layout.size().div_ceil(page) * page;
+        let len = data.checked_add(page).ok_or(AllocError)?;
+        let base = unsafe { imp::map(len) };
+        if base.is_null() {
+            return Err(AllocError);
+        }
+        let (guard, ptr) = match self.placement {
+            GuardPlacement::After => {
+                let ptr = (base as usize + data - layout.size()) & !(layout.align() - 1);
+                (base as usize + data, ptr)
+            }
+            GuardPlacement::Before => (base as usize, base as usize + page),
+        };
+        if !unsafe { imp::protect(guard as *mut u8, page) } {
+            unsafe { imp::unmap(base, len) };
+            return Err(AllocError);
+        }
+        self.lock().insert(
+            ptr,
+            Mapping {
+                base: base as usize,
+                len,
+            },
+        );
+        Ok(unsafe { NonNull::new_unchecked(ptr as *mut u8) })
+    }
+
+    /// Unmaps `ptr` if it is a guarded block.
+    fn unmap(&self, ptr: *mut u8) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let Some(mapping) = self.lock().remove(&(ptr as usize)) else {
+            return false;
+        };
+        unsafe { imp::unmap(mapping.base as *mut u8, mapping.len) };
+        true
+    }
+
+    pub(crate) fn owns(&self, ptr: *mut u8) -> bool {
+        self.is_enabled() && self.lock().contains_key(&(ptr as usize))
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.lock().len()
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Maps a guarded block for `layout`, or `None` if it goes to the inner
+    /// allocator. Mapped memory is zeroed.
+    pub(crate) fn efence_allocate(
+        &self,
+        layout: Layout,
+    ) -> Option<Result<NonNull<u8>, AllocError>> {
+        self.efence.guards(layout).then(|| self.efence.map(layout))
+    }
+
+    /// Unmaps `ptr` if it is a guarded block.
+    pub(crate) fn efence_deallocate(&self, ptr: *mut u8) -> bool {
+        self.efence.unmap(ptr)
+    }
+
+    /// Moves a guarded block to a new one, guarded if `new_layout` still is
+    /// and from `allocate` otherwise, or `None` if `ptr` isn't guarded.
+    /// Bytes past the old size are zeroed.
+    pub(crate) fn efence_resize(
+        &self,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_layout: Layout,
+        allocate: impl FnOnce(Layout) -> Result<NonNull<u8>, AllocError>,
+    ) -> Option<Result<NonNull<u8>, AllocError>> {
+        if !self.efence.owns(ptr) {
+            return None;
+        }
+        let new = match self.efence_allocate(new_layout) {
+            Some(result) => result,
+            None => allocate(new_layout),
+        };
+        Some(new.inspect(|new| {
+            let kept = old_layout.size().min(new_layout.size());
+            unsafe {
+                std::ptr::copy_nonoverlapping(ptr, new.as_ptr(), kept);
+                new.as_ptr()
+                    .add(kept)
+                    .write_bytes(0, new_layout.size() - kept);
+            }
+            self.efence.unmap(ptr);
+        }))
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::ffi::{c_int, c_long, c_void};
+
+    pub(super) const SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+
+    const PROT_NONE: c_int = 0;
+    const PROT_READ: c_int = 1;
+    const PROT_WRITE: c_int = 2;
+    const MAP_PRIVATE: c_int = 2;
+    #[cfg(target_os = "macos")]
+    const MAP_ANONYMOUS: c_int = 0x1000;
+    #[cfg(not(target_os = "macos"))]
+    const MAP_ANONYMOUS: c_int = 0x20;
+    #[cfg(target_os = "macos")]
+    const SC_PAGESIZE: c_int = 29;
+    #[cfg(not(target_os = "macos"))]
+    const SC_PAGESIZE: c_int = 30;
+
+    unsafe extern "C" {
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: c_long,
+        ) -> *mut c_void;
+        fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        fn sysconf(name: c_int) -> c_long;
+    }
+
+    pub(super) fn page_size() -> usize {
+        static PAGE_SIZE: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+        *PAGE_SIZE.get_or_init(|| usize::try_from(unsafe { sysconf(SC_PAGESIZE) }).unwrap_or(4096))
+    }
+
+    /// A read-write mapping of `len` bytes, or null.
+    pub(super) unsafe fn map(len: usize) -> *mut u8 {
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            std::ptr::null_mut()
+        } else {
+            ptr.cast()
+        }
+    }
+
+    /// Makes `len` bytes at `ptr` inaccessible.
+    pub(super) unsafe fn protect(ptr: *mut u8, len: usize) -> bool {
+        unsafe { mprotect(ptr.cast(), len, PROT_NONE) == 0 }
+    }
+
+    pub(super) unsafe fn unmap(ptr: *mut u8, len: usize) {
+        unsafe { munmap(ptr.cast(), len) };
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::c_void;
+
+    pub(super) const SUPPORTED: bool = true;
+
+    pub(super) fn page_size() -> usize {
+        4096
+    }
+
+    const MEM_COMMIT: u32 = 0x1000;
+    const MEM_RESERVE: u32 = 0x2000;
+    const MEM_RELEASE: u32 = 0x8000;
+    const PAGE_NOACCESS: u32 = 0x01;
+    const PAGE_READWRITE: u32 = 0x04;
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn VirtualAlloc(addr: *mut c_void, size: usize, kind: u32, protect: u32) -> *mut c_void;
+        fn VirtualProtect(addr: *mut c_void, size: usize, protect: u32, old: *mut u32) -> i32;
+        fn VirtualFree(addr: *mut c_void, size: usize, kind: u32) -> i32;
+    }
+
+    pub(super) unsafe fn map(len: usize) -> *mut u8 {
+        unsafe {
+            VirtualAlloc(
+                std::ptr::null_mut(),
+                len,
+                MEM_COMMIT | MEM_RESERVE,
+                PAGE_READWRITE,
+            )
+        }
+        .cast()
+    }
+
+    pub(super) unsafe fn protect(ptr: *mut u8, len: usize) -> bool {
+        let mut old = 0;
+        unsafe { VirtualProtect(ptr.cast(), len, PAGE_NOACCESS, &mut old) != 0 }
+    }
+
+    pub(super) unsafe fn unmap(ptr: *mut u8, _len: usize) {
+        unsafe { VirtualFree(ptr.cast(), 0, MEM_RELEASE) };
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    pub(super) const SUPPORTED: bool = false;
+
+    pub(super) fn page_size() -> usize {
+        4096
+    }
+
+    pub(super) unsafe fn map(_len: usize) -> *mut u8 {
+        std::ptr::null_mut()
+    }
+
+    pub(super) unsafe fn protect(_ptr: *mut u8, _len: usize) -> bool {
+        false
+    }
+
+    pub(super) unsafe fn unmap(_ptr: *mut u8, _len: usize) {}
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos", windows)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guarded_blocks_are_tracked() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .efence(4096, GuardPlacement::After)
+            .build();
+        let mut buffer = Vec::<u8, _>::with_capacity_in(10_000, &detector);
+        assert_eq!((buffer.as_ptr() as usize + 10_000) % imp::page_size(), 0);
+        assert_eq!(detector.efence.len(), 1);
+        buffer.resize(10_000, 7);
+        assert_eq!(detector.get_used(), 10_000);
+        assert_eq!(detector.live_allocations(), 1);
+
+        buffer.push(8);
+        assert_eq!(detector.efence.len(), 1);
+        assert_eq!(
+            (buffer.as_ptr() as usize + buffer.capacity()) % imp::page_size(),
+            0
+        );
+        assert!(buffer[..10_000].iter().all(|&byte| byte == 7));
+        assert_eq!(buffer[10_000], 8);
+
+        buffer.truncate(100);
+        buffer.shrink_to_fit();
+        assert_eq!(detector.efence.len(), 0);
+        assert_eq!(detector.get_used(), 100);
+        assert!(buffer.iter().all(|&byte| byte == 7));
+
+        let small = Vec::<u8, _>::with_capacity_in(100, &detector);
+        assert_eq!(detector.efence.len(), 0);
+        drop((buffer, small));
+        assert_eq!(detector.get_used(), 0);
+        assert!(detector.check().is_ok());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    ffi::{CStr, OsStr},
+    path::Path,
+};
+
+use crate::{LeakDetector, OnLeak};
+
+/// Reads variable `name` with `f`. On Unix this goes straight to `getenv`,
+/// so it doesn't allocate.
+#[cfg(unix)]
+fn with_var<R>(name: &CStr, f: impl FnOnce(&OsStr) -> R) -> Option<R> {
+    use std::{ffi::c_char, os::unix::ffi::OsStrExt};
+
+    unsafe extern "C" {
+        fn getenv(name: *const c_char) -> *const c_char;
+    }
+    let value = unsafe { getenv(name.as_ptr()) };
+    if value.is_null() {
+        return None;
+    }
+    Some(f(OsStr::from_bytes(
+        unsafe { CStr::from_ptr(value) }.to_bytes(),
+    )))
+}
+
+#[cfg(not(unix))]
+fn with_var<R>(name: &CStr, f: impl FnOnce(&OsStr) -> R) -> Option<R> {
+    let value = std::env::var_os(name.to_str().ok()?)?;
+    Some(f(&value))
+}
+
+fn warn(name: &CStr, value: &OsStr) {
+    eprintln!(
+        "mem_leak_detector: ignoring {}={}",
+        name.to_string_lossy(),
+        value.display()
+    );
+}
+
+impl<T> LeakDetector<T> {
+    /// Applies whichever of these variables are set:
+    ///
+    /// - `MEM_LEAK_DETECTOR_POLICY`: `panic`, `log` or `silent`, the
+    ///   [`OnLeak`] policy.
+    /// - `MEM_LEAK_DETECTOR_TOLERANCE`: bytes, see [`set_tolerance`].
+    /// - `MEM_LEAK_DETECTOR_BACKTRACE`: record a stack for every `n`th
+    ///   allocation, `0` for none. Needs the `backtrace` feature.
+    /// - `MEM_LEAK_DETECTOR_REPORT_PATH`: see [`report_path`].
+    /// - `MEM_LEAK_DETECTOR_CONFIG`: a file to [`load_config`] from, after
+    ///   the other variables. Needs the `config` feature.
+    ///
+    /// Values that don't parse are skipped with a warning on stderr. Reading
+    /// the variables doesn't allocate on Unix, so this is safe to call while
+    /// the detector is the global allocator and still bootstrapping; only
+    /// storing the report path and loading the config allocate, untracked.
+    ///
+    /// [`set_tolerance`]: LeakDetector::set_tolerance
+    /// [`report_path`]: LeakDetector::report_path
+    /// [`load_config`]: LeakDetector::load_config
+    pub fn configure_from_env(&self) {
+        const POLICY: &CStr = c"MEM_LEAK_DETECTOR_POLICY";
+        const TOLERANCE: &CStr = c"MEM_LEAK_DETECTOR_TOLERANCE";
+        const BACKTRACE: &CStr = c"MEM_LEAK_DETECTOR_BACKTRACE";
+        const REPORT_PATH: &CStr = c"MEM_LEAK_DETECTOR_REPORT_PATH";
+        #[cfg(feature = "config")]
+        const CONFIG: &CStr = c"MEM_LEAK_DETECTOR_CONFIG";
+
+        with_var(POLICY, |value| match value.to_str().map(str::trim) {
+            Some("panic") => self.set_on_leak(OnLeak::Panic),
+            Some("log") => self.set_on_leak(OnLeak::Log),
+            Some("silent") => self.set_on_leak(OnLeak::Ignore),
+            _ => warn(POLICY, value),
+        });
+        with_var(TOLERANCE, |value| match parse(value) {
+            Some(bytes) => self.set_tolerance(bytes),
+            None => warn(TOLERANCE, value),
+        });
+        with_var(BACKTRACE, |value| match parse(value) {
+            #[cfg(feature = "backtrace")]
+            Some(n) => self.set_backtrace_sampling(n),
+            _ => warn(BACKTRACE, value),
+        });
+        with_var(REPORT_PATH, |value| {
+            if value.is_empty() {
+                return warn(REPORT_PATH, value);
+            }
+            let _internal = crate::pause::internal();
+            self.set_report_path(Path::new(value));
+        });
+        #[cfg(feature = "config")]
+        with_var(CONFIG, |value| {
+            let _internal = crate::pause::internal();
+            if let Err(err) = self.load_config(value) {
+                eprintln!("mem_leak_detector: ignoring {}: {err}", value.display());
+            }
+        });
+    }
+}
+
+fn parse(value: &OsStr) -> Option<usize> {
+    value.to_str()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::System,
+        path::PathBuf,
+        sync::{Mutex, PoisonError},
+    };
+
+    use super::*;
+
+    /// Tests touching the environment run one at a time.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn with_env(vars: &[(&str, &str)], f: impl FnOnce()) {
+        let _lock = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        for (name, value) in vars {
+            unsafe { std::env::set_var(name, value) };
+        }
+        f();
+        for (name, _) in vars {
+            unsafe { std::env::remove_var(name) };
+        }
+    }
+
+    #[test]
+    fn reads_variables() {
+        with_env(
+            &[
+                ("MEM_LEAK_DETECTOR_POLICY", "log"),
+                ("MEM_LEAK_DETECTOR_TOLERANCE", " 1024 "),
+                ("MEM_LEAK_DETECTOR_BACKTRACE", "4"),
+                ("MEM_LEAK_DETECTOR_REPORT_PATH", "/tmp/leaks.json"),
+            ],
+            || {
+                let detector = LeakDetector::builder(System).registry(true).build();
+                detector.configure_from_env();
+                assert!(matches!(detector.on_leak(), OnLeak::Log));
+                assert_eq!(detector.tolerance(), 1024);
+                #[cfg(feature = "backtrace")]
+                assert_eq!(detector.backtrace_sampling(), 4);
+                assert_eq!(
+                    detector.report_path(),
+                    Some(PathBuf::from("/tmp/leaks.json"))
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn skips_bad_values() {
+        with_env(
+            &[
+                ("MEM_LEAK_DETECTOR_POLICY", "loud"),
+                ("MEM_LEAK_DETECTOR_TOLERANCE", "lots"),
+            ],
+            || {
+                let detector = LeakDetector::system();
+                detector.set_tolerance(16);
+                detector.configure_from_env();
+                assert!(matches!(detector.on_leak(), OnLeak::Panic));
+                assert_eq!(detector.tolerance(), 16);
+                assert_eq!(detector.report_path(), None);
+            },
+        );
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn loads_config_file() {
+        let path =
+            std::env::temp_dir().join(format!("mem_leak_detector-env-{}.toml", std::process::id()));
+        std::fs::write(&path, "tolerance = 64\n[suppressions]\nsizes = [24]\n").unwrap();
+        with_env(
+            &[
+                ("MEM_LEAK_DETECTOR_TOLERANCE", "16"),
+                ("MEM_LEAK_DETECTOR_CONFIG", path.to_str().unwrap()),
+            ],
+            || {
+                let detector = LeakDetector::builder(System).registry(true).build();
+                detector.configure_from_env();
+                assert_eq!(detector.tolerance(), 64);
+                let cached = Vec::<u8, _>::with_capacity_in(24, &detector);
+                let leaked = Vec::<u8, _>::with_capacity_in(128, &detector);
+                assert_eq!(detector.leak_report().suppressed().len(), 1);
+                assert!(detector.check().is_err());
+                drop((cached, leaked));
+            },
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{LeakDetector, LeakedAllocation, ledger::Ledger};
+
+/// A point in a detector's allocation history, see
+/// [`LeakDetector::advance_epoch`]. Later epochs compare greater; at one per
+/// nanosecond a `u64` takes centuries to wrap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Epoch {
+    value: u64,
+    pub(crate) used: usize,
+    /// Blocks live when it began.
+    pub(crate) live: usize,
+}
+
+impl Epoch {
+    /// Before anything was allocated.
+    pub(crate) const START: Self = Self {
+        value: 0,
+        used: 0,
+        live: 0,
+    };
+
+    pub fn value(self) -> u64 {
+        self.value
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Marks a phase boundary without a guard, for phases that don't fit a
+    /// scope, like a frame or a request handled across `await`s. Blocks
+    /// allocated from now on belong to the returned epoch or a later one.
+    /// Costs one atomic increment.
+    pub fn advance_epoch(&self) -> Epoch {
+        Epoch {
+            value: self.registry.advance_epoch(),
+            used: self.get_used(),
+            live: self
+                .counters
+                .allocations()
+                .saturating_sub(self.counters.deallocations()),
+        }
+    }
+
+    /// How much `used` changed since `epoch` began.
+    pub fn used_since(&self, epoch: Epoch) -> isize {
+        self.get_used().wrapping_sub(epoch.used) as isize
+    }
+
+    /// The blocks allocated since `epoch` began that are still live, sorted
+    /// by address; empty when the registry is off.
+    pub fn live_allocated_since(&self, epoch: Epoch) -> Vec<LeakedAllocation> {
+        self.allocations_after(epoch.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn interleaved_phases() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let frame = detector.advance_epoch();
+        let texture = Box::new_in([0u8; 64], &detector);
+        let request = detector.advance_epoch();
+        assert!(request > frame);
+        let body = Box::new_in([0u8; 16], &detector);
+        let scratch = Box::new_in([0u8; 8], &detector);
+
+        let sizes = |epoch| -> Vec<usize> {
+            let mut sizes: Vec<_> = detector
+                .live_allocated_since(epoch)
+                .iter()
+                .map(|allocation| allocation.size)
+                .collect();
+            sizes.sort();
+            sizes
+        };
+        assert_eq!(sizes(frame), [8, 16, 64]);
+        assert_eq!(sizes(request), [8, 16]);
+        assert_eq!(detector.used_since(request), 24);
+
+        drop((body, scratch));
+        assert!(sizes(request).is_empty());
+        assert_eq!(detector.used_since(request), 0);
+        assert_eq!(sizes(frame), [64]);
+        drop(texture);
+        assert_eq!(detector.used_since(frame), 0);
+    }
+}
@@ -0,0 +1,188 @@
+use std::{any::Any, fmt, ops::RangeInclusive, time::Duration};
+
+use crate::{AccountingDiagnostic, FirstFailure};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeakError {
+    Leaked {
+        bytes: isize,
+        poisoned_by: Option<FirstFailure>,
+    },
+    /// Still leaked after waiting `waited` for the memory to be freed.
+    LeakedAfterWait {
+        bytes: isize,
+        waited: Duration,
+        poisoned_by: Option<FirstFailure>,
+    },
+    UsedAbove {
+        used: usize,
+        max: usize,
+    },
+    OutOfRange {
+        used: usize,
+        range: RangeInclusive<usize>,
+    },
+    /// Retained more or less than expected, see
+    /// [`LeakDetector::check_retained`](crate::LeakDetector::check_retained)
+    /// and [`LeakDetectorScope::expect_retained`](crate::LeakDetectorScope::expect_retained).
+    RetainedOutOfRange {
+        retained: isize,
+        expected: RangeInclusive<usize>,
+    },
+    /// Frees didn't match what was allocated, so no leak verdict can be
+    /// trusted. `diagnostics` holds the first of the `bad_frees`, see
+    /// [`LeakDetector::diagnostics`](crate::LeakDetector::diagnostics).
+    AccountingCorrupted {
+        bad_frees: usize,
+        underflows: usize,
+        underflow_bytes: usize,
+        diagnostics: Vec<AccountingDiagnostic>,
+    },
+}
+
+impl std::fmt::Display for LeakError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LeakError::Leaked { bytes, poisoned_by } => {
+                write!(f, "{bytes} bytes leaked")?;
+                if let Some(first) = poisoned_by {
+                    write!(f, "; detector already poisoned by {first}")?;
+                }
+                Ok(())
+            }
+            LeakError::LeakedAfterWait {
+                bytes,
+                waited,
+                poisoned_by,
+            } => {
+                write!(f, "{bytes} bytes leaked after waiting {waited:?}")?;
+                if let Some(first) = poisoned_by {
+                    write!(f, "; detector already poisoned by {first}")?;
+                }
+                Ok(())
+            }
+            LeakError::UsedAbove { used, max } => write!(
+                f,
+                "{used} bytes used, exceeding the limit of {max} bytes by {} bytes",
+                used.saturating_sub(*max)
+            ),
+            LeakError::OutOfRange { used, range } => {
+                let (start, end) = (*range.start(), *range.end());
+                if used < &start {
+                    write!(
+                        f,
+                        "{used} bytes used, below the expected {start}..={end} bytes by {} bytes",
+                        start.saturating_sub(*used)
+                    )
+                } else {
+                    write!(
+                        f,
+                        "{used} bytes used, above the expected {start}..={end} bytes by {} bytes",
+                        used.saturating_sub(end)
+                    )
+                }
+            }
+            LeakError::RetainedOutOfRange { retained, expected } => {
+                write_retention(f, *retained, expected)
+            }
+            LeakError::AccountingCorrupted {
+                bad_frees,
+                underflows,
+                underflow_bytes,
+                diagnostics,
+            } => {
+                write!(f, "accounting corrupted by ")?;
+                if *bad_frees != 0 {
+                    write!(f, "{bad_frees} bad free(s)")?;
+                }
+                if *underflows != 0 {
+                    if *bad_frees != 0 {
+                        write!(f, " and ")?;
+                    }
+                    write!(
+                        f,
+                        "{underflows} free(s) taking used {underflow_bytes} bytes below zero"
+                    )?;
+                }
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {diagnostic}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Says whether `retained` bytes came out above or below `expected`.
+pub(crate) fn write_retention(
+    f: &mut fmt::Formatter<'_>,
+    retained: isize,
+    expected: &RangeInclusive<usize>,
+) -> fmt::Result {
+    let (start, end) = (*expected.start(), *expected.end());
+    if end == usize::MAX {
+        // No upper bound to speak of.
+        write!(
+            f,
+            "retained {retained} bytes, expected at least {start} bytes, short by {} bytes",
+            start as isize - retained
+        )
+    } else if retained < start as isize {
+        write!(
+            f,
+            "retained {retained} bytes, less than the expected {start}..={end} bytes by {} bytes",
+            start as isize - retained
+        )
+    } else {
+        write!(
+            f,
+            "retained {retained} bytes, more than the expected {start}..={end} bytes by {} bytes",
+            retained - end as isize
+        )
+    }
+}
+
+impl std::error::Error for LeakError {}
+
+/// How [`LeakDetector::scope_with_checked`](crate::LeakDetector::scope_with_checked)
+/// went wrong. `delta` is the change in used bytes over the closure.
+#[derive(Debug)]
+pub enum ScopeError<R> {
+    Leaked {
+        value: R,
+        delta: isize,
+    },
+    Panicked {
+        payload: Box<dyn Any + Send>,
+        delta: isize,
+    },
+}
+
+impl<R> ScopeError<R> {
+    pub fn delta(&self) -> isize {
+        match self {
+            ScopeError::Leaked { delta, .. } | ScopeError::Panicked { delta, .. } => *delta,
+        }
+    }
+}
+
+impl<R> fmt::Display for ScopeError<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScopeError::Leaked { delta, .. } => write!(f, "scope leaked {delta} bytes"),
+            ScopeError::Panicked { payload, delta } => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str));
+                match message {
+                    Some(message) => write!(f, "scope panicked: {message}")?,
+                    None => write!(f, "scope panicked")?,
+                }
+                write!(f, "; used changed by {delta} bytes")
+            }
+        }
+    }
+}
+
+impl<R: fmt::Debug> std::error::Error for ScopeError<R> {}
@@ -0,0 +1,121 @@
+use std::{
+    ffi::c_int,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{FailureMode, LeakDetector};
+
+/// How [`LeakDetector::report_at_exit`] reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitReport {
+    /// Sites listed in the order of the detector's
+    /// [`report_options`](LeakDetector::report_options), the rest are summed
+    /// up on one line.
+    pub max_sites: usize,
+    pub max_frames: usize,
+    /// Aborts the process after reporting a leak. With the detector's
+    /// [`FailureMode::Trap`] it aborts without reporting.
+    pub abort: bool,
+}
+
+impl Default for ExitReport {
+    fn default() -> Self {
+        Self {
+            max_sites: 10,
+            max_frames: 8,
+            abort: false,
+        }
+    }
+}
+
+struct Hook {
+    detector: usize,
+    report: fn(usize, &ExitReport) -> bool,
+    options: ExitReport,
+}
+
+static HOOK: Mutex<Option<Hook>> = Mutex::new(None);
+
+unsafe extern "C" {
+    fn atexit(callback: extern "C" fn()) -> c_int;
+}
+
+extern "C" fn run_hook() {
+    let hook = HOOK.lock().unwrap_or_else(PoisonError::into_inner).take();
+    if let Some(hook) = hook
+        && (hook.report)(hook.detector, &hook.options)
+        && hook.options.abort
+    {
+        std::process::abort();
+    }
+}
+
+fn report<T>(detector: usize, options: &ExitReport) -> bool {
+    let detector = unsafe { &*(detector as *const LeakDetector<T>) };
+    detector.write_ci_summary(options.max_sites);
+    if options.abort && detector.failure_mode() == FailureMode::Trap {
+        return detector.leaked_bytes() != 0;
+    }
+    detector.report_leaks(options)
+}
+
+impl<T> LeakDetector<T> {
+    /// Reports leaks on stderr when the process exits, see [`report_leaks`].
+    /// Only one detector reports; a second call replaces the first.
+    ///
+    /// If `MEM_LEAK_DETECTOR_SUMMARY` is set at exit, the [`ci_summary`] is
+    /// also written there as JSON, leak or not, by a rename so it appears
+    /// whole. Failing to write it only warns. If it names a directory, each
+    /// process writes its own file there, to be read back together with
+    /// [`MergedSummary::from_dir`](crate::ci::MergedSummary::from_dir).
+    ///
+    /// [`report_leaks`]: LeakDetector::report_leaks
+    /// [`ci_summary`]: LeakDetector::ci_summary
+    pub fn report_at_exit(&'static self, options: ExitReport) {
+        let hook = Hook {
+            detector: self as *const Self as usize,
+            report: report::<T>,
+            options,
+        };
+        let previous = HOOK
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(hook);
+        if previous.is_none() {
+            unsafe { atexit(run_hook) };
+        }
+    }
+
+    /// Prints nothing if [`check`](LeakDetector::check) would pass, or else
+    /// the leaked bytes and, with the registry, a [`summary`] of the live
+    /// allocations, or the report [rendered](crate::LeakReport::render_pretty)
+    /// in color and to width when stderr is a terminal. Returns whether there
+    /// was a leak.
+    ///
+    /// This thread isn't tracked while the report is built, so it doesn't
+    /// count its own allocations.
+    ///
+    /// [`summary`]: crate::LeakReport::summary
+    pub fn report_leaks(&self, options: &ExitReport) -> bool {
+        let _internal = crate::pause::internal();
+        let bytes = self.leaked_bytes();
+        if bytes == 0 {
+            return false;
+        }
+        eprintln!("mem_leak_detector: {bytes} bytes leaked");
+        if self.registry_enabled() {
+            let mut report = self.leak_report();
+            #[cfg(feature = "backtrace")]
+            report.symbolize();
+            report.options.top = options.max_sites;
+            report.options.max_frames = options.max_frames;
+            let pretty = report.for_stderr();
+            if pretty.is_plain() {
+                eprintln!("{}", report.summary(options.max_sites, options.max_frames));
+            } else {
+                eprintln!("{pretty}");
+            }
+        }
+        true
+    }
+}
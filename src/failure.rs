@@ -0,0 +1,122 @@
+use std::{
+    fmt::{self, Write as _},
+    sync::PoisonError,
+};
+
+use crate::LeakDetector;
+
+/// How a failed check stops the program where its policy says to panic:
+/// [`OnLeak::Panic`](crate::OnLeak::Panic) in scopes, balance guards and the
+/// drop check, and [`assert`](LeakDetector::assert).
+///
+/// Unwinding out of an `extern "C"` callback, a `Drop` already running
+/// during a panic, or the global allocator is undefined behavior or a
+/// confusing double-panic abort, so checks in such places should abort.
+/// Where unwinding isn't possible at all, at exit and in the alloc error
+/// hook, `Panic` acts as `Abort`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailureMode {
+    #[default]
+    Panic,
+    /// Writes the failure to stderr without allocating, straight to the
+    /// file descriptor on unix, then aborts.
+    Abort,
+    /// Aborts without writing anything, for the most constrained contexts.
+    Trap,
+}
+
+impl FailureMode {
+    #[track_caller]
+    pub(crate) fn fail(self, message: &dyn fmt::Display) {
+        match self {
+            FailureMode::Panic => panic!("{message}"),
+            FailureMode::Abort => {
+                let mut stderr = RawStderr::new();
+                let _ = writeln!(stderr, "{message}");
+                stderr.flush();
+                std::process::abort();
+            }
+            FailureMode::Trap => std::process::abort(),
+        }
+    }
+}
+
+/// Buffers on the stack and writes to file descriptor 2 directly, so it can
+/// run with the allocator or std's stderr lock held.
+pub(crate) struct RawStderr {
+    buffer: [u8; 512],
+    len: usize,
+}
+
+impl RawStderr {
+    pub(crate) fn new() -> Self {
+        Self {
+            buffer: [0; 512],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        imp::write_all(&self.buffer[..self.len]);
+        self.len = 0;
+    }
+}
+
+impl fmt::Write for RawStderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for chunk in s.as_bytes().chunks(self.buffer.len()) {
+            if self.len + chunk.len() > self.buffer.len() {
+                self.flush();
+            }
+            self.buffer[self.len..self.len + chunk.len()].copy_from_slice(chunk);
+            self.len += chunk.len();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::ffi::{c_int, c_void};
+
+    unsafe extern "C" {
+        fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+    }
+
+    pub(super) fn write_all(mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let written = unsafe { write(2, bytes.as_ptr().cast(), bytes.len()) };
+            let Ok(written @ 1..) = usize::try_from(written) else {
+                return;
+            };
+            bytes = &bytes[written..];
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::io::Write;
+
+    pub(super) fn write_all(bytes: &[u8]) {
+        let _ = std::io::stderr().write_all(bytes);
+    }
+}
+
+impl<T> LeakDetector<T> {
+    pub fn failure_mode(&self) -> FailureMode {
+        *self
+            .failure_mode
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sets how failed checks stop the program, for scopes that don't choose
+    /// with [`LeakDetectorScope::failure_mode`](crate::LeakDetectorScope::failure_mode).
+    pub fn set_failure_mode(&self, mode: FailureMode) {
+        *self
+            .failure_mode
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = mode;
+    }
+}
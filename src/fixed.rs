@@ -0,0 +1,359 @@
+//! [`FixedLeakDetector`], which names leaked blocks from a fixed table of
+//! live allocations rather than the registry. It uses nothing from `std` or
+//! `alloc`, so it carries over to firmware that has neither: without the
+//! default `std` feature, it is all the crate builds.
+
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    fmt, hint,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+};
+
+#[cfg(feature = "std")]
+use crate::LeakDetector;
+
+/// One live allocation in a [`FixedLeakDetector`]'s table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedEntry {
+    pub address: usize,
+    pub size: usize,
+    pub align: usize,
+    /// The tag set with [`FixedLeakDetector::set_tag`] when allocated.
+    pub tag: Option<u32>,
+}
+
+/// Live blocks the table had no slot for, counted in bulk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Untracked {
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+struct Table<const N: usize> {
+    slots: [Option<FixedEntry>; N],
+    untracked: Untracked,
+}
+
+/// An allocator wrapper that keeps up to `N` live allocations in an inline
+/// table, for targets with no heap to spare for the registry. Blocks beyond
+/// `N` are only counted, so `used` stays exact; a block freed while the
+/// table misses it comes off the untracked count.
+///
+/// The table sits behind a spin lock and is searched linearly, which suits
+/// the small pools it is meant for.
+pub struct FixedLeakDetector<A, const N: usize> {
+    inner: A,
+    used: AtomicUsize,
+    tag: AtomicU32,
+    locked: AtomicBool,
+    table: UnsafeCell<Table<N>>,
+}
+
+unsafe impl<A: Sync, const N: usize> Sync for FixedLeakDetector<A, N> {}
+
+#[cfg(feature = "std")]
+impl<T> LeakDetector<T> {
+    /// A detector over `inner` with a table of `N` entries in place of the
+    /// registry, see [`FixedLeakDetector`].
+    pub const fn with_fixed_registry<const N: usize>(inner: T) -> FixedLeakDetector<T, N> {
+        FixedLeakDetector::new(inner)
+    }
+}
+
+/// Releases the table lock when dropped.
+struct Guard<'a, A, const N: usize>(&'a FixedLeakDetector<A, N>);
+
+impl<A, const N: usize> Drop for Guard<'_, A, N> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
+impl<A, const N: usize> FixedLeakDetector<A, N> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            used: AtomicUsize::new(0),
+            tag: AtomicU32::new(NO_TAG),
+            locked: AtomicBool::new(false),
+            table: UnsafeCell::new(Table {
+                slots: [None; N],
+                untracked: Untracked {
+                    allocations: 0,
+                    bytes: 0,
+                },
+            }),
+        }
+    }
+
+    fn with_table<R>(&self, f: impl FnOnce(&mut Table<N>) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+        let _guard = Guard(self);
+        f(unsafe { &mut *self.table.get() })
+    }
+
+    /// Tags the allocations made from now on, from any thread, until
+    /// changed again; `None` stops tagging.
+    pub fn set_tag(&self, tag: Option<u32>) {
+        self.tag.store(tag.unwrap_or(NO_TAG), Ordering::Relaxed);
+    }
+
+    pub fn get_used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Live allocations in the table, by slot.
+    pub fn live(&self) -> impl Iterator<Item = FixedEntry> {
+        let slots = self.with_table(|table| table.slots);
+        slots.into_iter().flatten()
+    }
+
+    pub fn untracked(&self) -> Untracked {
+        self.with_table(|table| table.untracked)
+    }
+
+    /// The live allocations and untracked count, for printing.
+    pub fn leak_report(&self) -> FixedReport<N> {
+        self.with_table(|table| FixedReport {
+            slots: table.slots,
+            untracked: table.untracked,
+        })
+    }
+
+    fn on_alloc(&self, ptr: *mut u8, layout: Layout) {
+        self.used.fetch_add(layout.size(), Ordering::Relaxed);
+        if layout.size() == 0 {
+            return;
+        }
+        let tag = self.tag.load(Ordering::Relaxed);
+        let entry = FixedEntry {
+            address: ptr as usize,
+            size: layout.size(),
+            align: layout.align(),
+            tag: (tag != NO_TAG).then_some(tag),
+        };
+        self.with_table(
+            |table| match table.slots.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => *slot = Some(entry),
+                None => {
+                    table.untracked.allocations += 1;
+                    table.untracked.bytes += layout.size();
+                }
+            },
+        );
+    }
+
+    fn on_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.used.fetch_sub(layout.size(), Ordering::Relaxed);
+        if layout.size() == 0 {
+            return;
+        }
+        self.with_table(|table| {
+            match table
+                .slots
+                .iter_mut()
+                .find(|slot| slot.is_some_and(|entry| entry.address == ptr as usize))
+            {
+                Some(slot) => *slot = None,
+                None => {
+                    table.untracked.allocations = table.untracked.allocations.saturating_sub(1);
+                    table.untracked.bytes = table.untracked.bytes.saturating_sub(layout.size());
+                }
+            }
+        });
+    }
+}
+
+const NO_TAG: u32 = u32::MAX;
+
+/// What a [`FixedLeakDetector`] had live when the report was taken.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedReport<const N: usize> {
+    slots: [Option<FixedEntry>; N],
+    untracked: Untracked,
+}
+
+impl<const N: usize> FixedReport<N> {
+    pub fn allocations(&self) -> impl Iterator<Item = &FixedEntry> {
+        self.slots.iter().flatten()
+    }
+
+    pub fn untracked(&self) -> Untracked {
+        self.untracked
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.allocations().map(|entry| entry.size).sum::<usize>() + self.untracked.bytes
+    }
+}
+
+impl<const N: usize> fmt::Display for FixedReport<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes leaked in {} allocation(s)",
+            self.bytes(),
+            self.allocations().count() + self.untracked.allocations
+        )?;
+        for entry in self.allocations() {
+            write!(
+                f,
+                "\n  {} bytes (align {}) at {:#x}",
+                entry.size, entry.align, entry.address
+            )?;
+            if let Some(tag) = entry.tag {
+                write!(f, " tagged {tag}")?;
+            }
+        }
+        if self.untracked.allocations != 0 {
+            write!(
+                f,
+                "\n  {} allocation(s) not individually tracked, {} bytes",
+                self.untracked.allocations, self.untracked.bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+unsafe impl<A: GlobalAlloc, const N: usize> GlobalAlloc for FixedLeakDetector<A, N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            self.on_alloc(ptr, layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+        self.on_dealloc(ptr, layout);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            self.on_alloc(ptr, layout);
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new.is_null() {
+            self.on_dealloc(ptr, layout);
+            self.on_alloc(new, unsafe {
+                Layout::from_size_align_unchecked(new_size, layout.align())
+            });
+        }
+        new
+    }
+}
+
+unsafe impl<A: Allocator, const N: usize> Allocator for FixedLeakDetector<A, N> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.inner.allocate(layout)?;
+        self.on_alloc(ptr.cast().as_ptr(), layout);
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.inner.deallocate(ptr, layout) };
+        self.on_dealloc(ptr.as_ptr(), layout);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = unsafe { self.inner.grow(ptr, old_layout, new_layout) }?;
+        self.on_dealloc(ptr.as_ptr(), old_layout);
+        self.on_alloc(new.cast().as_ptr(), new_layout);
+        Ok(new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = unsafe { self.inner.shrink(ptr, old_layout, new_layout) }?;
+        self.on_dealloc(ptr.as_ptr(), old_layout);
+        self.on_alloc(new.cast().as_ptr(), new_layout);
+        Ok(new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn fills_to_capacity_then_counts() {
+        let detector = LeakDetector::with_fixed_registry::<4>(System);
+        let mut boxes = Vec::new();
+        for i in 0..4u64 {
+            boxes.push(Box::new_in(i, &detector));
+        }
+        assert_eq!(detector.live().count(), 4);
+        assert_eq!(detector.untracked(), Untracked::default());
+
+        let overflow = Box::new_in([0u8; 32], &detector);
+        assert_eq!(
+            detector.untracked(),
+            Untracked {
+                allocations: 1,
+                bytes: 32
+            }
+        );
+        assert_eq!(detector.get_used(), 64);
+
+        // A freed slot takes the next block; the overflowing one still frees.
+        drop(boxes.pop());
+        let tracked = Box::new_in(7u16, &detector);
+        assert!(detector.live().any(|entry| entry.size == 2));
+        drop(overflow);
+        assert_eq!(detector.untracked(), Untracked::default());
+        drop((boxes, tracked));
+        assert_eq!(detector.get_used(), 0);
+        assert_eq!(detector.live().count(), 0);
+    }
+
+    #[test]
+    fn reports_tagged_leaks() {
+        let detector = LeakDetector::with_fixed_registry::<1>(System);
+        detector.set_tag(Some(7));
+        let packet = Box::new_in([0u8; 16], &detector);
+        detector.set_tag(None);
+        let extra = Box::new_in(0u64, &detector);
+        let report = detector.leak_report();
+        assert_eq!(report.bytes(), 24);
+        assert_eq!(
+            report.allocations().copied().collect::<Vec<_>>(),
+            [FixedEntry {
+                address: &raw const *packet as usize,
+                size: 16,
+                align: 1,
+                tag: Some(7),
+            }]
+        );
+        let text = report.to_string();
+        assert!(text.starts_with("24 bytes leaked in 2 allocation(s)\n  16 bytes (align 1) at 0x"));
+        assert!(text.contains(" tagged 7\n  1 allocation(s) not individually tracked, 8 bytes"));
+        drop((packet, extra));
+        assert_eq!(detector.leak_report().bytes(), 0);
+    }
+}
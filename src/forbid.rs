@@ -0,0 +1,244 @@
+use std::{
+    alloc::Layout,
+    cell::Cell,
+    fmt,
+    panic::Location,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::{LeakDetector, stack};
+
+/// What a detector does when a thread allocates inside
+/// [`forbid_alloc`](LeakDetector::forbid_alloc) or frees inside
+/// [`forbid_dealloc`](LeakDetector::forbid_dealloc). Either way the
+/// operation is counted in [`forbidden_operations`] and then goes ahead.
+///
+/// These run inside the allocator. A [`GlobalAlloc`](std::alloc::GlobalAlloc)
+/// can't unwind, so `Panic` aborts the process there.
+///
+/// [`forbidden_operations`]: LeakDetector::forbidden_operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnForbidden {
+    Panic,
+    Log,
+}
+
+/// An allocator call made where it was forbidden.
+#[derive(Debug, Clone, Copy)]
+struct Forbidden {
+    layout: Layout,
+    /// The size before, when a block was resized.
+    resized_from: Option<usize>,
+    free: bool,
+    callsite: &'static Location<'static>,
+}
+
+impl fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = self.layout.size();
+        match (self.free, self.resized_from) {
+            (_, Some(old)) => write!(f, "forbidden resize from {old} to {size} bytes")?,
+            (true, None) => write!(f, "forbidden free of {size} bytes")?,
+            (false, None) => write!(f, "forbidden allocation of {size} bytes")?,
+        }
+        write!(f, " (align {}) at {}", self.layout.align(), self.callsite)
+    }
+}
+
+pub(crate) struct Forbid {
+    on_forbidden: Mutex<OnForbidden>,
+    operations: AtomicUsize,
+}
+
+impl Forbid {
+    pub(crate) const fn new() -> Self {
+        Self {
+            on_forbidden: Mutex::new(OnForbidden::Panic),
+            operations: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// How many `forbid_alloc` and `forbid_dealloc` calls this thread is inside,
+/// for any detector.
+#[derive(Clone, Copy)]
+struct Depth {
+    allocs: usize,
+    deallocs: usize,
+}
+
+thread_local! {
+    static DEPTH: Cell<Depth> = const { Cell::new(Depth { allocs: 0, deallocs: 0 }) };
+    static HANDLING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Leaves the region on drop, unwinding included.
+struct Leave(fn(&mut Depth) -> &mut usize);
+
+impl Leave {
+    fn enter(depth: fn(&mut Depth) -> &mut usize) -> Self {
+        let mut current = DEPTH.get();
+        *depth(&mut current) += 1;
+        DEPTH.set(current);
+        Self(depth)
+    }
+}
+
+impl Drop for Leave {
+    fn drop(&mut self) {
+        let mut current = DEPTH.get();
+        *(self.0)(&mut current) -= 1;
+        DEPTH.set(current);
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Runs `f`, handling every allocation or growth it makes on this thread,
+    /// through any detector, by [`OnForbidden`]. Frees are still allowed
+    /// unless inside [`forbid_dealloc`](LeakDetector::forbid_dealloc) too.
+    /// Regions nest, and unwinding out of one leaves it.
+    pub fn forbid_alloc<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _leave = Leave::enter(|depth| &mut depth.allocs);
+        f()
+    }
+
+    /// Like [`forbid_alloc`](LeakDetector::forbid_alloc) for frees and
+    /// shrinks, allowing allocations unless inside `forbid_alloc` too.
+    pub fn forbid_dealloc<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _leave = Leave::enter(|depth| &mut depth.deallocs);
+        f()
+    }
+
+    pub fn set_on_forbidden(&self, on_forbidden: OnForbidden) {
+        *self
+            .forbid
+            .on_forbidden
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = on_forbidden;
+    }
+
+    /// Allocations and frees made where they were forbidden.
+    pub fn forbidden_operations(&self) -> usize {
+        self.forbid.operations.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub(crate) fn check_forbidden(&self, layout: Layout, resized_from: Option<usize>, free: bool) {
+        let forbidden = DEPTH
+            .try_with(|depth| {
+                let depth = depth.get();
+                if free { depth.deallocs } else { depth.allocs }
+            })
+            .is_ok_and(|depth| depth != 0);
+        if forbidden {
+            self.forbidden(Forbidden {
+                layout,
+                resized_from,
+                free,
+                callsite: crate::traced::caller(),
+            });
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn forbidden(&self, forbidden: Forbidden) {
+        // Whatever reporting it allocates or frees is allowed.
+        if HANDLING.try_with(Cell::get).unwrap_or(true) || stack::capturing() {
+            return;
+        }
+        self.forbid.operations.fetch_add(1, Ordering::Relaxed);
+        let on_forbidden = *self
+            .forbid
+            .on_forbidden
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                HANDLING.set(false);
+            }
+        }
+        HANDLING.set(true);
+        let _reset = Reset;
+        match on_forbidden {
+            OnForbidden::Panic => panic!("{forbidden}"),
+            OnForbidden::Log => eprintln!("{forbidden}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::System,
+        panic::{AssertUnwindSafe, catch_unwind},
+    };
+
+    use super::*;
+
+    #[test]
+    fn arithmetic_passes() {
+        let detector = LeakDetector::system();
+        let sum = detector.forbid_alloc(|| (1..=10u64).map(|n| n * n).sum::<u64>());
+        assert_eq!(sum, 385);
+        assert_eq!(detector.forbidden_operations(), 0);
+    }
+
+    #[test]
+    fn growing_past_capacity_panics() {
+        let detector = LeakDetector::system();
+        let mut samples = Vec::<u32, _>::with_capacity_in(4, &detector);
+        let err = catch_unwind(AssertUnwindSafe(|| {
+            detector.forbid_alloc(|| {
+                for sample in 0..4 {
+                    samples.push(sample);
+                }
+                samples.push(4);
+            })
+        }))
+        .unwrap_err();
+        let message = err.downcast_ref::<String>().unwrap();
+        assert!(
+            message.starts_with("forbidden resize from 16 to 32 bytes (align 4) at "),
+            "{message}"
+        );
+        assert_eq!(detector.forbidden_operations(), 1);
+
+        // Unwinding left the region.
+        samples.push(5);
+        drop(samples);
+        detector.assert();
+    }
+
+    #[test]
+    fn frees_forbidden_separately() {
+        let detector = LeakDetector::system();
+        detector.set_on_forbidden(OnForbidden::Log);
+        let boxed = detector.forbid_dealloc(|| {
+            let boxed = Box::new_in(1u64, &detector);
+            detector.forbid_alloc(|| drop(Box::new_in(2u64, &detector)));
+            boxed
+        });
+        assert_eq!(detector.forbidden_operations(), 2);
+        detector.forbid_alloc(|| drop(boxed));
+        assert_eq!(detector.forbidden_operations(), 2);
+        detector.assert();
+    }
+
+    #[test]
+    fn global_allocator_sees_it() {
+        let detector = LeakDetector::builder(System).build();
+        detector.set_on_forbidden(OnForbidden::Log);
+        let ptr = detector.forbid_alloc(|| unsafe {
+            std::alloc::GlobalAlloc::alloc(&detector, Layout::new::<u64>())
+        });
+        unsafe { std::alloc::GlobalAlloc::dealloc(&detector, ptr, Layout::new::<u64>()) };
+        assert_eq!(detector.forbidden_operations(), 1);
+        detector.assert();
+    }
+}
@@ -0,0 +1,225 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::LeakDetector;
+
+/// Frames kept by [`LeakDetector::frame_window`].
+pub const FRAME_WINDOW: usize = 120;
+
+/// What happened between a [`begin_frame`] and the matching [`end_frame`].
+///
+/// [`begin_frame`]: LeakDetector::begin_frame
+/// [`end_frame`]: LeakDetector::end_frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Counts every frame the detector ended, from 0.
+    pub frame: u64,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub reallocations: usize,
+    pub bytes_allocated: usize,
+    pub bytes_deallocated: usize,
+    /// `used` when the frame ended.
+    pub used: usize,
+}
+
+/// The cumulative counters a frame started at.
+#[derive(Clone, Copy)]
+struct Start {
+    allocations: usize,
+    deallocations: usize,
+    reallocations: usize,
+    bytes_allocated: usize,
+    bytes_deallocated: usize,
+}
+
+/// The last [`FRAME_WINDOW`] frames in a fixed ring, so that ending a frame
+/// never allocates.
+struct Ring {
+    start: Option<Start>,
+    frames: [FrameStats; FRAME_WINDOW],
+    /// Frames ended so far; the next one goes at `ended % FRAME_WINDOW`.
+    ended: u64,
+}
+
+pub(crate) struct Frames(Mutex<Ring>);
+
+impl Frames {
+    pub(crate) const fn new() -> Self {
+        Self(Mutex::new(Ring {
+            start: None,
+            frames: [FrameStats {
+                frame: 0,
+                allocations: 0,
+                deallocations: 0,
+                reallocations: 0,
+                bytes_allocated: 0,
+                bytes_deallocated: 0,
+                used: 0,
+            }; FRAME_WINDOW],
+            ended: 0,
+        }))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Ring> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Ring {
+    /// The frames in the window, oldest first.
+    fn window(&self) -> impl DoubleEndedIterator<Item = &FrameStats> {
+        let first = self.ended.saturating_sub(FRAME_WINDOW as u64);
+        (first..self.ended).map(|frame| &self.frames[(frame % FRAME_WINDOW as u64) as usize])
+    }
+}
+
+/// Ends the frame when dropped, see [`LeakDetector::frame_guard`].
+pub struct FrameGuard<'a, T> {
+    detector: &'a LeakDetector<T>,
+}
+
+impl<T> LeakDetector<T> {
+    /// Starts a frame, restarting the one already begun if any.
+    pub fn begin_frame(&self) {
+        let start = Start {
+            allocations: self.counters.allocations(),
+            deallocations: self.counters.deallocations(),
+            reallocations: self.counters.reallocations(),
+            bytes_allocated: self.counters.bytes_allocated(),
+            bytes_deallocated: self.counters.bytes_deallocated(),
+        };
+        self.frames.lock().start = Some(start);
+    }
+
+    /// Ends the frame begun last and keeps it in the window, returning it.
+    /// `None` when no frame was begun. Never allocates, so both ends may sit
+    /// on a render thread or inside [`forbid_alloc`].
+    ///
+    /// [`forbid_alloc`]: LeakDetector::forbid_alloc
+    pub fn end_frame(&self) -> Option<FrameStats> {
+        let mut ring = self.frames.lock();
+        let start = ring.start.take()?;
+        let stats = FrameStats {
+            frame: ring.ended,
+            allocations: self
+                .counters
+                .allocations()
+                .saturating_sub(start.allocations),
+            deallocations: self
+                .counters
+                .deallocations()
+                .saturating_sub(start.deallocations),
+            reallocations: self
+                .counters
+                .reallocations()
+                .saturating_sub(start.reallocations),
+            bytes_allocated: self
+                .counters
+                .bytes_allocated()
+                .saturating_sub(start.bytes_allocated),
+            bytes_deallocated: self
+                .counters
+                .bytes_deallocated()
+                .saturating_sub(start.bytes_deallocated),
+            used: self.counters.used(),
+        };
+        let slot = (ring.ended % FRAME_WINDOW as u64) as usize;
+        ring.frames[slot] = stats;
+        ring.ended += 1;
+        Some(stats)
+    }
+
+    /// Begins a frame that ends when the guard is dropped.
+    pub fn frame_guard(&self) -> FrameGuard<'_, T> {
+        self.begin_frame();
+        FrameGuard { detector: self }
+    }
+
+    pub fn last_frame(&self) -> Option<FrameStats> {
+        self.frames.lock().window().last().copied()
+    }
+
+    /// The last [`FRAME_WINDOW`] frames, oldest first.
+    pub fn frame_window(&self) -> Vec<FrameStats> {
+        self.frames.lock().window().copied().collect()
+    }
+
+    /// The frame in the window that allocated the most bytes, the latest of
+    /// those tied.
+    pub fn worst_frame_in_window(&self) -> Option<FrameStats> {
+        self.frames
+            .lock()
+            .window()
+            .max_by_key(|frame| (frame.bytes_allocated, frame.allocations))
+            .copied()
+    }
+}
+
+impl<T> Drop for FrameGuard<'_, T> {
+    fn drop(&mut self) {
+        self.detector.end_frame();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::System, hint::black_box};
+
+    use super::*;
+
+    #[test]
+    fn ring_keeps_the_last_frames() {
+        let detector = LeakDetector::builder(System).build();
+        assert_eq!(detector.end_frame(), None);
+        let mut kept = Vec::new_in(System);
+        for frame in 0..FRAME_WINDOW + 5 {
+            let _frame = detector.frame_guard();
+            // One block of `frame + 1` bytes per frame, the third kept.
+            let block = black_box(Vec::<u8, _>::with_capacity_in(frame + 1, &detector));
+            if frame == 2 {
+                kept.push(block);
+            }
+        }
+        let window = detector.frame_window();
+        assert_eq!(window.len(), FRAME_WINDOW);
+        assert_eq!(window[0].frame, 5);
+        assert_eq!(window[0].bytes_allocated, 6);
+        assert_eq!(window[0].used, 3);
+        let last = detector.last_frame().unwrap();
+        assert_eq!(last, *window.last().unwrap());
+        assert_eq!(
+            last,
+            FrameStats {
+                frame: FRAME_WINDOW as u64 + 4,
+                allocations: 1,
+                deallocations: 1,
+                reallocations: 0,
+                bytes_allocated: FRAME_WINDOW + 5,
+                bytes_deallocated: FRAME_WINDOW + 5,
+                used: 3,
+            }
+        );
+        drop(kept);
+        detector.assert();
+    }
+
+    #[test]
+    fn worst_frame_and_quiet_simulation() {
+        let detector = LeakDetector::builder(System).build();
+        for bytes in [64, 4096, 0, 512] {
+            detector.begin_frame();
+            let sum = detector.forbid_alloc(|| (1..=100u64).sum::<u64>());
+            assert_eq!(sum, 5050);
+            if bytes != 0 {
+                drop(black_box(Vec::<u8, _>::with_capacity_in(bytes, &detector)));
+            }
+            detector.end_frame();
+        }
+        assert_eq!(detector.frame_window().len(), 4);
+        let worst = detector.worst_frame_in_window().unwrap();
+        assert_eq!((worst.frame, worst.bytes_allocated), (1, 4096));
+        let quiet = detector.frame_window()[2];
+        assert_eq!((quiet.allocations, quiet.deallocations), (0, 0));
+        assert_eq!(detector.forbidden_operations(), 0);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{self, NonNull},
+};
+
+use crate::LeakDetector;
+
+/// A [`GlobalAlloc`] as an [`Allocator`], so a detector over jemalloc,
+/// mimalloc or a C heap works with `Box::new_in` and `Vec::new_in`; see
+/// [`LeakDetector::from_global`]. It's still a `GlobalAlloc` too.
+///
+/// Zero-sized blocks never reach the inner allocator. `realloc` can't
+/// change alignment, so resizes that do copy into a new block.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GlobalAsAllocator<T>(pub T);
+
+/// The address of every zero-sized block of `layout`.
+fn dangling(layout: Layout) -> NonNull<u8> {
+    NonNull::new(ptr::without_provenance_mut(layout.align())).unwrap()
+}
+
+fn block(ptr: *mut u8, size: usize) -> Result<NonNull<[u8]>, AllocError> {
+    NonNull::new(ptr)
+        .map(|ptr| NonNull::slice_from_raw_parts(ptr, size))
+        .ok_or(AllocError)
+}
+
+impl<T: GlobalAlloc> GlobalAsAllocator<T> {
+    fn alloc_impl(&self, layout: Layout, zeroed: bool) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(NonNull::slice_from_raw_parts(dangling(layout), 0));
+        }
+        let ptr = unsafe {
+            if zeroed {
+                self.0.alloc_zeroed(layout)
+            } else {
+                self.0.alloc(layout)
+            }
+        };
+        block(ptr, layout.size())
+    }
+
+    /// Moves the block at `ptr` to one of `new_layout`, in place with
+    /// `realloc` when the alignment allows.
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.size() == 0 {
+            return self.alloc_impl(new_layout, zeroed);
+        }
+        if new_layout.size() == 0 {
+            unsafe { self.0.dealloc(ptr.as_ptr(), old_layout) };
+            return Ok(NonNull::slice_from_raw_parts(dangling(new_layout), 0));
+        }
+        let new = if old_layout.align() == new_layout.align() {
+            let new = unsafe { self.0.realloc(ptr.as_ptr(), old_layout, new_layout.size()) };
+            block(new, new_layout.size())?
+        } else {
+            let new = self.alloc_impl(new_layout, false)?;
+            let kept = old_layout.size().min(new_layout.size());
+            unsafe {
+                ptr::copy_nonoverlapping(ptr.as_ptr(), new.cast::<u8>().as_ptr(), kept);
+                self.0.dealloc(ptr.as_ptr(), old_layout);
+            }
+            new
+        };
+        if zeroed {
+            let grown = new_layout.size() - old_layout.size();
+            unsafe {
+                new.cast::<u8>()
+                    .as_ptr()
+                    .add(old_layout.size())
+                    .write_bytes(0, grown)
+            };
+        }
+        Ok(new)
+    }
+}
+
+unsafe impl<T: GlobalAlloc> Allocator for GlobalAsAllocator<T> {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloc_impl(layout, false)
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloc_impl(layout, true)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            unsafe { self.0.dealloc(ptr.as_ptr(), layout) }
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.resize(ptr, old_layout, new_layout, false) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.resize(ptr, old_layout, new_layout, true) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.resize(ptr, old_layout, new_layout, false) }
+    }
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for GlobalAsAllocator<T> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.0.alloc(layout) }
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.0.dealloc(ptr, layout) }
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.0.alloc_zeroed(layout) }
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        unsafe { self.0.realloc(ptr, layout, new_size) }
+    }
+}
+
+impl<T: GlobalAlloc> LeakDetector<GlobalAsAllocator<T>> {
+    /// A detector over an allocator that only implements [`GlobalAlloc`],
+    /// usable with the `_in` constructors.
+    pub const fn from_global(inner: T) -> Self {
+        Self::new(GlobalAsAllocator(inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn collections_and_zero_sizes() {
+        let detector = LeakDetector::from_global(System);
+        let mut vec = Vec::new_in(&detector);
+        vec.extend(0..100u32);
+        let unit = Box::new_in((), &detector);
+        let empty = Vec::<u64, _>::with_capacity_in(0, &detector);
+        assert_eq!(detector.get_used(), vec.capacity() * 4);
+
+        let layout = Layout::from_size_align(0, 64).unwrap();
+        let zero = detector.allocate(layout).unwrap().cast::<u8>();
+        assert_eq!(zero.as_ptr() as usize % 64, 0);
+        let over = Layout::from_size_align(32, 256).unwrap();
+        let grown = unsafe { detector.grow_zeroed(zero, layout, over) }.unwrap();
+        let grown = grown.cast::<u8>();
+        assert_eq!(grown.as_ptr() as usize % 256, 0);
+        assert!(
+            unsafe { std::slice::from_raw_parts(grown.as_ptr(), 32) }
+                .iter()
+                .all(|&b| b == 0)
+        );
+        unsafe { detector.deallocate(grown, over) };
+
+        drop((vec, unit, empty));
+        detector.assert();
+    }
+}
@@ -0,0 +1,220 @@
+use std::time::{Duration, Instant};
+
+use crate::LeakDetector;
+
+/// Usage samples kept by [`LeakDetector::sample_usage`]; older ones are
+/// dropped.
+const MAX_SAMPLES: usize = 4096;
+
+/// Whether usage trends somewhere, see [`GrowthAnalysis`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrowthVerdict {
+    Stable,
+    Growing,
+    Shrinking,
+    /// Too few samples to tell; any two points fit a line perfectly.
+    Inconclusive,
+}
+
+/// When a trend counts, see [`GrowthAnalysis::from_samples_with`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrowthThresholds {
+    /// Slopes within this many bytes per second either way are stable.
+    pub max_stable_slope: f64,
+    /// Fits explaining less of the variation than this are noise, so stable.
+    pub min_r_squared: f64,
+    /// Series shorter than this are inconclusive.
+    pub min_samples: usize,
+}
+
+impl Default for GrowthThresholds {
+    fn default() -> Self {
+        GrowthThresholds {
+            max_stable_slope: 1024.0,
+            min_r_squared: 0.5,
+            min_samples: 3,
+        }
+    }
+}
+
+/// A least-squares line through a series of usage samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrowthAnalysis {
+    pub samples: usize,
+    /// From the earliest to the latest sample.
+    pub duration: Duration,
+    /// Bytes per second.
+    pub slope: f64,
+    /// How much of the variation in usage the line explains, 1.0 for a
+    /// perfect fit, and for a constant series.
+    pub r_squared: f64,
+    pub verdict: GrowthVerdict,
+}
+
+impl GrowthAnalysis {
+    /// [`from_samples_with`](GrowthAnalysis::from_samples_with) with the default
+    /// thresholds.
+    pub fn from_samples(samples: &[(Instant, usize)]) -> Option<GrowthAnalysis> {
+        Self::from_samples_with(samples, GrowthThresholds::default())
+    }
+
+    /// Fits `samples`, in any order. `None` for fewer than two samples, or
+    /// when they were all taken at the same instant.
+    pub fn from_samples_with(
+        samples: &[(Instant, usize)],
+        thresholds: GrowthThresholds,
+    ) -> Option<GrowthAnalysis> {
+        let start = samples.iter().map(|&(at, _)| at).min()?;
+        let end = samples.iter().map(|&(at, _)| at).max()?;
+        if samples.len() < 2 || start == end {
+            return None;
+        }
+        // Centered sums, which keep their precision with large byte counts.
+        let n = samples.len() as f64;
+        let point = |&(at, used): &(Instant, usize)| ((at - start).as_secs_f64(), used as f64);
+        let (sum_x, sum_y) = samples
+            .iter()
+            .map(point)
+            .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+        let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+        let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+        for (x, y) in samples.iter().map(point) {
+            let (dx, dy) = (x - mean_x, y - mean_y);
+            sxx += dx * dx;
+            sxy += dx * dy;
+            syy += dy * dy;
+        }
+        let slope = sxy / sxx;
+        let r_squared = if syy == 0.0 {
+            1.0
+        } else {
+            (sxy * sxy / (sxx * syy)).min(1.0)
+        };
+        let verdict = if samples.len() < thresholds.min_samples.max(3) {
+            GrowthVerdict::Inconclusive
+        } else if slope.abs() <= thresholds.max_stable_slope || r_squared < thresholds.min_r_squared
+        {
+            GrowthVerdict::Stable
+        } else if slope > 0.0 {
+            GrowthVerdict::Growing
+        } else {
+            GrowthVerdict::Shrinking
+        };
+        Some(GrowthAnalysis {
+            samples: samples.len(),
+            duration: end - start,
+            slope,
+            r_squared,
+            verdict,
+        })
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Records `used` now, for [`analyze_growth`]. Call it periodically, the
+    /// last 4096 samples are kept.
+    ///
+    /// [`analyze_growth`]: LeakDetector::analyze_growth
+    pub fn sample_usage(&self) {
+        let used = self.get_used();
+        let mut samples = self.usage_samples.lock();
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back((Instant::now(), used));
+    }
+
+    /// The trend of the samples taken within `window` of the latest one.
+    pub fn analyze_growth(&self, window: Duration) -> Option<GrowthAnalysis> {
+        let samples = self.usage_samples.lock();
+        let &(latest, _) = samples.back()?;
+        let recent: Vec<_> = samples
+            .iter()
+            .copied()
+            .filter(|&(at, _)| latest - at <= window)
+            .collect();
+        drop(samples);
+        GrowthAnalysis::from_samples(&recent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(points: &[(u64, usize)]) -> Vec<(Instant, usize)> {
+        let start = Instant::now();
+        points
+            .iter()
+            .map(|&(millis, used)| (start + Duration::from_millis(millis), used))
+            .collect()
+    }
+
+    fn verdict(points: &[(u64, usize)]) -> GrowthVerdict {
+        GrowthAnalysis::from_samples(&series(points))
+            .unwrap()
+            .verdict
+    }
+
+    #[test]
+    fn verdicts() {
+        let growing = GrowthAnalysis::from_samples(&series(&[
+            (0, 1 << 20),
+            (1000, (1 << 20) + 4096),
+            (2000, (1 << 20) + 8192),
+            (3000, (1 << 20) + 12288),
+        ]))
+        .unwrap();
+        assert_eq!(growing.verdict, GrowthVerdict::Growing);
+        assert!((growing.slope - 4096.0).abs() < 1e-6);
+        assert!((growing.r_squared - 1.0).abs() < 1e-9);
+        assert_eq!(growing.duration, Duration::from_secs(3));
+
+        assert_eq!(
+            verdict(&[(0, 90_000), (1000, 60_000), (2000, 30_000)]),
+            GrowthVerdict::Shrinking
+        );
+        let constant = GrowthAnalysis::from_samples(&series(&[(0, 500), (10, 500), (20, 500)]));
+        assert_eq!(constant.unwrap().verdict, GrowthVerdict::Stable);
+        assert_eq!(constant.unwrap().r_squared, 1.0);
+        // Jitter around a level, with a slight upwards slope well within the
+        // stable threshold.
+        assert_eq!(
+            verdict(&[(0, 10_000), (1000, 10_400), (2000, 9_800), (3000, 10_300)]),
+            GrowthVerdict::Stable
+        );
+        // A steep but noisy series explains too little to count as growth.
+        let noisy = series(&[(0, 0), (1, 100_000), (2, 0), (3, 100_000), (4, 10_000)]);
+        let noisy = GrowthAnalysis::from_samples(&noisy).unwrap();
+        assert!(noisy.r_squared < 0.5);
+        assert_eq!(noisy.verdict, GrowthVerdict::Stable);
+    }
+
+    #[test]
+    fn short_series() {
+        assert_eq!(GrowthAnalysis::from_samples(&[]), None);
+        assert_eq!(GrowthAnalysis::from_samples(&series(&[(0, 10)])), None);
+        assert_eq!(
+            GrowthAnalysis::from_samples(&series(&[(5, 10), (5, 99)])),
+            None
+        );
+        let two = GrowthAnalysis::from_samples(&series(&[(0, 0), (1, 1 << 30)])).unwrap();
+        assert_eq!(two.r_squared, 1.0);
+        assert_eq!(two.verdict, GrowthVerdict::Inconclusive);
+    }
+
+    #[test]
+    fn sampled_usage() {
+        let detector = LeakDetector::system();
+        assert_eq!(detector.analyze_growth(Duration::from_secs(60)), None);
+        let mut kept = Vec::new();
+        for _ in 0..4 {
+            detector.sample_usage();
+            kept.push(Box::new_in([0u8; 64], &detector));
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        let analysis = detector.analyze_growth(Duration::from_secs(60)).unwrap();
+        assert_eq!(analysis.samples, 4);
+        assert!(analysis.slope > 0.0);
+    }
+}
@@ -0,0 +1,301 @@
+//! A test harness that checks every test for leaks, for test targets built
+//! with `harness = false`. Its [`Trial`] follows `libtest-mimic`'s, so
+//! porting a harness built on that is mostly a matter of passing the
+//! detector to [`run`].
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static GLOBAL: LeakDetector<System> = LeakDetector::system();
+//!
+//! mem_leak_detector::harness_main!(GLOBAL, [parses_empty_input, caches_lookups]);
+//! ```
+//!
+//! Tests run one at a time on the main thread, so each one's allocations
+//! can be told apart.
+
+use std::{
+    fmt,
+    panic::{AssertUnwindSafe, catch_unwind},
+    process,
+};
+
+use crate::LeakDetector;
+
+/// Why a test failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failed {
+    message: Option<String>,
+}
+
+impl Failed {
+    pub fn without_message() -> Self {
+        Failed { message: None }
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+impl<M: fmt::Display> From<M> for Failed {
+    fn from(message: M) -> Self {
+        Failed {
+            message: Some(message.to_string()),
+        }
+    }
+}
+
+type Runner = Box<dyn FnOnce() -> Result<(), Failed> + Send>;
+
+/// One test for [`run`].
+pub struct Trial {
+    name: String,
+    runner: Runner,
+    ignored: bool,
+    tolerance: Option<usize>,
+}
+
+impl Trial {
+    /// A test that fails if `runner` returns an error, panics or leaks.
+    pub fn test(
+        name: impl Into<String>,
+        runner: impl FnOnce() -> Result<(), Failed> + Send + 'static,
+    ) -> Self {
+        Trial {
+            name: name.into(),
+            runner: Box::new(runner),
+            ignored: false,
+            tolerance: None,
+        }
+    }
+
+    pub fn with_ignored_flag(mut self, ignored: bool) -> Self {
+        self.ignored = ignored;
+        self
+    }
+
+    /// Lets the test leave up to `bytes` behind, instead of the detector's
+    /// own [`tolerance`](LeakDetector::tolerance).
+    pub fn with_tolerance(mut self, bytes: usize) -> Self {
+        self.tolerance = Some(bytes);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// What one test allocated, relative to when it started.
+struct TrialStats {
+    /// Bytes still in use when it ended.
+    net: isize,
+    peak: usize,
+    allocations: usize,
+    /// Bytes that count as leaked, past the tolerance.
+    leaked: usize,
+}
+
+/// The outcome of [`run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Conclusion {
+    pub passed: usize,
+    /// Includes the tests that leaked.
+    pub failed: usize,
+    pub leaked: usize,
+    pub ignored: usize,
+    pub filtered_out: usize,
+}
+
+impl Conclusion {
+    pub fn has_failed(&self) -> bool {
+        self.failed != 0
+    }
+
+    /// Exits the process with 101 if a test failed, as libtest does, and 0
+    /// otherwise.
+    pub fn exit(&self) -> ! {
+        process::exit(if self.has_failed() { 101 } else { 0 })
+    }
+}
+
+/// The arguments understood from the command line: a name filter,
+/// `--exact`, `--ignored` and `--include-ignored`. Others cargo may pass are
+/// accepted and ignored.
+struct Arguments {
+    filter: Option<String>,
+    exact: bool,
+    ignored: bool,
+    include_ignored: bool,
+}
+
+impl Arguments {
+    fn from_args() -> Self {
+        let mut arguments = Arguments {
+            filter: None,
+            exact: false,
+            ignored: false,
+            include_ignored: false,
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--exact" => arguments.exact = true,
+                "--ignored" => arguments.ignored = true,
+                "--include-ignored" => arguments.include_ignored = true,
+                "--test-threads" | "--format" | "--color" | "--logfile" | "--skip" => {
+                    args.next();
+                }
+                _ if arg.starts_with('-') => {}
+                _ => arguments.filter = Some(arg),
+            }
+        }
+        arguments
+    }
+
+    fn selects(&self, name: &str) -> bool {
+        match &self.filter {
+            None => true,
+            Some(filter) if self.exact => name == filter,
+            Some(filter) => name.contains(filter.as_str()),
+        }
+    }
+}
+
+/// Runs `tests` against `detector`, which must be the global allocator for
+/// their allocations to be seen, and prints each outcome and a table of what
+/// each test allocated.
+///
+/// Every test starts from a fresh [baseline](LeakDetector::capture_baseline),
+/// so everything allocated before, by the runtime or earlier tests, is left
+/// out.
+pub fn run<T>(detector: &LeakDetector<T>, tests: Vec<Trial>) -> Conclusion {
+    let arguments = Arguments::from_args();
+    let mut conclusion = Conclusion::default();
+    let mut rows = Vec::new();
+    let (selected, filtered): (Vec<_>, Vec<_>) = tests
+        .into_iter()
+        .partition(|trial| arguments.selects(&trial.name));
+    conclusion.filtered_out = filtered.len();
+
+    println!("\nrunning {} tests", selected.len());
+    for trial in selected {
+        let run_it = if arguments.ignored {
+            trial.ignored
+        } else {
+            arguments.include_ignored || !trial.ignored
+        };
+        if !run_it {
+            println!("test {} ... ignored", trial.name);
+            conclusion.ignored += 1;
+            continue;
+        }
+        let (outcome, stats) = run_trial(detector, trial.runner, trial.tolerance);
+        let status = match &outcome {
+            Ok(()) => {
+                conclusion.passed += 1;
+                "ok".to_owned()
+            }
+            Err(failed) => {
+                conclusion.failed += 1;
+                if stats.leaked != 0 {
+                    conclusion.leaked += 1;
+                }
+                match failed.message() {
+                    Some(message) => format!("FAILED\n  {message}"),
+                    None => "FAILED".to_owned(),
+                }
+            }
+        };
+        println!("test {} ... {status}", trial.name);
+        rows.push((trial.name, stats));
+    }
+
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!(
+        "\n{:width$}  {:>12}  {:>12}  {:>11}",
+        "test", "net bytes", "peak", "allocations"
+    );
+    for (name, stats) in &rows {
+        println!(
+            "{name:width$}  {:>12}  {:>12}  {:>11}",
+            stats.net, stats.peak, stats.allocations
+        );
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed ({} leaked); {} ignored; {} filtered out\n",
+        if conclusion.has_failed() {
+            "FAILED"
+        } else {
+            "ok"
+        },
+        conclusion.passed,
+        conclusion.failed,
+        conclusion.leaked,
+        conclusion.ignored,
+        conclusion.filtered_out
+    );
+    conclusion
+}
+
+fn run_trial<T>(
+    detector: &LeakDetector<T>,
+    runner: Runner,
+    tolerance: Option<usize>,
+) -> (Result<(), Failed>, TrialStats) {
+    let saved_tolerance = detector.tolerance();
+    if let Some(tolerance) = tolerance {
+        detector.set_tolerance(tolerance);
+    }
+    let start = detector.capture_baseline();
+    let result = catch_unwind(AssertUnwindSafe(runner));
+    let end = detector.snapshot();
+    let leaked = detector.leaked_bytes();
+    detector.set_tolerance(saved_tolerance);
+
+    let stats = TrialStats {
+        net: end.used.wrapping_sub(start.used) as isize,
+        peak: end.peak.saturating_sub(start.used),
+        allocations: end.allocations - start.allocations,
+        leaked,
+    };
+    let outcome = match result {
+        Ok(Err(failed)) => Err(failed),
+        Err(payload) => Err(
+            match payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or(payload.downcast_ref::<String>().map(String::as_str))
+            {
+                Some(message) => Failed::from(format!("panicked: {message}")),
+                None => Failed::from("panicked"),
+            },
+        ),
+        Ok(Ok(())) if leaked != 0 => Err(Failed::from(format!("leaked {leaked} bytes"))),
+        Ok(Ok(())) => Ok(()),
+    };
+    (outcome, stats)
+}
+
+/// Defines a `main` running the listed test functions with [`run`] against
+/// a detector, and exiting with their outcome.
+#[macro_export]
+macro_rules! harness_main {
+    ($detector:expr, [$($test:path),* $(,)?]) => {
+        fn main() {
+            $crate::harness::run(
+                &$detector,
+                ::std::vec![$($crate::harness::Trial::test(::std::stringify!($test), || {
+                    $test();
+                    ::std::result::Result::Ok(())
+                })),*],
+            )
+            .exit()
+        }
+    };
+}
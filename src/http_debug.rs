@@ -0,0 +1,215 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write as _},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+};
+
+use crate::LeakDetector;
+
+/// A listener started by [`LeakDetector::serve_debug`]; stops when dropped.
+pub struct DebugServer {
+    address: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DebugServer {
+    /// Where it listens, with the port picked if it was asked for port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stops accepting and waits for the request being answered, if any.
+    pub fn stop(mut self) {
+        self.shut_down();
+    }
+
+    fn shut_down(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.stopping.store(true, Ordering::Release);
+        // Wakes the listener up from `accept`.
+        let _ = TcpStream::connect(self.address);
+        let _ = thread.join();
+    }
+}
+
+impl Drop for DebugServer {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Answers a GET of `path`, for mounting in any HTTP server, as a status
+    /// code, content type and body:
+    ///
+    /// - `/stats`: the counters as one JSON object,
+    /// - `/report`: the [`leak_report`](LeakDetector::leak_report), or the
+    ///   leaked bytes without the registry, as text,
+    /// - `/prometheus`: the counters in the Prometheus text format.
+    ///
+    /// The body is the caller's, allocated like any other of its memory.
+    pub fn debug_response(&self, path: &str) -> (u16, &'static str, String) {
+        let path = path.split('?').next().unwrap_or_default();
+        match path {
+            "/stats" => (200, "application/json", self.debug_stats()),
+            "/report" => (200, "text/plain; charset=utf-8", self.debug_report()),
+            "/prometheus" => (200, "text/plain; version=0.0.4", self.debug_prometheus()),
+            _ => (404, "text/plain; charset=utf-8", "not found\n".to_owned()),
+        }
+    }
+
+    /// The gauges and counters served, by name.
+    fn debug_metrics(&self) -> [(&'static str, usize); 9] {
+        [
+            ("used", self.counters.used()),
+            ("peak", self.counters.peak()),
+            ("leaked_bytes", self.leaked_bytes()),
+            ("live_allocations", self.live_allocations()),
+            ("allocations", self.counters.allocations()),
+            ("deallocations", self.counters.deallocations()),
+            ("reallocations", self.counters.reallocations()),
+            ("bytes_allocated", self.counters.bytes_allocated()),
+            ("bytes_deallocated", self.counters.bytes_deallocated()),
+        ]
+    }
+
+    fn debug_stats(&self) -> String {
+        let mut json = String::from("{");
+        for (name, value) in self.debug_metrics() {
+            let _ = write!(json, "\"{name}\":{value},");
+        }
+        let _ = writeln!(json, "\"poisoned\":{}}}", self.is_poisoned());
+        json
+    }
+
+    fn debug_report(&self) -> String {
+        if self.registry_enabled() {
+            #[cfg_attr(not(feature = "backtrace"), allow(unused_mut))]
+            let mut report = self.leak_report();
+            #[cfg(feature = "backtrace")]
+            report.symbolize();
+            format!("{report}\n")
+        } else {
+            format!("{} bytes leaked\n", self.leaked_bytes())
+        }
+    }
+
+    fn debug_prometheus(&self) -> String {
+        let mut text = String::new();
+        for (name, value) in self.debug_metrics() {
+            let kind = match name {
+                "allocations" | "deallocations" | "reallocations" => "counter",
+                "bytes_allocated" | "bytes_deallocated" => "counter",
+                _ => "gauge",
+            };
+            let suffix = if kind == "counter" { "_total" } else { "" };
+            let _ = writeln!(text, "# TYPE mem_leak_detector_{name}{suffix} {kind}");
+            let _ = writeln!(text, "mem_leak_detector_{name}{suffix} {value}");
+        }
+        text
+    }
+
+    fn answer(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Skips the headers; nothing served reads them.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        let mut parts = request.split_whitespace();
+        let (status, content_type, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some(path)) => self.debug_response(path),
+            _ => (
+                405,
+                "text/plain; charset=utf-8",
+                "method not allowed\n".to_owned(),
+            ),
+        };
+        let reason = match status {
+            200 => "OK",
+            404 => "Not Found",
+            _ => "Method Not Allowed",
+        };
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 {status} {reason}\r\nContent-Type: {content_type}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+}
+
+impl<T: Sync> LeakDetector<T> {
+    /// Serves [`debug_response`](LeakDetector::debug_response) over plain
+    /// HTTP at `address` from a background thread, one connection at a
+    /// time, until the returned server is stopped or dropped. Nothing the
+    /// thread allocates is tracked, while other threads are as usual.
+    pub fn serve_debug(&'static self, address: impl ToSocketAddrs) -> io::Result<DebugServer> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name("leak detector debug server".into())
+            .spawn({
+                let stopping = Arc::clone(&stopping);
+                move || {
+                    // For the rest of the thread, its exit included.
+                    std::mem::forget(crate::pause::internal());
+                    for stream in listener.incoming() {
+                        if stopping.load(Ordering::Acquire) {
+                            break;
+                        }
+                        if let Ok(stream) = stream {
+                            let _ = self.answer(stream);
+                        }
+                    }
+                }
+            })?;
+        Ok(DebugServer {
+            address,
+            stopping,
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn routes() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let leaked = Box::new_in([0u8; 48], &detector);
+
+        let (status, content_type, body) = detector.debug_response("/stats");
+        assert_eq!((status, content_type), (200, "application/json"));
+        assert!(body.starts_with("{\"used\":48,\"peak\":48,\"leaked_bytes\":48,"));
+        assert!(body.ends_with(",\"poisoned\":false}\n"));
+
+        let (_, _, body) = detector.debug_response("/prometheus?debug=1");
+        assert!(body.contains(
+            "# TYPE mem_leak_detector_allocations_total counter\n\
+             mem_leak_detector_allocations_total 1\n"
+        ));
+
+        let (_, _, body) = detector.debug_response("/report");
+        assert!(body.starts_with("48 bytes leaked in 1 allocation(s)\n"));
+        assert_eq!(detector.debug_response("/").0, 404);
+        drop(leaked);
+    }
+}
@@ -0,0 +1,373 @@
+//! `malloc`, `calloc`, `realloc`, `free` and `posix_memalign` exported
+//! under their C names, so that the C library's allocations, and those of any
+//! C code linked in, are tracked by a detector of their own.
+//!
+//! The real functions are found with `dlsym(RTLD_NEXT, …)`, which may itself
+//! allocate before they are known; those few blocks come from a static
+//! buffer and are never freed. Everything the detector does for a call runs
+//! with interception off on that thread, since its registry allocates
+//! through the same `malloc`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    ffi::{c_char, c_int, c_void},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{LeakDetector, LeakReport};
+
+type Malloc = unsafe extern "C" fn(usize) -> *mut c_void;
+type Calloc = unsafe extern "C" fn(usize, usize) -> *mut c_void;
+type Realloc = unsafe extern "C" fn(*mut c_void, usize) -> *mut c_void;
+type Free = unsafe extern "C" fn(*mut c_void);
+type PosixMemalign = unsafe extern "C" fn(*mut *mut c_void, usize, usize) -> c_int;
+
+const RTLD_NEXT: *mut c_void = -1isize as *mut c_void;
+
+unsafe extern "C" {
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    #[cfg(not(target_os = "macos"))]
+    fn malloc_usable_size(ptr: *mut c_void) -> usize;
+    #[cfg(target_os = "macos")]
+    fn malloc_size(ptr: *const c_void) -> usize;
+}
+
+/// The detector behind the exported functions. Its callsites are the lines
+/// below that counted each block, one per C function.
+static C_HEAP: LeakDetector<System> = LeakDetector::builder(System).registry(true).build();
+
+/// The next definitions of the exported functions, once resolved.
+static NEXT_MALLOC: AtomicUsize = AtomicUsize::new(0);
+static NEXT_CALLOC: AtomicUsize = AtomicUsize::new(0);
+static NEXT_REALLOC: AtomicUsize = AtomicUsize::new(0);
+static NEXT_FREE: AtomicUsize = AtomicUsize::new(0);
+static NEXT_POSIX_MEMALIGN: AtomicUsize = AtomicUsize::new(0);
+static RESOLVING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Set while the detector handles a call on this thread.
+    static BYPASSED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// What `dlsym` allocates before the real functions are known.
+const BOOTSTRAP_SIZE: usize = 16 * 1024;
+/// Room before each bootstrap block for its size, keeping it 16-aligned.
+const BOOTSTRAP_HEADER: usize = 16;
+
+#[repr(C, align(16))]
+struct Bootstrap([u8; BOOTSTRAP_SIZE]);
+
+static mut BOOTSTRAP: Bootstrap = Bootstrap([0; BOOTSTRAP_SIZE]);
+static BOOTSTRAP_USED: AtomicUsize = AtomicUsize::new(0);
+
+fn bootstrap_base() -> usize {
+    (&raw const BOOTSTRAP) as usize
+}
+
+fn in_bootstrap(ptr: *mut c_void) -> bool {
+    (bootstrap_base()..bootstrap_base() + BOOTSTRAP_SIZE).contains(&(ptr as usize))
+}
+
+/// A zeroed block from the bootstrap buffer, or null once it runs out.
+fn bootstrap_alloc(size: usize) -> *mut c_void {
+    let needed = BOOTSTRAP_HEADER + size.next_multiple_of(16);
+    let Ok(offset) = BOOTSTRAP_USED.fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+        (used + needed <= BOOTSTRAP_SIZE).then_some(used + needed)
+    }) else {
+        return ptr::null_mut();
+    };
+    let header = (bootstrap_base() + offset) as *mut usize;
+    unsafe {
+        header.write(size);
+        header.cast::<u8>().add(BOOTSTRAP_HEADER).cast()
+    }
+}
+
+fn bootstrap_size(ptr: *mut c_void) -> usize {
+    unsafe {
+        ptr.cast::<u8>()
+            .sub(BOOTSTRAP_HEADER)
+            .cast::<usize>()
+            .read()
+    }
+}
+
+/// Looks the real functions up on first use. `false` while they are being
+/// looked up, from any thread, so that `dlsym`'s own allocations go to the
+/// bootstrap buffer.
+fn resolve() -> bool {
+    if NEXT_FREE.load(Ordering::Acquire) != 0 {
+        return true;
+    }
+    if RESOLVING.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+    let next = |name: &std::ffi::CStr| unsafe { dlsym(RTLD_NEXT, name.as_ptr()) } as usize;
+    NEXT_MALLOC.store(next(c"malloc"), Ordering::Release);
+    NEXT_CALLOC.store(next(c"calloc"), Ordering::Release);
+    NEXT_REALLOC.store(next(c"realloc"), Ordering::Release);
+    NEXT_POSIX_MEMALIGN.store(next(c"posix_memalign"), Ordering::Release);
+    NEXT_FREE.store(next(c"free"), Ordering::Release);
+    RESOLVING.store(false, Ordering::Release);
+    true
+}
+
+fn next<F: Copy>(slot: &AtomicUsize) -> F {
+    let address = slot.load(Ordering::Acquire);
+    assert!(address != 0, "the C allocator wasn't found");
+    unsafe { std::mem::transmute_copy(&address) }
+}
+
+fn usable_size(ptr: *mut c_void) -> usize {
+    #[cfg(not(target_os = "macos"))]
+    return unsafe { malloc_usable_size(ptr) };
+    #[cfg(target_os = "macos")]
+    return unsafe { malloc_size(ptr) };
+}
+
+/// Turns interception off on this thread until dropped, restoring what was
+/// there before.
+pub(crate) struct Bypass(bool);
+
+impl Bypass {
+    pub(crate) fn new() -> Self {
+        // Threads past their locals' teardown are left as they are.
+        Self(
+            BYPASSED
+                .try_with(|bypassed| bypassed.replace(true))
+                .unwrap_or(true),
+        )
+    }
+}
+
+impl Drop for Bypass {
+    fn drop(&mut self) {
+        let _ = BYPASSED.try_with(|bypassed| bypassed.set(self.0));
+    }
+}
+
+fn bypassed() -> bool {
+    BYPASSED.try_with(Cell::get).unwrap_or(true)
+}
+
+/// Blocks are counted at their usable size, which `free` can read back.
+fn layout(ptr: *mut c_void) -> Layout {
+    unsafe { Layout::from_size_align_unchecked(usable_size(ptr), 1) }
+}
+
+#[track_caller]
+fn track(ptr: *mut c_void) {
+    if ptr.is_null() || bypassed() {
+        return;
+    }
+    let _bypass = Bypass::new();
+    // After the allocation: nothing unwinds out of C anyway.
+    C_HEAP.check_large(layout(ptr), None);
+    C_HEAP.on_alloc(ptr.cast(), layout(ptr));
+}
+
+/// Counts the free of `ptr` if it was counted when allocated, so that
+/// blocks from untracked relatives such as `memalign` pass unremarked.
+fn untrack(ptr: *mut c_void) {
+    if bypassed() {
+        return;
+    }
+    let _bypass = Bypass::new();
+    if C_HEAP.registry.lock().contains_key(&(ptr as usize)) {
+        C_HEAP.on_dealloc(ptr.cast(), layout(ptr), 0);
+    }
+}
+
+/// `malloc`, counted in [`c_heap`].
+///
+/// # Safety
+///
+/// As the C function; it is only meant to be called from C.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn malloc(size: usize) -> *mut c_void {
+    if !resolve() {
+        return bootstrap_alloc(size);
+    }
+    let ptr = unsafe { next::<Malloc>(&NEXT_MALLOC)(size) };
+    track(ptr);
+    ptr
+}
+
+/// `calloc`, counted in [`c_heap`].
+///
+/// # Safety
+///
+/// As the C function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn calloc(count: usize, size: usize) -> *mut c_void {
+    if !resolve() {
+        return count
+            .checked_mul(size)
+            .map_or(ptr::null_mut(), bootstrap_alloc);
+    }
+    let ptr = unsafe { next::<Calloc>(&NEXT_CALLOC)(count, size) };
+    track(ptr);
+    ptr
+}
+
+/// `realloc`, moving the block's entry in [`c_heap`].
+///
+/// # Safety
+///
+/// As the C function: `old` is null or a live block from this family.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn realloc(old: *mut c_void, size: usize) -> *mut c_void {
+    if old.is_null() {
+        return unsafe { malloc(size) };
+    }
+    if in_bootstrap(old) {
+        let new = unsafe { malloc(size) };
+        if !new.is_null() {
+            let kept = bootstrap_size(old).min(size);
+            unsafe { ptr::copy_nonoverlapping(old.cast::<u8>(), new.cast(), kept) };
+        }
+        return new;
+    }
+    if bypassed() {
+        return unsafe { next::<Realloc>(&NEXT_REALLOC)(old, size) };
+    }
+    let old_layout = layout(old);
+    let new = unsafe { next::<Realloc>(&NEXT_REALLOC)(old, size) };
+    let _bypass = Bypass::new();
+    let counted = C_HEAP.registry.lock().contains_key(&(old as usize));
+    if new.is_null() {
+        // `realloc(ptr, 0)` may free and return null; otherwise `old` lives.
+        if size == 0 && counted {
+            C_HEAP.on_dealloc(old.cast(), old_layout, 0);
+        }
+    } else if counted {
+        C_HEAP.check_large(layout(new), Some(old_layout.size()));
+        C_HEAP.on_resize(old.cast(), new.cast(), old_layout, layout(new), 0);
+    } else {
+        C_HEAP.check_large(layout(new), None);
+        C_HEAP.on_alloc(new.cast(), layout(new));
+    }
+    new
+}
+
+/// `free`, uncounting the block if it was counted.
+///
+/// # Safety
+///
+/// As the C function: `ptr` is null or a live block from this family.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free(ptr: *mut c_void) {
+    if ptr.is_null() || in_bootstrap(ptr) {
+        return;
+    }
+    untrack(ptr);
+    unsafe { next::<Free>(&NEXT_FREE)(ptr) };
+}
+
+/// `posix_memalign`, counted in [`c_heap`].
+///
+/// # Safety
+///
+/// As the C function: `out` is valid for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn posix_memalign(out: *mut *mut c_void, align: usize, size: usize) -> c_int {
+    if !resolve() {
+        // The bootstrap buffer only promises 16-byte alignment.
+        let ptr = if align <= 16 {
+            bootstrap_alloc(size)
+        } else {
+            ptr::null_mut()
+        };
+        if ptr.is_null() {
+            return 12; // ENOMEM
+        }
+        unsafe { out.write(ptr) };
+        return 0;
+    }
+    let status = unsafe { next::<PosixMemalign>(&NEXT_POSIX_MEMALIGN)(out, align, size) };
+    if status == 0 {
+        track(unsafe { out.read() });
+    }
+    status
+}
+
+/// The C library's allocator, reached past the exported functions so that
+/// its blocks aren't counted as C allocations. With `c-intercept` on, wrap
+/// this rather than [`System`] in the global detector: `System` calls
+/// `malloc`, and every Rust allocation would be counted twice.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Libc;
+
+/// The largest alignment `malloc` promises.
+const MALLOC_ALIGN: usize = 16;
+
+unsafe impl GlobalAlloc for Libc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _bypass = Bypass::new();
+        if layout.align() <= MALLOC_ALIGN && layout.align() <= layout.size() {
+            unsafe { malloc(layout.size()) }.cast()
+        } else {
+            let mut out = ptr::null_mut();
+            let align = layout.align().max(size_of::<usize>());
+            match unsafe { posix_memalign(&mut out, align, layout.size()) } {
+                0 => out.cast(),
+                _ => ptr::null_mut(),
+            }
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if layout.align() <= MALLOC_ALIGN && layout.align() <= layout.size() {
+            let _bypass = Bypass::new();
+            unsafe { calloc(layout.size(), 1) }.cast()
+        } else {
+            let ptr = unsafe { self.alloc(layout) };
+            if !ptr.is_null() {
+                unsafe { ptr.write_bytes(0, layout.size()) };
+            }
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        let _bypass = Bypass::new();
+        unsafe { free(ptr.cast()) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if layout.align() <= MALLOC_ALIGN && layout.align() <= new_size {
+            let _bypass = Bypass::new();
+            return unsafe { realloc(ptr.cast(), new_size) }.cast();
+        }
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new = unsafe { self.alloc(new_layout) };
+        if !new.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new
+    }
+}
+
+/// Runs `f` with the detector counting the C library's allocations. Its
+/// calls allocate through `malloc` themselves, so they only go through
+/// here, with interception off on this thread meanwhile.
+pub fn c_heap<R>(f: impl FnOnce(&LeakDetector<System>) -> R) -> R {
+    let _bypass = Bypass::new();
+    f(&C_HEAP)
+}
+
+impl<T> LeakDetector<T> {
+    /// This detector's [`leak_report`](LeakDetector::leak_report) together
+    /// with what C code leaked since [`c_heap`]'s baseline, in one report.
+    /// The C blocks carry no stacks.
+    pub fn combined_leak_report(&self) -> LeakReport {
+        let mut report = self.leak_report();
+        report.absorb(c_heap(LeakDetector::leak_report));
+        report
+    }
+}
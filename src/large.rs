@@ -0,0 +1,186 @@
+use std::{
+    alloc::Layout,
+    cell::Cell,
+    fmt,
+    panic::Location,
+    sync::{PoisonError, atomic::Ordering},
+};
+
+use crate::{
+    LeakDetector,
+    stack::{Backend, CapturedStack, StackCapture},
+};
+
+/// What a detector does with an allocation at or above its threshold, see
+/// [`LeakDetector::set_large_allocation_threshold`].
+///
+/// These run inside the allocator. A [`GlobalAlloc`](std::alloc::GlobalAlloc)
+/// can't unwind, so `Panic` aborts the process there.
+#[derive(Debug, Clone, Copy)]
+pub enum OnLargeAllocation {
+    /// A warning through `log` with the `log` feature, to stderr otherwise.
+    Log,
+    /// May allocate; allocations made by the callback itself are not checked
+    /// against the threshold.
+    Callback(fn(&LargeAllocation<'_>)),
+    Panic,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LargeAllocation<'a> {
+    pub layout: Layout,
+    /// The size before, when a block grew past the threshold.
+    pub grown_from: Option<usize>,
+    pub callsite: &'static Location<'static>,
+    /// Raw instruction pointers, innermost first, when the detector records
+    /// backtraces with the `backtrace-crate` backend; empty otherwise.
+    pub stack: &'a [usize],
+}
+
+impl fmt::Display for LargeAllocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.grown_from {
+            Some(old) => write!(f, "block grew from {old} to {} bytes", self.layout.size())?,
+            None => write!(f, "allocation of {} bytes", self.layout.size())?,
+        }
+        write!(f, " (align {}) at {}", self.layout.align(), self.callsite)
+    }
+}
+
+thread_local! {
+    static HANDLING: Cell<bool> = const { Cell::new(false) };
+}
+
+impl OnLargeAllocation {
+    fn apply(self, large: &LargeAllocation<'_>) {
+        match self {
+            #[cfg(feature = "log")]
+            OnLargeAllocation::Log => log::warn!("large {large}"),
+            #[cfg(not(feature = "log"))]
+            OnLargeAllocation::Log => eprintln!("large {large}"),
+            OnLargeAllocation::Callback(callback) => callback(large),
+            OnLargeAllocation::Panic => panic!("large {large}"),
+        }
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Flags every allocation of at least `bytes`, and every block growing to
+    /// that size, with the action set by [`set_on_large_allocation`]. They
+    /// are counted in [`Snapshot::large_allocations`](crate::Snapshot).
+    ///
+    /// [`set_on_large_allocation`]: LeakDetector::set_on_large_allocation
+    pub fn set_large_allocation_threshold(&self, bytes: usize) {
+        self.large_threshold.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn clear_large_allocation_threshold(&self) {
+        self.large_threshold.store(usize::MAX, Ordering::Relaxed);
+    }
+
+    pub fn large_allocation_threshold(&self) -> Option<usize> {
+        Some(self.large_threshold.load(Ordering::Relaxed)).filter(|&bytes| bytes != usize::MAX)
+    }
+
+    pub fn set_on_large_allocation(&self, action: OnLargeAllocation) {
+        *self.on_large.lock().unwrap_or_else(PoisonError::into_inner) = action;
+    }
+
+    /// Runs before the inner allocator, so that a panicking action leaves
+    /// no block behind. `grown_from` is the old size of a block resized to
+    /// `layout`; shrinking never counts.
+    #[inline]
+    #[track_caller]
+    pub(crate) fn check_large(&self, layout: Layout, grown_from: Option<usize>) {
+        if layout.size() >= self.large_threshold.load(Ordering::Relaxed)
+            && grown_from.is_none_or(|old| layout.size() > old)
+            && self.tracks_here()
+        {
+            self.large_allocation(layout, grown_from);
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    #[track_caller]
+    fn large_allocation(&self, layout: Layout, grown_from: Option<usize>) {
+        if HANDLING.try_with(Cell::get).unwrap_or(true) {
+            return;
+        }
+        self.counters.large();
+        let action = *self.on_large.lock().unwrap_or_else(PoisonError::into_inner);
+        let stack = self.large_allocation_stack();
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                HANDLING.set(false);
+            }
+        }
+        HANDLING.set(true);
+        let _reset = Reset;
+        action.apply(&LargeAllocation {
+            layout,
+            grown_from,
+            callsite: crate::traced::caller(),
+            stack: stack.as_ref().map_or(&[], Backend::ips),
+        });
+    }
+
+    fn large_allocation_stack(&self) -> Option<CapturedStack> {
+        if self.registry.backtrace_sampling() == 0 {
+            return None;
+        }
+        Backend::capture()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, System},
+        panic::{AssertUnwindSafe, catch_unwind},
+        sync::atomic::AtomicUsize,
+    };
+
+    use super::*;
+
+    static SEEN: AtomicUsize = AtomicUsize::new(0);
+
+    fn record(large: &LargeAllocation<'_>) {
+        assert_eq!(large.callsite.file(), file!());
+        SEEN.store(large.layout.size(), Ordering::Relaxed);
+    }
+
+    #[test]
+    fn callback_sees_size() {
+        let detector = LeakDetector::system();
+        detector.set_large_allocation_threshold(1024);
+        detector.set_on_large_allocation(OnLargeAllocation::Callback(record));
+        let small = Vec::<u8, _>::with_capacity_in(512, &detector);
+        assert_eq!(SEEN.load(Ordering::Relaxed), 0);
+        let layout = Layout::from_size_align(2048, 8).unwrap();
+        let large = detector.allocate(layout).unwrap();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 2048);
+        assert_eq!(detector.snapshot().large_allocations, 1);
+        unsafe { detector.deallocate(large.cast(), layout) };
+        drop(small);
+    }
+
+    #[test]
+    fn panicking_leaves_nothing_allocated() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        detector.set_large_allocation_threshold(1024);
+        detector.set_on_large_allocation(OnLargeAllocation::Panic);
+        let mut grown = Vec::<u8, _>::with_capacity_in(512, &detector);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            Vec::<u8, _>::with_capacity_in(2048, &detector)
+        }));
+        assert!(result.is_err());
+        let result = catch_unwind(AssertUnwindSafe(|| grown.reserve_exact(2048)));
+        assert!(result.is_err());
+        assert_eq!(detector.get_used(), 512);
+        assert_eq!(detector.snapshot().large_allocations, 2);
+        drop(grown);
+        detector.check().unwrap();
+    }
+}
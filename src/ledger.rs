@@ -0,0 +1,95 @@
+use std::{alloc::System, sync::atomic::AtomicUsize, time::Duration};
+
+use crate::{
+    LeakDetector, LeakReport, LeakedAllocation, Overhead, ReportOptions, Snapshot,
+    counters::{Counter, Counters},
+    registry::Entry,
+};
+
+/// What stats and reports read from a detector, so that
+/// [`LeakDetector`](crate::LeakDetector) and
+/// [`LocalLeakDetector`](crate::LocalLeakDetector) build them the same way.
+pub(crate) trait Ledger {
+    type Counter: Counter;
+
+    fn counters(&self) -> &Counters<Self::Counter>;
+
+    /// Starts a new registry epoch, returning the one that ended.
+    fn advance_epoch(&self) -> u64;
+
+    /// Live registry entries allocated after epoch `after`, with their
+    /// addresses; empty when the registry is off.
+    fn entries(&self, after: u64) -> Vec<(usize, Entry), System>;
+
+    /// The registry's clock in milliseconds, 0 until something drives it.
+    fn clock(&self) -> u64;
+
+    fn self_overhead(&self) -> Overhead;
+
+    fn read_snapshot(&self) -> Snapshot {
+        let counters = self.counters();
+        Snapshot {
+            used: counters.used(),
+            peak: counters.peak(),
+            allocations: counters.allocations(),
+            deallocations: counters.deallocations(),
+            reallocations: counters.reallocations(),
+            bytes_allocated: counters.bytes_allocated(),
+            bytes_deallocated: counters.bytes_deallocated(),
+            used_actual: counters.used_actual(),
+            padding_bytes: counters.padding(),
+            large_allocations: counters.large_allocations(),
+            overhead_bytes: self.self_overhead().bytes(),
+            epoch: self.advance_epoch(),
+        }
+    }
+
+    /// Live registry entries allocated after `epoch`, sorted by address.
+    fn allocations_after(&self, epoch: u64) -> Vec<LeakedAllocation> {
+        let now = self.clock();
+        self.entries(epoch)
+            .into_iter()
+            .map(|(address, entry)| LeakedAllocation {
+                id: entry.id,
+                address,
+                size: entry.size,
+                usable_size: (entry.usable != 0).then_some(entry.usable),
+                padding: entry.padding,
+                callsite: entry.callsite,
+                age: Duration::from_millis(now.saturating_sub(entry.born)),
+                stack: entry.stack,
+            })
+            .collect()
+    }
+
+    /// A report of the allocations after `epoch`, without stacks.
+    fn report_after(&self, epoch: u64, options: ReportOptions) -> LeakReport {
+        let mut report = LeakReport::new(self.allocations_after(epoch), self.clock() != 0, options);
+        report.overhead = Some(self.self_overhead());
+        report
+    }
+}
+
+impl<T> Ledger for LeakDetector<T> {
+    type Counter = AtomicUsize;
+
+    fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    fn advance_epoch(&self) -> u64 {
+        self.registry.advance_epoch()
+    }
+
+    fn entries(&self, after: u64) -> Vec<(usize, Entry), System> {
+        self.registry.entries(after)
+    }
+
+    fn clock(&self) -> u64 {
+        self.registry.clock()
+    }
+
+    fn self_overhead(&self) -> Overhead {
+        LeakDetector::self_overhead(self)
+    }
+}
@@ -1,80 +1,646 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "std", feature(alloc_error_hook))]
 #![feature(allocator_api)]
-#![feature(slice_ptr_get)]
-#![feature(const_default)]
-#![feature(decl_macro)]
+#![cfg_attr(feature = "std", feature(btreemap_alloc))]
+#![cfg_attr(feature = "std", feature(const_default))]
 #![feature(const_trait_impl)]
-#![feature(unboxed_closures)]
-#![feature(tuple_trait)]
-#![feature(fn_traits)]
+#![cfg_attr(feature = "std", feature(unboxed_closures))]
+#![cfg_attr(feature = "std", feature(tuple_trait))]
+#![cfg_attr(feature = "std", feature(fn_traits))]
 
+#[cfg(feature = "std")]
 use std::{
     alloc::{Allocator, GlobalAlloc},
-    sync::atomic::AtomicUsize,
+    panic::Location,
+    sync::{Mutex, atomic::AtomicUsize},
 };
 
+#[cfg(feature = "std")]
+use crate::{counters::Counters, poison::Poison, registry::Registry};
+
+#[cfg(feature = "std")]
+mod age;
+#[cfg(feature = "std")]
+mod allocator_as_global;
+#[cfg(feature = "std")]
+mod arena;
+#[cfg(feature = "std")]
+mod balance;
+#[cfg(feature = "std")]
+mod budget;
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+pub mod ci;
+#[cfg(feature = "std")]
+mod ci_summary;
+#[cfg(feature = "std")]
+mod collections;
+#[cfg(feature = "config")]
+mod config;
+#[cfg(feature = "std")]
+mod counters;
+#[cfg(feature = "std")]
+mod crates;
+#[cfg(feature = "std")]
+mod diagnostics;
+#[cfg(feature = "std")]
+mod dyn_alloc;
+#[cfg(feature = "efence")]
+mod efence;
+#[cfg(feature = "env-config")]
+mod env;
+#[cfg(feature = "std")]
+mod epoch;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(test)]
+mod executor;
+#[cfg(feature = "std")]
+mod exit;
+#[cfg(feature = "std")]
+mod failure;
+mod fixed;
+#[cfg(feature = "std")]
+mod forbid;
+#[cfg(feature = "std")]
+mod frame;
+#[cfg(feature = "std")]
+mod global_as_allocator;
+#[cfg(feature = "std")]
+mod growth;
+#[cfg(feature = "harness")]
+pub mod harness;
+#[cfg(feature = "http-debug")]
+mod http_debug;
+#[cfg(all(unix, feature = "c-intercept"))]
+pub mod intercept;
+#[cfg(feature = "std")]
+mod large;
+#[cfg(feature = "std")]
+mod ledger;
+#[cfg(feature = "std")]
+mod limits;
+#[cfg(feature = "std")]
+mod live;
+#[cfg(feature = "std")]
+mod local;
+#[cfg(feature = "std")]
+pub mod mock;
+#[cfg(feature = "std")]
+mod normalize;
+#[cfg(feature = "std")]
+mod oom;
+#[cfg(feature = "std")]
+pub mod os;
+#[cfg(feature = "std")]
+mod overhead;
+#[cfg(feature = "std")]
+mod pause;
+#[cfg(feature = "std")]
+mod poison;
+#[cfg(feature = "std")]
+mod policy;
+#[cfg(feature = "std")]
+mod pretty;
+#[cfg(feature = "std")]
+mod quarantine;
+#[cfg(feature = "std")]
+mod registry;
+#[cfg(feature = "std")]
+mod render;
+#[cfg(feature = "std")]
+mod report;
+#[cfg(feature = "std")]
+mod sampling;
+#[cfg(feature = "std")]
 mod scope;
+#[cfg(feature = "std")]
+mod scope_future;
+#[cfg(feature = "std")]
+mod scope_stack;
+#[cfg(feature = "std")]
+mod scope_token;
+#[cfg(feature = "std")]
+mod shutdown;
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "tracing-attribution")]
+mod span_attribution;
+#[cfg(feature = "std")]
+mod stack;
+#[cfg(feature = "compat-stats-alloc")]
+mod stats_alloc;
+#[cfg(feature = "std")]
+mod summary;
+#[cfg(feature = "std")]
+mod suppress;
+#[cfg(feature = "std")]
+mod suspects;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "std")]
+mod thread_exit;
+#[cfg(feature = "std")]
+mod thread_limit;
+#[cfg(feature = "std")]
+mod ticket;
+#[cfg(feature = "std")]
+mod traced;
+#[cfg(feature = "std")]
+mod until;
+#[cfg(feature = "usable-size")]
+mod usable_size;
+#[cfg(feature = "std")]
+mod wait;
+
+#[cfg(feature = "std")]
+pub use age::AgeDistribution;
+#[cfg(feature = "std")]
+pub use allocator_as_global::AllocatorAsGlobal;
+#[cfg(feature = "std")]
+pub use balance::BalanceGuard;
+#[cfg(feature = "std")]
+pub use builder::LeakDetectorBuilder;
+#[cfg(feature = "std")]
+pub use ci_summary::{CiSite, CiSummary};
+#[cfg(feature = "config")]
+pub use config::{ConfigError, LoadedConfig};
+#[cfg(feature = "std")]
+pub use crates::{CrateAttribution, DEFAULT_SKIPPED_PREFIXES};
+#[cfg(feature = "std")]
+pub use diagnostics::{AccountingDiagnostic, BadFree, MAX_DIAGNOSTICS};
+#[cfg(feature = "std")]
+pub use dyn_alloc::{DynAllocator, DynGlobalAlloc};
+#[cfg(feature = "efence")]
+pub use efence::GuardPlacement;
+#[cfg(feature = "std")]
+pub use epoch::Epoch;
+#[cfg(feature = "std")]
+pub use error::{LeakError, ScopeError};
+#[cfg(feature = "std")]
+pub use exit::ExitReport;
+#[cfg(feature = "std")]
+pub use failure::FailureMode;
+pub use fixed::{FixedEntry, FixedLeakDetector, FixedReport, Untracked};
+#[cfg(feature = "std")]
+pub use forbid::OnForbidden;
+#[cfg(feature = "std")]
+pub use frame::{FRAME_WINDOW, FrameGuard, FrameStats};
+#[cfg(feature = "std")]
+pub use global_as_allocator::GlobalAsAllocator;
+#[cfg(feature = "std")]
+pub use growth::{GrowthAnalysis, GrowthThresholds, GrowthVerdict};
+#[cfg(feature = "http-debug")]
+pub use http_debug::DebugServer;
+#[cfg(feature = "std")]
+pub use large::{LargeAllocation, OnLargeAllocation};
+#[cfg(feature = "std")]
+pub use live::{LiveAllocation, LiveTotals};
+#[cfg(feature = "std")]
+pub use local::{LocalLeakDetector, LocalLeakDetectorScope};
+#[cfg(feature = "macros")]
+pub use mem_leak_detector_macros::leak_checked;
+#[cfg(feature = "std")]
+pub use normalize::normalize_report;
+#[cfg(feature = "std")]
+pub use overhead::Overhead;
+#[cfg(feature = "std")]
+pub use pause::PauseGuard;
+#[cfg(feature = "std")]
+pub use poison::FirstFailure;
+#[cfg(feature = "std")]
+pub use policy::OnLeak;
+#[cfg(feature = "std")]
+pub use pretty::{ColorChoice, Pretty};
+#[cfg(feature = "std")]
+pub use render::{Rendered, ReportOptions, SortSites};
+#[cfg(feature = "std")]
+pub use report::{LeakReport, LeakedAllocation, SuppressedAllocation, Symbol};
+#[cfg(feature = "std")]
+pub use sampling::Sampling;
+#[cfg(feature = "std")]
+pub use scope::{LeakDetectorScope, ScopeAttribution, ScopeLeak};
+#[cfg(feature = "std")]
+pub use scope_future::ScopedFuture;
+#[cfg(feature = "std")]
+pub use scope_token::ScopeToken;
+#[cfg(feature = "std")]
+pub use shutdown::ShutdownCheck;
+#[cfg(feature = "std")]
+pub use snapshot::Snapshot;
+#[cfg(feature = "tracing-attribution")]
+pub use span_attribution::{MAX_SPAN_NAMES, SpanAttributionLayer, SpanStats};
+#[cfg(feature = "std")]
+pub use stack::{MAX_STACKS, StackId};
+#[cfg(feature = "compat-stats-alloc")]
+pub use stats_alloc::{Region, Stats};
+#[cfg(feature = "std")]
+pub use summary::Summary;
+#[cfg(feature = "std")]
+pub use suspects::{SuspectOptions, SuspectSite};
+#[cfg(feature = "std")]
+pub use thread_exit::{OnThreadExit, ThreadExitReport};
+#[cfg(feature = "std")]
+pub use ticket::ScopeTicket;
+#[cfg(feature = "std")]
+pub use traced::Traced;
+#[cfg(feature = "std")]
+pub use until::Drained;
 
+#[cfg(feature = "std")]
 pub struct LeakDetector<T> {
     inner: T,
-    used: AtomicUsize,
+    counters: Counters,
+    poison: Poison,
+    paused: AtomicUsize,
+    baseline: Mutex<Snapshot>,
+    on_leak: Mutex<OnLeak>,
+    failure_mode: Mutex<FailureMode>,
+    registry: Registry,
+    sampler: sampling::Sampler,
+    tolerance: AtomicUsize,
+    check_on_drop: bool,
+    large_threshold: AtomicUsize,
+    on_large: Mutex<OnLargeAllocation>,
+    report_options: Mutex<ReportOptions>,
+    forbid: forbid::Forbid,
+    frames: frame::Frames,
+    /// Epochs ended by [`LeakDetector::checkpoint`] and `used` at each.
+    checkpoints: overhead::Accounted<Vec<(u64, usize), overhead::Internal>>,
+    thread_limits: thread_limit::ThreadLimits,
+    thread_exit: thread_exit::ThreadExit,
+    /// Taken by [`LeakDetector::sample_usage`], oldest first.
+    usage_samples: overhead::Accounted<
+        std::collections::VecDeque<(std::time::Instant, usize), overhead::Internal>,
+    >,
+    quarantine: quarantine::Quarantine,
+    diagnostics: diagnostics::Diagnostics,
+    waiters: wait::Waiters,
+    #[cfg(feature = "efence")]
+    efence: efence::Efence,
+    #[cfg(feature = "usable-size")]
+    usable_size: bool,
+    #[cfg(feature = "tracing-attribution")]
+    spans: span_attribution::SpanTotals,
+    suppressions: overhead::Accounted<Vec<suppress::Suppression, overhead::Internal>>,
+    #[cfg(any(feature = "env-config", feature = "config"))]
+    report_path: overhead::Accounted<Option<Vec<u8, overhead::Internal>>>,
 }
 
+#[cfg(feature = "std")]
 impl<T: [const] Default> const Default for LeakDetector<T> {
     fn default() -> Self {
         Self::new(T::default())
     }
 }
 
+#[cfg(feature = "std")]
 impl LeakDetector<std::alloc::System> {
     pub const fn system() -> Self {
         Self::new(std::alloc::System)
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> LeakDetector<T> {
     pub const fn new(val: T) -> Self {
-        Self {
-            inner: val,
-            used: AtomicUsize::new(0),
-        }
+        Self::builder(val).build()
     }
     pub fn get_used(&self) -> usize {
-        self.used.load(std::sync::atomic::Ordering::Acquire)
+        self.counters.used()
+    }
+    pub fn get_peak(&self) -> usize {
+        self.counters.peak()
+    }
+
+    /// Estimated bytes lost to aligning the over-aligned blocks in
+    /// [`get_used`]: a 1-byte block aligned to 4096 counts 4095. Blocks with
+    /// no more than the platform's usual `malloc` alignment count nothing.
+    ///
+    /// [`get_used`]: LeakDetector::get_used
+    pub fn padding_bytes(&self) -> usize {
+        self.counters.padding()
+    }
+
+    /// Bytes the inner allocator reserved for the blocks in [`get_used`],
+    /// counting size-class rounding. Zero unless the builder's `usable_size`
+    /// is on.
+    ///
+    /// [`get_used`]: LeakDetector::get_used
+    pub fn get_used_actual(&self) -> usize {
+        self.counters.used_actual()
+    }
+
+    /// Whether this thread's allocations are counted right now: not while
+    /// tracking is paused or the thread does the detector's own work, nor
+    /// those of a backtrace backend capturing a stack.
+    #[inline]
+    fn tracks_here(&self) -> bool {
+        self.is_tracking() && !pause::in_internal() && !stack::capturing()
+    }
+
+    /// Whether a request for `additional` more bytes passes every scope
+    /// budget and thread limit.
+    #[inline]
+    fn may_allocate(&self, additional: usize) -> bool {
+        self.within_budget(additional) && self.within_thread_limit(additional)
+    }
+
+    #[track_caller]
+    fn on_alloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        #[cfg(all(unix, feature = "c-intercept"))]
+        let _bypass = intercept::Bypass::new();
+        if !self.tracks_here() {
+            if !stack::capturing() {
+                self.diagnostics.skipped();
+            }
+            return;
+        }
+        self.counters.alloc(layout.size());
+        self.charge_thread(None, 0, layout.size());
+        self.charge_budgets(0, layout.size());
+        #[cfg(feature = "tracing-attribution")]
+        self.spans.charge(layout.size());
+        let usable = self.usable_size(ptr);
+        self.counters.actual(0, usable);
+        self.counters.pad(0, alignment_padding(layout));
+        if self.registry.is_enabled() && layout.size() != 0 && self.sampler.sample(layout.size()) {
+            self.registry
+                .insert(ptr as usize, self.new_entry(layout, usable));
+            self.watch_thread();
+        }
+    }
+
+    #[track_caller]
+    fn new_entry(&self, layout: std::alloc::Layout, usable: usize) -> registry::Entry {
+        registry::Entry {
+            id: self.registry.next_id(),
+            size: layout.size(),
+            align: layout.align(),
+            usable,
+            padding: alignment_padding(layout),
+            thread: registry::thread_tag(),
+            scope: scope_stack::innermost(self),
+            epoch: self.registry.epoch(),
+            born: self.registry.clock(),
+            callsite: traced::caller(),
+            stack: self.capture_stack(),
+        }
+    }
+
+    #[cfg(feature = "backtrace")]
+    fn capture_stack(&self) -> Option<StackId> {
+        if !stack::sample(self.registry.backtrace_sampling()) {
+            return None;
+        }
+        use stack::StackCapture;
+        stack::Backend::capture().and_then(|stack| self.registry.stacks.intern(stack))
+    }
+
+    #[cfg(not(feature = "backtrace"))]
+    fn capture_stack(&self) -> Option<StackId> {
+        None
+    }
+
+    /// Bytes the inner allocator reserved for `ptr`, or 0 when not tracked.
+    #[cfg(feature = "usable-size")]
+    fn usable_size(&self, ptr: *mut u8) -> usize {
+        if !self.usable_size || self.efence_owns(ptr) {
+            return 0;
+        }
+        unsafe { usable_size::usable_size(ptr) }.unwrap_or(0)
+    }
+
+    #[cfg(not(feature = "usable-size"))]
+    fn usable_size(&self, _ptr: *mut u8) -> usize {
+        0
+    }
+
+    #[cfg(all(feature = "usable-size", feature = "efence"))]
+    fn efence_owns(&self, ptr: *mut u8) -> bool {
+        self.efence.owns(ptr)
+    }
+
+    #[cfg(all(feature = "usable-size", not(feature = "efence")))]
+    fn efence_owns(&self, _ptr: *mut u8) -> bool {
+        false
+    }
+
+    #[cfg(not(feature = "efence"))]
+    #[inline]
+    fn efence_allocate(
+        &self,
+        _layout: std::alloc::Layout,
+    ) -> Option<Result<std::ptr::NonNull<u8>, std::alloc::AllocError>> {
+        None
+    }
+
+    #[cfg(not(feature = "efence"))]
+    #[inline]
+    fn efence_deallocate(&self, _ptr: *mut u8) -> bool {
+        false
+    }
+
+    #[cfg(not(feature = "efence"))]
+    #[inline]
+    fn efence_resize(
+        &self,
+        _ptr: *mut u8,
+        _old_layout: std::alloc::Layout,
+        _new_layout: std::alloc::Layout,
+        _allocate: impl FnOnce(
+            std::alloc::Layout,
+        ) -> Result<std::ptr::NonNull<u8>, std::alloc::AllocError>,
+    ) -> Option<Result<std::ptr::NonNull<u8>, std::alloc::AllocError>> {
+        None
+    }
+
+    /// With the registry, a free is counted exactly when its block was
+    /// counted, whether or not tracking is paused right now. `usable` is the
+    /// block's usable size, read before it was freed. Returns where the
+    /// block was allocated, if the registry knows. Frees the registry can't
+    /// match go to [`LeakDetector::diagnostics`].
+    #[track_caller]
+    fn on_dealloc(
+        &self,
+        ptr: *mut u8,
+        layout: std::alloc::Layout,
+        usable: usize,
+    ) -> Option<&'static Location<'static>> {
+        #[cfg(all(unix, feature = "c-intercept"))]
+        let _bypass = intercept::Bypass::new();
+        let mut size = layout.size();
+        let (tracked, owner, callsite) = if self.registry.is_enabled() && size != 0 {
+            match self.registry.remove(ptr as usize) {
+                Some(entry) => {
+                    if entry.size != size {
+                        self.bad_free(
+                            ptr,
+                            layout,
+                            BadFree::SizeMismatch {
+                                allocated: entry.size,
+                            },
+                        );
+                        size = entry.size;
+                    }
+                    (true, Some(entry), Some(entry.callsite))
+                }
+                // Unsampled blocks have no entry to go by.
+                None if self.sampler.get().is_some() => (self.tracks_here(), None, None),
+                None => {
+                    if self.tracks_here() && self.diagnostics.none_skipped() {
+                        self.bad_free(ptr, layout, BadFree::Unmatched);
+                    }
+                    (false, None, None)
+                }
+            }
+        } else {
+            (self.tracks_here(), None, None)
+        };
+        if tracked {
+            let underflow = self.counters.dealloc(size);
+            self.diagnostics.underflow(underflow);
+            self.waiters.freed(self.counters.used());
+            self.charge_thread(owner.as_ref(), size, 0);
+            self.charge_budgets(size, 0);
+            self.counters.actual(usable, 0);
+            self.counters.pad(alignment_padding(layout), 0);
+        }
+        callsite
+    }
+
+    #[track_caller]
+    fn on_resize(
+        &self,
+        old_ptr: *mut u8,
+        new_ptr: *mut u8,
+        old_layout: std::alloc::Layout,
+        new_layout: std::alloc::Layout,
+        old_usable: usize,
+    ) {
+        #[cfg(all(unix, feature = "c-intercept"))]
+        let _bypass = intercept::Bypass::new();
+        let new_usable = self.usable_size(new_ptr);
+        let registry = self.registry.is_enabled();
+        let mut underflow = None;
+        let (tracked, owner) = if registry && old_layout.size() != 0 {
+            match self.registry.resize(
+                old_ptr as usize,
+                new_ptr as usize,
+                new_layout,
+                new_usable,
+                || underflow = Some(self.counters.realloc(old_layout.size(), new_layout.size())),
+            ) {
+                Some(entry) => (true, Some(entry)),
+                None if self.sampler.get().is_some() => (self.tracks_here(), None),
+                None => (false, None),
+            }
+        } else {
+            let tracked = self.tracks_here();
+            if registry && tracked && new_layout.size() != 0 {
+                self.registry
+                    .insert(new_ptr as usize, self.new_entry(new_layout, new_usable));
+            }
+            (tracked, None)
+        };
+        if tracked {
+            let underflow = underflow
+                .unwrap_or_else(|| self.counters.realloc(old_layout.size(), new_layout.size()));
+            self.diagnostics.underflow(underflow);
+            if new_layout.size() < old_layout.size() {
+                self.waiters.freed(self.counters.used());
+            }
+            self.charge_thread(owner.as_ref(), old_layout.size(), new_layout.size());
+            self.charge_budgets(old_layout.size(), new_layout.size());
+            self.counters.actual(old_usable, new_usable);
+            self.counters
+                .pad(alignment_padding(old_layout), alignment_padding(new_layout));
+        }
+    }
+}
+
+/// What rounding an over-aligned block up to its alignment adds to it.
+#[cfg(feature = "std")]
+fn alignment_padding(layout: std::alloc::Layout) -> usize {
+    const MIN_ALIGN: usize = 2 * size_of::<usize>();
+    if layout.align() <= MIN_ALIGN {
+        0
+    } else {
+        layout.pad_to_align().size() - layout.size()
     }
 }
 
+#[cfg(feature = "std")]
 unsafe impl<T: Allocator> Allocator for LeakDetector<T> {
+    #[inline]
     #[track_caller]
     fn allocate(
         &self,
         layout: std::alloc::Layout,
     ) -> Result<std::ptr::NonNull<[u8]>, std::alloc::AllocError> {
-        self.inner.allocate(layout).inspect(|_| {
-            self.used
-                .fetch_add(layout.size(), std::sync::atomic::Ordering::AcqRel);
-        })
+        self.check_forbidden(layout, None, false);
+        self.check_large(layout, None);
+        if !self.may_allocate(layout.size()) {
+            return Err(std::alloc::AllocError);
+        }
+        let result = match self.efence_allocate(layout) {
+            Some(result) => {
+                result.map(|ptr| std::ptr::NonNull::slice_from_raw_parts(ptr, layout.size()))
+            }
+            None => self.inner.allocate(layout),
+        };
+        if let Ok(ptr) = result {
+            self.on_alloc(ptr.cast::<u8>().as_ptr(), layout);
+        }
+        result
     }
 
+    #[inline]
     #[track_caller]
     unsafe fn deallocate(&self, ptr: std::ptr::NonNull<u8>, layout: std::alloc::Layout) {
+        self.check_forbidden(layout, None, true);
+        let usable = self.usable_size(ptr.as_ptr());
+        if self.efence_deallocate(ptr.as_ptr()) {
+            self.on_dealloc(ptr.as_ptr(), layout, usable);
+            return;
+        }
+        if self.quarantine.is_enabled() && layout.size() != 0 {
+            unsafe {
+                self.quarantine_block(ptr, layout, usable, quarantine::release_allocator::<T>);
+            }
+            return;
+        }
         unsafe {
             self.inner.deallocate(ptr, layout);
         }
-        self.used
-            .fetch_sub(layout.size(), std::sync::atomic::Ordering::AcqRel);
+        self.on_dealloc(ptr.as_ptr(), layout, usable);
     }
 
+    #[inline]
     #[track_caller]
     fn allocate_zeroed(
         &self,
         layout: std::alloc::Layout,
     ) -> Result<std::ptr::NonNull<[u8]>, std::alloc::AllocError> {
-        self.inner.allocate_zeroed(layout).inspect(|_| {
-            self.used
-                .fetch_add(layout.size(), std::sync::atomic::Ordering::AcqRel);
-        })
+        self.check_forbidden(layout, None, false);
+        self.check_large(layout, None);
+        if !self.may_allocate(layout.size()) {
+            return Err(std::alloc::AllocError);
+        }
+        let result = match self.efence_allocate(layout) {
+            Some(result) => {
+                result.map(|ptr| std::ptr::NonNull::slice_from_raw_parts(ptr, layout.size()))
+            }
+            None => self.inner.allocate_zeroed(layout),
+        };
+        if let Ok(ptr) = result {
+            self.on_alloc(ptr.cast::<u8>().as_ptr(), layout);
+        }
+        result
     }
 
+    #[inline]
     #[track_caller]
     unsafe fn grow(
         &self,
@@ -82,16 +648,34 @@ unsafe impl<T: Allocator> Allocator for LeakDetector<T> {
         old_layout: std::alloc::Layout,
         new_layout: std::alloc::Layout,
     ) -> Result<std::ptr::NonNull<[u8]>, std::alloc::AllocError> {
-        unsafe {
-            self.inner.grow(ptr, old_layout, new_layout).inspect(|_| {
-                self.used.fetch_add(
-                    new_layout.size().unchecked_sub(old_layout.size()),
-                    std::sync::atomic::Ordering::AcqRel,
-                );
-            })
+        self.check_forbidden(new_layout, Some(old_layout.size()), false);
+        self.check_large(new_layout, Some(old_layout.size()));
+        if !self.may_allocate(new_layout.size() - old_layout.size()) {
+            return Err(std::alloc::AllocError);
         }
+        let old_usable = self.usable_size(ptr.as_ptr());
+        let moved = self.efence_resize(ptr.as_ptr(), old_layout, new_layout, |layout| {
+            self.inner.allocate(layout).map(std::ptr::NonNull::cast)
+        });
+        let result = match moved {
+            Some(result) => {
+                result.map(|ptr| std::ptr::NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+            }
+            None => unsafe { self.inner.grow(ptr, old_layout, new_layout) },
+        };
+        if let Ok(new) = result {
+            self.on_resize(
+                ptr.as_ptr(),
+                new.cast::<u8>().as_ptr(),
+                old_layout,
+                new_layout,
+                old_usable,
+            );
+        }
+        result
     }
 
+    #[inline]
     #[track_caller]
     unsafe fn grow_zeroed(
         &self,
@@ -99,18 +683,34 @@ unsafe impl<T: Allocator> Allocator for LeakDetector<T> {
         old_layout: std::alloc::Layout,
         new_layout: std::alloc::Layout,
     ) -> Result<std::ptr::NonNull<[u8]>, std::alloc::AllocError> {
-        unsafe {
-            self.inner
-                .grow_zeroed(ptr, old_layout, new_layout)
-                .inspect(|_| {
-                    self.used.fetch_add(
-                        new_layout.size().unchecked_sub(old_layout.size()),
-                        std::sync::atomic::Ordering::AcqRel,
-                    );
-                })
+        self.check_forbidden(new_layout, Some(old_layout.size()), false);
+        self.check_large(new_layout, Some(old_layout.size()));
+        if !self.may_allocate(new_layout.size() - old_layout.size()) {
+            return Err(std::alloc::AllocError);
+        }
+        let old_usable = self.usable_size(ptr.as_ptr());
+        let moved = self.efence_resize(ptr.as_ptr(), old_layout, new_layout, |layout| {
+            self.inner.allocate(layout).map(std::ptr::NonNull::cast)
+        });
+        let result = match moved {
+            Some(result) => {
+                result.map(|ptr| std::ptr::NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+            }
+            None => unsafe { self.inner.grow_zeroed(ptr, old_layout, new_layout) },
+        };
+        if let Ok(new) = result {
+            self.on_resize(
+                ptr.as_ptr(),
+                new.cast::<u8>().as_ptr(),
+                old_layout,
+                new_layout,
+                old_usable,
+            );
         }
+        result
     }
 
+    #[inline]
     #[track_caller]
     unsafe fn shrink(
         &self,
@@ -118,67 +718,237 @@ unsafe impl<T: Allocator> Allocator for LeakDetector<T> {
         old_layout: std::alloc::Layout,
         new_layout: std::alloc::Layout,
     ) -> Result<std::ptr::NonNull<[u8]>, std::alloc::AllocError> {
-        unsafe {
-            self.inner.shrink(ptr, old_layout, new_layout).inspect(|_| {
-                self.used.fetch_add(
-                    old_layout.size().unchecked_sub(new_layout.size()),
-                    std::sync::atomic::Ordering::AcqRel,
-                );
-            })
+        self.check_forbidden(new_layout, Some(old_layout.size()), true);
+        let old_usable = self.usable_size(ptr.as_ptr());
+        let moved = self.efence_resize(ptr.as_ptr(), old_layout, new_layout, |layout| {
+            self.inner.allocate(layout).map(std::ptr::NonNull::cast)
+        });
+        let result = match moved {
+            Some(result) => {
+                result.map(|ptr| std::ptr::NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+            }
+            None => unsafe { self.inner.shrink(ptr, old_layout, new_layout) },
+        };
+        if let Ok(new) = result {
+            self.on_resize(
+                ptr.as_ptr(),
+                new.cast::<u8>().as_ptr(),
+                old_layout,
+                new_layout,
+                old_usable,
+            );
         }
+        result
     }
 }
 
+#[cfg(feature = "std")]
 unsafe impl<T: GlobalAlloc> GlobalAlloc for LeakDetector<T> {
+    #[inline]
     #[track_caller]
     unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
-        let result = unsafe { self.inner.alloc(layout) };
-        self.used
-            .fetch_add(layout.size(), std::sync::atomic::Ordering::AcqRel);
+        self.check_forbidden(layout, None, false);
+        self.check_large(layout, None);
+        if !self.may_allocate(layout.size()) {
+            return std::ptr::null_mut();
+        }
+        let result = match self.efence_allocate(layout) {
+            Some(result) => result.map_or(std::ptr::null_mut(), std::ptr::NonNull::as_ptr),
+            None => unsafe { self.inner.alloc(layout) },
+        };
+        if !result.is_null() {
+            self.on_alloc(result, layout);
+        }
         result
     }
 
+    #[inline]
     #[track_caller]
     unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        self.check_forbidden(layout, None, true);
+        let usable = self.usable_size(ptr);
+        if self.efence_deallocate(ptr) {
+            self.on_dealloc(ptr, layout, usable);
+            return;
+        }
+        if self.quarantine.is_enabled() && layout.size() != 0 {
+            unsafe {
+                let ptr = std::ptr::NonNull::new_unchecked(ptr);
+                self.quarantine_block(ptr, layout, usable, quarantine::release_global::<T>);
+            }
+            return;
+        }
         unsafe {
             self.inner.dealloc(ptr, layout);
         }
-        self.used
-            .fetch_sub(layout.size(), std::sync::atomic::Ordering::AcqRel);
+        self.on_dealloc(ptr, layout, usable);
     }
 
+    #[inline]
     #[track_caller]
     unsafe fn alloc_zeroed(&self, layout: std::alloc::Layout) -> *mut u8 {
-        let result = unsafe { self.inner.alloc_zeroed(layout) };
-        self.used
-            .fetch_add(layout.size(), std::sync::atomic::Ordering::AcqRel);
+        self.check_forbidden(layout, None, false);
+        self.check_large(layout, None);
+        if !self.may_allocate(layout.size()) {
+            return std::ptr::null_mut();
+        }
+        let result = match self.efence_allocate(layout) {
+            Some(result) => result.map_or(std::ptr::null_mut(), std::ptr::NonNull::as_ptr),
+            None => unsafe { self.inner.alloc_zeroed(layout) },
+        };
+        if !result.is_null() {
+            self.on_alloc(result, layout);
+        }
         result
     }
 
+    #[inline]
     #[track_caller]
     unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
-        let result = unsafe { self.inner.realloc(ptr, layout, new_size) };
-        self.used.update(
-            std::sync::atomic::Ordering::Release,
-            std::sync::atomic::Ordering::Acquire,
-            |x| unsafe { x.unchecked_sub(layout.size()) } + new_size,
-        );
+        let new_layout =
+            unsafe { std::alloc::Layout::from_size_align_unchecked(new_size, layout.align()) };
+        self.check_forbidden(new_layout, Some(layout.size()), new_size < layout.size());
+        self.check_large(new_layout, Some(layout.size()));
+        if !self.may_allocate(new_size.saturating_sub(layout.size())) {
+            return std::ptr::null_mut();
+        }
+        let old_usable = self.usable_size(ptr);
+        let moved = self.efence_resize(ptr, layout, new_layout, |layout| {
+            std::ptr::NonNull::new(unsafe { self.inner.alloc(layout) })
+                .ok_or(std::alloc::AllocError)
+        });
+        let result = match moved {
+            Some(result) => result.map_or(std::ptr::null_mut(), std::ptr::NonNull::as_ptr),
+            None => unsafe { self.inner.realloc(ptr, layout, new_size) },
+        };
+        if !result.is_null() {
+            self.on_resize(ptr, result, layout, new_layout, old_usable);
+        }
         result
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> LeakDetector<T> {
+    #[track_caller]
+    pub fn check(&self) -> Result<(), LeakError> {
+        if let Some(err) = self.accounting_error() {
+            return Err(err);
+        }
+        let bytes = self.leaked_bytes();
+        if bytes == 0 {
+            return Ok(());
+        }
+        let bytes = bytes as isize;
+        Err(LeakError::Leaked {
+            bytes,
+            poisoned_by: self.record_failure(bytes, None, Location::caller()),
+        })
+    }
+
+    #[track_caller]
     pub fn assert(&self) {
-        assert!(self.used.load(std::sync::atomic::Ordering::Acquire) == 0);
+        if let Err(err) = self.check() {
+            self.failure_mode().fail(&err);
+        }
+    }
+
+    /// Bytes in use above the baseline that no suppression excuses, or 0
+    /// within the tolerance.
+    ///
+    /// With the registry this is exactly the live blocks allocated after the
+    /// baseline; without it, or sampling into it, the growth of `used` since
+    /// then.
+    pub(crate) fn leaked_bytes(&self) -> usize {
+        let bytes = self.retained_bytes();
+        if bytes <= self.tolerance() {
+            return 0;
+        }
+        let bytes = self.unexcused(bytes);
+        if bytes <= self.tolerance() { 0 } else { bytes }
+    }
+
+    /// Bytes in use above the baseline, counted as
+    /// [`leaked_bytes`](Self::leaked_bytes) does but before the tolerance and
+    /// suppressions.
+    pub(crate) fn retained_bytes(&self) -> usize {
+        let baseline = self.baseline_snapshot();
+        if self.registry.is_enabled() && self.sampler.get().is_none() {
+            self.registry.bytes_after(baseline.epoch)
+        } else {
+            self.get_used().saturating_sub(baseline.used)
+        }
+    }
+
+    fn unexcused(&self, leaked: usize) -> usize {
+        leaked.saturating_sub(self.suppressed_bytes())
+    }
+
+    pub(crate) fn record_failure(
+        &self,
+        bytes: isize,
+        scope_name: Option<&'static str>,
+        location: &'static Location<'static>,
+    ) -> Option<FirstFailure> {
+        self.poison.record(FirstFailure {
+            bytes,
+            scope_name,
+            location,
+            timestamp: std::time::SystemTime::now(),
+        })
+    }
+
+    /// Where reports should be written, from `MEM_LEAK_DETECTOR_REPORT_PATH`
+    /// or a config file's `report_path`.
+    #[cfg(any(feature = "env-config", feature = "config"))]
+    pub fn report_path(&self) -> Option<std::path::PathBuf> {
+        let path = self.report_path.lock();
+        // The bytes were taken from an `OsStr` by `set_report_path`.
+        let path = unsafe { std::ffi::OsStr::from_encoded_bytes_unchecked(path.as_deref()?) };
+        Some(path.into())
+    }
+
+    #[cfg(any(feature = "env-config", feature = "config"))]
+    pub(crate) fn set_report_path(&self, path: &std::path::Path) {
+        let mut stored = self.report_path.lock();
+        let mut bytes = Vec::new_in(overhead::Internal);
+        bytes.extend_from_slice(path.as_os_str().as_encoded_bytes());
+        *stored = Some(bytes);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Drop for LeakDetector<T> {
+    fn drop(&mut self) {
+        self.unwatch_threads();
+        self.flush_quarantine();
+        if !self.check_on_drop {
+            return;
+        }
+        if let Err(err) = self.check() {
+            let mode = self.failure_mode();
+            match self.on_leak() {
+                OnLeak::Panic if mode != FailureMode::Panic || !std::thread::panicking() => {
+                    mode.fail(&err)
+                }
+                OnLeak::Ignore => {}
+                _ => eprintln!("{err}"),
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::alloc::System;
+    use std::{
+        alloc::System,
+        panic::{AssertUnwindSafe, catch_unwind},
+    };
 
     use super::*;
 
+    use crate::mock::{FailWhen, FailingAlloc};
+
     static _GLOBAL: LeakDetector<System> = LeakDetector::system();
 
     #[test]
@@ -190,4 +960,88 @@ mod tests {
         drop((boxed1, boxed2, boxed3, boxed4));
         _GLOBAL.assert();
     }
+
+    #[test]
+    fn over_aligned_padding() {
+        let detector = LeakDetector::system();
+        let layout = std::alloc::Layout::from_size_align(1, 4096).unwrap();
+        let page = detector.allocate(layout).unwrap();
+        assert_eq!(detector.get_used(), 1);
+        assert_eq!(detector.padding_bytes(), 4095);
+        assert_eq!(detector.snapshot().padding_bytes, 4095);
+        let aligned = std::alloc::Layout::from_size_align(2048, 4096).unwrap();
+        let page = unsafe { detector.grow(page.cast(), layout, aligned) }.unwrap();
+        assert_eq!(detector.padding_bytes(), 2048);
+        unsafe { detector.deallocate(page.cast(), aligned) };
+        assert_eq!(detector.padding_bytes(), 0);
+
+        let boxed = Box::new_in(1u8, &detector);
+        assert_eq!(detector.padding_bytes(), 0);
+        drop(boxed);
+    }
+
+    #[test]
+    fn check_on_drop() {
+        let layout = std::alloc::Layout::new::<u64>();
+        let payload = catch_unwind(AssertUnwindSafe(|| {
+            let detector = LeakDetector::builder(System).check_on_drop(true).build();
+            detector.allocate(layout).unwrap();
+        }))
+        .unwrap_err();
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "8 bytes leaked");
+
+        let detector = LeakDetector::system();
+        detector.allocate(layout).unwrap();
+        drop(detector);
+    }
+
+    #[test]
+    fn shrink() {
+        let detector = LeakDetector::system();
+        let mut vec = Vec::<u8, _>::with_capacity_in(64, &detector);
+        vec.push(1);
+        vec.shrink_to_fit();
+        assert_eq!(detector.get_used(), 1);
+        assert_eq!(detector.get_peak(), 64);
+        drop(vec);
+        detector.assert();
+    }
+
+    #[test]
+    fn failed_allocations_are_not_counted() {
+        let detector = LeakDetector::builder(FailingAlloc::always())
+            .registry(true)
+            .build();
+        let layout = std::alloc::Layout::new::<u64>();
+        assert!(unsafe { detector.alloc(layout) }.is_null());
+        assert!(unsafe { detector.alloc_zeroed(layout) }.is_null());
+        assert!(detector.allocate(layout).is_err());
+        assert_eq!(detector.get_used(), 0);
+        assert_eq!(detector.snapshot().allocations, 0);
+        detector.assert();
+    }
+
+    #[test]
+    fn failed_reallocations_keep_the_old_block() {
+        let detector = LeakDetector::builder(FailingAlloc::new(System, FailWhen::Above(64)))
+            .registry(true)
+            .build();
+        let small = std::alloc::Layout::from_size_align(16, 8).unwrap();
+        let large = std::alloc::Layout::from_size_align(128, 8).unwrap();
+
+        let ptr = unsafe { detector.alloc(small) };
+        assert!(unsafe { detector.realloc(ptr, small, large.size()) }.is_null());
+        assert_eq!(detector.get_used(), 16);
+        unsafe { detector.dealloc(ptr, small) };
+
+        let block = detector.allocate(small).unwrap().cast();
+        assert!(unsafe { detector.grow(block, small, large) }.is_err());
+        assert!(unsafe { detector.grow_zeroed(block, small, large) }.is_err());
+        assert_eq!(detector.get_used(), 16);
+        assert_eq!(detector.leak_report().allocations().len(), 1);
+        unsafe { detector.deallocate(block, small) };
+
+        detector.assert();
+        assert!(detector.diagnostics().is_empty());
+    }
 }
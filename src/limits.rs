@@ -0,0 +1,147 @@
+use std::ops::RangeInclusive;
+
+use crate::{LeakDetector, LeakError};
+
+impl<T> LeakDetector<T> {
+    pub fn check_used_le(&self, max: usize) -> Result<(), LeakError> {
+        let used = self.get_used();
+        if used <= max {
+            Ok(())
+        } else {
+            Err(LeakError::UsedAbove { used, max })
+        }
+    }
+
+    pub fn check_within(&self, range: RangeInclusive<usize>) -> Result<(), LeakError> {
+        let used = self.get_used();
+        if range.contains(&used) {
+            Ok(())
+        } else {
+            Err(LeakError::OutOfRange { used, range })
+        }
+    }
+
+    /// Checks that what is in use above the baseline, as
+    /// [`check`](LeakDetector::check) counts it before the tolerance and
+    /// suppressions, falls in `range`: for testing that a cache or pool did
+    /// keep memory, and no more than it should. Returns the retained bytes.
+    pub fn check_retained(&self, range: RangeInclusive<usize>) -> Result<usize, LeakError> {
+        let retained = self.retained_bytes();
+        if range.contains(&retained) {
+            Ok(retained)
+        } else {
+            Err(LeakError::RetainedOutOfRange {
+                retained: retained as isize,
+                expected: range,
+            })
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_leaked_at_least(&self, bytes: usize) {
+        if let Err(err) = self.check_retained(bytes..=usize::MAX) {
+            self.failure_mode().fail(&err);
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_used_le(&self, max: usize) {
+        if let Err(err) = self.check_used_le(max) {
+            self.failure_mode().fail(&err);
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_within(&self, range: RangeInclusive<usize>) {
+        if let Err(err) = self.check_within(range) {
+            self.failure_mode().fail(&err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        panic::{AssertUnwindSafe, catch_unwind},
+        sync::Once,
+    };
+
+    use super::*;
+
+    thread_local! {
+        static PANIC_SITE: Cell<Option<(String, u32)>> = const { Cell::new(None) };
+    }
+
+    fn panic_site(f: impl FnOnce()) -> (String, String, u32) {
+        static HOOK: Once = Once::new();
+        HOOK.call_once(|| {
+            let previous = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                if let Some(location) = info.location() {
+                    PANIC_SITE.set(Some((location.file().to_owned(), location.line())));
+                }
+                previous(info);
+            }));
+        });
+        let payload = catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+        let (file, line) = PANIC_SITE.take().unwrap();
+        (*payload.downcast::<String>().unwrap(), file, line)
+    }
+
+    #[test]
+    fn used_le() {
+        let detector = LeakDetector::system();
+        let buffer = Vec::<u8, _>::with_capacity_in(100, &detector);
+        detector.check_used_le(100).unwrap();
+        assert_eq!(
+            detector.check_used_le(60),
+            Err(LeakError::UsedAbove { used: 100, max: 60 })
+        );
+        let (message, file, line) = panic_site(|| detector.assert_used_le(60));
+        assert_eq!(
+            message,
+            "100 bytes used, exceeding the limit of 60 bytes by 40 bytes"
+        );
+        assert_eq!((file.as_str(), line), (file!(), line!() - 5));
+        drop(buffer);
+    }
+
+    #[test]
+    fn within() {
+        let detector = LeakDetector::system();
+        let buffer = Vec::<u8, _>::with_capacity_in(100, &detector);
+        detector.check_within(50..=100).unwrap();
+        let (message, file, line) = panic_site(|| detector.assert_within(120..=200));
+        assert_eq!(
+            message,
+            "100 bytes used, below the expected 120..=200 bytes by 20 bytes"
+        );
+        assert_eq!((file.as_str(), line), (file!(), line!() - 5));
+        assert_eq!(
+            detector.check_within(0..=10).unwrap_err().to_string(),
+            "100 bytes used, above the expected 0..=10 bytes by 90 bytes"
+        );
+        drop(buffer);
+    }
+
+    #[test]
+    fn retained() {
+        let detector = LeakDetector::system();
+        detector.capture_baseline();
+        let cache = Vec::<u8, _>::with_capacity_in(256, &detector);
+        assert_eq!(detector.check_retained(200..=300), Ok(256));
+        detector.assert_leaked_at_least(256);
+        let (message, file, line) = panic_site(|| detector.assert_leaked_at_least(1000));
+        assert_eq!(
+            message,
+            "retained 256 bytes, expected at least 1000 bytes, short by 744 bytes"
+        );
+        assert_eq!((file.as_str(), line), (file!(), line!() - 5));
+        drop(cache);
+        assert_eq!(
+            detector.check_retained(1..=512).unwrap_err().to_string(),
+            "retained 0 bytes, less than the expected 1..=512 bytes by 1 bytes"
+        );
+    }
+}
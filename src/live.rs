@@ -0,0 +1,175 @@
+use std::{panic::Location, time::Duration};
+
+use crate::{LeakDetector, StackId};
+#[cfg(feature = "backtrace")]
+use crate::{
+    Symbol,
+    stack::{Backend, StackCapture},
+};
+
+/// One block in the registry, as [`LeakDetector::iter_live`] found it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveAllocation {
+    /// See [`LeakedAllocation::id`](crate::LeakedAllocation::id).
+    pub id: u64,
+    pub address: usize,
+    pub size: usize,
+    pub align: usize,
+    /// The detector's epoch when the block was allocated. Snapshots and
+    /// scopes each start a new one.
+    pub epoch: u64,
+    /// A small number for the thread that allocated the block, the same
+    /// for all blocks allocated on one thread.
+    pub thread: u64,
+    /// The name of the innermost scope open on the allocating thread, if it
+    /// was named.
+    pub scope: Option<&'static str>,
+    /// By the detector's clock, see
+    /// [`LeakedAllocation::age`](crate::LeakedAllocation::age).
+    pub age: Duration,
+    pub callsite: &'static Location<'static>,
+    pub stack: Option<StackId>,
+}
+
+/// What [`LeakDetector::live_matching`] adds up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LiveTotals {
+    pub bytes: usize,
+    pub allocations: usize,
+}
+
+impl LiveAllocation {
+    /// The frames of the block's stack, innermost first, each with the
+    /// symbols its address resolves to. Empty without a stack. Slow and
+    /// allocating, as [`LeakReport::symbolize`](crate::LeakReport::symbolize)
+    /// is.
+    #[cfg(feature = "backtrace")]
+    pub fn frames<T>(&self, detector: &LeakDetector<T>) -> Vec<Vec<Symbol>> {
+        let Some(stack) = self.stack.and_then(|id| detector.registry.stacks.get(id)) else {
+            return Vec::new();
+        };
+        if let Some(frames) = Backend::resolved(&stack) {
+            return frames.to_vec();
+        }
+        #[cfg(feature = "backtrace-crate")]
+        return Backend::ips(&stack)
+            .iter()
+            .map(|&ip| crate::report::resolve(ip))
+            .collect();
+        #[cfg(not(feature = "backtrace-crate"))]
+        Vec::new()
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Every block in the registry, by address; nothing when it's off.
+    ///
+    /// The entries are copied out under the registry's lock before the first
+    /// one is yielded, so other threads may allocate and free meanwhile and
+    /// the caller may allocate through the detector while iterating. The
+    /// copy, about 120 bytes per block, comes from `System` and isn't
+    /// tracked.
+    pub fn iter_live(&self) -> impl Iterator<Item = LiveAllocation> + '_ {
+        let now = self.registry.clock();
+        self.registry
+            .entries(0)
+            .into_iter()
+            .map(move |(address, entry)| LiveAllocation {
+                id: entry.id,
+                address,
+                size: entry.size,
+                align: entry.align,
+                epoch: entry.epoch,
+                thread: entry.thread,
+                scope: entry.scope.and_then(|scope| scope.name),
+                age: Duration::from_millis(now.saturating_sub(entry.born)),
+                callsite: entry.callsite,
+                stack: entry.stack,
+            })
+    }
+
+    /// The bytes and number of the blocks in [`iter_live`] that `predicate`
+    /// accepts.
+    ///
+    /// [`iter_live`]: LeakDetector::iter_live
+    pub fn live_matching(&self, mut predicate: impl FnMut(&LiveAllocation) -> bool) -> LiveTotals {
+        self.iter_live()
+            .filter(|allocation| predicate(allocation))
+            .fold(LiveTotals::default(), |totals, allocation| LiveTotals {
+                bytes: totals.bytes + allocation.size,
+                allocations: totals.allocations + 1,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout, System};
+
+    use super::*;
+
+    #[test]
+    fn finds_tagged_blocks() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let untagged = Box::new_in(0u64, &detector);
+        let epoch = detector.snapshot().epoch;
+        detector.advance_clock(Duration::from_secs(5));
+        let layouts = [
+            Layout::from_size_align(24, 8).unwrap(),
+            Layout::from_size_align(40, 64).unwrap(),
+            Layout::from_size_align(3, 1).unwrap(),
+        ];
+        let mut scope = detector.scope().named("cache");
+        let blocks = layouts.map(|layout| detector.allocate(layout).unwrap().cast::<u8>());
+        scope.defuse();
+        drop(scope);
+        detector.advance_clock(Duration::from_secs(2));
+
+        let tagged: Vec<_> = detector
+            .iter_live()
+            .filter(|allocation| allocation.scope == Some("cache"))
+            .collect();
+        assert_eq!(tagged.len(), 3);
+        for (block, layout) in blocks.iter().zip(layouts) {
+            let allocation = tagged
+                .iter()
+                .find(|allocation| allocation.address == block.as_ptr() as usize)
+                .unwrap();
+            assert_eq!(allocation.size, layout.size());
+            assert_eq!(allocation.align, layout.align());
+            assert!(allocation.epoch > epoch);
+            assert_eq!(allocation.age, Duration::from_secs(2));
+            assert_eq!(allocation.stack, None);
+        }
+        let mut ids: Vec<_> = tagged.iter().map(|allocation| allocation.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 3);
+        assert!(
+            tagged
+                .iter()
+                .all(|allocation| allocation.thread == tagged[0].thread)
+        );
+
+        assert_eq!(
+            detector.live_matching(|allocation| allocation.scope == Some("cache")),
+            LiveTotals {
+                bytes: 67,
+                allocations: 3
+            }
+        );
+        assert_eq!(
+            detector.live_matching(|allocation| allocation.scope.is_none()),
+            LiveTotals {
+                bytes: 8,
+                allocations: 1
+            }
+        );
+
+        for (block, layout) in blocks.into_iter().zip(layouts) {
+            unsafe { detector.deallocate(block, layout) };
+        }
+        drop(untagged);
+        assert_eq!(detector.iter_live().count(), 0);
+    }
+}
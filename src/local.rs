@@ -0,0 +1,802 @@
+//! [`LocalLeakDetector`], the detector for one thread: the same accounting
+//! as [`LeakDetector`] kept in plain cells, for loops where even relaxed
+//! atomics show up and for allocators that aren't `Sync`.
+
+use std::{
+    alloc::{AllocError, Allocator, Layout, System},
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    ops::RangeInclusive,
+    panic::Location,
+    ptr::NonNull,
+};
+
+#[cfg(feature = "backtrace")]
+use crate::stack::{self, StackCapture};
+use crate::{
+    FailureMode, FirstFailure, LeakDetector, LeakError, LeakReport, OnLeak, Overhead,
+    ReportOptions, ScopeLeak, Snapshot, StackId, alignment_padding, budget,
+    counters::Counters,
+    ledger::Ledger,
+    overhead::{Account, Internal},
+    registry::Entry,
+    stack::StackTable,
+    suppress::{Pattern, Suppression},
+};
+
+/// A leak detector whose counters are [`Cell`]s instead of atomics. It is
+/// `Send` but not `Sync`: share it between the collections of one thread by
+/// reference or through an `Rc`.
+///
+/// It can't be a `#[global_allocator]`, which every thread allocates from;
+/// use a [`LeakDetector`] there.
+pub struct LocalLeakDetector<T> {
+    inner: T,
+    counters: Counters<Cell<usize>>,
+    baseline: Cell<Snapshot>,
+    tolerance: Cell<usize>,
+    on_leak: Cell<OnLeak>,
+    failure_mode: Cell<FailureMode>,
+    first_failure: Cell<Option<FirstFailure>>,
+    repanic: Cell<bool>,
+    suppressions: RefCell<Vec<Suppression, Internal>>,
+    /// Every how many allocations records a stack, 0 for none.
+    backtrace_every: Cell<usize>,
+    stacks: StackTable,
+    report_options: Cell<ReportOptions>,
+    /// Moves on with every snapshot, as the registry's epoch does.
+    epoch: Cell<u64>,
+    /// The id the next allocation gets.
+    next_id: Cell<u64>,
+    /// What the registry holds from [`Internal`].
+    overhead: Account,
+    /// What the suppressions hold.
+    other: Account,
+    /// Live allocations by address, `None` without the registry.
+    registry: Option<RefCell<BTreeMap<usize, Entry, Internal>>>,
+}
+
+impl<T> LeakDetector<T> {
+    /// A detector over `inner` for a single thread, see
+    /// [`LocalLeakDetector`].
+    pub const fn local(inner: T) -> LocalLeakDetector<T> {
+        LocalLeakDetector::new(inner)
+    }
+}
+
+impl<T> LocalLeakDetector<T> {
+    pub const fn new(inner: T) -> Self {
+        Self::with(inner, None)
+    }
+
+    /// A detector that keeps every live allocation and where it was made,
+    /// for [`leak_report`](Self::leak_report).
+    pub const fn with_registry(inner: T) -> Self {
+        Self::with(inner, Some(RefCell::new(BTreeMap::new_in(Internal))))
+    }
+
+    const fn with(inner: T, registry: Option<RefCell<BTreeMap<usize, Entry, Internal>>>) -> Self {
+        Self {
+            inner,
+            counters: Counters::local(),
+            baseline: Cell::new(Snapshot::ZERO),
+            tolerance: Cell::new(0),
+            on_leak: Cell::new(OnLeak::Panic),
+            failure_mode: Cell::new(FailureMode::Panic),
+            first_failure: Cell::new(None),
+            repanic: Cell::new(true),
+            suppressions: RefCell::new(Vec::new_in(Internal)),
+            backtrace_every: Cell::new(0),
+            stacks: StackTable::new(),
+            report_options: Cell::new(ReportOptions::DEFAULT),
+            epoch: Cell::new(1),
+            next_id: Cell::new(1),
+            overhead: Account::new(),
+            other: Account::new(),
+            registry,
+        }
+    }
+
+    pub fn get_used(&self) -> usize {
+        self.counters.used()
+    }
+
+    pub fn get_peak(&self) -> usize {
+        self.counters.peak()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.read_snapshot()
+    }
+
+    /// See [`LeakDetector::reset_to`].
+    pub fn reset_to(&self, snap: &Snapshot) {
+        self.baseline.set(*snap);
+        self.counters.reset_peak();
+    }
+
+    pub fn rebaseline(&self) {
+        self.reset_to(&self.snapshot());
+    }
+
+    /// See [`LeakDetector::capture_baseline`].
+    pub fn capture_baseline(&self) -> Snapshot {
+        let snap = self.snapshot();
+        self.reset_to(&snap);
+        snap
+    }
+
+    /// Bytes in use at the baseline.
+    pub fn baseline(&self) -> usize {
+        self.baseline.get().used
+    }
+
+    pub fn since_baseline(&self) -> Snapshot {
+        self.snapshot().since(&self.baseline.get())
+    }
+
+    /// Lets [`check`](Self::check) pass with up to `bytes` in use above the
+    /// baseline.
+    pub fn set_tolerance(&self, bytes: usize) {
+        self.tolerance.set(bytes);
+    }
+
+    pub fn tolerance(&self) -> usize {
+        self.tolerance.get()
+    }
+
+    pub fn on_leak(&self) -> OnLeak {
+        self.on_leak.get()
+    }
+
+    /// Sets the policy of scopes that don't choose their own.
+    pub fn set_on_leak(&self, on_leak: OnLeak) {
+        self.on_leak.set(on_leak);
+    }
+
+    pub fn failure_mode(&self) -> FailureMode {
+        self.failure_mode.get()
+    }
+
+    /// Sets how failed checks stop the program, for scopes that don't choose
+    /// with [`LocalLeakDetectorScope::failure_mode`].
+    pub fn set_failure_mode(&self, mode: FailureMode) {
+        self.failure_mode.set(mode);
+    }
+
+    pub fn report_options(&self) -> ReportOptions {
+        self.report_options.get()
+    }
+
+    pub fn set_report_options(&self, options: ReportOptions) {
+        self.report_options.set(options);
+    }
+
+    /// Bytes in use above the baseline: with the registry, exactly the live
+    /// blocks allocated after it.
+    fn retained_bytes(&self) -> usize {
+        let baseline = self.baseline.get();
+        match &self.registry {
+            Some(registry) => registry
+                .borrow()
+                .values()
+                .filter(|entry| entry.epoch > baseline.epoch)
+                .map(|entry| entry.size)
+                .sum(),
+            None => self.get_used().saturating_sub(baseline.used),
+        }
+    }
+
+    /// Fails when more than the tolerance is in use above the baseline,
+    /// less what the suppressions excuse.
+    #[track_caller]
+    pub fn check(&self) -> Result<(), LeakError> {
+        let bytes = self.retained_bytes();
+        if bytes <= self.tolerance() {
+            return Ok(());
+        }
+        let bytes = bytes.saturating_sub(self.suppressed_bytes());
+        if bytes <= self.tolerance() {
+            return Ok(());
+        }
+        let bytes = bytes as isize;
+        Err(LeakError::Leaked {
+            bytes,
+            poisoned_by: self.record_failure(bytes, None, Location::caller()),
+        })
+    }
+
+    #[track_caller]
+    pub fn assert(&self) {
+        if let Err(err) = self.check() {
+            self.failure_mode().fail(&err);
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.first_failure.get().is_some()
+    }
+
+    pub fn first_failure(&self) -> Option<FirstFailure> {
+        self.first_failure.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.first_failure.set(None);
+    }
+
+    /// See [`LeakDetector::set_repanic_when_poisoned`].
+    pub fn set_repanic_when_poisoned(&self, repanic: bool) {
+        self.repanic.set(repanic);
+    }
+
+    /// Records the first failure, or returns the one recorded before.
+    fn record_failure(
+        &self,
+        bytes: isize,
+        scope_name: Option<&'static str>,
+        location: &'static Location<'static>,
+    ) -> Option<FirstFailure> {
+        let earlier = self.first_failure.get();
+        if earlier.is_none() {
+            self.first_failure.set(Some(FirstFailure {
+                bytes,
+                scope_name,
+                location,
+                timestamp: std::time::SystemTime::now(),
+            }));
+        }
+        earlier
+    }
+
+    /// See [`LeakDetector::add_symbol_suppression`].
+    #[cfg(feature = "backtrace")]
+    pub fn add_symbol_suppression(&self, pattern: &str) {
+        self.add_suppression(Suppression::Symbol(Pattern::new(pattern)));
+    }
+
+    /// See [`LeakDetector::add_callsite_suppression`].
+    pub fn add_callsite_suppression(&self, pattern: &str) {
+        self.add_suppression(Suppression::Callsite(Pattern::new(pattern)));
+    }
+
+    pub fn add_size_suppression(&self, sizes: RangeInclusive<usize>) {
+        self.add_suppression(Suppression::Size(sizes));
+    }
+
+    pub fn expect_leak_by_id(&self, id: u64) {
+        self.add_suppression(Suppression::Id(id));
+    }
+
+    fn add_suppression(&self, suppression: Suppression) {
+        let _charge = self.other.charge();
+        self.suppressions.borrow_mut().push(suppression.clone());
+    }
+
+    fn suppressed_bytes(&self) -> usize {
+        if self.suppressions.borrow().is_empty() {
+            return 0;
+        }
+        self.leak_report().suppressed_bytes()
+    }
+
+    /// See [`LeakDetector::set_backtrace_sampling`].
+    #[cfg(feature = "backtrace")]
+    pub fn set_backtrace_sampling(&self, n: usize) {
+        self.backtrace_every.set(n);
+    }
+
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace_sampling(&self) -> usize {
+        self.backtrace_every.get()
+    }
+
+    /// Distinct stacks recorded so far.
+    pub fn unique_stacks(&self) -> usize {
+        self.stacks.len()
+    }
+
+    pub fn registry_enabled(&self) -> bool {
+        self.registry.is_some()
+    }
+
+    /// Number of live allocations in the registry; zero-sized allocations
+    /// aren't kept there.
+    pub fn live_allocations(&self) -> usize {
+        self.registry
+            .as_ref()
+            .map_or(0, |registry| registry.borrow().len())
+    }
+
+    /// What the registry holds, see [`LeakDetector::self_overhead`].
+    pub fn self_overhead(&self) -> Overhead {
+        Ledger::self_overhead(self)
+    }
+
+    /// Every allocation made since the baseline that is still live in the
+    /// registry; empty when the registry is off.
+    pub fn leak_report(&self) -> LeakReport {
+        let mut report = self.report_after(self.baseline.get().epoch, self.report_options());
+        report.add_stacks(&self.stacks);
+        report.backtrace_sampling = self.backtrace_every.get();
+        report.suppress(&self.suppressions.borrow());
+        report
+    }
+
+    #[track_caller]
+    pub fn scope(&self) -> LocalLeakDetectorScope<'_, T> {
+        LocalLeakDetectorScope {
+            detector: self,
+            start: self.get_used(),
+            epoch: self.advance_epoch(),
+            name: None,
+            location: Location::caller(),
+            max_delta: None,
+            expected_retention: None,
+            on_leak: None,
+            failure_mode: None,
+            budget: false,
+            defused: false,
+        }
+    }
+
+    /// See [`LeakDetector::scope_with_budget`].
+    #[track_caller]
+    pub fn scope_with_budget(&self, bytes: usize) -> LocalLeakDetectorScope<'_, T> {
+        self.scope().with_budget(bytes)
+    }
+
+    fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    #[track_caller]
+    pub fn scope_with<F: FnOnce<Args, Output = R>, Args: std::marker::Tuple, R>(
+        &self,
+        f: F,
+        args: Args,
+    ) -> R {
+        let _guard = self.scope();
+        f.call_once(args)
+    }
+
+    #[track_caller]
+    fn on_alloc(&self, ptr: *mut u8, layout: Layout) {
+        self.counters.alloc(layout.size());
+        self.counters.pad(0, alignment_padding(layout));
+        budget::charge(self.address(), 0, layout.size());
+        if let Some(registry) = &self.registry
+            && layout.size() != 0
+        {
+            let entry = self.new_entry(layout);
+            let _charge = self.overhead.charge();
+            registry.borrow_mut().insert(ptr as usize, entry);
+        }
+    }
+
+    #[track_caller]
+    fn new_entry(&self, layout: Layout) -> Entry {
+        Entry {
+            id: self.next_id.replace(self.next_id.get() + 1),
+            size: layout.size(),
+            align: layout.align(),
+            usable: 0,
+            padding: alignment_padding(layout),
+            thread: 0,
+            scope: None,
+            epoch: self.epoch.get(),
+            born: 0,
+            callsite: Location::caller(),
+            stack: self.capture_stack(),
+        }
+    }
+
+    #[cfg(feature = "backtrace")]
+    fn capture_stack(&self) -> Option<StackId> {
+        if !stack::sample(self.backtrace_every.get()) {
+            return None;
+        }
+        stack::Backend::capture().and_then(|stack| self.stacks.intern(stack))
+    }
+
+    #[cfg(not(feature = "backtrace"))]
+    fn capture_stack(&self) -> Option<StackId> {
+        None
+    }
+
+    fn on_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(registry) = &self.registry
+            && layout.size() != 0
+        {
+            let _charge = self.overhead.charge();
+            registry.borrow_mut().remove(&(ptr as usize));
+        }
+        self.counters.dealloc(layout.size());
+        self.counters.pad(alignment_padding(layout), 0);
+        budget::charge(self.address(), layout.size(), 0);
+    }
+
+    #[track_caller]
+    fn on_resize(&self, old_ptr: *mut u8, new_ptr: *mut u8, old: Layout, new: Layout) {
+        if let Some(registry) = &self.registry {
+            let _charge = self.overhead.charge();
+            let mut entries = registry.borrow_mut();
+            let entry = entries.remove(&(old_ptr as usize));
+            if new.size() != 0 {
+                let entry = match entry {
+                    Some(entry) => Entry {
+                        size: new.size(),
+                        align: new.align(),
+                        padding: alignment_padding(new),
+                        ..entry
+                    },
+                    None => self.new_entry(new),
+                };
+                entries.insert(new_ptr as usize, entry);
+            }
+        }
+        self.counters.realloc(old.size(), new.size());
+        self.counters
+            .pad(alignment_padding(old), alignment_padding(new));
+        budget::charge(self.address(), old.size(), new.size());
+    }
+}
+
+impl<T> Ledger for LocalLeakDetector<T> {
+    type Counter = Cell<usize>;
+
+    fn counters(&self) -> &Counters<Cell<usize>> {
+        &self.counters
+    }
+
+    fn advance_epoch(&self) -> u64 {
+        self.epoch.replace(self.epoch.get() + 1)
+    }
+
+    fn entries(&self, after: u64) -> Vec<(usize, Entry), System> {
+        let mut entries = Vec::new_in(System);
+        if let Some(registry) = &self.registry {
+            entries.extend(
+                registry
+                    .borrow()
+                    .iter()
+                    .filter(|(_, entry)| entry.epoch > after)
+                    .map(|(&ptr, &entry)| (ptr, entry)),
+            );
+        }
+        entries
+    }
+
+    fn clock(&self) -> u64 {
+        0
+    }
+
+    fn self_overhead(&self) -> Overhead {
+        let stacks = self.stacks.account();
+        Overhead {
+            registry: self.overhead.bytes(),
+            stacks: stacks.bytes(),
+            other: self.other.bytes(),
+            blocks: self.overhead.blocks() + stacks.blocks() + self.other.blocks(),
+            ..Overhead::default()
+        }
+    }
+}
+
+unsafe impl<T: Allocator> Allocator for LocalLeakDetector<T> {
+    #[inline]
+    #[track_caller]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !budget::within(self.address(), layout.size()) {
+            return Err(AllocError);
+        }
+        let ptr = self.inner.allocate(layout)?;
+        self.on_alloc(ptr.cast().as_ptr(), layout);
+        Ok(ptr)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !budget::within(self.address(), layout.size()) {
+            return Err(AllocError);
+        }
+        let ptr = self.inner.allocate_zeroed(layout)?;
+        self.on_alloc(ptr.cast().as_ptr(), layout);
+        Ok(ptr)
+    }
+
+    #[inline]
+    #[track_caller]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.inner.deallocate(ptr, layout) };
+        self.on_dealloc(ptr.as_ptr(), layout);
+    }
+
+    #[inline]
+    #[track_caller]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !budget::within(self.address(), new_layout.size() - old_layout.size()) {
+            return Err(AllocError);
+        }
+        let new = unsafe { self.inner.grow(ptr, old_layout, new_layout) }?;
+        self.on_resize(ptr.as_ptr(), new.cast().as_ptr(), old_layout, new_layout);
+        Ok(new)
+    }
+
+    #[inline]
+    #[track_caller]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !budget::within(self.address(), new_layout.size() - old_layout.size()) {
+            return Err(AllocError);
+        }
+        let new = unsafe { self.inner.grow_zeroed(ptr, old_layout, new_layout) }?;
+        self.on_resize(ptr.as_ptr(), new.cast().as_ptr(), old_layout, new_layout);
+        Ok(new)
+    }
+
+    #[inline]
+    #[track_caller]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = unsafe { self.inner.shrink(ptr, old_layout, new_layout) }?;
+        self.on_resize(ptr.as_ptr(), new.cast().as_ptr(), old_layout, new_layout);
+        Ok(new)
+    }
+}
+
+/// A [`LeakDetectorScope`](crate::LeakDetectorScope) for a
+/// [`LocalLeakDetector`], checked the same way when dropped.
+pub struct LocalLeakDetectorScope<'a, T> {
+    detector: &'a LocalLeakDetector<T>,
+    start: usize,
+    epoch: u64,
+    name: Option<&'static str>,
+    location: &'static Location<'static>,
+    max_delta: Option<usize>,
+    expected_retention: Option<RangeInclusive<usize>>,
+    on_leak: Option<OnLeak>,
+    failure_mode: Option<FailureMode>,
+    budget: bool,
+    defused: bool,
+}
+
+impl<T> LocalLeakDetectorScope<'_, T> {
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Lets the scope end with up to `max` more bytes in use than it started
+    /// with, instead of requiring an exact balance.
+    pub fn with_max_delta(mut self, max: usize) -> Self {
+        self.max_delta = Some(max);
+        self
+    }
+
+    /// Expects the scope to end holding more memory than it started with,
+    /// by an amount in `range`.
+    pub fn expect_retained(mut self, range: RangeInclusive<usize>) -> Self {
+        self.expected_retention = Some(range);
+        self
+    }
+
+    /// Overrides the detector's [`OnLeak`] policy for this scope.
+    pub fn on_leak(mut self, on_leak: OnLeak) -> Self {
+        self.on_leak = Some(on_leak);
+        self
+    }
+
+    /// Fails allocations made in the scope once they come to more than
+    /// `bytes`, see [`LeakDetector::scope_with_budget`].
+    pub fn with_budget(mut self, bytes: usize) -> Self {
+        if !self.budget {
+            self.budget = true;
+            budget::push(self.detector.address(), self.epoch, bytes);
+        }
+        self
+    }
+
+    /// Overrides the detector's [`FailureMode`] for this scope.
+    pub fn failure_mode(mut self, mode: FailureMode) -> Self {
+        self.failure_mode = Some(mode);
+        self
+    }
+
+    /// Disarms the scope: it won't check anything when dropped.
+    pub fn defuse(&mut self) {
+        self.defused = true;
+    }
+}
+
+impl<T> Drop for LocalLeakDetectorScope<'_, T> {
+    fn drop(&mut self) {
+        if self.budget {
+            budget::pop(self.detector.address(), self.epoch);
+        }
+        if !cfg!(debug_assertions) || self.defused {
+            return;
+        }
+        let bytes = self.detector.get_used().wrapping_sub(self.start) as isize;
+        let balanced = match (&self.expected_retention, self.max_delta) {
+            (Some(expected), _) => {
+                usize::try_from(bytes).is_ok_and(|bytes| expected.contains(&bytes))
+            }
+            (None, Some(max)) => bytes <= max as isize,
+            (None, None) => bytes == 0,
+        };
+        if balanced {
+            return;
+        }
+        let mut on_leak = self.on_leak.unwrap_or_else(|| self.detector.on_leak());
+        let expectation = self.max_delta.is_some() || self.expected_retention.is_some();
+        let poisoned_by = match (on_leak, expectation) {
+            (OnLeak::Ignore, _) | (_, true) => None,
+            _ => self
+                .detector
+                .record_failure(bytes, self.name, self.location),
+        };
+        if poisoned_by.is_some()
+            && !self.detector.repanic.get()
+            && let OnLeak::Panic = on_leak
+        {
+            on_leak = OnLeak::Log;
+        }
+        on_leak.apply(
+            &ScopeLeak {
+                scope_name: self.name,
+                location: self.location,
+                bytes,
+                max_delta: self.max_delta,
+                expected_retention: self.expected_retention.clone(),
+                waited: None,
+                count: None,
+                poisoned_by,
+                enclosing_scopes: Vec::new(),
+                attribution: Vec::new(),
+                allocations: self.detector.allocations_after(self.epoch),
+                unclosed: false,
+            },
+            self.failure_mode
+                .unwrap_or_else(|| self.detector.failure_mode()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn counts_through_rc() {
+        let detector = Rc::new(LocalLeakDetector::with_registry(System));
+        let mut v = Vec::new_in(Rc::clone(&detector));
+        v.extend(0..100u32);
+        v.shrink_to_fit();
+        assert_eq!(detector.get_used(), 400);
+        assert_eq!(detector.live_allocations(), 1);
+        let report = detector.leak_report();
+        assert_eq!(report.bytes(), 400);
+        assert!(detector.check().is_err());
+        drop(v);
+        assert_eq!(detector.get_used(), 0);
+        assert_eq!(detector.snapshot().deallocations, 1);
+        assert!(detector.check().is_ok());
+    }
+
+    #[test]
+    fn reports_since_the_baseline() {
+        let detector = LocalLeakDetector::with_registry(System);
+        let early = Box::new_in([0u8; 32], &detector);
+        detector.capture_baseline();
+        let word = Layout::new::<u64>();
+        let late = detector.allocate(word).unwrap();
+        let text = detector.leak_report().to_string();
+        assert!(text.starts_with("8 bytes leaked"), "{text}");
+        assert!(text.contains("src/local.rs"), "{text}");
+        assert_eq!(
+            detector.check(),
+            Err(LeakError::Leaked {
+                bytes: 8,
+                poisoned_by: None
+            })
+        );
+        detector.set_tolerance(8);
+        detector.assert();
+        unsafe { detector.deallocate(late.cast(), word) };
+        drop(early);
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn scopes() {
+        let detector = LocalLeakDetector::new(System);
+        detector.scope_with(|| drop(Box::new_in(1u8, &detector)), ());
+        let leaked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _scope = detector.scope().named("loop");
+            std::mem::forget(Box::new_in(0u32, &detector));
+        }))
+        .unwrap_err();
+        let message = leaked.downcast_ref::<String>().unwrap();
+        assert!(message.starts_with("scope 'loop' created at "), "{message}");
+        assert!(message.ends_with(" leaked 4 bytes"), "{message}");
+        let scope = detector.scope().with_max_delta(4);
+        let kept = Box::new_in(0u32, &detector);
+        drop(scope);
+        drop(kept);
+    }
+
+    #[test]
+    fn poisons_and_suppresses() {
+        let detector = LocalLeakDetector::with_registry(System);
+        let leaked = Box::new_in([0u8; 16], &detector);
+        let err = detector.check().unwrap_err();
+        assert!(!err.to_string().contains("already poisoned"), "{err}");
+        let err = detector.check().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("detector already poisoned by 16 bytes leaked at src/local.rs"),
+            "{err}"
+        );
+        detector.clear_poison();
+        assert!(!detector.is_poisoned());
+
+        detector.add_size_suppression(16..=16);
+        detector.check().unwrap();
+        assert_eq!(detector.leak_report().suppressed().len(), 1);
+        assert_ne!(detector.self_overhead().other, 0);
+        drop(leaked);
+    }
+
+    #[test]
+    fn fails_allocations_past_budget() {
+        let detector = LocalLeakDetector::new(System);
+        let other = LocalLeakDetector::new(System);
+        {
+            let _scope = detector.scope_with_budget(1024);
+            let mut buffer = Vec::<u8, _>::with_capacity_in(512, &detector);
+            assert!(buffer.try_reserve_exact(2048).is_err());
+            buffer.try_reserve_exact(1024).unwrap();
+            assert!(Box::try_new_in(0u8, &detector).is_err());
+            assert!(Vec::<u8, _>::try_with_capacity_in(2048, &other).is_ok());
+        }
+        detector.assert();
+        drop(Vec::<u8, _>::with_capacity_in(4096, &detector));
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[test]
+    fn records_stacks() {
+        let detector = LocalLeakDetector::with_registry(System);
+        detector.set_backtrace_sampling(1);
+        let boxes: Vec<_> = (0..100).map(|i| Box::new_in(i, &detector)).collect();
+        assert_eq!(detector.unique_stacks(), 1);
+        let report = detector.leak_report();
+        assert!(
+            report
+                .allocations()
+                .iter()
+                .all(|allocation| allocation.stack.is_some())
+        );
+        drop(boxes);
+    }
+}
@@ -0,0 +1,482 @@
+//! Allocators for testing failure paths, usable both as [`Allocator`] and as
+//! [`GlobalAlloc`], and meant to sit under a [`LeakDetector`]:
+//!
+//! ```ignore
+//! let detector = LeakDetector::new(FailingAlloc::after(3));
+//! ```
+//!
+//! Each wraps an inner allocator, [`System`] unless given another, and
+//! forwards whatever it doesn't fail. Frees are always forwarded.
+//!
+//! [`LeakDetector`]: crate::LeakDetector
+
+use std::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout, System},
+    ptr::{self, NonNull},
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+/// One request made of a mock allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Call {
+    Allocate(Layout),
+    AllocateZeroed(Layout),
+    Deallocate(Layout),
+    Grow {
+        old: Layout,
+        new: Layout,
+    },
+    GrowZeroed {
+        old: Layout,
+        new: Layout,
+    },
+    Shrink {
+        old: Layout,
+        new: Layout,
+    },
+    /// From [`GlobalAlloc::realloc`].
+    Realloc {
+        old: Layout,
+        new_size: usize,
+    },
+}
+
+impl Call {
+    /// The size asked for, or `None` for a free.
+    pub fn requested(&self) -> Option<usize> {
+        match *self {
+            Call::Allocate(layout) | Call::AllocateZeroed(layout) => Some(layout.size()),
+            Call::Deallocate(_) => None,
+            Call::Grow { new, .. } | Call::GrowZeroed { new, .. } | Call::Shrink { new, .. } => {
+                Some(new.size())
+            }
+            Call::Realloc { new_size, .. } => Some(new_size),
+        }
+    }
+}
+
+/// What a mock allocator does before forwarding a call.
+trait Hook {
+    type Inner;
+
+    fn inner(&self) -> &Self::Inner;
+
+    /// Whether to forward `call`. Frees are forwarded regardless.
+    fn admit(&self, call: &Call) -> bool;
+}
+
+/// When a [`FailingAlloc`] fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailWhen {
+    Always,
+    /// After this many requests succeeded.
+    After(usize),
+    /// For requests of more than this many bytes.
+    Above(usize),
+}
+
+/// Fails requests, see [`FailWhen`]. Growing, shrinking and reallocating
+/// count as requests for the new size.
+#[derive(Debug)]
+pub struct FailingAlloc<A = System> {
+    inner: A,
+    when: FailWhen,
+    requests: AtomicUsize,
+}
+
+impl FailingAlloc {
+    pub const fn always() -> Self {
+        Self::new(System, FailWhen::Always)
+    }
+
+    pub const fn after(successes: usize) -> Self {
+        Self::new(System, FailWhen::After(successes))
+    }
+
+    pub const fn above(size: usize) -> Self {
+        Self::new(System, FailWhen::Above(size))
+    }
+}
+
+impl<A> FailingAlloc<A> {
+    pub const fn new(inner: A, when: FailWhen) -> Self {
+        Self {
+            inner,
+            when,
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// Requests made so far, failed or not.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+}
+
+impl<A> Hook for FailingAlloc<A> {
+    type Inner = A;
+
+    fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn admit(&self, call: &Call) -> bool {
+        let Some(size) = call.requested() else {
+            return true;
+        };
+        let made = self.requests.fetch_add(1, Ordering::Relaxed);
+        match self.when {
+            FailWhen::Always => false,
+            FailWhen::After(successes) => made < successes,
+            FailWhen::Above(limit) => size <= limit,
+        }
+    }
+}
+
+/// Records every call, failed or not, for later assertions.
+#[derive(Debug)]
+pub struct CountingAlloc<A = System> {
+    inner: A,
+    calls: Mutex<Vec<Call, System>>,
+}
+
+impl CountingAlloc {
+    pub const fn system() -> Self {
+        Self::new(System)
+    }
+}
+
+impl<A> CountingAlloc<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            calls: Mutex::new(Vec::new_in(System)),
+        }
+    }
+
+    /// The calls so far, oldest first.
+    pub fn calls(&self) -> Vec<Call> {
+        self.lock().to_vec()
+    }
+
+    /// How many calls so far `matches` accepts.
+    pub fn count(&self, matches: impl Fn(&Call) -> bool) -> usize {
+        self.lock().iter().filter(|call| matches(call)).count()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Call, System>> {
+        self.calls.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<A> Hook for CountingAlloc<A> {
+    type Inner = A;
+
+    fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn admit(&self, call: &Call) -> bool {
+        self.lock().push(*call);
+        true
+    }
+}
+
+/// Asks a closure about every call; requests it returns `false` for fail.
+/// The closure runs inside the allocator, so it mustn't allocate from it.
+pub struct DelegatingAlloc<F, A = System> {
+    inner: A,
+    admit: F,
+}
+
+impl<F: Fn(&Call) -> bool> DelegatingAlloc<F> {
+    pub const fn system(admit: F) -> Self {
+        Self::new(System, admit)
+    }
+}
+
+impl<F: Fn(&Call) -> bool, A> DelegatingAlloc<F, A> {
+    pub const fn new(inner: A, admit: F) -> Self {
+        Self { inner, admit }
+    }
+}
+
+impl<F: Fn(&Call) -> bool, A> Hook for DelegatingAlloc<F, A> {
+    type Inner = A;
+
+    fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn admit(&self, call: &Call) -> bool {
+        (self.admit)(call)
+    }
+}
+
+macro_rules! forward {
+    ([$($generics:tt)*] $ty:ty) => {
+        unsafe impl<$($generics)*> Allocator for $ty
+        where
+            $ty: Hook,
+            <$ty as Hook>::Inner: Allocator,
+        {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                if !self.admit(&Call::Allocate(layout)) {
+                    return Err(AllocError);
+                }
+                self.inner().allocate(layout)
+            }
+
+            fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                if !self.admit(&Call::AllocateZeroed(layout)) {
+                    return Err(AllocError);
+                }
+                self.inner().allocate_zeroed(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.admit(&Call::Deallocate(layout));
+                unsafe { self.inner().deallocate(ptr, layout) }
+            }
+
+            unsafe fn grow(
+                &self,
+                ptr: NonNull<u8>,
+                old: Layout,
+                new: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                if !self.admit(&Call::Grow { old, new }) {
+                    return Err(AllocError);
+                }
+                unsafe { self.inner().grow(ptr, old, new) }
+            }
+
+            unsafe fn grow_zeroed(
+                &self,
+                ptr: NonNull<u8>,
+                old: Layout,
+                new: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                if !self.admit(&Call::GrowZeroed { old, new }) {
+                    return Err(AllocError);
+                }
+                unsafe { self.inner().grow_zeroed(ptr, old, new) }
+            }
+
+            unsafe fn shrink(
+                &self,
+                ptr: NonNull<u8>,
+                old: Layout,
+                new: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                if !self.admit(&Call::Shrink { old, new }) {
+                    return Err(AllocError);
+                }
+                unsafe { self.inner().shrink(ptr, old, new) }
+            }
+        }
+
+        unsafe impl<$($generics)*> GlobalAlloc for $ty
+        where
+            $ty: Hook,
+            <$ty as Hook>::Inner: GlobalAlloc,
+        {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                if !self.admit(&Call::Allocate(layout)) {
+                    return ptr::null_mut();
+                }
+                unsafe { self.inner().alloc(layout) }
+            }
+
+            unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+                if !self.admit(&Call::AllocateZeroed(layout)) {
+                    return ptr::null_mut();
+                }
+                unsafe { self.inner().alloc_zeroed(layout) }
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                self.admit(&Call::Deallocate(layout));
+                unsafe { self.inner().dealloc(ptr, layout) }
+            }
+
+            unsafe fn realloc(&self, ptr: *mut u8, old: Layout, new_size: usize) -> *mut u8 {
+                if !self.admit(&Call::Realloc { old, new_size }) {
+                    return ptr::null_mut();
+                }
+                unsafe { self.inner().realloc(ptr, old, new_size) }
+            }
+        }
+    };
+}
+
+forward!([A] FailingAlloc<A>);
+forward!([A] CountingAlloc<A>);
+forward!([F: Fn(&Call) -> bool, A] DelegatingAlloc<F, A>);
+
+/// Moves every block it resizes: the new block is allocated before the old
+/// one is freed, so it never comes back at the same address.
+#[derive(Debug, Default)]
+pub struct MovingAlloc<A = System> {
+    inner: A,
+}
+
+impl MovingAlloc {
+    pub const fn system() -> Self {
+        Self::new(System)
+    }
+}
+
+impl<A> MovingAlloc<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for MovingAlloc<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.allocate(layout)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.inner.deallocate(ptr, layout) }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let moved = self.inner.allocate(new)?;
+        unsafe {
+            ptr::copy_nonoverlapping(ptr.as_ptr(), moved.cast().as_ptr(), old.size());
+            self.inner.deallocate(ptr, old);
+        }
+        Ok(moved)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let moved = self.inner.allocate_zeroed(new)?;
+        unsafe {
+            ptr::copy_nonoverlapping(ptr.as_ptr(), moved.cast().as_ptr(), old.size());
+            self.inner.deallocate(ptr, old);
+        }
+        Ok(moved)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let moved = self.inner.allocate(new)?;
+        unsafe {
+            ptr::copy_nonoverlapping(ptr.as_ptr(), moved.cast().as_ptr(), new.size());
+            self.inner.deallocate(ptr, old);
+        }
+        Ok(moved)
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for MovingAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.inner.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.inner.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, old: Layout, new_size: usize) -> *mut u8 {
+        let new = unsafe { Layout::from_size_align_unchecked(new_size, old.align()) };
+        let moved = unsafe { self.inner.alloc(new) };
+        if !moved.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, moved, old.size().min(new_size));
+                self.inner.dealloc(ptr, old);
+            }
+        }
+        moved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_as_configured() {
+        let layout = Layout::new::<u64>();
+        assert!(FailingAlloc::always().allocate(layout).is_err());
+
+        let after = FailingAlloc::after(2);
+        let blocks: Vec<_> = (0..3).map(|_| after.allocate(layout)).collect();
+        assert!(blocks[0].is_ok() && blocks[1].is_ok() && blocks[2].is_err());
+        assert_eq!(after.requests(), 3);
+        for block in blocks.into_iter().flatten() {
+            unsafe { after.deallocate(block.cast(), layout) };
+        }
+        assert_eq!(after.requests(), 3);
+
+        let above = FailingAlloc::above(8);
+        let block = unsafe { above.alloc(layout) };
+        assert!(!block.is_null());
+        assert!(unsafe { above.realloc(block, layout, 9) }.is_null());
+        unsafe { above.dealloc(block, layout) };
+    }
+
+    #[test]
+    fn counts_calls_through_a_detector() {
+        let counting = CountingAlloc::system();
+        let detector = crate::LeakDetector::new(&counting);
+        let mut vec = Vec::with_capacity_in(4, &detector);
+        vec.extend([1u32, 2, 3, 4, 5]);
+        drop(vec);
+        let four = Layout::array::<u32>(4).unwrap();
+        let eight = Layout::array::<u32>(8).unwrap();
+        assert_eq!(
+            counting.calls(),
+            [
+                Call::Allocate(four),
+                Call::Grow {
+                    old: four,
+                    new: eight
+                },
+                Call::Deallocate(eight),
+            ]
+        );
+        assert_eq!(counting.count(|call| call.requested().is_some()), 2);
+        counting.clear();
+        assert!(counting.calls().is_empty());
+    }
+
+    #[test]
+    fn delegates_to_the_closure() {
+        let small = DelegatingAlloc::system(|call: &Call| call.requested() < Some(64));
+        assert!(small.allocate(Layout::new::<[u8; 64]>()).is_err());
+        let block = small.allocate(Layout::new::<u8>()).unwrap();
+        unsafe { small.deallocate(block.cast(), Layout::new::<u8>()) };
+    }
+}
@@ -0,0 +1,132 @@
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use crate::{LeakReport, SortSites};
+
+impl LeakReport {
+    /// The report as text that is the same from run to run of the same
+    /// program, for committing as a golden file: allocations are grouped in
+    /// callsite order, then by size and id, ages and the detector's own
+    /// overhead are left out and [`normalize_report`] hides the addresses.
+    /// Sites print by size when the options sort them by age.
+    pub fn normalized(&self) -> String {
+        let mut report = self.clone();
+        let key = |allocation: &crate::LeakedAllocation| {
+            let callsite = allocation.callsite;
+            (
+                callsite.file(),
+                callsite.line(),
+                callsite.column(),
+                allocation.size,
+                allocation.id,
+            )
+        };
+        report.allocations.sort_by(|a, b| key(a).cmp(&key(b)));
+        report.suppressed.sort_by(|a, b| {
+            (key(&a.allocation), &a.pattern).cmp(&(key(&b.allocation), &b.pattern))
+        });
+        report.clock_running = false;
+        report.overhead = None;
+        let mut options = report.options;
+        if options.sort == SortSites::ByAge {
+            options.sort = SortSites::BySize;
+        }
+        normalize_report(&report.render(&options).to_string())
+    }
+}
+
+/// Rewrites any of the crate's text or JSON output so that it no longer
+/// changes between runs: each hex address becomes `0xPTR1`, `0xPTR2` and so
+/// on in order of first appearance, the same address always the same
+/// placeholder; thread ids become `ThreadId(0)`; and `by age:` lines go.
+pub fn normalize_report(text: &str) -> String {
+    let mut placeholders = BTreeMap::new();
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        if line.trim_start().starts_with("by age:") {
+            continue;
+        }
+        let mut rest = line;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("ThreadId(")
+                && let Some(end) = after.find(')')
+                && end > 0
+                && after[..end].bytes().all(|byte| byte.is_ascii_digit())
+            {
+                out.push_str("ThreadId(0)");
+                rest = &after[end + 1..];
+                continue;
+            }
+            let at_word_start = !out
+                .chars()
+                .next_back()
+                .is_some_and(|previous| previous.is_ascii_alphanumeric() || previous == '_');
+            if at_word_start && let Some(after) = rest.strip_prefix("0x") {
+                let digits = after
+                    .find(|c: char| !c.is_ascii_hexdigit())
+                    .unwrap_or(after.len());
+                if digits > 0 {
+                    let next = placeholders.len() + 1;
+                    let n = *placeholders.entry(&after[..digits]).or_insert(next);
+                    let _ = write!(out, "0xPTR{n}");
+                    rest = &after[digits..];
+                    continue;
+                }
+            }
+            let c = rest.chars().next().unwrap();
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, Layout, System},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::LeakDetector;
+
+    #[test]
+    fn placeholders_and_threads() {
+        let text = "thread 'worker' (ThreadId(17)) exited\n  by age: 3 under 1s\n\
+                    \n  8 bytes at 0x7f00a0 and 0x10, again 0x7f00a0, not 10x3 or Ox1";
+        assert_eq!(
+            normalize_report(text),
+            "thread 'worker' (ThreadId(0)) exited\n\
+             \n  8 bytes at 0xPTR1 and 0xPTR2, again 0xPTR1, not 10x3 or Ox1"
+        );
+    }
+
+    /// Leaks the same blocks from a fresh detector, with one callsite
+    /// suppressed so that the report prints their addresses.
+    fn run() -> String {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        detector.add_callsite_suppression(&format!("{}:{}", file!(), line!() + 4));
+        let blocks: Vec<_> = [24, 8, 24, 100]
+            .into_iter()
+            .map(|size| Layout::from_size_align(size, 1).unwrap())
+            .map(|layout| (detector.allocate(layout).unwrap(), layout))
+            .collect();
+        let word = Layout::new::<u64>();
+        let extra = detector.allocate(word).unwrap();
+        detector.advance_clock(Duration::from_secs(3));
+        let text = detector.leak_report().normalized();
+        for (ptr, layout) in blocks.into_iter().chain([(extra, word)]) {
+            unsafe { detector.deallocate(ptr.cast(), layout) };
+        }
+        text
+    }
+
+    #[test]
+    fn two_runs_match() {
+        let first = run();
+        assert_eq!(first, run());
+        assert!(first.contains(" bytes at 0xPTR1 allocated at "), "{first}");
+        assert!(first.contains("0xPTR4"), "{first}");
+        assert!(!first.contains("by age"), "{first}");
+    }
+}
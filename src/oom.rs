@@ -0,0 +1,138 @@
+use std::{
+    alloc::Layout,
+    io::Write,
+    panic::Location,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{FailureMode, LeakDetector};
+
+/// Sites told apart in an out-of-memory report; blocks from others are
+/// summed up.
+const MAX_SITES: usize = 32;
+/// Sites listed in an out-of-memory report.
+const LISTED_SITES: usize = 5;
+
+struct Hook {
+    detector: usize,
+    report: fn(usize, Layout),
+    previous: Option<fn(Layout)>,
+}
+
+static HOOK: Mutex<Option<Hook>> = Mutex::new(None);
+
+fn run_hook(layout: Layout) {
+    let hook = HOOK.lock().unwrap_or_else(PoisonError::into_inner).take();
+    if let Some(hook) = hook {
+        (hook.report)(hook.detector, layout);
+        if let Some(previous) = hook.previous {
+            previous(layout);
+        }
+    }
+}
+
+fn report<T>(detector: usize, layout: Layout) {
+    let detector = unsafe { &*(detector as *const LeakDetector<T>) };
+    detector.report_alloc_error(layout);
+}
+
+#[derive(Clone, Copy)]
+struct Site {
+    callsite: &'static Location<'static>,
+    bytes: usize,
+    allocations: usize,
+}
+
+impl<T> LeakDetector<T> {
+    /// Prints the detector's usage, and with the registry its biggest live
+    /// sites, when an allocation fails and the process is about to abort.
+    /// Replaces whatever alloc error hook was installed, std's message
+    /// included; see [`chain_alloc_error_hook`] to keep it. With the
+    /// detector's [`FailureMode::Trap`] it prints nothing.
+    ///
+    /// [`chain_alloc_error_hook`]: LeakDetector::chain_alloc_error_hook
+    pub fn install_alloc_error_hook(&'static self) {
+        self.set_alloc_error_hook(None);
+    }
+
+    /// Like [`install_alloc_error_hook`], then runs the hook that was
+    /// installed before.
+    ///
+    /// [`install_alloc_error_hook`]: LeakDetector::install_alloc_error_hook
+    pub fn chain_alloc_error_hook(&'static self) {
+        self.set_alloc_error_hook(Some(std::alloc::take_alloc_error_hook()));
+    }
+
+    fn set_alloc_error_hook(&'static self, previous: Option<fn(Layout)>) {
+        *HOOK.lock().unwrap_or_else(PoisonError::into_inner) = Some(Hook {
+            detector: self as *const Self as usize,
+            report: report::<T>,
+            previous,
+        });
+        std::alloc::set_alloc_error_hook(run_hook);
+    }
+
+    /// Writes straight to stderr without allocating, since nothing can be.
+    fn report_alloc_error(&self, layout: Layout) {
+        if self.failure_mode() == FailureMode::Trap {
+            return;
+        }
+        let _internal = crate::pause::internal();
+        let mut stderr = std::io::stderr().lock();
+        let _ = writeln!(
+            stderr,
+            "mem_leak_detector: allocation of {} bytes (align {}) failed; {} bytes used, peak {} bytes",
+            layout.size(),
+            layout.align(),
+            self.get_used(),
+            self.get_peak()
+        );
+        if !self.registry_enabled() {
+            return;
+        }
+        let mut sites = [None::<Site>; MAX_SITES];
+        let (mut other_bytes, mut other_allocations) = (0, 0);
+        let complete = self.registry.try_for_each(|entry| {
+            let slot = sites
+                .iter_mut()
+                .find(|site| site.is_none_or(|site| site.callsite == entry.callsite));
+            match slot {
+                Some(slot) => {
+                    let site = slot.get_or_insert(Site {
+                        callsite: entry.callsite,
+                        bytes: 0,
+                        allocations: 0,
+                    });
+                    site.bytes += entry.size;
+                    site.allocations += 1;
+                }
+                None => {
+                    other_bytes += entry.size;
+                    other_allocations += 1;
+                }
+            }
+        });
+        if !complete {
+            let _ = writeln!(stderr, "  (registry busy, no sites)");
+            return;
+        }
+        sites.sort_unstable_by_key(|site| std::cmp::Reverse(site.map_or(0, |site| site.bytes)));
+        for site in sites.iter().flatten().take(LISTED_SITES) {
+            let _ = writeln!(
+                stderr,
+                "  {} bytes in {} allocation(s) at {}",
+                site.bytes, site.allocations, site.callsite
+            );
+        }
+        for site in sites.iter().flatten().skip(LISTED_SITES) {
+            other_bytes += site.bytes;
+            other_allocations += site.allocations;
+        }
+        if other_allocations != 0 {
+            let _ = writeln!(
+                stderr,
+                "  ... {other_bytes} bytes in {other_allocations} allocation(s) elsewhere"
+            );
+        }
+    }
+}
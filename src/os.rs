@@ -0,0 +1,200 @@
+//! What the operating system says about the process, to tell leaks in
+//! memory the detector tracks apart from growth elsewhere: allocator slack,
+//! fragmentation, `mmap`s and the like.
+
+use std::fmt;
+
+use crate::LeakDetector;
+
+/// The resident set size of the process in bytes, or `None` where it can't
+/// be read.
+pub fn current_rss() -> Option<usize> {
+    imp::current_rss()
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::ffi::{c_int, c_long};
+
+    unsafe extern "C" {
+        fn sysconf(name: c_int) -> c_long;
+    }
+
+    const SC_PAGESIZE: c_int = 30;
+
+    pub(super) fn current_rss() -> Option<usize> {
+        let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+        let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+        let page_size = usize::try_from(unsafe { sysconf(SC_PAGESIZE) }).ok()?;
+        Some(pages * page_size)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    #[allow(dead_code)]
+    #[repr(C, packed(4))]
+    struct MachTaskBasicInfo {
+        virtual_size: u64,
+        resident_size: u64,
+        resident_size_max: u64,
+        user_time: [i32; 2],
+        system_time: [i32; 2],
+        policy: i32,
+        suspend_count: i32,
+    }
+
+    const MACH_TASK_BASIC_INFO: u32 = 20;
+
+    unsafe extern "C" {
+        static mach_task_self_: u32;
+        fn task_info(task: u32, flavor: u32, info: *mut i32, count: *mut u32) -> i32;
+    }
+
+    pub(super) fn current_rss() -> Option<usize> {
+        let mut info = std::mem::MaybeUninit::<MachTaskBasicInfo>::zeroed();
+        let mut count = (size_of::<MachTaskBasicInfo>() / size_of::<i32>()) as u32;
+        let status = unsafe {
+            task_info(
+                mach_task_self_,
+                MACH_TASK_BASIC_INFO,
+                info.as_mut_ptr().cast(),
+                &mut count,
+            )
+        };
+        if status != 0 {
+            return None;
+        }
+        let info = unsafe { info.assume_init() };
+        usize::try_from(info.resident_size).ok()
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::c_void;
+
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct ProcessMemoryCounters {
+        cb: u32,
+        page_fault_count: u32,
+        peak_working_set_size: usize,
+        working_set_size: usize,
+        quota_peak_paged_pool_usage: usize,
+        quota_paged_pool_usage: usize,
+        quota_peak_non_paged_pool_usage: usize,
+        quota_non_paged_pool_usage: usize,
+        pagefile_usage: usize,
+        peak_pagefile_usage: usize,
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn K32GetProcessMemoryInfo(
+            process: *mut c_void,
+            counters: *mut ProcessMemoryCounters,
+            cb: u32,
+        ) -> i32;
+    }
+
+    pub(super) fn current_rss() -> Option<usize> {
+        let mut counters = std::mem::MaybeUninit::<ProcessMemoryCounters>::zeroed();
+        let cb = size_of::<ProcessMemoryCounters>() as u32;
+        let ok = unsafe { K32GetProcessMemoryInfo(GetCurrentProcess(), counters.as_mut_ptr(), cb) };
+        if ok == 0 {
+            return None;
+        }
+        Some(unsafe { counters.assume_init() }.working_set_size)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod imp {
+    pub(super) fn current_rss() -> Option<usize> {
+        None
+    }
+}
+
+/// How much the detector's usage and the process's RSS moved over an
+/// [`RssScope`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RssDelta {
+    pub used: isize,
+    /// `None` if the RSS couldn't be read at either end.
+    pub rss: Option<isize>,
+}
+
+impl fmt::Display for RssDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "used changed by {} bytes", self.used)?;
+        match self.rss {
+            Some(rss) => write!(f, ", RSS by {rss} bytes"),
+            None => write!(f, ", RSS unavailable"),
+        }
+    }
+}
+
+/// Records usage and RSS when created, see [`LeakDetector::rss_scope`].
+pub struct RssScope<'a, T> {
+    detector: &'a LeakDetector<T>,
+    used: usize,
+    rss: Option<usize>,
+}
+
+impl<'a, T> RssScope<'a, T> {
+    /// The change since the scope was created.
+    pub fn delta(&self) -> RssDelta {
+        RssDelta {
+            used: self.detector.get_used() as isize - self.used as isize,
+            rss: self
+                .rss
+                .zip(current_rss())
+                .map(|(start, now)| now as isize - start as isize),
+        }
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// The process's RSS minus the bytes this detector counts as used.
+    pub fn rss_overhead(&self) -> Option<isize> {
+        Some(current_rss()? as isize - self.get_used() as isize)
+    }
+
+    pub fn rss_scope(&self) -> RssScope<'_, T> {
+        RssScope {
+            detector: self,
+            used: self.get_used(),
+            rss: current_rss(),
+        }
+    }
+
+    /// Runs `f` and reports how usage and RSS changed while it ran.
+    pub fn measure_rss<R>(&self, f: impl FnOnce() -> R) -> (R, RssDelta) {
+        let scope = self.rss_scope();
+        let result = f();
+        (result, scope.delta())
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos", windows)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rss_grows_with_large_allocation() {
+        const SIZE: usize = 64 << 20;
+        assert!(current_rss().unwrap() > 0);
+        let detector = LeakDetector::system();
+        let (buffer, delta) = detector.measure_rss(|| {
+            let mut buffer = Vec::with_capacity_in(SIZE, &detector);
+            buffer.resize(SIZE, 1u8);
+            buffer
+        });
+        assert_eq!(delta.used, SIZE as isize);
+        assert!(delta.rss.unwrap() > (SIZE / 2) as isize);
+        assert!(detector.rss_overhead().unwrap() > 0);
+        drop(buffer);
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    alloc::{AllocError, Allocator, Layout, System},
+    cell::Cell,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    sync::{
+        Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::{LeakDetector, report::HumanBytes};
+
+/// Memory a detector allocated for its own bookkeeping. It never counts
+/// towards `used` or any leak check. See [`LeakDetector::self_overhead`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Overhead {
+    pub registry: usize,
+    pub stacks: usize,
+    /// The quarantine's queue, not the freed blocks it holds back.
+    pub quarantine: usize,
+    /// Checkpoints, usage samples, suppressions, thread exit reports,
+    /// diagnostics, thread limits and async waiters.
+    pub other: usize,
+    /// Live blocks making up all of the above.
+    pub blocks: usize,
+}
+
+impl Overhead {
+    pub fn bytes(&self) -> usize {
+        self.registry + self.stacks + self.quarantine + self.other
+    }
+}
+
+impl fmt::Display for Overhead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "detector overhead: {}", HumanBytes(self.bytes()))?;
+        let parts = [
+            ("registry", self.registry),
+            ("stacks", self.stacks),
+            ("quarantine", self.quarantine),
+            ("other", self.other),
+        ];
+        let mut separator = ": ";
+        for (name, bytes) in parts.into_iter().filter(|&(_, bytes)| bytes != 0) {
+            write!(f, "{separator}{name} {}", HumanBytes(bytes))?;
+            separator = ", ";
+        }
+        Ok(())
+    }
+}
+
+/// What one of the detector's structures holds from [`Internal`].
+pub(crate) struct Account {
+    bytes: AtomicUsize,
+    blocks: AtomicUsize,
+}
+
+thread_local! {
+    /// The account [`Internal`] charges on this thread, null for none.
+    static CHARGED: Cell<*const Account> = const { Cell::new(ptr::null()) };
+}
+
+impl Account {
+    pub(crate) const fn new() -> Self {
+        Self {
+            bytes: AtomicUsize::new(0),
+            blocks: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn blocks(&self) -> usize {
+        self.blocks.load(Ordering::Relaxed)
+    }
+
+    /// Charges what [`Internal`] allocates and frees on this thread to this
+    /// account until the guard is dropped.
+    pub(crate) fn charge(&self) -> Charge<'_> {
+        let previous = CHARGED
+            .try_with(|charged| charged.replace(self))
+            .unwrap_or(ptr::null());
+        Charge {
+            previous,
+            _account: PhantomData,
+        }
+    }
+}
+
+pub(crate) struct Charge<'a> {
+    previous: *const Account,
+    _account: PhantomData<&'a Account>,
+}
+
+impl Drop for Charge<'_> {
+    fn drop(&mut self) {
+        let _ = CHARGED.try_with(|charged| charged.set(self.previous));
+    }
+}
+
+/// Moves the charged account, if any, from `old` to `new` bytes, and from
+/// `old_blocks` to `new_blocks` live blocks.
+fn record(old: usize, new: usize, old_blocks: usize, new_blocks: usize) {
+    let _ = CHARGED.try_with(|charged| {
+        if let Some(account) = unsafe { charged.get().as_ref() } {
+            adjust(&account.bytes, old, new);
+            adjust(&account.blocks, old_blocks, new_blocks);
+        }
+    });
+}
+
+/// Moves `counter` from `old` to `new`, stopping at zero for blocks
+/// allocated before the account was charged.
+fn adjust(counter: &AtomicUsize, old: usize, new: usize) {
+    if new >= old {
+        counter.fetch_add(new - old, Ordering::Relaxed);
+    } else {
+        let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+            Some(value.saturating_sub(old - new))
+        });
+    }
+}
+
+/// The allocator of the detector's own containers: [`System`], so it never
+/// re-enters a detector, with each block charged to the account of the
+/// structure being locked.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Internal;
+
+unsafe impl Allocator for Internal {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = System.allocate(layout)?;
+        record(0, layout.size(), 0, 1);
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { System.deallocate(ptr, layout) };
+        record(layout.size(), 0, 1, 0);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = unsafe { System.grow(ptr, old_layout, new_layout) }?;
+        record(old_layout.size(), new_layout.size(), 1, 1);
+        Ok(new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = unsafe { System.shrink(ptr, old_layout, new_layout) }?;
+        record(old_layout.size(), new_layout.size(), 1, 1);
+        Ok(new)
+    }
+}
+
+/// A mutex around one of the detector's structures, whose containers
+/// allocate from [`Internal`] and are charged to it while it's locked.
+pub(crate) struct Accounted<T> {
+    value: Mutex<T>,
+    account: Account,
+}
+
+impl<T> Accounted<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            value: Mutex::new(value),
+            account: Account::new(),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> Locked<'_, T> {
+        let guard = self.value.lock().unwrap_or_else(PoisonError::into_inner);
+        Locked {
+            _charge: self.account.charge(),
+            guard,
+        }
+    }
+
+    pub(crate) fn try_lock(&self) -> Option<Locked<'_, T>> {
+        let guard = self.value.try_lock().ok()?;
+        Some(Locked {
+            _charge: self.account.charge(),
+            guard,
+        })
+    }
+
+    pub(crate) fn account(&self) -> &Account {
+        &self.account
+    }
+}
+
+/// An [`Accounted`] structure, locked. The charge ends with the lock.
+pub(crate) struct Locked<'a, T> {
+    guard: MutexGuard<'a, T>,
+    _charge: Charge<'a>,
+}
+
+impl<T> Deref for Locked<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for Locked<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// What the detector's registry, stack table and other bookkeeping
+    /// hold, by structure. Shown at the end of [`leak_report`]s and in
+    /// [`Snapshot::overhead_bytes`](crate::Snapshot::overhead_bytes).
+    ///
+    /// [`leak_report`]: LeakDetector::leak_report
+    pub fn self_overhead(&self) -> Overhead {
+        let other = [
+            self.checkpoints.account(),
+            self.usage_samples.account(),
+            self.suppressions.account(),
+            #[cfg(any(feature = "env-config", feature = "config"))]
+            self.report_path.account(),
+            self.thread_exit.account(),
+            self.diagnostics.account(),
+            self.thread_limits.account(),
+            self.waiters.account(),
+        ];
+        let accounts = [
+            self.registry.account(),
+            self.registry.stacks.account(),
+            self.quarantine.account(),
+        ];
+        Overhead {
+            registry: accounts[0].bytes(),
+            stacks: accounts[1].bytes(),
+            quarantine: accounts[2].bytes(),
+            other: other.iter().map(|account| account.bytes()).sum(),
+            blocks: accounts
+                .iter()
+                .chain(&other)
+                .map(|account| account.blocks())
+                .sum(),
+        }
+    }
+
+    /// Bytes the detector allocated for itself, see
+    /// [`self_overhead`](LeakDetector::self_overhead).
+    pub fn self_overhead_bytes(&self) -> usize {
+        self.self_overhead().bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+    use crate::registry::Entry;
+
+    #[test]
+    fn registry_overhead_stays_out_of_checks() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let before = detector.self_overhead();
+        let blocks: Vec<_> = (0..1000u32).map(|i| Box::new_in(i, &detector)).collect();
+        let during = detector.self_overhead();
+        assert!(
+            during.registry >= before.registry + 1000 * size_of::<(usize, Entry)>(),
+            "{during:?}"
+        );
+        assert!(during.blocks > before.blocks);
+        assert_eq!(detector.get_used(), 4000);
+        assert_eq!(detector.snapshot().overhead_bytes, during.bytes());
+        let text = detector.leak_report().to_string();
+        assert!(text.contains("\ndetector overhead: "), "{text}");
+        assert!(text.contains(": registry "), "{text}");
+        assert!(!detector.leak_report().normalized().contains("overhead"));
+
+        drop(blocks);
+        detector.check().unwrap();
+        assert!(detector.self_overhead().registry < during.registry);
+    }
+
+    #[test]
+    fn lists_nonzero_parts() {
+        let overhead = Overhead {
+            registry: 2 << 20,
+            other: 1 << 20,
+            blocks: 3,
+            ..Overhead::default()
+        };
+        assert_eq!(
+            overhead.to_string(),
+            "detector overhead: 3.0 MiB: registry 2.0 MiB, other 1.0 MiB"
+        );
+    }
+}
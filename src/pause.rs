@@ -0,0 +1,136 @@
+use std::{cell::Cell, marker::PhantomData, sync::atomic::Ordering};
+
+use crate::LeakDetector;
+
+/// Resumes tracking when dropped, see [`LeakDetector::pause_guard`].
+pub struct PauseGuard<'a, T> {
+    detector: &'a LeakDetector<T>,
+}
+
+thread_local! {
+    /// How many [`internal`] guards are alive on this thread.
+    static INTERNAL: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Marks this thread as doing the detector's own work, such as reading its
+/// configuration or writing a report, until the guard is dropped. No
+/// detector tracks the thread's allocations and frees meanwhile, as if it
+/// were paused, but other threads are tracked as usual.
+pub(crate) fn internal() -> InternalGuard {
+    let entered = INTERNAL
+        .try_with(|depth| depth.set(depth.get() + 1))
+        .is_ok();
+    InternalGuard {
+        entered,
+        _thread: PhantomData,
+    }
+}
+
+/// Whether this thread is inside an [`internal`] guard.
+#[inline]
+pub(crate) fn in_internal() -> bool {
+    INTERNAL.try_with(|depth| depth.get() != 0).unwrap_or(false)
+}
+
+/// Ends the internal work [`internal`] started when dropped, on the thread
+/// that started it.
+pub(crate) struct InternalGuard {
+    entered: bool,
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for InternalGuard {
+    fn drop(&mut self) {
+        if self.entered {
+            let _ = INTERNAL.try_with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Stops counting allocations and frees until the matching [`resume`].
+    /// Pauses nest: tracking restarts once every `pause` has been resumed.
+    ///
+    /// With the registry, frees are counted exactly when their block was, so
+    /// blocks may be freed on either side of the pause. Without it, frees made
+    /// while paused are not counted either: a block allocated while tracking
+    /// and freed during a pause stays in `used`, and a block allocated during a
+    /// pause and freed after it underflows `used`.
+    ///
+    /// [`resume`]: LeakDetector::resume
+    pub fn pause(&self) {
+        self.paused.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn resume(&self) {
+        let _ = self
+            .paused
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |depth| {
+                depth.checked_sub(1)
+            });
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.paused.load(Ordering::Acquire) == 0
+    }
+
+    pub fn pause_guard(&self) -> PauseGuard<'_, T> {
+        self.pause();
+        PauseGuard { detector: self }
+    }
+}
+
+impl<'a, T> Drop for PauseGuard<'a, T> {
+    fn drop(&mut self) {
+        self.detector.resume();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paused_leak_is_not_counted() {
+        let detector = LeakDetector::system();
+        let tracked = Box::new_in([0u8; 32], &detector);
+        {
+            let _guard = detector.pause_guard();
+            assert!(!detector.is_tracking());
+            std::mem::forget(Vec::<u8, _>::with_capacity_in(128, &detector));
+        }
+        assert!(detector.is_tracking());
+        assert_eq!(detector.get_used(), 32);
+        drop(tracked);
+        detector.assert();
+    }
+
+    #[test]
+    fn internal_work_is_per_thread() {
+        let detector = LeakDetector::system();
+        let tracked = Box::new_in([0u8; 32], &detector);
+        let other = std::thread::scope(|scope| {
+            let _internal = internal();
+            let other = scope.spawn(|| Box::new_in([0u8; 16], &detector));
+            std::mem::forget(Vec::<u8, _>::with_capacity_in(128, &detector));
+            other.join().unwrap()
+        });
+        assert!(!in_internal());
+        assert_eq!(detector.get_used(), 48);
+        drop((tracked, other));
+        detector.assert();
+    }
+
+    #[test]
+    fn nested_pauses() {
+        let detector = LeakDetector::system();
+        detector.pause();
+        detector.pause();
+        detector.resume();
+        assert!(!detector.is_tracking());
+        detector.resume();
+        assert!(detector.is_tracking());
+        detector.resume();
+        assert!(detector.is_tracking());
+    }
+}
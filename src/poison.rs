@@ -0,0 +1,166 @@
+use std::{
+    panic::Location,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::SystemTime,
+};
+
+use crate::LeakDetector;
+
+/// The first failed check seen by a detector, kept so later failures can point
+/// back at the original culprit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirstFailure {
+    pub bytes: isize,
+    pub scope_name: Option<&'static str>,
+    pub location: &'static Location<'static>,
+    pub timestamp: SystemTime,
+}
+
+impl std::fmt::Display for FirstFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes leaked", self.bytes)?;
+        if let Some(name) = self.scope_name {
+            write!(f, " in scope '{name}'")?;
+        }
+        write!(f, " at {}", self.location)
+    }
+}
+
+pub(crate) struct Poison {
+    poisoned: AtomicBool,
+    first: Mutex<Option<FirstFailure>>,
+    repanic: AtomicBool,
+}
+
+impl Poison {
+    pub(crate) const fn new() -> Self {
+        Self {
+            poisoned: AtomicBool::new(false),
+            first: Mutex::new(None),
+            repanic: AtomicBool::new(true),
+        }
+    }
+
+    /// Records `failure` unless an earlier one is already stored, in which case
+    /// the earlier one is returned.
+    pub(crate) fn record(&self, failure: FirstFailure) -> Option<FirstFailure> {
+        let mut first = self.first.lock().unwrap_or_else(PoisonError::into_inner);
+        match *first {
+            Some(earlier) => Some(earlier),
+            None => {
+                *first = Some(failure);
+                self.poisoned.store(true, Ordering::Release);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn repanic(&self) -> bool {
+        self.repanic.load(Ordering::Acquire)
+    }
+}
+
+impl<T> LeakDetector<T> {
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.poisoned.load(Ordering::Acquire)
+    }
+
+    pub fn first_failure(&self) -> Option<FirstFailure> {
+        *self
+            .poison
+            .first
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn clear_poison(&self) {
+        let mut first = self
+            .poison
+            .first
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *first = None;
+        self.poison.poisoned.store(false, Ordering::Release);
+    }
+
+    /// Whether a scope that fails on an already poisoned detector panics again
+    /// (the default) or only logs its failure to stderr.
+    pub fn set_repanic_when_poisoned(&self, repanic: bool) {
+        self.poison.repanic.store(repanic, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    use super::*;
+
+    fn leak_in_scope(detector: &LeakDetector<std::alloc::System>, name: &'static str) -> String {
+        let payload = catch_unwind(AssertUnwindSafe(|| {
+            let _scope = detector.scope().named(name);
+            std::mem::forget(Box::new_in(7u64, detector));
+        }))
+        .unwrap_err();
+        payload.downcast::<String>().map(|s| *s).unwrap()
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn second_failure_references_first() {
+        let detector = LeakDetector::system();
+        let first = leak_in_scope(&detector, "first");
+        assert!(!first.contains("already poisoned"), "{first}");
+        assert!(detector.is_poisoned());
+        assert_eq!(detector.first_failure().unwrap().scope_name, Some("first"));
+
+        let second = leak_in_scope(&detector, "second");
+        assert!(second.contains("scope 'second'"), "{second}");
+        assert!(
+            second.contains("detector already poisoned by 8 bytes leaked in scope 'first'"),
+            "{second}"
+        );
+
+        detector.clear_poison();
+        assert!(!detector.is_poisoned());
+        assert!(detector.first_failure().is_none());
+    }
+
+    #[test]
+    fn check_poisons() {
+        let detector = LeakDetector::system();
+        let leaked = Box::new_in(1u8, &detector);
+        let err = detector.check().unwrap_err();
+        assert!(!err.to_string().contains("already poisoned"), "{err}");
+        let err = detector.check().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("detector already poisoned by 1 bytes leaked at src/poison.rs"),
+            "{err}"
+        );
+        drop(leaked);
+        detector.check().unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn log_when_poisoned() {
+        let detector = LeakDetector::system();
+        detector.set_repanic_when_poisoned(false);
+        leak_in_scope(&detector, "first");
+        {
+            let _scope = detector.scope().named("second");
+            std::mem::forget(Box::new_in(7u64, &detector));
+        }
+        assert_eq!(detector.first_failure().unwrap().scope_name, Some("first"));
+    }
+}
@@ -0,0 +1,58 @@
+use std::{cell::Cell, sync::PoisonError};
+
+use crate::{FailureMode, LeakDetector, ScopeLeak};
+
+/// What a scope does when it ends unbalanced.
+#[derive(Debug, Clone, Copy)]
+pub enum OnLeak {
+    Panic,
+    Log,
+    /// Called on the dropping thread after the detector has released its
+    /// locks, so the callback may allocate. A scope failing while a callback
+    /// is already running on the same thread is logged instead.
+    Callback(fn(&ScopeLeak)),
+    Ignore,
+}
+
+thread_local! {
+    static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+impl OnLeak {
+    /// `Panic` fails as `mode` says, only logging when `mode` would panic
+    /// while the thread is already unwinding.
+    pub(crate) fn apply(self, leak: &ScopeLeak, mode: FailureMode) {
+        match self {
+            OnLeak::Panic if mode == FailureMode::Panic && std::thread::panicking() => {
+                eprintln!("{leak}")
+            }
+            OnLeak::Panic => mode.fail(leak),
+            OnLeak::Log => eprintln!("{leak}"),
+            OnLeak::Callback(_) if IN_CALLBACK.get() => eprintln!("{leak}"),
+            OnLeak::Callback(callback) => {
+                struct Reset;
+                impl Drop for Reset {
+                    fn drop(&mut self) {
+                        IN_CALLBACK.set(false);
+                    }
+                }
+                IN_CALLBACK.set(true);
+                let _reset = Reset;
+                callback(leak);
+            }
+            OnLeak::Ignore => {}
+        }
+    }
+}
+
+impl<T> LeakDetector<T> {
+    pub fn on_leak(&self) -> OnLeak {
+        *self.on_leak.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sets the policy of scopes that don't choose their own with
+    /// [`LeakDetectorScope::on_leak`](crate::LeakDetectorScope::on_leak).
+    pub fn set_on_leak(&self, on_leak: OnLeak) {
+        *self.on_leak.lock().unwrap_or_else(PoisonError::into_inner) = on_leak;
+    }
+}
@@ -0,0 +1,397 @@
+//! Reports for a terminal: colored with raw ANSI codes and fitted to its
+//! width.
+
+use std::{borrow::Cow, fmt, io::IsTerminal};
+
+use crate::{
+    LeakReport, StackId,
+    render::{Breakdown, Header},
+    report::HumanBytes,
+    summary,
+};
+
+/// Whether [`LeakReport::render_pretty`] colors its output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// When stderr is a terminal and `NO_COLOR` is unset or empty.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::io::stderr().is_terminal()
+            }
+        }
+    }
+}
+
+const RED: &str = "\x1b[1;31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// A [`LeakReport`] printed for a terminal, see
+/// [`LeakReport::render_pretty`].
+pub struct Pretty<'a> {
+    report: &'a LeakReport,
+    width: Option<usize>,
+    color: bool,
+}
+
+impl LeakReport {
+    /// The report for a terminal `width` columns wide, with the report's
+    /// [`options`](LeakReport::options). With color, leaked byte counts are
+    /// red and suppressed allocations and the detector's overhead dim. With
+    /// a width, sites line up, and callsites and frames that would run past
+    /// it are cut in the middle. With neither it prints exactly as
+    /// `Display` does.
+    pub fn render_pretty(&self, width: Option<usize>, color: ColorChoice) -> Pretty<'_> {
+        Pretty {
+            report: self,
+            width,
+            color: color.enabled(),
+        }
+    }
+
+    /// [`render_pretty`](LeakReport::render_pretty) for stderr: colored and
+    /// fitted to it when it's a terminal, plain otherwise.
+    pub(crate) fn for_stderr(&self) -> Pretty<'_> {
+        let width = std::io::stderr().is_terminal().then(stderr_width).flatten();
+        self.render_pretty(width, ColorChoice::Auto)
+    }
+}
+
+/// `text` in `style`, when coloring.
+struct Paint<'a> {
+    text: &'a str,
+    style: &'static str,
+    on: bool,
+}
+
+impl fmt::Display for Paint<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.on {
+            write!(f, "{}{}{RESET}", self.style, self.text)
+        } else {
+            f.write_str(self.text)
+        }
+    }
+}
+
+struct Frames<'a>(&'a LeakReport, Option<StackId>, usize);
+
+impl fmt::Display for Frames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write_frames(f, self.1, self.2)
+    }
+}
+
+impl Pretty<'_> {
+    /// Whether this prints just as the report's `Display` does.
+    pub(crate) fn is_plain(&self) -> bool {
+        !self.color && self.width.is_none()
+    }
+
+    fn paint<'t>(&self, text: &'t str, style: &'static str) -> Paint<'t> {
+        Paint {
+            text,
+            style,
+            on: self.color,
+        }
+    }
+
+    /// `line`, keeping its indentation, cut to the width.
+    fn fit<'t>(&self, line: &'t str) -> Cow<'t, str> {
+        let Some(width) = self.width else {
+            return Cow::Borrowed(line);
+        };
+        let text = line.trim_start_matches(' ');
+        let indent = line.len() - text.len();
+        match truncate_middle(text, width.saturating_sub(indent)) {
+            Cow::Borrowed(_) => Cow::Borrowed(line),
+            Cow::Owned(text) => Cow::Owned(format!("{}{text}", &line[..indent])),
+        }
+    }
+}
+
+impl fmt::Display for Pretty<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = self.report;
+        if self.is_plain() {
+            return fmt::Display::fmt(report, f);
+        }
+        let options = report.options();
+        write!(f, "{}", self.paint(&Header(report).to_string(), RED))?;
+        let sites = report.sites(options.sort);
+        let (shown, rest) = summary::split_sites(&sites, options.top, options.min_bytes);
+        let sampled = if report.sampling().is_some() { "~" } else { "" };
+        let bytes_width = shown
+            .iter()
+            .map(|site| sampled.len() + site.bytes.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let count_width = shown
+            .iter()
+            .map(|site| site.allocations.to_string().len())
+            .max()
+            .unwrap_or(0);
+        for site in shown {
+            let bytes = format!("{:>bytes_width$}", format!("{sampled}{}", site.bytes));
+            let kind = if sampled.is_empty() { "" } else { "sampled " };
+            let middle = format!(
+                " bytes in {:>count_width$} {kind}allocation(s) at ",
+                site.allocations
+            );
+            let callsite = site.callsite.to_string();
+            let callsite = match self.width {
+                Some(width) => {
+                    let used = 2 + bytes_width + middle.chars().count();
+                    truncate_middle(&callsite, width.saturating_sub(used))
+                }
+                None => Cow::Borrowed(callsite.as_str()),
+            };
+            write!(f, "\n  {}{middle}{callsite}", self.paint(&bytes, RED))?;
+            let frames = Frames(report, site.stack, options.max_frames).to_string();
+            for line in frames.lines().skip(1) {
+                write!(f, "\n{}", self.fit(line))?;
+            }
+        }
+        if !rest.is_empty() {
+            let line = format!(
+                "  … and {} more site(s) totalling {}",
+                rest.len(),
+                HumanBytes(rest.iter().map(|site| site.bytes).sum())
+            );
+            write!(f, "\n{}", self.paint(&line, DIM))?;
+        }
+        for line in Breakdown(report).to_string().lines().skip(1) {
+            write!(f, "\n{}", self.fit(line))?;
+        }
+        if !report.suppressed().is_empty() {
+            let line = format!(
+                "{} bytes in {} allocation(s) suppressed",
+                report.suppressed_bytes(),
+                report.suppressed().len()
+            );
+            write!(f, "\n{}", self.paint(&line, DIM))?;
+            for suppressed in report.suppressed() {
+                let line = format!("  {suppressed}");
+                write!(f, "\n{}", self.paint(&self.fit(&line), DIM))?;
+            }
+        }
+        if let Some(overhead) = report.overhead.filter(|overhead| overhead.bytes() != 0) {
+            write!(f, "\n{}", self.paint(&overhead.to_string(), DIM))?;
+        }
+        Ok(())
+    }
+}
+
+/// `text` cut to at most `max` characters by replacing its middle with `…`,
+/// never splitting a character.
+fn truncate_middle(text: &str, max: usize) -> Cow<'_, str> {
+    let len = text.chars().count();
+    if len <= max {
+        return Cow::Borrowed(text);
+    }
+    if max == 0 {
+        return Cow::Borrowed("");
+    }
+    let kept = max - 1;
+    let (head, tail) = (kept.div_ceil(2), kept / 2);
+    let byte = |chars: usize| {
+        text.char_indices()
+            .nth(chars)
+            .map_or(text.len(), |(index, _)| index)
+    };
+    Cow::Owned(format!(
+        "{}…{}",
+        &text[..byte(head)],
+        &text[byte(len - tail)..]
+    ))
+}
+
+/// The width of the terminal on stderr: `COLUMNS`, or else what the
+/// terminal says.
+fn stderr_width() -> Option<usize> {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|&columns| columns != 0)
+        .or_else(imp::stderr_width)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod imp {
+    use std::ffi::{c_int, c_ulong};
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Winsize {
+        rows: u16,
+        columns: u16,
+        x_pixels: u16,
+        y_pixels: u16,
+    }
+
+    #[cfg(target_os = "linux")]
+    const TIOCGWINSZ: c_ulong = 0x5413;
+    #[cfg(target_os = "macos")]
+    const TIOCGWINSZ: c_ulong = 0x4008_7468;
+
+    unsafe extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    pub(super) fn stderr_width() -> Option<usize> {
+        let mut size = Winsize::default();
+        let status = unsafe { ioctl(2, TIOCGWINSZ, &mut size as *mut Winsize) };
+        (status == 0 && size.columns != 0).then_some(usize::from(size.columns))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod imp {
+    pub(super) fn stderr_width() -> Option<usize> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, panic::Location, time::Duration};
+
+    use super::*;
+    use crate::{LeakedAllocation, Overhead, SuppressedAllocation, Symbol};
+
+    fn symbol(name: &str) -> Vec<Symbol> {
+        vec![Symbol {
+            name: Some(name.to_owned()),
+            file: None,
+            line: None,
+        }]
+    }
+
+    /// Two sites with symbolized stacks, one suppressed block and some
+    /// overhead.
+    fn report() -> (LeakReport, &'static Location<'static>) {
+        let callsite = Location::caller();
+        let allocation = |id: u64, size, stack| LeakedAllocation {
+            id,
+            address: 0x1000 * id as usize,
+            size,
+            usable_size: None,
+            padding: 0,
+            callsite,
+            age: Duration::ZERO,
+            stack: Some(StackId::from_index(stack)),
+        };
+        let stacks = BTreeMap::from([
+            (StackId::from_index(0), vec![0xa0, 0xb0]),
+            (StackId::from_index(1), vec![0xb0]),
+        ]);
+        let symbols = BTreeMap::from([
+            (
+                0xa0,
+                symbol("ünïcödé::cache::Entry<κλειδί>::insert_with_capacity"),
+            ),
+            (0xb0, symbol("app::main")),
+        ]);
+        let mut report = LeakReport::from_parts(
+            vec![
+                allocation(1, 1024, 0),
+                allocation(2, 1024, 0),
+                allocation(3, 8, 1),
+            ],
+            stacks,
+            symbols,
+        );
+        report.suppressed.push(SuppressedAllocation {
+            allocation: allocation(4, 16, 1),
+            pattern: "app::main".to_owned(),
+        });
+        report.overhead = Some(Overhead {
+            registry: 2048,
+            ..Overhead::default()
+        });
+        (report, callsite)
+    }
+
+    #[test]
+    fn plain_without_color_or_width() {
+        let (report, _) = report();
+        assert_eq!(
+            report.render_pretty(None, ColorChoice::Never).to_string(),
+            report.to_string()
+        );
+    }
+
+    #[test]
+    fn colored() {
+        let (report, at) = report();
+        assert_eq!(
+            report.render_pretty(None, ColorChoice::Always).to_string(),
+            format!(
+                "\x1b[1;31m2056 bytes leaked in 3 allocation(s)\x1b[0m\
+                 \n  \x1b[1;31m2048\x1b[0m bytes in 2 allocation(s) at {at}\
+                 \n    0xa0 ünïcödé::cache::Entry<κλειδί>::insert_with_capacity\
+                 \n    0xb0 app::main\
+                 \n  \x1b[1;31m   8\x1b[0m bytes in 1 allocation(s) at {at}\
+                 \n    0xb0 app::main\
+                 \n  by crate: ünïcödé 2048 bytes in 2 allocation(s), app 8 bytes in 1 allocation(s)\
+                 \n\x1b[2m16 bytes in 1 allocation(s) suppressed\x1b[0m\
+                 \n\x1b[2m  allocation #4, 16 bytes at 0x4000 allocated at {at}, \
+                 matching 'app::main'\x1b[0m\
+                 \n\x1b[2mdetector overhead: 2.0 KiB: registry 2.0 KiB\x1b[0m"
+            )
+        );
+    }
+
+    #[test]
+    fn narrow() {
+        let (report, at) = report();
+        let text = report
+            .render_pretty(Some(50), ColorChoice::Never)
+            .to_string();
+        let callsite = truncate_middle(&at.to_string(), 50 - 35).into_owned();
+        let suppressed = truncate_middle(
+            &format!("allocation #4, 16 bytes at 0x4000 allocated at {at}, matching 'app::main'"),
+            48,
+        )
+        .into_owned();
+        assert_eq!(
+            text,
+            format!(
+                "2056 bytes leaked in 3 allocation(s)\
+                 \n  2048 bytes in 2 allocation(s) at {callsite}\
+                 \n    0xa0 ünïcödé::cache::En…::insert_with_capacity\
+                 \n    0xb0 app::main\
+                 \n     8 bytes in 1 allocation(s) at {callsite}\
+                 \n    0xb0 app::main\
+                 \n  by crate: ünïcödé 2048 b…ytes in 1 allocation(s)\
+                 \n16 bytes in 1 allocation(s) suppressed\
+                 \n  {suppressed}\
+                 \ndetector overhead: 2.0 KiB: registry 2.0 KiB"
+            )
+        );
+        assert!(
+            text.lines().all(|line| line.chars().count() <= 50),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn cuts_at_char_boundaries() {
+        assert_eq!(truncate_middle("κλειδί", 6), "κλειδί");
+        assert_eq!(truncate_middle("κλειδί", 5), "κλ…δί");
+        assert_eq!(truncate_middle("κλειδί", 4), "κλ…ί");
+        assert_eq!(truncate_middle("κλειδί", 1), "…");
+        assert_eq!(truncate_middle("κλειδί", 0), "");
+    }
+}
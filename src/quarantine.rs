@@ -0,0 +1,299 @@
+use std::{
+    alloc::{Allocator, GlobalAlloc, Layout},
+    collections::VecDeque,
+    fmt,
+    panic::Location,
+    ptr::NonNull,
+};
+
+use crate::{
+    LeakDetector,
+    overhead::{Account, Accounted, Internal, Locked},
+};
+
+/// Written over every block while it is quarantined.
+const PATTERN: u8 = 0xdd;
+
+/// Hands a block back to the inner allocator, which `*const ()` points to.
+pub(crate) type Release = unsafe fn(*const (), NonNull<u8>, Layout);
+
+pub(crate) unsafe fn release_allocator<A: Allocator>(
+    inner: *const (),
+    ptr: NonNull<u8>,
+    layout: Layout,
+) {
+    unsafe { (*inner.cast::<A>()).deallocate(ptr, layout) }
+}
+
+pub(crate) unsafe fn release_global<A: GlobalAlloc>(
+    inner: *const (),
+    ptr: NonNull<u8>,
+    layout: Layout,
+) {
+    unsafe { (*inner.cast::<A>()).dealloc(ptr.as_ptr(), layout) }
+}
+
+struct Block {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    allocated_at: Option<&'static Location<'static>>,
+    freed_at: &'static Location<'static>,
+    release: Release,
+}
+
+// The blocks are owned by the quarantine until released.
+unsafe impl Send for Block {}
+
+struct Held {
+    blocks: VecDeque<Block, Internal>,
+    bytes: usize,
+}
+
+/// Freed blocks held back from the inner allocator, oldest first, see
+/// [`LeakDetectorBuilder::quarantine`](crate::LeakDetectorBuilder::quarantine).
+/// They already count as freed, so they're not part of `used` or of any
+/// leak check.
+pub(crate) struct Quarantine {
+    max_bytes: usize,
+    max_blocks: usize,
+    verify: bool,
+    held: Accounted<Held>,
+}
+
+impl Quarantine {
+    pub(crate) const fn new(max_bytes: usize, max_blocks: usize, verify: bool) -> Self {
+        Self {
+            max_bytes,
+            max_blocks,
+            verify,
+            held: Accounted::new(Held {
+                blocks: VecDeque::new_in(Internal),
+                bytes: 0,
+            }),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_bytes != 0 && self.max_blocks != 0
+    }
+
+    fn lock(&self) -> Locked<'_, Held> {
+        self.held.lock()
+    }
+
+    pub(crate) fn account(&self) -> &Account {
+        self.held.account()
+    }
+}
+
+struct Callsite(Option<&'static Location<'static>>);
+
+impl fmt::Display for Callsite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(callsite) => write!(f, "{callsite}"),
+            None => f.write_str("an unknown callsite (the registry is off)"),
+        }
+    }
+}
+
+/// Panics, or only logs if the thread is already panicking. From a
+/// [`GlobalAlloc`] the panic aborts the process.
+#[cold]
+#[inline(never)]
+fn violation(message: fmt::Arguments<'_>) {
+    if std::thread::panicking() {
+        eprintln!("{message}");
+    } else {
+        panic!("{message}");
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Bytes freed but still held in quarantine. They're not in
+    /// [`get_used`](LeakDetector::get_used).
+    pub fn quarantined_bytes(&self) -> usize {
+        self.quarantine.lock().bytes
+    }
+
+    pub fn quarantined_blocks(&self) -> usize {
+        self.quarantine.lock().blocks.len()
+    }
+
+    /// Returns every quarantined block to the inner allocator, checking each
+    /// for writes after free first if verification is on. Dropping the
+    /// detector does this too.
+    pub fn flush_quarantine(&self) {
+        self.evict(true);
+    }
+
+    /// Counts the free of `ptr` and quarantines the block instead of handing
+    /// it to the inner allocator, releasing the oldest blocks once over
+    /// either limit. A block freed again while still quarantined is a double
+    /// free.
+    #[track_caller]
+    pub(crate) unsafe fn quarantine_block(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        usable: usize,
+        release: Release,
+    ) {
+        let freed_at = Location::caller();
+        let mut held = self.quarantine.lock();
+        if let Some(first) = held.blocks.iter().find(|block| block.ptr == ptr) {
+            let (allocated_at, first_freed_at) = (first.allocated_at, first.freed_at);
+            drop(held);
+            violation(format_args!(
+                "double free of {} bytes at {:#x} at {freed_at}, allocated at {}, first freed at {first_freed_at}",
+                layout.size(),
+                ptr.as_ptr() as usize,
+                Callsite(allocated_at),
+            ));
+            return;
+        }
+        let allocated_at = self.on_dealloc(ptr.as_ptr(), layout, usable);
+        unsafe { ptr.as_ptr().write_bytes(PATTERN, layout.size()) };
+        held.blocks.push_back(Block {
+            ptr,
+            layout,
+            allocated_at,
+            freed_at,
+            release,
+        });
+        held.bytes += layout.size();
+        drop(held);
+        self.evict(false);
+    }
+
+    /// Releases the oldest blocks while over a limit, or all of them.
+    fn evict(&self, all: bool) {
+        let quarantine = &self.quarantine;
+        loop {
+            let block = {
+                let mut held = quarantine.lock();
+                let over =
+                    held.blocks.len() > quarantine.max_blocks || held.bytes > quarantine.max_bytes;
+                if !(all || over) {
+                    return;
+                }
+                let Some(block) = held.blocks.pop_front() else {
+                    return;
+                };
+                held.bytes -= block.layout.size();
+                block
+            };
+            let size = block.layout.size();
+            let written = quarantine
+                .verify
+                .then(|| {
+                    unsafe { std::slice::from_raw_parts(block.ptr.as_ptr(), size) }
+                        .iter()
+                        .position(|&byte| byte != PATTERN)
+                })
+                .flatten();
+            unsafe { (block.release)((&raw const self.inner).cast(), block.ptr, block.layout) };
+            if let Some(offset) = written {
+                violation(format_args!(
+                    "write after free: {size} bytes at {:#x} changed at offset {offset}, allocated at {}, freed at {}",
+                    block.ptr.as_ptr() as usize,
+                    Callsite(block.allocated_at),
+                    block.freed_at,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, System},
+        panic::{AssertUnwindSafe, catch_unwind},
+    };
+
+    use super::*;
+
+    fn message(payload: Box<dyn std::any::Any + Send>) -> String {
+        payload.downcast::<String>().map(|s| *s).unwrap_or_default()
+    }
+
+    #[test]
+    fn write_after_free_found_in_eviction_order() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .quarantine(1 << 20, 2)
+            .verify_quarantine(true)
+            .build();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = detector.allocate(layout).unwrap().cast::<u8>();
+        let b = detector.allocate(layout).unwrap().cast::<u8>();
+        let c = detector.allocate(layout).unwrap().cast::<u8>();
+        let d = detector.allocate(layout).unwrap().cast::<u8>();
+        unsafe {
+            detector.deallocate(a, layout);
+            detector.deallocate(b, layout);
+            b.as_ptr().add(3).write(0);
+            // Releases `a`, which is untouched.
+            detector.deallocate(c, layout);
+        }
+        assert_eq!(detector.quarantined_blocks(), 2);
+        let err = catch_unwind(AssertUnwindSafe(|| unsafe {
+            detector.deallocate(d, layout)
+        }));
+        let message = message(err.unwrap_err());
+        assert!(
+            message.starts_with("write after free: 64 bytes"),
+            "{message}"
+        );
+        assert!(message.contains("offset 3"), "{message}");
+        assert!(
+            message.contains(&format!("allocated at {}", file!())),
+            "{message}"
+        );
+        assert!(
+            message.contains(&format!("freed at {}", file!())),
+            "{message}"
+        );
+        assert_eq!(detector.quarantined_blocks(), 2);
+        detector.flush_quarantine();
+        assert_eq!(detector.quarantined_bytes(), 0);
+    }
+
+    #[test]
+    fn catches_double_free() {
+        let detector = LeakDetector::builder(System)
+            .quarantine(1 << 20, 16)
+            .build();
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let block = detector.allocate(layout).unwrap().cast::<u8>();
+        unsafe { detector.deallocate(block, layout) };
+        let err = catch_unwind(AssertUnwindSafe(|| unsafe {
+            detector.deallocate(block, layout)
+        }));
+        assert!(message(err.unwrap_err()).starts_with("double free of 32 bytes"));
+        assert_eq!(detector.get_used(), 0);
+        assert_eq!(detector.quarantined_blocks(), 1);
+    }
+
+    #[test]
+    fn check_ignores_quarantine() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .quarantine(256, 16)
+            .build();
+        let buffer = Vec::<u8, _>::with_capacity_in(100, &detector);
+        let other = Vec::<u8, _>::with_capacity_in(100, &detector);
+        drop(buffer);
+        drop(other);
+        assert_eq!(detector.get_used(), 0);
+        assert_eq!(detector.quarantined_bytes(), 200);
+        assert!(detector.check().is_ok());
+        let big = Vec::<u8, _>::with_capacity_in(100, &detector);
+        drop(big);
+        // Over 256 bytes, so the oldest went back to the inner allocator.
+        assert_eq!(detector.quarantined_bytes(), 200);
+        assert!(detector.check().is_ok());
+    }
+}
@@ -0,0 +1,477 @@
+use std::{
+    alloc::{Layout, System},
+    cell::Cell,
+    collections::BTreeMap,
+    ops::Deref,
+    panic::Location,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::{
+    LeakDetector, ScopeAttribution, StackId, alignment_padding,
+    overhead::{Account, Accounted, Internal, Locked},
+    scope_stack::ScopeTag,
+    stack::StackTable,
+};
+
+/// One live allocation known to the registry.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Entry {
+    /// See [`LeakedAllocation::id`](crate::LeakedAllocation::id).
+    pub(crate) id: u64,
+    pub(crate) size: usize,
+    pub(crate) align: usize,
+    /// Usable size of the block, 0 when not tracked.
+    pub(crate) usable: usize,
+    pub(crate) padding: usize,
+    pub(crate) thread: u64,
+    pub(crate) scope: Option<ScopeTag>,
+    /// The registry's epoch when the block was allocated.
+    pub(crate) epoch: u64,
+    /// The registry's clock when the block was allocated, in milliseconds.
+    pub(crate) born: u64,
+    pub(crate) callsite: &'static Location<'static>,
+    pub(crate) stack: Option<StackId>,
+}
+
+/// The registry's map, with how many of its entries each scope holds. Reads
+/// go through to the map; changes go through here to keep the counts.
+pub(crate) struct Entries {
+    map: BTreeMap<usize, Entry, Internal>,
+    /// Live entries by the thread and innermost scope they were allocated
+    /// in, so that a scope counts its blocks without a scan.
+    scoped: BTreeMap<(u64, u64), usize, Internal>,
+}
+
+impl Deref for Entries {
+    type Target = BTreeMap<usize, Entry, Internal>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl Entries {
+    fn insert(&mut self, ptr: usize, entry: Entry) {
+        self.count(&entry, true);
+        if let Some(replaced) = self.map.insert(ptr, entry) {
+            self.count(&replaced, false);
+        }
+    }
+
+    fn remove(&mut self, ptr: usize) -> Option<Entry> {
+        let entry = self.map.remove(&ptr)?;
+        self.count(&entry, false);
+        Some(entry)
+    }
+
+    /// Keeps the entries `keep` accepts, returning the others.
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&Entry) -> bool) -> Vec<Entry, System> {
+        let mut removed = Vec::new_in(System);
+        self.map.retain(|_, entry| {
+            let kept = keep(entry);
+            if !kept {
+                removed.push(*entry);
+            }
+            kept
+        });
+        for entry in &removed {
+            self.count(entry, false);
+        }
+        removed
+    }
+
+    fn count(&mut self, entry: &Entry, live: bool) {
+        let Some(scope) = entry.scope else {
+            return;
+        };
+        let key = (entry.thread, scope.id);
+        if live {
+            *self.scoped.entry(key).or_insert(0) += 1;
+        } else if let Some(count) = self.scoped.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                self.scoped.remove(&key);
+            }
+        }
+    }
+}
+
+/// Every live allocation, keyed by address. Its own bookkeeping goes to
+/// [`Internal`], so it never re-enters the detector; nothing may allocate
+/// through the global allocator while the lock is held.
+pub(crate) struct Registry {
+    enabled: AtomicBool,
+    /// Every how many allocations per thread a stack is recorded, zero for
+    /// never.
+    backtrace_every: AtomicUsize,
+    /// Moves on with every snapshot, so entries can tell whether they were
+    /// allocated before or after one.
+    epoch: AtomicU64,
+    /// A coarse clock in milliseconds, moved by whoever drives it rather than
+    /// read from the OS on every allocation.
+    clock: AtomicU64,
+    /// The id the next allocation gets.
+    next_id: AtomicU64,
+    entries: Accounted<Entries>,
+    pub(crate) stacks: StackTable,
+}
+
+impl Registry {
+    pub(crate) const fn new(enabled: bool, backtrace_every: usize) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            backtrace_every: AtomicUsize::new(backtrace_every),
+            epoch: AtomicU64::new(1),
+            clock: AtomicU64::new(0),
+            next_id: AtomicU64::new(1),
+            entries: Accounted::new(Entries {
+                map: BTreeMap::new_in(Internal),
+                scoped: BTreeMap::new_in(Internal),
+            }),
+            stacks: StackTable::new(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Starts a new epoch, returning the one that ended.
+    pub(crate) fn advance_epoch(&self) -> u64 {
+        self.epoch.fetch_add(1, Ordering::AcqRel)
+    }
+
+    pub(crate) fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The id the next block will get, without taking it.
+    pub(crate) fn peek_id(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn clock(&self) -> u64 {
+        self.clock.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn advance_clock(&self, millis: u64) {
+        self.clock.fetch_add(millis, Ordering::Relaxed);
+    }
+
+    /// Moves the clock to `millis` unless it is already past it.
+    pub(crate) fn set_clock(&self, millis: u64) {
+        self.clock.fetch_max(millis, Ordering::Relaxed);
+    }
+
+    pub(crate) fn backtrace_sampling(&self) -> usize {
+        self.backtrace_every.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "backtrace")]
+    pub(crate) fn set_backtrace_sampling(&self, every: usize) {
+        self.backtrace_every.store(every, Ordering::Relaxed);
+    }
+
+    pub(crate) fn lock(&self) -> Locked<'_, Entries> {
+        self.entries.lock()
+    }
+
+    pub(crate) fn account(&self) -> &Account {
+        self.entries.account()
+    }
+
+    pub(crate) fn insert(&self, ptr: usize, entry: Entry) {
+        self.lock().insert(ptr, entry);
+    }
+
+    pub(crate) fn remove(&self, ptr: usize) -> Option<Entry> {
+        self.lock().remove(ptr)
+    }
+
+    /// Calls `f` with every live entry, unless the lock is held elsewhere,
+    /// for callers that can't wait and mustn't allocate.
+    pub(crate) fn try_for_each(&self, mut f: impl FnMut(&Entry)) -> bool {
+        let Some(entries) = self.entries.try_lock() else {
+            return false;
+        };
+        entries.values().for_each(&mut f);
+        true
+    }
+
+    /// Every live entry allocated after epoch `after`, with its address,
+    /// copied out so that the caller may allocate while going through them.
+    pub(crate) fn entries(&self, after: u64) -> Vec<(usize, Entry), System> {
+        let mut entries = Vec::new_in(System);
+        entries.extend(
+            self.lock()
+                .iter()
+                .filter(|(_, entry)| entry.epoch > after)
+                .map(|(&ptr, &entry)| (ptr, entry)),
+        );
+        entries
+    }
+
+    /// Bytes of the live entries allocated after epoch `after`.
+    pub(crate) fn bytes_after(&self, after: u64) -> usize {
+        self.lock()
+            .values()
+            .filter(|entry| entry.epoch > after)
+            .map(|entry| entry.size)
+            .sum()
+    }
+
+    /// Number of the live entries allocated on `thread` while scope
+    /// `scope_id` or one nested in it was the innermost scope.
+    pub(crate) fn count_in_scope(&self, thread: u64, scope_id: u64) -> usize {
+        self.lock()
+            .scoped
+            .range((thread, scope_id)..=(thread, u64::MAX))
+            .map(|(_, &count)| count)
+            .sum()
+    }
+
+    /// Moves the entry of a resized block to its new address in one step,
+    /// returning the entry as it was before the resize. `account` runs
+    /// before the lock is released, so that counters it updates never
+    /// disagree with the registry for anyone holding the lock. Call it only
+    /// once the inner allocator has resized the block.
+    pub(crate) fn resize(
+        &self,
+        old_ptr: usize,
+        new_ptr: usize,
+        new_layout: Layout,
+        new_usable: usize,
+        account: impl FnOnce(),
+    ) -> Option<Entry> {
+        let mut entries = self.lock();
+        let old = entries.remove(old_ptr)?;
+        account();
+        if new_layout.size() != 0 {
+            entries.insert(
+                new_ptr,
+                Entry {
+                    size: new_layout.size(),
+                    align: new_layout.align(),
+                    usable: new_usable,
+                    padding: alignment_padding(new_layout),
+                    ..old
+                },
+            );
+        }
+        Some(old)
+    }
+
+    /// Live blocks allocated on `thread` while scope `scope_id` or one nested
+    /// in it was the innermost scope, grouped by that innermost scope's name.
+    pub(crate) fn attribute(&self, thread: u64, scope_id: u64) -> Vec<ScopeAttribution> {
+        let mut groups = Vec::new_in(System);
+        for entry in self.lock().values() {
+            let Some(scope) = entry.scope.filter(|scope| scope.id >= scope_id) else {
+                continue;
+            };
+            if entry.thread != thread {
+                continue;
+            }
+            match groups
+                .iter_mut()
+                .find(|group: &&mut ScopeAttribution| group.scope_name == scope.name)
+            {
+                Some(group) => {
+                    group.bytes += entry.size;
+                    group.allocations += 1;
+                }
+                None => groups.push(ScopeAttribution {
+                    scope_name: scope.name,
+                    bytes: entry.size,
+                    allocations: 1,
+                }),
+            }
+        }
+        groups.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.scope_name.cmp(&b.scope_name)));
+        groups.into_iter().collect()
+    }
+}
+
+thread_local! {
+    static THREAD_TAG: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A small per-thread number, cheaper to get than a `ThreadId` and safe to
+/// read from inside the allocator.
+pub(crate) fn thread_tag() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    THREAD_TAG
+        .try_with(|tag| {
+            if tag.get() == 0 {
+                tag.set(NEXT.fetch_add(1, Ordering::Relaxed));
+            }
+            tag.get()
+        })
+        .unwrap_or(0)
+}
+
+impl<T> LeakDetector<T> {
+    pub fn registry_enabled(&self) -> bool {
+        self.registry.is_enabled()
+    }
+
+    /// Number of live allocations in the registry; zero-sized allocations
+    /// aren't kept there.
+    pub fn live_allocations(&self) -> usize {
+        self.registry.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, GlobalAlloc, Layout, System},
+        ptr::NonNull,
+    };
+
+    use super::*;
+    use crate::mock::{FailingAlloc, MovingAlloc};
+
+    /// The registry's entries as `(address, size)`, by address.
+    fn entries<T>(detector: &LeakDetector<T>) -> Vec<(usize, usize)> {
+        detector
+            .registry
+            .entries(0)
+            .iter()
+            .map(|&(address, entry)| (address, entry.size))
+            .collect()
+    }
+
+    #[test]
+    fn tracks_live_blocks() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let mut vec = Vec::<u8, _>::new_in(&detector);
+        vec.extend_from_slice(&[1; 100]);
+        let boxed = Box::new_in(1u64, &detector);
+        assert_eq!(detector.live_allocations(), 2);
+        vec.truncate(10);
+        vec.shrink_to_fit();
+        assert_eq!(detector.get_used(), 18);
+        drop((vec, boxed));
+        assert_eq!(detector.live_allocations(), 0);
+        detector.assert();
+    }
+
+    #[test]
+    fn paused_frees_of_tracked_blocks() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let tracked = Box::new_in([0u8; 32], &detector);
+        detector.pause();
+        let untracked = Box::new_in([0u8; 64], &detector);
+        drop(tracked);
+        detector.resume();
+        assert_eq!(detector.get_used(), 0);
+        drop(untracked);
+        detector.assert();
+    }
+
+    #[test]
+    fn moved_blocks_are_rekeyed() {
+        let detector = LeakDetector::builder(MovingAlloc::system())
+            .registry(true)
+            .build();
+        let (small, medium, large) = (
+            Layout::from_size_align(16, 8).unwrap(),
+            Layout::from_size_align(48, 8).unwrap(),
+            Layout::from_size_align(96, 8).unwrap(),
+        );
+        let block = detector.allocate(small).unwrap().cast::<u8>();
+        let grown = unsafe { detector.grow(block, small, medium) }
+            .unwrap()
+            .cast::<u8>();
+        assert_ne!(grown, block);
+        assert_eq!(entries(&detector), [(grown.as_ptr() as usize, 48)]);
+        let zeroed = unsafe { detector.grow_zeroed(grown, medium, large) }
+            .unwrap()
+            .cast::<u8>();
+        assert_eq!(entries(&detector), [(zeroed.as_ptr() as usize, 96)]);
+        let shrunk = unsafe { detector.shrink(zeroed, large, small) }
+            .unwrap()
+            .cast::<u8>();
+        assert_eq!(entries(&detector), [(shrunk.as_ptr() as usize, 16)]);
+        assert_eq!(detector.get_used(), 16);
+
+        let reallocated = unsafe { detector.realloc(shrunk.as_ptr(), small, 200) };
+        assert_ne!(reallocated, shrunk.as_ptr());
+        assert_eq!(entries(&detector), [(reallocated as usize, 200)]);
+        assert_eq!(detector.get_used(), 200);
+        let layout = Layout::from_size_align(200, 8).unwrap();
+        unsafe { detector.dealloc(reallocated, layout) };
+        assert!(entries(&detector).is_empty());
+        assert_eq!(detector.diagnostics().len(), 0);
+        detector.assert();
+    }
+
+    #[test]
+    fn ids_are_unique_and_follow_moves() {
+        let detector = LeakDetector::builder(MovingAlloc::system())
+            .registry(true)
+            .build();
+        let ids = || -> Vec<u64> {
+            let entries = detector.registry.entries(0);
+            entries.iter().map(|(_, entry)| entry.id).collect()
+        };
+        let first = Box::new_in(0u64, &detector);
+        let mut moving = Vec::<u8, _>::with_capacity_in(8, &detector);
+        let third = Box::new_in(0u32, &detector);
+        let mut before = ids();
+        before.sort();
+        assert_eq!(before, [1, 2, 3]);
+        let id = detector
+            .leak_report()
+            .allocations()
+            .iter()
+            .find(|allocation| allocation.address == moving.as_ptr() as usize)
+            .unwrap()
+            .id;
+
+        let old = moving.as_ptr();
+        moving.reserve_exact(100);
+        assert_ne!(moving.as_ptr(), old);
+        let report = detector.leak_report();
+        let moved = report
+            .allocations()
+            .iter()
+            .find(|allocation| allocation.address == moving.as_ptr() as usize)
+            .unwrap();
+        assert_eq!((moved.id, moved.size), (id, 100));
+        drop((first, moving, third));
+        assert_eq!(detector.registry.next_id(), 4);
+    }
+
+    #[test]
+    fn failed_resizes_leave_entries_alone() {
+        let detector = LeakDetector::builder(FailingAlloc::above(64))
+            .registry(true)
+            .build();
+        let (small, large) = (
+            Layout::from_size_align(32, 8).unwrap(),
+            Layout::from_size_align(128, 8).unwrap(),
+        );
+        let block = detector.allocate(small).unwrap().cast::<u8>();
+        let before = entries(&detector);
+        assert_eq!(before, [(block.as_ptr() as usize, 32)]);
+        assert!(unsafe { detector.grow(block, small, large) }.is_err());
+        assert!(unsafe { detector.grow_zeroed(block, small, large) }.is_err());
+        assert!(unsafe { detector.realloc(block.as_ptr(), small, 128) }.is_null());
+        assert_eq!(entries(&detector), before);
+        assert_eq!(detector.get_used(), 32);
+
+        let shrunk = unsafe { detector.shrink(block, small, Layout::new::<u64>()) }.unwrap();
+        let shrunk: NonNull<u8> = shrunk.cast();
+        assert_eq!(entries(&detector), [(shrunk.as_ptr() as usize, 8)]);
+        assert_eq!(detector.get_used(), 8);
+        unsafe { detector.deallocate(shrunk, Layout::new::<u64>()) };
+        detector.assert();
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    fmt,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{LeakDetector, LeakReport, SuppressedAllocation, report::HumanBytes, summary};
+
+/// The order sites are listed in, biggest first by each measure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortSites {
+    BySize,
+    ByCount,
+    /// By the age of each site's oldest allocation.
+    ByAge,
+}
+
+/// How a [`LeakReport`] prints, see [`LeakReport::render`]. Its allocations
+/// are grouped into sites by callsite and stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportOptions {
+    pub sort: SortSites,
+    /// Sites listed; the rest are summed up on one line.
+    pub top: usize,
+    pub max_frames: usize,
+    /// Sites with fewer bytes are only counted in the summed up line.
+    pub min_bytes: usize,
+}
+
+impl ReportOptions {
+    /// The top 20 sites by size, with 8 frames each.
+    pub const DEFAULT: Self = Self {
+        sort: SortSites::BySize,
+        top: 20,
+        max_frames: 8,
+        min_bytes: 0,
+    };
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A [`LeakReport`] printed with some [`ReportOptions`].
+pub struct Rendered<'a> {
+    report: &'a LeakReport,
+    options: ReportOptions,
+}
+
+impl LeakReport {
+    /// Prints as the report's own `Display` does, with `options` instead of
+    /// [`options`](LeakReport::options).
+    pub fn render(&self, options: &ReportOptions) -> Rendered<'_> {
+        Rendered {
+            report: self,
+            options: *options,
+        }
+    }
+}
+
+/// The first line of a report: how much leaked, with notes on sampling,
+/// usable sizes, padding and stacks.
+pub(crate) struct Header<'a>(pub(crate) &'a LeakReport);
+
+impl fmt::Display for Header<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = self.0;
+        let allocations = report.allocations();
+        let bytes = report.bytes();
+        match report.sampling() {
+            Some(sampling) => write!(
+                f,
+                "~{} bytes leaked (estimated from {} sampled allocation(s) of {bytes} bytes, \
+                 {sampling})",
+                report.estimated_bytes(),
+                allocations.len()
+            )?,
+            None => write!(
+                f,
+                "{bytes} bytes leaked in {} allocation(s)",
+                allocations.len()
+            )?,
+        }
+        let usable: Option<usize> = allocations
+            .iter()
+            .map(|allocation| allocation.usable_size)
+            .sum();
+        if let Some(usable) = usable.filter(|_| !allocations.is_empty()) {
+            write!(
+                f,
+                " (requested {}, resident in allocator {})",
+                HumanBytes(bytes),
+                HumanBytes(usable)
+            )?;
+        }
+        let padding: usize = allocations
+            .iter()
+            .map(|allocation| allocation.padding)
+            .sum();
+        if padding != 0 {
+            write!(f, " (plus ~{padding} bytes of alignment padding)")?;
+        }
+        let with_stacks = report.bytes_with_stacks();
+        if report.backtrace_sampling != 0 && with_stacks != bytes {
+            let percent = (with_stacks * 100 + bytes / 2) / bytes;
+            write!(f, " (~{percent}% of leaked bytes have stacks)")?;
+        }
+        Ok(())
+    }
+}
+
+/// The `by crate` and `by age` lines after the sites, each starting with a
+/// newline, or nothing.
+pub(crate) struct Breakdown<'a>(pub(crate) &'a LeakReport);
+
+impl fmt::Display for Breakdown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = self.0;
+        crate::crates::write_by_crate(f, &report.by_crate())?;
+        if report.clock_running && !report.allocations().is_empty() {
+            write!(f, "\n  by age: {}", report.age_distribution())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Rendered<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = self.report;
+        Header(report).fmt(f)?;
+        let sites = report.sites(self.options.sort);
+        let (shown, rest) = summary::split_sites(&sites, self.options.top, self.options.min_bytes);
+        for site in shown {
+            site.write(f, report, self.options.max_frames)?;
+        }
+        if !rest.is_empty() {
+            write!(
+                f,
+                "\n  … and {} more site(s) totalling {}",
+                rest.len(),
+                HumanBytes(rest.iter().map(|site| site.bytes).sum())
+            )?;
+        }
+        Breakdown(report).fmt(f)?;
+        if !report.suppressed().is_empty() {
+            write!(
+                f,
+                "\n{} bytes in {} allocation(s) suppressed",
+                report.suppressed_bytes(),
+                report.suppressed().len()
+            )?;
+            for suppressed in report.suppressed() {
+                write!(f, "\n  {suppressed}")?;
+            }
+        }
+        if let Some(overhead) = report.overhead.filter(|overhead| overhead.bytes() != 0) {
+            write!(f, "\n{overhead}")?;
+        }
+        Ok(())
+    }
+}
+
+/// `allocation #id, size bytes at address allocated at callsite, matching
+/// 'pattern'`.
+impl fmt::Display for SuppressedAllocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let SuppressedAllocation {
+            allocation,
+            pattern,
+        } = self;
+        write!(
+            f,
+            "allocation #{}, {} bytes at {:#x} allocated at {}, matching '{pattern}'",
+            allocation.id, allocation.size, allocation.address, allocation.callsite
+        )
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// How the detector's [`leak_report`](LeakDetector::leak_report)s print,
+    /// including when a balance guard panics, and how
+    /// [`report_at_exit`](LeakDetector::report_at_exit) orders and filters
+    /// its sites.
+    pub fn set_report_options(&self, options: ReportOptions) {
+        *lock(&self.report_options) = options;
+    }
+
+    pub fn report_options(&self) -> ReportOptions {
+        *lock(&self.report_options)
+    }
+}
+
+fn lock(options: &Mutex<ReportOptions>) -> std::sync::MutexGuard<'_, ReportOptions> {
+    options.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{Allocator, Layout, System},
+        collections::BTreeMap,
+        panic::Location,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{LeakedAllocation, StackId};
+
+    /// 50 sites sharing a callsite, told apart by their two-frame stacks.
+    /// Site `i` has `1 + i * 3 % 5` allocations of `16 * (50 - i)` bytes,
+    /// each `i * 7 % 50` seconds old.
+    fn report() -> (LeakReport, &'static Location<'static>) {
+        let callsite = Location::caller();
+        let mut allocations = Vec::new();
+        let mut stacks = BTreeMap::new();
+        for i in 0..50 {
+            stacks.insert(StackId::from_index(i), vec![0x1000 + i as usize, 0x2000]);
+            for n in 0..1 + i * 3 % 5 {
+                allocations.push(LeakedAllocation {
+                    id: allocations.len() as u64 + 1,
+                    address: 0x10000 * (i as usize + 1) + 0x1000 * n as usize,
+                    size: 16 * (50 - i as usize),
+                    usable_size: None,
+                    padding: 0,
+                    callsite,
+                    age: Duration::from_secs(u64::from(i * 7 % 50)),
+                    stack: Some(StackId::from_index(i)),
+                });
+            }
+        }
+        (
+            LeakReport::from_parts(allocations, stacks, BTreeMap::new()),
+            callsite,
+        )
+    }
+
+    fn render(report: &LeakReport, sort: SortSites, min_bytes: usize) -> String {
+        let options = ReportOptions {
+            sort,
+            top: 3,
+            max_frames: 1,
+            min_bytes,
+        };
+        report.render(&options).to_string()
+    }
+
+    #[test]
+    fn by_size() {
+        let (report, at) = report();
+        assert_eq!(
+            render(&report, SortSites::BySize, 0),
+            format!(
+                "60400 bytes leaked in 150 allocation(s)\
+                 \n  3760 bytes in 5 allocation(s) at {at}\n    0x1003\n    ... 1 more frame(s)\
+                 \n  3360 bytes in 5 allocation(s) at {at}\n    0x1008\n    ... 1 more frame(s)\
+                 \n  3136 bytes in 4 allocation(s) at {at}\n    0x1001\n    ... 1 more frame(s)\
+                 \n  … and 47 more site(s) totalling 49.0 KiB"
+            )
+        );
+    }
+
+    #[test]
+    fn by_count() {
+        let (report, at) = report();
+        assert_eq!(
+            render(&report, SortSites::ByCount, 0),
+            format!(
+                "60400 bytes leaked in 150 allocation(s)\
+                 \n  3760 bytes in 5 allocation(s) at {at}\n    0x1003\n    ... 1 more frame(s)\
+                 \n  3360 bytes in 5 allocation(s) at {at}\n    0x1008\n    ... 1 more frame(s)\
+                 \n  2960 bytes in 5 allocation(s) at {at}\n    0x100d\n    ... 1 more frame(s)\
+                 \n  … and 47 more site(s) totalling 49.1 KiB"
+            )
+        );
+    }
+
+    #[test]
+    fn by_age_above_min_bytes() {
+        let (report, at) = report();
+        assert_eq!(
+            render(&report, SortSites::ByAge, 1800),
+            format!(
+                "60400 bytes leaked in 150 allocation(s)\
+                 \n  1856 bytes in 4 allocation(s) at {at}\n    0x1015\n    ... 1 more frame(s)\
+                 \n  2816 bytes in 4 allocation(s) at {at}\n    0x1006\n    ... 1 more frame(s)\
+                 \n  2960 bytes in 5 allocation(s) at {at}\n    0x100d\n    ... 1 more frame(s)\
+                 \n  … and 47 more site(s) totalling 51.5 KiB"
+            )
+        );
+    }
+
+    #[test]
+    fn defaults_and_detector_options() {
+        let (report, _) = report();
+        let text = report.to_string();
+        assert_eq!(text.lines().count(), 1 + 20 * 3 + 1);
+        assert!(text.ends_with("\n  … and 30 more site(s) totalling 16.5 KiB"));
+
+        let detector = LeakDetector::builder(System).registry(true).build();
+        detector.set_report_options(ReportOptions {
+            top: 1,
+            ..ReportOptions::DEFAULT
+        });
+        let (big, small) = (Layout::new::<[u8; 64]>(), Layout::new::<u32>());
+        let blocks = [
+            (detector.allocate(big).unwrap(), big),
+            (detector.allocate(small).unwrap(), small),
+        ];
+        let text = detector.leak_report().to_string();
+        assert_eq!(text.lines().count(), 4, "{text}");
+        assert!(text.contains("\n  … and 1 more site(s) totalling 4 B\ndetector overhead: "));
+        for (block, layout) in blocks {
+            unsafe { detector.deallocate(block.cast(), layout) };
+        }
+    }
+}
@@ -0,0 +1,465 @@
+use std::{collections::BTreeMap, fmt, panic::Location, path::PathBuf, time::Duration};
+
+use crate::{
+    LeakDetector, Overhead, ReportOptions, Sampling, StackId,
+    ledger::Ledger,
+    stack::{Backend, StackCapture, StackTable},
+    suppress::Suppression,
+};
+
+/// The allocations live in a detector's registry when the report was taken.
+#[derive(Debug, Clone)]
+pub struct LeakReport {
+    pub(crate) allocations: Vec<LeakedAllocation>,
+    /// Each stack the allocations refer to, once.
+    stacks: BTreeMap<StackId, Vec<usize>>,
+    /// The frames of stacks the backend resolved while capturing.
+    resolved: BTreeMap<StackId, Vec<Vec<Symbol>>>,
+    /// What each instruction pointer resolved to, once symbolized.
+    symbols: BTreeMap<usize, Vec<Symbol>>,
+    pub(crate) suppressed: Vec<SuppressedAllocation>,
+    pub(crate) backtrace_sampling: usize,
+    /// Whether the detector's clock had started, so that ages mean something.
+    pub(crate) clock_running: bool,
+    pub(crate) options: ReportOptions,
+    pub(crate) sampling: Option<Sampling>,
+    /// The detector's own memory when the report was taken.
+    pub(crate) overhead: Option<Overhead>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeakedAllocation {
+    /// Unique in the detector, counting up from 1 in the order blocks were
+    /// allocated. A block keeps it when grown or shrunk, even if it moves.
+    pub id: u64,
+    pub address: usize,
+    pub size: usize,
+    /// What the inner allocator reserved for the block, when tracked.
+    pub usable_size: Option<usize>,
+    /// See [`LeakDetector::padding_bytes`].
+    pub padding: usize,
+    pub callsite: &'static Location<'static>,
+    /// How long the block had been live when the report was taken, by the
+    /// detector's clock.
+    pub age: Duration,
+    /// `None` when stacks are off, the allocation wasn't sampled or the
+    /// detector already held [`MAX_STACKS`](crate::MAX_STACKS) stacks.
+    pub stack: Option<StackId>,
+}
+
+/// A live allocation excused by a suppression, see
+/// [`LeakDetector::add_callsite_suppression`] and its siblings.
+#[derive(Debug, Clone)]
+pub struct SuppressedAllocation {
+    pub allocation: LeakedAllocation,
+    pub pattern: String,
+}
+
+/// One function an instruction pointer resolved to. Inlined calls give
+/// several, innermost first.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    pub name: Option<String>,
+    pub file: Option<PathBuf>,
+    pub line: Option<u32>,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name.as_deref().unwrap_or("<unknown>"))?;
+        if let Some(file) = &self.file {
+            write!(f, " at {}", file.display())?;
+            if let Some(line) = self.line {
+                write!(f, ":{line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LeakReport {
+    pub(crate) fn new(
+        allocations: Vec<LeakedAllocation>,
+        clock_running: bool,
+        options: ReportOptions,
+    ) -> Self {
+        LeakReport {
+            allocations,
+            stacks: BTreeMap::new(),
+            resolved: BTreeMap::new(),
+            symbols: BTreeMap::new(),
+            suppressed: Vec::new(),
+            backtrace_sampling: 0,
+            clock_running,
+            options,
+            sampling: None,
+            overhead: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_parts(
+        allocations: Vec<LeakedAllocation>,
+        stacks: BTreeMap<StackId, Vec<usize>>,
+        symbols: BTreeMap<usize, Vec<Symbol>>,
+    ) -> Self {
+        LeakReport {
+            allocations,
+            stacks,
+            resolved: BTreeMap::new(),
+            symbols,
+            suppressed: Vec::new(),
+            backtrace_sampling: 0,
+            clock_running: false,
+            options: ReportOptions::DEFAULT,
+            sampling: None,
+            overhead: None,
+        }
+    }
+
+    /// Sorted by address.
+    pub fn allocations(&self) -> &[LeakedAllocation] {
+        &self.allocations
+    }
+
+    /// Raw instruction pointers of a stack, innermost first. Empty with the
+    /// `backtrace-std` backend, whose frames only show in the report's text.
+    pub fn stack(&self, id: StackId) -> Option<&[usize]> {
+        self.stacks.get(&id).map(Vec::as_slice)
+    }
+
+    /// How the report prints, the detector's
+    /// [`report_options`](LeakDetector::report_options) when it was taken.
+    pub fn options(&self) -> &ReportOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: ReportOptions) {
+        self.options = options;
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.allocations
+            .iter()
+            .map(|allocation| allocation.size)
+            .sum()
+    }
+
+    /// How the detector sampled the allocations, `None` when each one was
+    /// registered.
+    pub fn sampling(&self) -> Option<Sampling> {
+        self.sampling
+    }
+
+    /// [`bytes`](LeakReport::bytes) scaled up by the sampling rate, the
+    /// estimate of what all allocations leaked; the same without sampling.
+    pub fn estimated_bytes(&self) -> usize {
+        self.allocations
+            .iter()
+            .map(|allocation| self.estimate(allocation.size))
+            .sum()
+    }
+
+    pub(crate) fn estimate(&self, size: usize) -> usize {
+        self.sampling
+            .map_or(size, |sampling| sampling.estimate(size))
+    }
+
+    /// Resolves every instruction pointer of the report's stacks to function
+    /// names and source lines, each address once. Slow and allocating, so
+    /// reports are taken raw and only symbolized on request; until then they
+    /// print hex addresses. The `backtrace-std` backend resolves stacks as it
+    /// captures them, so this does nothing there.
+    #[cfg(feature = "backtrace")]
+    pub fn symbolize(&mut self) {
+        #[cfg(feature = "backtrace-crate")]
+        for &ip in self.stacks.values().flatten() {
+            self.symbols.entry(ip).or_insert_with(|| resolve(ip));
+        }
+    }
+
+    /// Each frame of `stack`, innermost first, with its instruction pointer
+    /// if the backend has them and whatever it resolved to so far.
+    pub(crate) fn frames(&self, stack: Option<StackId>) -> Vec<(Option<usize>, &[Symbol])> {
+        let Some(id) = stack else {
+            return Vec::new();
+        };
+        if let Some(resolved) = self.resolved.get(&id) {
+            return resolved
+                .iter()
+                .map(|symbols| (None, symbols.as_slice()))
+                .collect();
+        }
+        self.stack(id)
+            .unwrap_or_default()
+            .iter()
+            .map(|&ip| (Some(ip), self.symbols(ip).unwrap_or_default()))
+            .collect()
+    }
+
+    /// What `ip` resolved to; `None` until [`symbolize`] has run.
+    ///
+    /// [`symbolize`]: LeakReport::symbolize
+    pub fn symbols(&self, ip: usize) -> Option<&[Symbol]> {
+        self.symbols.get(&ip).map(Vec::as_slice)
+    }
+
+    /// Allocations left out of [`allocations`] by a suppression.
+    ///
+    /// [`allocations`]: LeakReport::allocations
+    /// What the detector had allocated for itself when the report was
+    /// taken, see [`LeakDetector::self_overhead`].
+    pub fn overhead(&self) -> Option<Overhead> {
+        self.overhead
+    }
+
+    pub fn suppressed(&self) -> &[SuppressedAllocation] {
+        &self.suppressed
+    }
+
+    pub fn suppressed_bytes(&self) -> usize {
+        self.suppressed
+            .iter()
+            .map(|suppressed| suppressed.allocation.size)
+            .sum()
+    }
+
+    /// Moves the allocations `suppressions` excuse, first match wins, to
+    /// [`suppressed`].
+    ///
+    /// [`suppressed`]: LeakReport::suppressed
+    /// Copies the stacks the allocations refer to out of `table`.
+    pub(crate) fn add_stacks(&mut self, table: &StackTable) {
+        for id in self
+            .allocations
+            .iter()
+            .filter_map(|allocation| allocation.stack)
+        {
+            if self.stacks.contains_key(&id) {
+                continue;
+            }
+            if let Some(stack) = table.get(id) {
+                self.stacks.insert(id, Backend::ips(&stack).to_vec());
+                if let Some(frames) = Backend::resolved(&stack) {
+                    self.resolved.insert(id, frames.to_vec());
+                }
+            }
+        }
+    }
+
+    pub(crate) fn suppress(&mut self, suppressions: &[Suppression]) {
+        if suppressions.is_empty() {
+            return;
+        }
+        #[cfg(feature = "backtrace")]
+        if suppressions
+            .iter()
+            .any(|suppression| suppression.symbol().is_some())
+        {
+            self.symbolize();
+        }
+        let (suppressed, kept) = std::mem::take(&mut self.allocations)
+            .into_iter()
+            .map(|allocation| {
+                let suppression = self.matching_suppression(&allocation, suppressions);
+                (allocation, suppression)
+            })
+            .partition::<Vec<_>, _>(|(_, suppression)| suppression.is_some());
+        self.allocations = kept.into_iter().map(|(allocation, _)| allocation).collect();
+        self.suppressed
+            .extend(
+                suppressed
+                    .into_iter()
+                    .map(|(allocation, suppression)| SuppressedAllocation {
+                        allocation,
+                        pattern: suppression.unwrap().to_string(),
+                    }),
+            );
+    }
+
+    /// Takes in another detector's allocations, dropping their stacks,
+    /// whose ids only mean something to that detector.
+    #[cfg(all(unix, feature = "c-intercept"))]
+    pub(crate) fn absorb(&mut self, other: LeakReport) {
+        let unstacked = |mut allocation: LeakedAllocation| {
+            allocation.stack = None;
+            allocation
+        };
+        self.allocations
+            .extend(other.allocations.into_iter().map(unstacked));
+        self.allocations
+            .sort_by_key(|allocation| allocation.address);
+        self.suppressed
+            .extend(
+                other
+                    .suppressed
+                    .into_iter()
+                    .map(|suppressed| SuppressedAllocation {
+                        allocation: unstacked(suppressed.allocation),
+                        pattern: suppressed.pattern,
+                    }),
+            );
+    }
+
+    fn matching_suppression<'s>(
+        &self,
+        allocation: &LeakedAllocation,
+        suppressions: &'s [Suppression],
+    ) -> Option<&'s Suppression> {
+        #[cfg(feature = "backtrace")]
+        let names: Vec<&str> = self
+            .frames(allocation.stack)
+            .into_iter()
+            .flat_map(|(_, symbols)| symbols)
+            .filter_map(|symbol| symbol.name.as_deref())
+            .collect();
+        suppressions.iter().find(|suppression| {
+            #[cfg(feature = "backtrace")]
+            if let Some(pattern) = suppression.symbol() {
+                return names.iter().any(|name| name.contains(pattern));
+            }
+            suppression.matches_block(allocation)
+        })
+    }
+
+    /// Writes the first `max` frames of `stack` one per line, symbolized if
+    /// the report is, noting how many were left out.
+    pub(crate) fn write_frames(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        stack: Option<StackId>,
+        max: usize,
+    ) -> fmt::Result {
+        let frames = self.frames(stack);
+        for &(ip, symbols) in frames.iter().take(max) {
+            write!(f, "\n    ")?;
+            match (ip, symbols) {
+                (_, [first, inlined @ ..]) => {
+                    if let Some(ip) = ip {
+                        write!(f, "{ip:#x} ")?;
+                    }
+                    write!(f, "{first}")?;
+                    for symbol in inlined {
+                        write!(f, "\n      inlined into {symbol}")?;
+                    }
+                }
+                (Some(ip), []) => write!(f, "{ip:#x}")?,
+                (None, []) => write!(f, "<unknown>")?,
+            }
+        }
+        if frames.len() > max {
+            write!(f, "\n    ... {} more frame(s)", frames.len() - max)?;
+        }
+        Ok(())
+    }
+
+    /// Leaked bytes whose allocation recorded a stack.
+    pub fn bytes_with_stacks(&self) -> usize {
+        self.allocations
+            .iter()
+            .filter(|allocation| allocation.stack.is_some())
+            .map(|allocation| allocation.size)
+            .sum()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(&self.options).fmt(f)
+    }
+}
+
+/// A byte count in binary units, to one decimal place.
+pub(crate) struct HumanBytes(pub(crate) usize);
+
+impl fmt::Display for HumanBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit + 1 < UNITS.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// What the `backtrace` crate resolves `ip` to, one symbol per inlined call.
+#[cfg(feature = "backtrace-crate")]
+pub(crate) fn resolve(ip: usize) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    backtrace::resolve(ip as *mut std::ffi::c_void, |symbol| {
+        symbols.push(Symbol {
+            name: symbol.name().map(|name| format!("{name:#}")),
+            file: symbol.filename().map(PathBuf::from),
+            line: symbol.lineno(),
+        });
+    });
+    symbols
+}
+
+impl<T> LeakDetector<T> {
+    /// Every allocation made since the baseline that is still live in the
+    /// registry; empty when the registry is off.
+    pub fn leak_report(&self) -> LeakReport {
+        let mut report = self.report_after(self.baseline_snapshot().epoch, self.report_options());
+        report.add_stacks(&self.registry.stacks);
+        report.backtrace_sampling = self.registry.backtrace_sampling();
+        report.sampling = self.sampling();
+        self.with_suppressions(|suppressions| report.suppress(suppressions));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn lists_live_allocations() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let small = Box::new_in([0u8; 8], &detector);
+        let large = Box::new_in([0u8; 24], &detector);
+        let report = detector.leak_report();
+        assert_eq!(report.bytes(), 32);
+        assert_eq!(report.bytes_with_stacks(), 0);
+        let text = report.to_string();
+        assert!(text.starts_with("32 bytes leaked in 2 allocation(s)\n"));
+        assert!(!text.contains("have stacks"));
+        drop((small, large));
+        assert!(detector.leak_report().allocations().is_empty());
+    }
+
+    #[test]
+    fn human_bytes() {
+        assert_eq!(HumanBytes(512).to_string(), "512 B");
+        assert_eq!(HumanBytes(1 << 20).to_string(), "1.0 MiB");
+        assert_eq!(HumanBytes(1363149).to_string(), "1.3 MiB");
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[test]
+    fn symbolized_names() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .backtraces(true)
+            .build();
+        detector.set_report_options(ReportOptions {
+            max_frames: usize::MAX,
+            ..ReportOptions::DEFAULT
+        });
+        let leaked = Box::new_in(1u64, &detector);
+        let mut report = detector.leak_report();
+        #[cfg(feature = "backtrace-crate")]
+        assert!(!report.to_string().contains("symbolized_names"));
+        report.symbolize();
+        assert!(report.to_string().contains("symbolized_names"));
+        drop(leaked);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{cell::Cell, fmt};
+
+use crate::{LeakDetector, report::HumanBytes};
+
+/// Which allocations a sampling detector registers, see
+/// [`LeakDetectorBuilder::sampling`](crate::LeakDetectorBuilder::sampling).
+/// Either way the decision is a countdown per thread, shared by the
+/// detectors sampling on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    /// Every `n`th allocation on each thread.
+    Every(usize),
+    /// On average one allocation per this many bytes allocated on each
+    /// thread, at random intervals, as heap profilers sample. A block is
+    /// likelier to be sampled the bigger it is.
+    Bytes(usize),
+}
+
+impl Sampling {
+    /// The bytes a sampled block of `size` stands for.
+    pub fn estimate(self, size: usize) -> usize {
+        match self {
+            Sampling::Every(n) => size.saturating_mul(n.max(1)),
+            Sampling::Bytes(0) => size,
+            Sampling::Bytes(rate) => {
+                let chance = -(-(size as f64) / rate as f64).exp_m1();
+                (size as f64 / chance).round() as usize
+            }
+        }
+    }
+}
+
+impl fmt::Display for Sampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Sampling::Every(n) => write!(f, "sampling every {n} allocation(s)"),
+            Sampling::Bytes(rate) => write!(f, "sampling once per ~{} allocated", HumanBytes(rate)),
+        }
+    }
+}
+
+/// A detector's sampling settings.
+pub(crate) struct Sampler {
+    sampling: Option<Sampling>,
+    seed: u64,
+}
+
+/// What's left on this thread before the next sample, allocations or
+/// bytes, and the generator its random intervals come from.
+#[derive(Clone, Copy)]
+struct Countdown {
+    left: usize,
+    random: u64,
+}
+
+thread_local! {
+    static COUNTDOWN: Cell<Option<Countdown>> = const { Cell::new(None) };
+}
+
+/// splitmix64.
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Sampler {
+    pub(crate) const fn new(sampling: Option<Sampling>, seed: u64) -> Self {
+        Self { sampling, seed }
+    }
+
+    pub(crate) fn get(&self) -> Option<Sampling> {
+        self.sampling
+    }
+
+    /// The next interval: fixed for [`Sampling::Every`], exponentially
+    /// distributed around the rate for [`Sampling::Bytes`].
+    fn interval(sampling: Sampling, random: &mut u64) -> usize {
+        match sampling {
+            Sampling::Every(n) => n.max(1),
+            Sampling::Bytes(rate) => {
+                // Uniform in (0, 1], so the logarithm stays finite.
+                let uniform = ((next_random(random) >> 11) + 1) as f64 / (1u64 << 53) as f64;
+                (-uniform.ln() * rate as f64) as usize + 1
+            }
+        }
+    }
+
+    /// Whether a block of `size` being allocated on this thread gets a
+    /// registry entry. Always with sampling off.
+    #[inline]
+    pub(crate) fn sample(&self, size: usize) -> bool {
+        let Some(sampling) = self.sampling else {
+            return true;
+        };
+        COUNTDOWN
+            .try_with(|cell| {
+                let mut countdown = cell.get().unwrap_or_else(|| {
+                    let mut random = self.seed;
+                    Countdown {
+                        left: Self::interval(sampling, &mut random),
+                        random,
+                    }
+                });
+                let step = match sampling {
+                    Sampling::Every(_) => 1,
+                    Sampling::Bytes(_) => size,
+                };
+                let sampled = step >= countdown.left;
+                countdown.left = if sampled {
+                    Self::interval(sampling, &mut countdown.random)
+                } else {
+                    countdown.left - step
+                };
+                cell.set(Some(countdown));
+                sampled
+            })
+            .unwrap_or(false)
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// How allocations are sampled into the registry, `None` when each one
+    /// is.
+    pub fn sampling(&self) -> Option<Sampling> {
+        self.sampler.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::System, thread};
+
+    use super::*;
+
+    /// Allocates `count` blocks of `size` on a fresh thread and returns the
+    /// report's sampled allocations and estimate, keeping the blocks live
+    /// until then.
+    fn run(detector: &LeakDetector<System>, count: usize, size: usize) -> (usize, usize) {
+        thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let mut blocks = Vec::with_capacity_in(count, System);
+                    for _ in 0..count {
+                        blocks.push(Vec::<u8, _>::with_capacity_in(size, detector));
+                    }
+                    let report = detector.leak_report();
+                    (report.allocations().len(), report.estimated_bytes())
+                })
+                .join()
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn every_nth_scales_exactly() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .sampling(Sampling::Every(10))
+            .build();
+        assert_eq!(run(&detector, 1000, 48), (100, 48_000));
+        assert_eq!(detector.get_used(), 0);
+        assert_eq!(detector.leak_report().estimated_bytes(), 0);
+    }
+
+    #[test]
+    fn byte_rate_converges_and_repeats() {
+        const COUNT: usize = 100_000;
+        let sampled = || {
+            LeakDetector::builder(System)
+                .registry(true)
+                .sampling(Sampling::Bytes(4096))
+                .sampling_seed(7)
+                .build()
+        };
+        let detector = sampled();
+        let (samples, estimate) = run(&detector, COUNT, 64);
+        let truth = COUNT * 64;
+        assert!(samples > 1000 && samples < 2200, "{samples}");
+        assert!(
+            estimate.abs_diff(truth) < truth / 10,
+            "{estimate} for {truth}"
+        );
+        assert_eq!(run(&sampled(), COUNT, 64), (samples, estimate));
+
+        // Exact counters stay exact, and leaks still count them.
+        let leaked = Vec::<u8, _>::with_capacity_in(64, &detector);
+        assert_eq!(detector.get_used(), 64);
+        assert_eq!(detector.snapshot().allocations, COUNT + 1);
+        assert!(detector.check().is_err());
+        drop(leaked);
+        assert!(detector.check().is_ok());
+    }
+
+    #[test]
+    fn estimates_are_marked() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .sampling(Sampling::Every(4))
+            .build();
+        let blocks: Vec<_> = (0..8)
+            .map(|_| Vec::<u8, _>::with_capacity_in(100, &detector))
+            .collect();
+        let text = detector.leak_report().to_string();
+        assert!(
+            text.starts_with(
+                "~800 bytes leaked (estimated from 2 sampled allocation(s) of 200 bytes, \
+                 sampling every 4 allocation(s))\n  ~800 bytes in 2 sampled allocation(s) at "
+            ),
+            "{text}"
+        );
+        drop(blocks);
+    }
+}
@@ -1,17 +1,187 @@
-use crate::LeakDetector;
+use std::{ops::RangeInclusive, panic::Location, time::Duration};
+
+use crate::{
+    FailureMode, FirstFailure, LeakDetector, LeakedAllocation, OnLeak, ScopeError, budget,
+    ledger::Ledger, registry, scope_stack, wait,
+};
 
 pub struct LeakDetectorScope<'a, T> {
-    detector: &'a LeakDetector<T>,
+    pub(crate) detector: &'a LeakDetector<T>,
+    pub(crate) id: u64,
     start: usize,
+    /// Allocations minus deallocations when the scope opened.
+    start_count: usize,
+    /// The registry epoch before the scope opened; blocks allocated while it
+    /// was open, in nested scopes too, have a later one.
+    epoch: u64,
+    pub(crate) name: Option<&'static str>,
+    location: &'static Location<'static>,
+    max_delta: Option<usize>,
+    expected_retention: Option<RangeInclusive<usize>>,
+    grace_period: Option<Duration>,
+    budget: bool,
+    on_leak: Option<OnLeak>,
+    failure_mode: Option<FailureMode>,
+    bytes_only: bool,
+    defused: bool,
+}
+
+/// Why a scope ended unbalanced, as handed to [`OnLeak::Callback`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeLeak {
+    pub scope_name: Option<&'static str>,
+    pub location: &'static Location<'static>,
+    pub bytes: isize,
+    pub max_delta: Option<usize>,
+    /// What the scope was expected to retain, see
+    /// [`LeakDetectorScope::expect_retained`].
+    pub expected_retention: Option<RangeInclusive<usize>>,
+    /// How long the scope waited for its memory to come back, with a grace
+    /// period.
+    pub waited: Option<Duration>,
+    /// Allocations minus deallocations while the scope was open, when it
+    /// checked them; see [`LeakDetectorScope::bytes_only`].
+    pub count: Option<isize>,
+    pub poisoned_by: Option<FirstFailure>,
+    /// Names of the scopes this one was nested in on its thread, outermost
+    /// first.
+    pub enclosing_scopes: Vec<Option<&'static str>>,
+    /// With the registry, the blocks allocated on this scope's thread while it
+    /// was open that are still live, grouped by the innermost scope active
+    /// when each was allocated.
+    pub attribution: Vec<ScopeAttribution>,
+    /// With the registry, every block allocated while the scope was open, on
+    /// any thread, that is still live, sorted by address.
+    pub allocations: Vec<LeakedAllocation>,
+    /// A [`ScopeTicket`](crate::ScopeTicket) dropped without being closed,
+    /// leaking or not.
+    pub unclosed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeAttribution {
+    pub scope_name: Option<&'static str>,
+    pub bytes: usize,
+    pub allocations: usize,
+}
+
+fn write_scope_name(
+    f: &mut std::fmt::Formatter<'_>,
+    name: Option<&'static str>,
+) -> std::fmt::Result {
+    match name {
+        Some(name) => write!(f, "'{name}'"),
+        None => write!(f, "<unnamed>"),
+    }
+}
+
+impl std::fmt::Display for ScopeLeak {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.scope_name {
+            Some(name) => write!(f, "scope '{name}'")?,
+            None => write!(f, "scope")?,
+        }
+        if !self.enclosing_scopes.is_empty() {
+            write!(f, " (inside ")?;
+            for (index, name) in self.enclosing_scopes.iter().enumerate() {
+                if index > 0 {
+                    write!(f, " > ")?;
+                }
+                write_scope_name(f, *name)?;
+            }
+            write!(f, ")")?;
+        }
+        write!(f, " created at {}", self.location)?;
+        if self.unclosed {
+            write!(f, " was dropped without being closed")?;
+            if self.bytes != 0 {
+                write!(f, " and leaked {} bytes", self.bytes)?;
+            }
+        } else if let Some(expected) = &self.expected_retention {
+            write!(f, " ")?;
+            crate::error::write_retention(f, self.bytes, expected)?;
+        } else {
+            match self.max_delta {
+                Some(max) => write!(
+                    f,
+                    " grew by {} bytes, exceeding its limit of {max} bytes by {} bytes",
+                    self.bytes,
+                    self.bytes - max as isize
+                )?,
+                None => write!(f, " leaked {} bytes", self.bytes)?,
+            }
+            if let Some(count) = self.count {
+                let plural = if count == 1 { "" } else { "s" };
+                write!(f, " and {count} allocation{plural}")?;
+                let live = self.allocations.len();
+                if usize::try_from(count).is_ok_and(|count| live > count) {
+                    let (plural, verb) = if live == 1 { ("", "is") } else { ("s", "are") };
+                    write!(
+                        f,
+                        ", but {live} allocation{plural} made in it {verb} still live"
+                    )?;
+                }
+            }
+        }
+        if let Some(waited) = self.waited {
+            write!(f, " after waiting {waited:?}")?;
+        }
+        for (index, group) in self.attribution.iter().enumerate() {
+            write!(f, "{}", if index == 0 { "; " } else { ", " })?;
+            let plural = if group.allocations == 1 { "" } else { "s" };
+            write!(
+                f,
+                "{} bytes in {} allocation{plural} while scope ",
+                group.bytes, group.allocations
+            )?;
+            write_scope_name(f, group.scope_name)?;
+            write!(f, " was active")?;
+        }
+        if let Some(first) = self.poisoned_by {
+            write!(f, "; detector already poisoned by {first}")?;
+        }
+        for allocation in &self.allocations {
+            write!(
+                f,
+                "\n  allocation #{}, {} bytes at {:#x} allocated at {}",
+                allocation.id, allocation.size, allocation.address, allocation.callsite
+            )?;
+        }
+        Ok(())
+    }
 }
 
 impl<T> LeakDetector<T> {
+    #[track_caller]
     pub fn scope<'a>(&'a self) -> LeakDetectorScope<'a, T> {
+        self.scope_at(Location::caller())
+    }
+
+    /// Like [`scope`](LeakDetector::scope), but reports `location` as where
+    /// the scope was created, so helpers can pass on their own caller.
+    pub fn scope_at<'a>(
+        &'a self,
+        location: &'static Location<'static>,
+    ) -> LeakDetectorScope<'a, T> {
         LeakDetectorScope {
             detector: self,
+            id: scope_stack::push(self),
             start: self.get_used(),
+            start_count: net_count(self),
+            epoch: self.registry.advance_epoch(),
+            name: None,
+            location,
+            max_delta: None,
+            expected_retention: None,
+            grace_period: None,
+            budget: false,
+            on_leak: None,
+            failure_mode: None,
+            bytes_only: false,
+            defused: false,
         }
     }
+    #[track_caller]
     pub fn scope_with<F: FnOnce<Args, Output = R>, Args: std::marker::Tuple, R>(
         &self,
         f: F,
@@ -20,19 +190,221 @@ impl<T> LeakDetector<T> {
         let _guard = self.scope();
         f.call_once(args)
     }
+
+    /// Runs `f` in a scope and hands back what happened instead of applying
+    /// any [`OnLeak`] policy: its value if usage came back to where it
+    /// started, or a [`ScopeError`] if it leaked or panicked. A panic is
+    /// caught, so there's never a second one while unwinding, and nothing
+    /// poisons the detector.
+    ///
+    /// `f` is treated as unwind safe; don't use state it may have left
+    /// half-updated.
+    #[track_caller]
+    pub fn scope_with_checked<R>(&self, f: impl FnOnce() -> R) -> Result<R, ScopeError<R>> {
+        let mut scope = self.scope();
+        scope.defuse();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+        let delta = self.get_used().wrapping_sub(scope.start) as isize;
+        drop(scope);
+        match result {
+            Err(payload) => Err(ScopeError::Panicked { payload, delta }),
+            Ok(value) if delta > 0 => Err(ScopeError::Leaked { value, delta }),
+            Ok(value) => Ok(value),
+        }
+    }
 }
 
-#[cfg(debug_assertions)]
+impl<'a, T> LeakDetectorScope<'a, T> {
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        scope_stack::rename(self.id, name);
+        self
+    }
+
+    /// Lets the scope end with up to `max` more bytes in use than it started
+    /// with, instead of requiring an exact balance.
+    pub fn with_max_delta(mut self, max: usize) -> Self {
+        self.max_delta = Some(max);
+        self
+    }
+
+    /// Expects the scope to end holding more memory than it started with,
+    /// by an amount in `range`, as a cache or pool filled inside it should.
+    /// The check fails when the scope retains more than that, or less,
+    /// freeing everything included; it doesn't poison the detector.
+    pub fn expect_retained(mut self, range: RangeInclusive<usize>) -> Self {
+        self.expected_retention = Some(range);
+        self
+    }
+
+    /// Gives memory freed shortly after the scope ends, such as by a
+    /// background thread, up to `grace` to come back before the scope fails.
+    pub fn with_grace_period(mut self, grace: Duration) -> Self {
+        self.grace_period = Some(grace);
+        self
+    }
+
+    /// Makes allocations on this thread fail while the scope is open once
+    /// the thread's allocations in it, less its frees, would come to more
+    /// than `bytes`, see [`LeakDetector::scope_with_budget`].
+    pub fn with_budget(mut self, bytes: usize) -> Self {
+        if !self.budget {
+            self.budget = true;
+            budget::push(
+                self.detector as *const LeakDetector<T> as usize,
+                self.id,
+                bytes,
+            );
+        }
+        self
+    }
+
+    /// Overrides the detector's [`OnLeak`] policy for this scope.
+    pub fn on_leak(mut self, on_leak: OnLeak) -> Self {
+        self.on_leak = Some(on_leak);
+        self
+    }
+
+    /// Overrides the detector's [`FailureMode`] for this scope, as for one
+    /// dropped in an `extern "C"` callback.
+    pub fn failure_mode(mut self, mode: FailureMode) -> Self {
+        self.failure_mode = Some(mode);
+        self
+    }
+
+    /// Checks only that usage in bytes comes back, not that every block
+    /// allocated in the scope is freed in it, for code that hands blocks over
+    /// to its caller and takes others back, or allocates zero-sized ones it
+    /// keeps. Scopes with [`with_max_delta`] or [`expect_retained`] only
+    /// check bytes anyway.
+    ///
+    /// [`with_max_delta`]: LeakDetectorScope::with_max_delta
+    /// [`expect_retained`]: LeakDetectorScope::expect_retained
+    pub fn bytes_only(mut self) -> Self {
+        self.bytes_only = true;
+        self
+    }
+
+    /// Disarms the scope: it won't check anything when dropped.
+    pub fn defuse(&mut self) {
+        self.defused = true;
+    }
+}
+
+/// Checks run in this order: a defused scope does nothing, a balanced scope
+/// does nothing, then the policy is applied. Unless it's
+/// [`bytes_only`](LeakDetectorScope::bytes_only) or allowed to grow, a scope
+/// is balanced when both its bytes and its count of allocations minus
+/// deallocations come back, and, with the registry, no block its thread
+/// allocated in it is still live: freeing an older block in place of a new
+/// one of the same size fails it too. `OnLeak::Panic` fails as the scope's
+/// [`FailureMode`] says; panicking, it only logs while the thread is already
+/// unwinding, or when the detector was poisoned before and is set not to
+/// panic again.
 impl<'a, T> Drop for LeakDetectorScope<'a, T> {
     fn drop(&mut self) {
-        let end = self.detector.get_used();
-        assert_eq!(self.start, end);
+        if self.budget {
+            budget::pop(self.detector as *const LeakDetector<T> as usize, self.id);
+        }
+        if !cfg!(debug_assertions) || self.defused {
+            scope_stack::pop(self.id);
+            return;
+        }
+        let counted =
+            !self.bytes_only && self.max_delta.is_none() && self.expected_retention.is_none();
+        let registry = &self.detector.registry;
+        let measure = || {
+            let bytes = self.detector.get_used().wrapping_sub(self.start) as isize;
+            let count =
+                counted.then(|| net_count(self.detector).wrapping_sub(self.start_count) as isize);
+            let live = if counted && registry.is_enabled() {
+                registry.count_in_scope(registry::thread_tag(), self.id)
+            } else {
+                0
+            };
+            (bytes, count, live)
+        };
+        let balanced = |(bytes, count, live): (isize, Option<isize>, usize)| {
+            let bytes = match (&self.expected_retention, self.max_delta) {
+                (Some(expected), _) => {
+                    usize::try_from(bytes).is_ok_and(|bytes| expected.contains(&bytes))
+                }
+                (None, Some(max)) => bytes <= max as isize,
+                (None, None) => bytes == 0,
+            };
+            bytes && count.unwrap_or(0) == 0 && live == 0
+        };
+        let mut waited = None;
+        if let Some(grace) = self.grace_period
+            && !balanced(measure())
+        {
+            waited = Some(wait::wait_until(grace, || balanced(measure())).1);
+        }
+        let measured = measure();
+        let (bytes, count, _) = measured;
+        if balanced(measured) {
+            scope_stack::pop(self.id);
+            return;
+        }
+        let enclosing_scopes = scope_stack::enclosing(self.detector, self.id);
+        scope_stack::pop(self.id);
+        let mut on_leak = self.on_leak.unwrap_or_else(|| self.detector.on_leak());
+        let expectation = self.max_delta.is_some() || self.expected_retention.is_some();
+        let poisoned_by = match (on_leak, expectation) {
+            (OnLeak::Ignore, _) | (_, true) => None,
+            _ => self
+                .detector
+                .record_failure(bytes, self.name, self.location),
+        };
+        if poisoned_by.is_some()
+            && !self.detector.poison.repanic()
+            && let OnLeak::Panic = on_leak
+        {
+            on_leak = OnLeak::Log;
+        }
+        let (attribution, allocations) = if self.detector.registry.is_enabled() {
+            (
+                self.detector
+                    .registry
+                    .attribute(registry::thread_tag(), self.id),
+                self.detector.allocations_after(self.epoch),
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        let leak = ScopeLeak {
+            scope_name: self.name,
+            location: self.location,
+            bytes,
+            max_delta: self.max_delta,
+            expected_retention: self.expected_retention.clone(),
+            waited,
+            count,
+            poisoned_by,
+            enclosing_scopes,
+            attribution,
+            allocations,
+            unclosed: false,
+        };
+        on_leak.apply(
+            &leak,
+            self.failure_mode
+                .unwrap_or_else(|| self.detector.failure_mode()),
+        );
     }
 }
 
+/// Allocations minus deallocations so far, wrapping.
+fn net_count<T>(detector: &LeakDetector<T>) -> usize {
+    let counters = &detector.counters;
+    counters
+        .allocations()
+        .wrapping_sub(counters.deallocations())
+}
+
 #[cfg(test)]
 mod tests {
-    use std::alloc::System;
+    use std::alloc::{Allocator, Layout, System};
 
     use super::*;
 
@@ -50,4 +422,405 @@ mod tests {
             (),
         );
     }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn max_delta() {
+        let detector = LeakDetector::system();
+        let mut kept = Vec::new();
+        {
+            let _scope = detector.scope().with_max_delta(4096);
+            kept.push(Vec::<u8, _>::with_capacity_in(4096, &detector));
+        }
+        let here = Location::caller();
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _scope = detector.scope_at(here).named("cache").with_max_delta(4096);
+            kept.push(Vec::<u8, _>::with_capacity_in(5000, &detector));
+        }))
+        .unwrap_err();
+        assert_eq!(
+            *payload.downcast_ref::<String>().unwrap(),
+            format!(
+                "scope 'cache' created at {here} grew by 5000 bytes, \
+                 exceeding its limit of 4096 bytes by 904 bytes"
+            )
+        );
+        assert!(!detector.is_poisoned());
+    }
+
+    #[test]
+    fn scope_with_checked() {
+        let detector = LeakDetector::system();
+        let value = detector.scope_with_checked(|| Box::new(1).to_string());
+        assert_eq!(value.unwrap(), "1");
+
+        let leaked = detector.scope_with_checked(|| Box::new_in(7u64, &detector));
+        let Err(ScopeError::Leaked { value, delta: 8 }) = leaked else {
+            panic!("expected a leak, got {leaked:?}");
+        };
+        assert_eq!(*value, 7);
+        drop(value);
+
+        let panicked = detector.scope_with_checked(|| {
+            std::mem::forget(Box::new_in(0u32, &detector));
+            panic!("boom");
+        });
+        let Err(error @ ScopeError::Panicked { delta: 4, .. }) = panicked else {
+            panic!("expected a panic, got {panicked:?}");
+        };
+        assert_eq!(
+            error.to_string(),
+            "scope panicked: boom; used changed by 4 bytes"
+        );
+        assert!(!detector.is_poisoned());
+    }
+
+    fn leak(detector: &LeakDetector<System>) {
+        std::mem::forget(Box::new_in(0u32, detector));
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn policies() {
+        let detector = LeakDetector::builder(System).on_leak(OnLeak::Log).build();
+        {
+            let _scope = detector.scope().named("logged");
+            leak(&detector);
+        }
+        assert_eq!(detector.first_failure().unwrap().scope_name, Some("logged"));
+        detector.clear_poison();
+
+        {
+            let _scope = detector.scope().on_leak(OnLeak::Ignore);
+            leak(&detector);
+        }
+        assert!(!detector.is_poisoned());
+
+        detector.set_on_leak(OnLeak::Panic);
+        let here = Location::caller();
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _scope = detector.scope_at(here).named("panicking");
+            leak(&detector);
+        }))
+        .unwrap_err();
+        assert_eq!(
+            *payload.downcast_ref::<String>().unwrap(),
+            format!("scope 'panicking' created at {here} leaked 4 bytes and 1 allocation")
+        );
+    }
+
+    static RECORDING: LeakDetector<System> = LeakDetector::system();
+    static RECORDED: std::sync::Mutex<Vec<ScopeLeak>> = std::sync::Mutex::new(Vec::new());
+
+    fn record(leak: &ScopeLeak) {
+        RECORDED.lock().unwrap().push(leak.clone());
+        // Failing again from inside the callback is logged rather than recursing.
+        let _scope = RECORDING
+            .scope()
+            .named("inside callback")
+            .on_leak(OnLeak::Callback(record));
+        std::mem::forget(Box::new_in([0u8; 16], &RECORDING));
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn callback() {
+        {
+            let _scope = RECORDING
+                .scope()
+                .named("recorded")
+                .on_leak(OnLeak::Callback(record));
+            leak(&RECORDING);
+        }
+        let recorded = RECORDED.lock().unwrap();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].scope_name, Some("recorded"));
+        assert_eq!(recorded[0].bytes, 4);
+    }
+
+    #[test]
+    fn defuse() {
+        let detector = LeakDetector::system();
+        let mut scope = detector.scope();
+        leak(&detector);
+        scope.defuse();
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn no_double_panic() {
+        let detector = LeakDetector::system();
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _scope = detector.scope();
+            leak(&detector);
+            panic!("original");
+        }))
+        .unwrap_err();
+        assert_eq!(*payload.downcast_ref::<&str>().unwrap(), "original");
+        assert!(detector.is_poisoned());
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn nested_attribution() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let here = Location::caller();
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _outer = detector.scope_at(here).named("outer");
+            {
+                let _parse = detector.scope().named("json parse").on_leak(OnLeak::Ignore);
+                std::mem::forget(Box::new_in([0u8; 128], &detector));
+                {
+                    let _tokenize = detector.scope().named("tokenize").with_max_delta(64);
+                    std::mem::forget(Box::new_in([0u8; 32], &detector));
+                    std::mem::forget(Box::new_in([0u8; 32], &detector));
+                }
+            }
+        }))
+        .unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert_eq!(
+            message.lines().next().unwrap(),
+            format!(
+                "scope 'outer' created at {here} leaked 192 bytes and 3 allocations; \
+                 128 bytes in 1 allocation while scope 'json parse' was active, \
+                 64 bytes in 2 allocations while scope 'tokenize' was active"
+            )
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn enclosing_chain() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let other = LeakDetector::system();
+        let here = Location::caller();
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _outer = detector.scope().named("outer").on_leak(OnLeak::Ignore);
+            let _unrelated = other.scope().named("other detector");
+            let _unnamed = detector.scope().on_leak(OnLeak::Ignore);
+            let _inner = detector.scope_at(here).named("inner");
+            std::mem::forget(Box::new_in(0u64, &detector));
+        }))
+        .unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert_eq!(
+            message.lines().next().unwrap(),
+            format!(
+                "scope 'inner' (inside 'outer' > <unnamed>) created at {here} leaked 8 bytes and 1 allocation; \
+                 8 bytes in 1 allocation while scope 'inner' was active"
+            )
+        );
+    }
+
+    static LISTING: LeakDetector<System> = LeakDetector::builder(System).registry(true).build();
+    static LISTED: std::sync::Mutex<Vec<LeakedAllocation>> = std::sync::Mutex::new(Vec::new());
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn lists_live_allocations() {
+        let before = Box::new_in(0u8, &LISTING);
+        {
+            let _scope = LISTING.scope().on_leak(OnLeak::Callback(|leak| {
+                LISTED.lock().unwrap().extend_from_slice(&leak.allocations)
+            }));
+            let first = Box::new_in([0u8; 8], &LISTING);
+            let second = Box::new_in([0u8; 16], &LISTING);
+            std::mem::forget(Box::new_in([0u8; 32], &LISTING));
+            drop((first, second));
+        }
+        let listed = LISTED.lock().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!((listed[0].id, listed[0].size), (4, 32));
+        drop(before);
+    }
+
+    #[track_caller]
+    fn checked_helper(detector: &LeakDetector<System>) -> LeakDetectorScope<'_, System> {
+        detector.scope_at(Location::caller())
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn creation_location() {
+        let detector = LeakDetector::system();
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _scope = detector.scope();
+            leak(&detector);
+        }))
+        .unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        let expected = format!("scope created at {}:{}:", file!(), line!() - 5);
+        assert!(message.starts_with(&expected), "{message}");
+
+        static WITH: LeakDetector<System> = LeakDetector::system();
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            WITH.scope_with(|| leak(&WITH), ());
+        }))
+        .unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        let expected = format!("scope created at {}:{}:", file!(), line!() - 4);
+        assert!(message.starts_with(&expected), "{message}");
+
+        detector.clear_poison();
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _scope = checked_helper(&detector);
+            leak(&detector);
+        }))
+        .unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        let expected = format!("scope created at {}:{}:", file!(), line!() - 5);
+        assert!(message.starts_with(&expected), "{message}");
+        assert!(
+            message.ends_with(" leaked 4 bytes and 1 allocation"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn expect_retained() {
+        let detector = LeakDetector::system();
+        let mut cache = Vec::new();
+        let mut memoize = |size| cache.push(Vec::<u8, _>::with_capacity_in(size, &detector));
+        {
+            let _scope = detector.scope().expect_retained(64..=128);
+            memoize(100);
+        }
+
+        let here = Location::caller();
+        let failure = |range, f: &mut dyn FnMut()| {
+            let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let _scope = detector.scope_at(here).named("memo").expect_retained(range);
+                f();
+            }))
+            .unwrap_err();
+            *payload.downcast::<String>().unwrap()
+        };
+        assert_eq!(
+            failure(0..=0, &mut || memoize(32)),
+            format!(
+                "scope 'memo' created at {here} retained 32 bytes, \
+                 more than the expected 0..=0 bytes by 32 bytes"
+            )
+        );
+        assert_eq!(
+            failure(64..=128, &mut || cache.clear()),
+            format!(
+                "scope 'memo' created at {here} retained -132 bytes, \
+                 less than the expected 64..=128 bytes by 196 bytes"
+            )
+        );
+        assert!(!detector.is_poisoned());
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn counts_allocations() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let here = Location::caller();
+        let failure = |f: &mut dyn FnMut()| {
+            let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let _scope = detector.scope_at(here).named("swap");
+                f();
+            }))
+            .unwrap_err();
+            detector.clear_poison();
+            *payload.downcast::<String>().unwrap()
+        };
+
+        let mut owned = Some(Box::new_in([0u8; 64], &detector));
+        let message = failure(&mut || {
+            drop(owned.take());
+            std::mem::forget(Box::new_in([1u8; 64], &detector));
+        });
+        assert!(
+            message.starts_with(&format!(
+                "scope 'swap' created at {here} leaked 0 bytes and 0 allocations, \
+                 but 1 allocation made in it is still live; \
+                 64 bytes in 1 allocation while scope 'swap' was active\n  \
+                 allocation #2, 64 bytes at "
+            )),
+            "{message}"
+        );
+
+        let zst = Layout::new::<()>();
+        let message = failure(&mut || {
+            for _ in 0..3 {
+                detector.allocate(zst).unwrap();
+            }
+        });
+        assert_eq!(
+            message,
+            format!("scope 'swap' created at {here} leaked 0 bytes and 3 allocations")
+        );
+
+        let mut owned = Some(Box::new_in([0u8; 64], &detector));
+        {
+            let _scope = detector.scope().bytes_only();
+            drop(owned.take());
+            std::mem::forget(Box::new_in([1u8; 64], &detector));
+            detector.allocate(zst).unwrap();
+        }
+        let unregistered = LeakDetector::system();
+        let mut owned = Some(Box::new_in([0u8; 64], &unregistered));
+        {
+            let _scope = unregistered.scope();
+            drop(owned.take());
+            std::mem::forget(Box::new_in([1u8; 64], &unregistered));
+        }
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn ignores_other_threads_blocks() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let old = Box::new_in([0u8; 64], &detector);
+        let new = {
+            let _scope = detector.scope();
+            std::thread::scope(|scope| {
+                scope
+                    .spawn(|| {
+                        drop(old);
+                        Box::new_in([1u8; 64], &detector)
+                    })
+                    .join()
+                    .unwrap()
+            })
+        };
+        drop(new);
+    }
 }
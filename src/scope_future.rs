@@ -0,0 +1,145 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    LeakDetectorScope,
+    scope_stack::{self, ScopeTag},
+};
+
+/// A future run inside a scope, see [`LeakDetectorScope::around`].
+#[must_use = "futures do nothing unless polled"]
+pub struct ScopedFuture<'a, T, F> {
+    // Dropped before the scope, so a cancelled future frees its memory
+    // before the check.
+    future: F,
+    scope: Option<LeakDetectorScope<'a, T>>,
+}
+
+impl<'a, T> LeakDetectorScope<'a, T> {
+    /// Keeps the scope open until `future` completes or is dropped, and
+    /// checks it then. Allocations are attributed to the scope on whichever
+    /// thread polls the future, only while it is being polled, so awaiting
+    /// it on a multi-threaded runtime works like a scope on one thread
+    /// would. Usage is still compared detector-wide, so other tasks running
+    /// meanwhile count too.
+    pub fn around<F: Future>(self, future: F) -> ScopedFuture<'a, T, F> {
+        scope_stack::pop(self.id);
+        ScopedFuture {
+            future,
+            scope: Some(self),
+        }
+    }
+}
+
+impl<T, F: Future> Future for ScopedFuture<'_, T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // `future` is never moved out of the pinned `self`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let scope = this
+            .scope
+            .as_ref()
+            .expect("ScopedFuture polled after completion");
+        let (detector, id) = (scope.detector, scope.id);
+        scope_stack::enter(
+            detector,
+            ScopeTag {
+                id,
+                name: scope.name,
+            },
+        );
+        let poll = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx);
+        if poll.is_ready() {
+            // Checked with its frame still on this thread, which it pops.
+            this.scope = None;
+        } else {
+            scope_stack::pop(id);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::System,
+        pin::pin,
+        sync::{Arc, Mutex},
+        task::Waker,
+    };
+
+    use super::*;
+    use crate::{LeakDetector, OnLeak, ScopeLeak};
+
+    static LEAKS: Mutex<Vec<ScopeLeak>> = Mutex::new(Vec::new());
+
+    fn record(leak: &ScopeLeak) {
+        LEAKS.lock().unwrap().push(leak.clone());
+    }
+
+    /// Pending until its flag is set.
+    struct Gate(Arc<Mutex<bool>>);
+
+    impl Future for Gate {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+            if *self.0.lock().unwrap() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn checks_when_the_future_completes() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let open = Arc::new(Mutex::new(false));
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut future = pin!(
+            detector
+                .scope()
+                .named("handler")
+                .on_leak(OnLeak::Callback(record))
+                .around(async {
+                    let buffer = detector.vec_with_capacity::<u8>(64);
+                    Gate(open.clone()).await;
+                    std::mem::forget(buffer);
+                    detector.vec_with_capacity::<u8>(16)
+                })
+        );
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        assert!(crate::scope_stack::innermost(&detector).is_none());
+        *open.lock().unwrap() = true;
+        let kept = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    match future
+                        .as_mut()
+                        .poll(&mut Context::from_waker(Waker::noop()))
+                    {
+                        Poll::Ready(kept) => kept,
+                        Poll::Pending => unreachable!(),
+                    }
+                })
+                .join()
+                .unwrap()
+        });
+
+        let leaks = std::mem::take(&mut *LEAKS.lock().unwrap());
+        assert_eq!(leaks.len(), 1);
+        assert_eq!(leaks[0].scope_name, Some("handler"));
+        assert_eq!(leaks[0].bytes, 80);
+        assert_eq!(leaks[0].allocations.len(), 2);
+        drop(kept);
+    }
+}
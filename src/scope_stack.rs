@@ -0,0 +1,127 @@
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::LeakDetector;
+
+/// Scopes deeper than this on one thread still check their balance but are
+/// left out of attribution.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ScopeTag {
+    pub(crate) id: u64,
+    pub(crate) name: Option<&'static str>,
+}
+
+#[derive(Clone, Copy)]
+struct Frame {
+    detector: usize,
+    tag: ScopeTag,
+}
+
+/// The scopes open on one thread, innermost last. A fixed array rather than a
+/// `Vec` so that reading it from inside the allocator never allocates.
+struct Frames {
+    len: usize,
+    frames: [Frame; MAX_DEPTH],
+}
+
+thread_local! {
+    static FRAMES: RefCell<Frames> = const {
+        RefCell::new(Frames {
+            len: 0,
+            frames: [Frame {
+                detector: 0,
+                tag: ScopeTag { id: 0, name: None },
+            }; MAX_DEPTH],
+        })
+    };
+}
+
+fn with_frames<R>(f: impl FnOnce(&mut Frames) -> R) -> Option<R> {
+    FRAMES
+        .try_with(|frames| {
+            frames
+                .try_borrow_mut()
+                .ok()
+                .map(|mut frames| f(&mut frames))
+        })
+        .ok()
+        .flatten()
+}
+
+fn address<T>(detector: &LeakDetector<T>) -> usize {
+    detector as *const LeakDetector<T> as usize
+}
+
+pub(crate) fn push<T>(detector: &LeakDetector<T>) -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    enter(detector, ScopeTag { id, name: None });
+    id
+}
+
+/// Puts an existing scope back on this thread, for a future being polled.
+pub(crate) fn enter<T>(detector: &LeakDetector<T>, tag: ScopeTag) {
+    let detector = address(detector);
+    with_frames(|frames| {
+        if frames.len < MAX_DEPTH {
+            frames.frames[frames.len] = Frame { detector, tag };
+            frames.len += 1;
+        }
+    });
+}
+
+pub(crate) fn rename(id: u64, name: &'static str) {
+    with_frames(|frames| {
+        if let Some(frame) = frames.frames[..frames.len]
+            .iter_mut()
+            .find(|frame| frame.tag.id == id)
+        {
+            frame.tag.name = Some(name);
+        }
+    });
+}
+
+pub(crate) fn pop(id: u64) {
+    with_frames(|frames| {
+        if let Some(index) = frames.frames[..frames.len]
+            .iter()
+            .rposition(|frame| frame.tag.id == id)
+        {
+            frames.frames.copy_within(index + 1..frames.len, index);
+            frames.len -= 1;
+        }
+    });
+}
+
+/// The innermost scope of `detector` open on this thread.
+pub(crate) fn innermost<T>(detector: &LeakDetector<T>) -> Option<ScopeTag> {
+    let detector = address(detector);
+    with_frames(|frames| {
+        frames.frames[..frames.len]
+            .iter()
+            .rev()
+            .find(|frame| frame.detector == detector)
+            .map(|frame| frame.tag)
+    })
+    .flatten()
+}
+
+/// Names of the scopes of `detector` enclosing scope `id`, outermost first.
+pub(crate) fn enclosing<T>(detector: &LeakDetector<T>, id: u64) -> Vec<Option<&'static str>> {
+    let detector = address(detector);
+    // Copied out first: collecting may allocate, and the allocator reads the
+    // frames too.
+    let Some((len, frames)) = with_frames(|frames| (frames.len, frames.frames)) else {
+        return Vec::new();
+    };
+    frames[..len]
+        .iter()
+        .take_while(|frame| frame.tag.id != id)
+        .filter(|frame| frame.detector == detector)
+        .map(|frame| frame.tag.name)
+        .collect()
+}
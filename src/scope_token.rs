@@ -0,0 +1,143 @@
+use std::{ops::RangeInclusive, panic::Location, time::Duration};
+
+use crate::{FailureMode, LeakDetector, LeakDetectorScope, OnLeak, scope_stack};
+
+/// A scope that owns its place instead of borrowing the detector, so it can
+/// be kept in a struct next to memory from the same detector or moved into
+/// a spawned thread or task. Made by [`LeakDetector::owned_scope`]; checked
+/// wherever it's dropped, like a [`LeakDetectorScope`].
+///
+/// It isn't tied to the thread that made it, so allocations aren't
+/// attributed to it and it can't carry a budget; usage is compared
+/// detector-wide.
+#[must_use = "a token checks when dropped"]
+pub struct ScopeToken<T: 'static> {
+    scope: LeakDetectorScope<'static, T>,
+}
+
+impl<T> LeakDetector<T> {
+    /// Opens a [`ScopeToken`] on a `'static` detector, such as a global one.
+    #[track_caller]
+    pub fn owned_scope(&'static self) -> ScopeToken<T> {
+        let scope = self.scope_at(Location::caller());
+        scope_stack::pop(scope.id);
+        ScopeToken { scope }
+    }
+}
+
+impl<T> ScopeToken<T> {
+    pub fn named(self, name: &'static str) -> Self {
+        Self {
+            scope: self.scope.named(name),
+        }
+    }
+
+    /// See [`LeakDetectorScope::with_max_delta`].
+    pub fn with_max_delta(self, max: usize) -> Self {
+        Self {
+            scope: self.scope.with_max_delta(max),
+        }
+    }
+
+    /// See [`LeakDetectorScope::expect_retained`].
+    pub fn expect_retained(self, range: RangeInclusive<usize>) -> Self {
+        Self {
+            scope: self.scope.expect_retained(range),
+        }
+    }
+
+    /// See [`LeakDetectorScope::with_grace_period`].
+    pub fn with_grace_period(self, grace: Duration) -> Self {
+        Self {
+            scope: self.scope.with_grace_period(grace),
+        }
+    }
+
+    /// See [`LeakDetectorScope::bytes_only`].
+    pub fn bytes_only(self) -> Self {
+        Self {
+            scope: self.scope.bytes_only(),
+        }
+    }
+
+    /// See [`LeakDetectorScope::failure_mode`].
+    pub fn failure_mode(self, mode: FailureMode) -> Self {
+        Self {
+            scope: self.scope.failure_mode(mode),
+        }
+    }
+
+    /// Overrides the detector's [`OnLeak`] policy for this token.
+    pub fn on_leak(self, on_leak: OnLeak) -> Self {
+        Self {
+            scope: self.scope.on_leak(on_leak),
+        }
+    }
+
+    /// Disarms the token: it won't check anything when dropped.
+    pub fn defuse(&mut self) {
+        self.scope.defuse();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::System,
+        sync::Mutex,
+        thread::{self, ThreadId},
+    };
+
+    use super::*;
+
+    static LEAKS: Mutex<Vec<(isize, ThreadId)>> = Mutex::new(Vec::new());
+
+    fn record(leak: &crate::ScopeLeak) {
+        LEAKS
+            .lock()
+            .unwrap()
+            .push((leak.bytes, thread::current().id()));
+    }
+
+    static DETECTOR: LeakDetector<System> = LeakDetector::system();
+
+    /// Test state holding memory from the detector it checks; the buffer
+    /// goes first, then the token checks.
+    struct Fixture {
+        buffer: Vec<u8, &'static LeakDetector<System>>,
+        _token: ScopeToken<System>,
+    }
+
+    fn fixture() -> Fixture {
+        Fixture {
+            _token: DETECTOR
+                .owned_scope()
+                .named("fixture")
+                .on_leak(OnLeak::Callback(record)),
+            buffer: Vec::with_capacity_in(32, &DETECTOR),
+        }
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn checks_where_dropped() {
+        let balanced = fixture();
+        thread::spawn(move || drop(balanced)).join().unwrap();
+        assert!(LEAKS.lock().unwrap().is_empty());
+
+        let mut leaking = fixture();
+        let worker = thread::spawn(move || {
+            std::mem::forget(std::mem::replace(
+                &mut leaking.buffer,
+                Vec::with_capacity_in(16, &DETECTOR),
+            ));
+            drop(leaking);
+            thread::current().id()
+        });
+        let worker = worker.join().unwrap();
+        assert_eq!(*LEAKS.lock().unwrap(), [(32, worker)]);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    future::Future,
+    panic::Location,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Drained, LeakDetector, LeakError, Snapshot};
+
+/// The verdict after a server's graceful shutdown, see
+/// [`LeakDetector::shutdown_check`]. Await it, or [`wait`] for it from a
+/// thread.
+///
+/// [`wait`]: ShutdownCheck::wait
+#[must_use = "futures do nothing unless polled"]
+pub struct ShutdownCheck<T: 'static> {
+    detector: &'static LeakDetector<T>,
+    drained: Drained<'static, T>,
+    timeout: Duration,
+    started: Option<Instant>,
+    timer: Option<(thread::JoinHandle<()>, Arc<Timer>)>,
+    callsite: &'static Location<'static>,
+}
+
+/// Wakes the task once the timeout runs out, unless cancelled first.
+struct Timer {
+    waker: Mutex<Option<Waker>>,
+    cancelled: AtomicBool,
+}
+
+impl<T> LeakDetector<T> {
+    /// Resolves once nothing is leaked, as [`check`](LeakDetector::check)
+    /// counts it, to a [`snapshot`](LeakDetector::snapshot), or else after
+    /// 10 seconds, see [`ShutdownCheck::timeout`], to the leak. Await it
+    /// after the shutdown signal and the connections draining; take the
+    /// baseline once the server is set up so its long-lived state isn't
+    /// counted.
+    ///
+    /// It is woken by the free that gets there, and by a thread that sleeps
+    /// until the timeout once it has been polled. That thread's own memory
+    /// isn't tracked.
+    #[track_caller]
+    pub fn shutdown_check(&'static self) -> ShutdownCheck<T> {
+        ShutdownCheck {
+            detector: self,
+            drained: self.until_zero(),
+            timeout: Duration::from_secs(10),
+            started: None,
+            timer: None,
+            callsite: Location::caller(),
+        }
+    }
+
+    fn leaked_after(&self, waited: Duration, callsite: &'static Location<'static>) -> LeakError {
+        let bytes = self.leaked_bytes() as isize;
+        LeakError::LeakedAfterWait {
+            bytes,
+            waited,
+            poisoned_by: self.record_failure(bytes, None, callsite),
+        }
+    }
+}
+
+impl<T> ShutdownCheck<T> {
+    /// How long to wait for memory to come back, from the first poll.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Blocks this thread for the verdict instead of awaiting it.
+    pub fn wait(self) -> Result<Snapshot, LeakError> {
+        let detector = self.detector;
+        match detector.wait_for_zero(self.timeout) {
+            Ok(()) => Ok(detector.snapshot()),
+            Err(LeakError::LeakedAfterWait { waited, .. }) => {
+                Err(detector.leaked_after(waited, self.callsite))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn start_timer(&mut self, waker: &Waker) {
+        if let Some((_, timer)) = &self.timer {
+            let mut slot = timer.waker.lock().unwrap_or_else(PoisonError::into_inner);
+            if !slot.as_ref().is_some_and(|old| old.will_wake(waker)) {
+                *slot = Some(waker.clone());
+            }
+            return;
+        }
+        let _internal = crate::pause::internal();
+        let timer = Arc::new(Timer {
+            waker: Mutex::new(Some(waker.clone())),
+            cancelled: AtomicBool::new(false),
+        });
+        let deadline = self.started.unwrap_or_else(Instant::now) + self.timeout;
+        let spawned = thread::Builder::new()
+            .name("leak detector shutdown timer".into())
+            .spawn({
+                let timer = Arc::clone(&timer);
+                move || {
+                    // For the rest of the thread, its exit included.
+                    std::mem::forget(crate::pause::internal());
+                    while !timer.cancelled.load(Ordering::Acquire) {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            let waker = timer
+                                .waker
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner)
+                                .take();
+                            waker.into_iter().for_each(Waker::wake);
+                            return;
+                        }
+                        thread::park_timeout(deadline - now);
+                    }
+                }
+            });
+        // Without a timer the check is still decided on the next poll.
+        if let Ok(handle) = spawned {
+            self.timer = Some((handle, timer));
+        }
+    }
+
+    /// Stops and joins the timer untracked, so that freeing what it
+    /// allocated isn't counted either.
+    fn stop_timer(&mut self) {
+        if let Some((handle, timer)) = self.timer.take() {
+            let _internal = crate::pause::internal();
+            timer.cancelled.store(true, Ordering::Release);
+            handle.thread().unpark();
+            let _ = handle.join();
+            drop(timer);
+        }
+    }
+}
+
+impl<T> Future for ShutdownCheck<T> {
+    type Output = Result<Snapshot, LeakError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let started = *this.started.get_or_insert_with(Instant::now);
+        if Pin::new(&mut this.drained).poll(cx).is_ready() {
+            this.stop_timer();
+            return Poll::Ready(Ok(this.detector.snapshot()));
+        }
+        let waited = started.elapsed();
+        if waited >= this.timeout {
+            this.stop_timer();
+            return Poll::Ready(Err(this.detector.leaked_after(waited, this.callsite)));
+        }
+        this.start_timer(cx.waker());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for ShutdownCheck<T> {
+    fn drop(&mut self) {
+        self.stop_timer();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::System, sync::mpsc};
+
+    use super::*;
+    use crate::executor::block_on;
+
+    /// A toy server: a worker thread holds a buffer from `detector` for
+    /// each open connection, and leaks one when `leak` is set. Returns once
+    /// every connection is open and shutdown has been signalled; the worker
+    /// drains them a little later.
+    fn serve(detector: &'static LeakDetector<System>, leak: bool) -> thread::JoinHandle<()> {
+        let (requests, incoming) = mpsc::channel::<usize>();
+        let (opened, accepted) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            let mut open = Vec::new();
+            for size in incoming {
+                open.push(Vec::<u8, _>::with_capacity_in(size, detector));
+                opened.send(()).unwrap();
+            }
+            thread::sleep(Duration::from_millis(20));
+            if leak {
+                std::mem::forget(open.pop());
+            }
+            drop(open);
+        });
+        for size in [64, 128, 256] {
+            requests.send(size).unwrap();
+            accepted.recv().unwrap();
+        }
+        worker
+    }
+
+    #[test]
+    fn clean_shutdown_passes() {
+        static DETECTOR: LeakDetector<System> = LeakDetector::system();
+        let _config = DETECTOR.vec_with_capacity::<u8>(32);
+        DETECTOR.capture_baseline();
+        let worker = serve(&DETECTOR, false);
+        let stats = block_on(DETECTOR.shutdown_check()).unwrap();
+        assert_eq!(stats.used, 32);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn leaking_shutdown_times_out() {
+        static DETECTOR: LeakDetector<System> = LeakDetector::system();
+        let worker = serve(&DETECTOR, true);
+        let check = DETECTOR
+            .shutdown_check()
+            .timeout(Duration::from_millis(100));
+        let err = block_on(check).unwrap_err();
+        assert!(
+            matches!(err, LeakError::LeakedAfterWait { bytes: 256, waited, .. } if waited >= Duration::from_millis(100)),
+            "{err}"
+        );
+        worker.join().unwrap();
+        let check = DETECTOR.shutdown_check().timeout(Duration::from_millis(10));
+        assert!(matches!(
+            check.wait(),
+            Err(LeakError::LeakedAfterWait { bytes: 256, .. })
+        ));
+    }
+}
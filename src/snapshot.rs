@@ -0,0 +1,193 @@
+use std::sync::{PoisonError, atomic::Ordering};
+
+use crate::{LeakDetector, ledger::Ledger};
+
+/// A reading of every counter of a detector.
+///
+/// The counters are read one after the other, so allocations racing with
+/// [`LeakDetector::snapshot`] may show up in some fields and not in others.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub used: usize,
+    pub peak: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub reallocations: usize,
+    pub bytes_allocated: usize,
+    pub bytes_deallocated: usize,
+    /// See [`LeakDetector::get_used_actual`].
+    pub used_actual: usize,
+    /// See [`LeakDetector::padding_bytes`].
+    pub padding_bytes: usize,
+    /// Allocations and grows that reached the large allocation threshold.
+    pub large_allocations: usize,
+    /// See [`LeakDetector::self_overhead_bytes`]; not part of `used`.
+    pub overhead_bytes: usize,
+    /// The registry epoch that ended with this snapshot: blocks allocated
+    /// after it belong to later epochs.
+    pub(crate) epoch: u64,
+}
+
+impl Snapshot {
+    pub(crate) const ZERO: Self = Self {
+        used: 0,
+        peak: 0,
+        allocations: 0,
+        deallocations: 0,
+        reallocations: 0,
+        bytes_allocated: 0,
+        bytes_deallocated: 0,
+        used_actual: 0,
+        padding_bytes: 0,
+        large_allocations: 0,
+        overhead_bytes: 0,
+        epoch: 0,
+    };
+
+    /// The counters relative to `earlier`, as if it had been the zero point.
+    pub fn since(&self, earlier: &Snapshot) -> Snapshot {
+        Snapshot {
+            used: self.used.saturating_sub(earlier.used),
+            peak: self.peak.saturating_sub(earlier.used),
+            allocations: self.allocations.saturating_sub(earlier.allocations),
+            deallocations: self.deallocations.saturating_sub(earlier.deallocations),
+            reallocations: self.reallocations.saturating_sub(earlier.reallocations),
+            bytes_allocated: self.bytes_allocated.saturating_sub(earlier.bytes_allocated),
+            bytes_deallocated: self
+                .bytes_deallocated
+                .saturating_sub(earlier.bytes_deallocated),
+            used_actual: self.used_actual.saturating_sub(earlier.used_actual),
+            padding_bytes: self.padding_bytes.saturating_sub(earlier.padding_bytes),
+            large_allocations: self
+                .large_allocations
+                .saturating_sub(earlier.large_allocations),
+            overhead_bytes: self.overhead_bytes,
+            epoch: self.epoch,
+        }
+    }
+}
+
+impl<T> LeakDetector<T> {
+    pub fn snapshot(&self) -> Snapshot {
+        self.read_snapshot()
+    }
+
+    /// Makes `snap` the detector's zero point: [`check`] then only fails for
+    /// memory allocated on top of `snap.used`, and [`since_baseline`] reports
+    /// the counters relative to it. The peak restarts from the current usage.
+    ///
+    /// Safe to call while other threads allocate; their activity lands either
+    /// before or after the new baseline. Freeing blocks allocated before the
+    /// baseline is fine and never counts as a leak.
+    ///
+    /// [`check`]: LeakDetector::check
+    /// [`since_baseline`]: LeakDetector::since_baseline
+    pub fn reset_to(&self, snap: &Snapshot) {
+        let mut baseline = self.baseline.lock().unwrap_or_else(PoisonError::into_inner);
+        *baseline = *snap;
+        self.counters.reset_peak();
+    }
+
+    pub fn rebaseline(&self) {
+        self.reset_to(&self.snapshot());
+    }
+
+    /// Makes the current usage the reference point for [`check`], so memory
+    /// the runtime, test harness or lazy statics allocated before doesn't
+    /// count, and returns it.
+    ///
+    /// [`check`]: LeakDetector::check
+    pub fn capture_baseline(&self) -> Snapshot {
+        let snap = self.snapshot();
+        self.reset_to(&snap);
+        snap
+    }
+
+    /// Bytes in use at the baseline.
+    pub fn baseline(&self) -> usize {
+        self.baseline_snapshot().used
+    }
+
+    pub fn used_above_baseline(&self) -> usize {
+        self.get_used().saturating_sub(self.baseline())
+    }
+
+    /// Lets [`check`] pass with up to `bytes` in use above the baseline.
+    ///
+    /// [`check`]: LeakDetector::check
+    pub fn set_tolerance(&self, bytes: usize) {
+        self.tolerance.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn tolerance(&self) -> usize {
+        self.tolerance.load(Ordering::Relaxed)
+    }
+
+    pub fn since_baseline(&self) -> Snapshot {
+        self.snapshot().since(&self.baseline_snapshot())
+    }
+
+    pub(crate) fn baseline_snapshot(&self) -> Snapshot {
+        *self.baseline.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebaseline() {
+        let detector = LeakDetector::system();
+        let setup = Vec::<u8, _>::with_capacity_in(1024, &detector);
+        assert!(detector.check().is_err());
+        detector.clear_poison();
+
+        detector.rebaseline();
+        detector.check().unwrap();
+        let work = Box::new_in([0u8; 64], &detector);
+        assert_eq!(detector.since_baseline().used, 64);
+        assert_eq!(detector.since_baseline().allocations, 1);
+        drop(work);
+        detector.check().unwrap();
+
+        drop(setup);
+        detector.check().unwrap();
+        assert_eq!(detector.since_baseline().used, 0);
+        assert_eq!(detector.get_used(), 0);
+    }
+
+    #[test]
+    fn captured_baseline() {
+        let detector = LeakDetector::system();
+        let runtime = Vec::<u8, _>::with_capacity_in(300, &detector);
+        assert_eq!(detector.capture_baseline().used, 300);
+        assert_eq!(detector.baseline(), 300);
+
+        let work = Box::new_in([0u8; 40], &detector);
+        assert_eq!(detector.used_above_baseline(), 40);
+        drop(work);
+        detector.check().unwrap();
+
+        let leaked = Box::new_in([0u8; 40], &detector);
+        assert!(detector.check().is_err());
+        detector.clear_poison();
+        detector.set_tolerance(40);
+        detector.check().unwrap();
+        detector.set_tolerance(39);
+        assert!(detector.check().is_err());
+        drop((leaked, runtime));
+    }
+
+    #[test]
+    fn reset_to_earlier_snapshot() {
+        let detector = LeakDetector::system();
+        let snap = detector.snapshot();
+        let leaked = Box::new_in(1u32, &detector);
+        detector.rebaseline();
+        detector.reset_to(&snap);
+        assert!(detector.check().is_err());
+        drop(leaked);
+        detector.check().unwrap();
+    }
+}
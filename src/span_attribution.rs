@@ -0,0 +1,276 @@
+use std::{
+    alloc::System,
+    cell::RefCell,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use tracing_core::{Subscriber, span};
+use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
+
+use crate::LeakDetector;
+
+/// Span names past this many distinct ones aren't attributed.
+pub const MAX_SPAN_NAMES: usize = 256;
+
+/// Spans entered deeper than this on one thread aren't attributed.
+const MAX_DEPTH: usize = 64;
+
+/// What was allocated while a span of one name was the innermost entered,
+/// see [`LeakDetector::span_usage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpanStats {
+    pub bytes: usize,
+    pub allocations: usize,
+}
+
+/// Span names seen by the layer; a name's id is its index plus one, and 0
+/// means no span.
+static NAMES: Mutex<Vec<&'static str, System>> = Mutex::new(Vec::new_in(System));
+
+fn intern(name: &'static str) -> u32 {
+    let mut names = NAMES.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(i) = names.iter().position(|known| *known == name) {
+        return i as u32 + 1;
+    }
+    if names.len() == MAX_SPAN_NAMES {
+        return 0;
+    }
+    names.push(name);
+    names.len() as u32
+}
+
+/// The spans entered on one thread, innermost last, as span id and name id.
+/// A fixed array so that the allocator can read it without allocating.
+struct Entered {
+    len: usize,
+    spans: [(u64, u32); MAX_DEPTH],
+}
+
+thread_local! {
+    static ENTERED: RefCell<Entered> = const {
+        RefCell::new(Entered {
+            len: 0,
+            spans: [(0, 0); MAX_DEPTH],
+        })
+    };
+}
+
+fn with_entered<R>(f: impl FnOnce(&mut Entered) -> R) -> Option<R> {
+    ENTERED
+        .try_with(|entered| {
+            entered
+                .try_borrow_mut()
+                .ok()
+                .map(|mut entered| f(&mut entered))
+        })
+        .ok()
+        .flatten()
+}
+
+/// The name id of the innermost span entered on this thread.
+fn current() -> u32 {
+    with_entered(|entered| match entered.len {
+        0 => 0,
+        len => entered.spans[len - 1].1,
+    })
+    .unwrap_or(0)
+}
+
+/// A [`tracing_subscriber`] layer tracking which span each thread is in, so
+/// that detectors built with [`span_attribution`] can charge allocations to
+/// it by name:
+///
+/// ```ignore
+/// tracing_subscriber::registry().with(SpanAttributionLayer).init();
+/// ```
+///
+/// [`span_attribution`]: crate::LeakDetectorBuilder::span_attribution
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpanAttributionLayer;
+
+/// The interned name, kept in the span's extensions.
+struct NameId(u32);
+
+impl<S> Layer<S> for SpanAttributionLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let name = intern(span.name());
+            span.extensions_mut().insert(NameId(name));
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let name = ctx
+            .span(id)
+            .and_then(|span| span.extensions().get::<NameId>().map(|name| name.0))
+            .unwrap_or(0);
+        with_entered(|entered| {
+            if entered.len < MAX_DEPTH {
+                entered.spans[entered.len] = (id.into_u64(), name);
+                entered.len += 1;
+            }
+        });
+    }
+
+    fn on_exit(&self, id: &span::Id, _ctx: Context<'_, S>) {
+        let id = id.into_u64();
+        with_entered(|entered| {
+            let spans = &mut entered.spans[..entered.len];
+            if let Some(i) = spans.iter().rposition(|&(entered, _)| entered == id) {
+                spans.copy_within(i + 1.., i);
+                entered.len -= 1;
+            }
+        });
+    }
+}
+
+struct Totals {
+    bytes: AtomicUsize,
+    allocations: AtomicUsize,
+}
+
+/// A detector's totals, indexed by name id minus one.
+pub(crate) struct SpanTotals {
+    enabled: bool,
+    totals: [Totals; MAX_SPAN_NAMES],
+}
+
+impl SpanTotals {
+    pub(crate) const fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            totals: [const {
+                Totals {
+                    bytes: AtomicUsize::new(0),
+                    allocations: AtomicUsize::new(0),
+                }
+            }; MAX_SPAN_NAMES],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn charge(&self, size: usize) {
+        if !self.enabled {
+            return;
+        }
+        let Some(totals) = current()
+            .checked_sub(1)
+            .map(|index| &self.totals[index as usize])
+        else {
+            return;
+        };
+        totals.bytes.fetch_add(size, Ordering::Relaxed);
+        totals.allocations.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Bytes and allocations made under each span name, biggest first,
+    /// counted while the innermost entered span had that name. Empty unless
+    /// the detector was built with [`span_attribution`] and a
+    /// [`SpanAttributionLayer`] is installed.
+    ///
+    /// [`span_attribution`]: crate::LeakDetectorBuilder::span_attribution
+    pub fn span_usage(&self) -> Vec<(String, SpanStats)> {
+        let names = NAMES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .to_vec();
+        let mut usage: Vec<(String, SpanStats)> = names
+            .into_iter()
+            .zip(&self.spans.totals)
+            .map(|(name, totals)| {
+                let stats = SpanStats {
+                    bytes: totals.bytes.load(Ordering::Relaxed),
+                    allocations: totals.allocations.load(Ordering::Relaxed),
+                };
+                (name.to_owned(), stats)
+            })
+            .filter(|(_, stats)| stats.allocations != 0)
+            .collect();
+        usage.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.bytes));
+        usage
+    }
+
+    /// Forgets the totals of [`span_usage`](LeakDetector::span_usage).
+    pub fn clear_span_usage(&self) {
+        for totals in &self.spans.totals {
+            totals.bytes.store(0, Ordering::Relaxed);
+            totals.allocations.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[test]
+    fn charges_the_innermost_span() {
+        let detector = LeakDetector::builder(System).span_attribution(true).build();
+        let subscriber = tracing_subscriber::registry().with(SpanAttributionLayer);
+        let allocate = |size| {
+            let layout = Layout::from_size_align(size, 1).unwrap();
+            let block = detector.allocate(layout).unwrap();
+            unsafe { detector.deallocate(block.cast(), layout) };
+        };
+        tracing::subscriber::with_default(subscriber, || {
+            allocate(1);
+            let request = tracing::info_span!("span_test_request");
+            let _request = request.enter();
+            allocate(100);
+            {
+                let parse = tracing::info_span!("span_test_parse");
+                let _parse = parse.enter();
+                allocate(30);
+                allocate(20);
+            }
+            allocate(200);
+            drop(_request);
+            allocate(1000);
+        });
+
+        assert_eq!(
+            detector.span_usage(),
+            [
+                (
+                    "span_test_request".to_owned(),
+                    SpanStats {
+                        bytes: 300,
+                        allocations: 2
+                    }
+                ),
+                (
+                    "span_test_parse".to_owned(),
+                    SpanStats {
+                        bytes: 50,
+                        allocations: 2
+                    }
+                ),
+            ]
+        );
+        detector.clear_span_usage();
+        assert!(detector.span_usage().is_empty());
+    }
+
+    #[test]
+    fn off_unless_enabled() {
+        let detector = LeakDetector::system();
+        let subscriber = tracing_subscriber::registry().with(SpanAttributionLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("span_test_disabled").entered();
+            drop(Box::new_in(0u64, &detector));
+        });
+        assert!(detector.span_usage().is_empty());
+    }
+}
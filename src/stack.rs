@@ -0,0 +1,403 @@
+#[cfg(feature = "backtrace")]
+use std::{
+    cell::Cell,
+    hash::{DefaultHasher, Hasher},
+};
+use std::{collections::BTreeMap, hash::Hash};
+
+use crate::{
+    LeakDetector, Symbol,
+    overhead::{Account, Accounted, Internal, Locked},
+};
+
+/// Frames kept per captured stack, innermost first; deeper ones are dropped.
+pub(crate) const MAX_FRAMES: usize = 32;
+
+/// Distinct stacks a detector keeps. Allocations with a new stack past this
+/// are registered without one.
+pub const MAX_STACKS: usize = 1 << 16;
+
+/// A stack interned by a detector, see [`LeakReport::stack`].
+///
+/// [`LeakReport::stack`]: crate::LeakReport::stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StackId(u32);
+
+#[cfg(test)]
+impl StackId {
+    pub(crate) fn from_index(index: u32) -> Self {
+        StackId(index)
+    }
+}
+
+/// How stacks are captured and kept, so that the registry, the stack table
+/// and reports work the same with either backtrace backend.
+pub(crate) trait StackCapture {
+    /// One captured stack. Cloning it must not allocate, since the table
+    /// hands out clones with its lock held.
+    type Stack: Clone + PartialEq + Hash;
+
+    fn capture() -> Option<Self::Stack>;
+
+    /// Raw instruction pointers, innermost first; empty if the backend
+    /// doesn't expose them.
+    fn ips(stack: &Self::Stack) -> &[usize];
+
+    /// The frames, innermost first, with their inlined calls, for backends
+    /// that resolve them while capturing rather than on request.
+    fn resolved(stack: &Self::Stack) -> Option<&[Vec<Symbol>]>;
+}
+
+/// Raw instruction pointers of one allocation's stack. A fixed array so that
+/// capturing it from inside the allocator never allocates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    all(feature = "backtrace", not(feature = "backtrace-crate")),
+    allow(dead_code)
+)]
+pub(crate) struct Stack {
+    len: usize,
+    frames: [usize; MAX_FRAMES],
+}
+
+impl Stack {
+    #[cfg_attr(
+        all(feature = "backtrace", not(feature = "backtrace-crate")),
+        allow(dead_code)
+    )]
+    pub(crate) fn frames(&self) -> &[usize] {
+        &self.frames[..self.len]
+    }
+}
+
+/// The `backtrace` crate: raw frames captured without allocating, resolved
+/// only when a report is symbolized.
+#[cfg(feature = "backtrace-crate")]
+pub(crate) struct CrateBacktrace;
+
+#[cfg(feature = "backtrace-crate")]
+impl StackCapture for CrateBacktrace {
+    type Stack = Stack;
+
+    fn capture() -> Option<Stack> {
+        let mut stack = Stack {
+            len: 0,
+            frames: [0; MAX_FRAMES],
+        };
+        backtrace::trace(|frame| {
+            stack.frames[stack.len] = frame.ip() as usize;
+            stack.len += 1;
+            stack.len < MAX_FRAMES
+        });
+        Some(stack)
+    }
+
+    fn ips(stack: &Stack) -> &[usize] {
+        stack.frames()
+    }
+
+    fn resolved(_: &Stack) -> Option<&[Vec<Symbol>]> {
+        None
+    }
+}
+
+/// `std::backtrace`: no extra dependencies, but frames only come out as
+/// text, so each stack is resolved, allocating, as it is captured.
+#[cfg(all(feature = "backtrace", not(feature = "backtrace-crate")))]
+pub(crate) struct StdBacktrace;
+
+#[cfg(all(feature = "backtrace", not(feature = "backtrace-crate")))]
+impl StackCapture for StdBacktrace {
+    type Stack = std::sync::Arc<[Vec<Symbol>]>;
+
+    fn capture() -> Option<Self::Stack> {
+        if CAPTURING.try_with(Cell::get).unwrap_or(true) {
+            return None;
+        }
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                CAPTURING.set(false);
+            }
+        }
+        CAPTURING.set(true);
+        let _reset = Reset;
+        let text = std::backtrace::Backtrace::force_capture().to_string();
+        Some(parse_std_backtrace(&text).into())
+    }
+
+    fn ips(_: &Self::Stack) -> &[usize] {
+        &[]
+    }
+
+    fn resolved(stack: &Self::Stack) -> Option<&[Vec<Symbol>]> {
+        Some(stack)
+    }
+}
+
+/// Parses `std::backtrace::Backtrace`'s `Display` output: a numbered line
+/// per frame, an indented line per inlined call and an `at` line with the
+/// location of each.
+#[cfg(all(feature = "backtrace", not(feature = "backtrace-crate")))]
+fn parse_std_backtrace(text: &str) -> Vec<Vec<Symbol>> {
+    let mut frames: Vec<Vec<Symbol>> = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(location) = trimmed.strip_prefix("at ") {
+            let Some(symbol) = frames.last_mut().and_then(|frame| frame.last_mut()) else {
+                continue;
+            };
+            let mut parts = location.rsplitn(3, ':');
+            let (_column, line, file) = (parts.next(), parts.next(), parts.next());
+            if let (Some(line), Some(file)) = (line.and_then(|line| line.parse().ok()), file) {
+                symbol.line = Some(line);
+                symbol.file = Some(file.into());
+            }
+            continue;
+        }
+        let name = match trimmed.split_once(": ") {
+            Some((index, name)) if index.bytes().all(|b| b.is_ascii_digit()) => {
+                if frames.len() == MAX_FRAMES {
+                    break;
+                }
+                frames.push(Vec::new());
+                name
+            }
+            _ if line.starts_with(' ') && !frames.is_empty() => trimmed,
+            _ => continue,
+        };
+        frames.last_mut().unwrap().push(Symbol {
+            name: (name != "<unknown>").then(|| name.to_owned()),
+            file: None,
+            line: None,
+        });
+    }
+    frames
+}
+
+/// Builds without the `backtrace` feature never capture.
+#[cfg(not(feature = "backtrace"))]
+pub(crate) struct NoBacktrace;
+
+#[cfg(not(feature = "backtrace"))]
+impl StackCapture for NoBacktrace {
+    type Stack = Stack;
+
+    fn capture() -> Option<Stack> {
+        None
+    }
+
+    fn ips(stack: &Stack) -> &[usize] {
+        stack.frames()
+    }
+
+    fn resolved(_: &Stack) -> Option<&[Vec<Symbol>]> {
+        None
+    }
+}
+
+#[cfg(feature = "backtrace-crate")]
+pub(crate) type Backend = CrateBacktrace;
+#[cfg(all(feature = "backtrace", not(feature = "backtrace-crate")))]
+pub(crate) type Backend = StdBacktrace;
+#[cfg(not(feature = "backtrace"))]
+pub(crate) type Backend = NoBacktrace;
+
+pub(crate) type CapturedStack = <Backend as StackCapture>::Stack;
+
+#[cfg(all(feature = "backtrace", not(feature = "backtrace-crate")))]
+thread_local! {
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether this thread is capturing a stack with a backend that allocates.
+/// Those allocations, including the captured frames themselves, are not
+/// tracked.
+#[inline]
+pub(crate) fn capturing() -> bool {
+    #[cfg(all(feature = "backtrace", not(feature = "backtrace-crate")))]
+    return CAPTURING.try_with(Cell::get).unwrap_or(false);
+    #[cfg(not(all(feature = "backtrace", not(feature = "backtrace-crate"))))]
+    false
+}
+
+/// Each distinct stack once, so that registry entries only hold a
+/// [`StackId`]. Like the registry, it allocates from [`Internal`].
+pub(crate) struct StackTable {
+    inner: Accounted<Interned>,
+}
+
+struct Interned {
+    /// The newest stack with each hash; older ones are chained behind it.
+    #[cfg_attr(not(feature = "backtrace"), allow(dead_code))]
+    by_hash: BTreeMap<u64, StackId, Internal>,
+    stacks: Vec<(CapturedStack, Option<StackId>), Internal>,
+}
+
+impl StackTable {
+    pub(crate) const fn new() -> Self {
+        Self {
+            inner: Accounted::new(Interned {
+                by_hash: BTreeMap::new_in(Internal),
+                stacks: Vec::new_in(Internal),
+            }),
+        }
+    }
+
+    fn lock(&self) -> Locked<'_, Interned> {
+        self.inner.lock()
+    }
+
+    pub(crate) fn account(&self) -> &Account {
+        self.inner.account()
+    }
+
+    /// `None` once the table holds [`MAX_STACKS`] other stacks.
+    #[cfg(feature = "backtrace")]
+    pub(crate) fn intern(&self, stack: CapturedStack) -> Option<StackId> {
+        let mut hasher = DefaultHasher::new();
+        stack.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let mut interned = self.lock();
+        let head = interned.by_hash.get(&hash).copied();
+        let mut next = head;
+        while let Some(id) = next {
+            let (known, older) = &interned.stacks[id.0 as usize];
+            if *known == stack {
+                return Some(id);
+            }
+            next = *older;
+        }
+        if interned.stacks.len() >= MAX_STACKS {
+            return None;
+        }
+        let id = StackId(interned.stacks.len() as u32);
+        interned.stacks.push((stack, head));
+        interned.by_hash.insert(hash, id);
+        Some(id)
+    }
+
+    pub(crate) fn get(&self, id: StackId) -> Option<CapturedStack> {
+        self.lock()
+            .stacks
+            .get(id.0 as usize)
+            .map(|(stack, _)| stack)
+            .cloned()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.lock().stacks.len()
+    }
+}
+
+#[cfg(feature = "backtrace")]
+thread_local! {
+    static SAMPLE_COUNT: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Whether the next allocation on this thread records a stack when every
+/// `every`th one does. The count is per thread and only moves while stacks
+/// are being recorded, so a fixed allocation order always samples the same
+/// allocations.
+#[cfg(feature = "backtrace")]
+pub(crate) fn sample(every: usize) -> bool {
+    every != 0
+        && SAMPLE_COUNT
+            .try_with(|count| {
+                let n = count.get();
+                count.set(n.wrapping_add(1));
+                n % every == 0
+            })
+            .unwrap_or(false)
+}
+
+impl<T> LeakDetector<T> {
+    /// Distinct stacks recorded so far. They are kept for the detector's
+    /// whole life, up to [`MAX_STACKS`] of them.
+    pub fn unique_stacks(&self) -> usize {
+        self.registry.stacks.len()
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl<T> LeakDetector<T> {
+    /// Records a stack for only every `n`th allocation made on each thread,
+    /// `1` for all of them and `0` for none. Unsampled allocations still keep
+    /// their size and callsite in the registry.
+    pub fn set_backtrace_sampling(&self, n: usize) {
+        self.registry.set_backtrace_sampling(n);
+    }
+
+    pub fn backtrace_sampling(&self) -> usize {
+        self.registry.backtrace_sampling()
+    }
+}
+
+#[cfg(all(test, feature = "backtrace"))]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn sampling() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .backtraces(true)
+            .build();
+        detector.set_backtrace_sampling(4);
+        let boxes: Vec<_> = (0..16).map(|i| Box::new_in(i, &detector)).collect();
+        let report = detector.leak_report();
+        assert_eq!(report.allocations().len(), 16);
+        let sampled = report
+            .allocations()
+            .iter()
+            .filter(|allocation| allocation.stack.is_some())
+            .count();
+        assert_eq!(sampled, 4);
+        assert!(
+            report
+                .to_string()
+                .contains("~25% of leaked bytes have stacks")
+        );
+        drop(boxes);
+        detector.assert();
+    }
+
+    #[test]
+    fn one_stack_per_callsite() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .backtraces(true)
+            .build();
+        let boxes: Vec<_> = (0..1000).map(|i| Box::new_in(i, &detector)).collect();
+        assert_eq!(detector.unique_stacks(), 1);
+        let report = detector.leak_report();
+        let first = report.allocations()[0].stack.unwrap();
+        assert!(
+            report
+                .allocations()
+                .iter()
+                .all(|allocation| allocation.stack == Some(first))
+        );
+        #[cfg(feature = "backtrace-crate")]
+        assert!(!report.stack(first).unwrap().is_empty());
+        drop(boxes);
+        detector.assert();
+    }
+
+    #[cfg(not(feature = "backtrace-crate"))]
+    #[test]
+    fn parses_std_backtraces() {
+        let text = "   0: app::leak\n             at ./src/main.rs:4:13\n         \
+                    app::inlined_helper\n             at ./src/util.rs:9:5\n   \
+                    1: <unknown>\n";
+        let frames = parse_std_backtrace(text);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].len(), 2);
+        assert_eq!(frames[0][0].to_string(), "app::leak at ./src/main.rs:4");
+        assert_eq!(frames[0][1].name.as_deref(), Some("app::inlined_helper"));
+        assert_eq!(frames[1][0].name, None);
+    }
+}
@@ -0,0 +1,150 @@
+//! A stand-in for the `stats_alloc` crate's [`Region`] and [`Stats`], so tests
+//! written against it can switch to a [`LeakDetector`] unchanged.
+
+use std::ops;
+
+use crate::LeakDetector;
+
+/// Cumulative allocator activity, as `stats_alloc` counts it: growing
+/// reallocations add to `bytes_allocated`, shrinking ones to
+/// `bytes_deallocated`, and both to `bytes_reallocated`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Stats {
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub reallocations: usize,
+    pub bytes_allocated: usize,
+    pub bytes_deallocated: usize,
+    pub bytes_reallocated: isize,
+}
+
+impl ops::Sub for Stats {
+    type Output = Stats;
+
+    fn sub(self, earlier: Stats) -> Stats {
+        Stats {
+            allocations: self.allocations.wrapping_sub(earlier.allocations),
+            deallocations: self.deallocations.wrapping_sub(earlier.deallocations),
+            reallocations: self.reallocations.wrapping_sub(earlier.reallocations),
+            bytes_allocated: self.bytes_allocated.wrapping_sub(earlier.bytes_allocated),
+            bytes_deallocated: self
+                .bytes_deallocated
+                .wrapping_sub(earlier.bytes_deallocated),
+            bytes_reallocated: self
+                .bytes_reallocated
+                .wrapping_sub(earlier.bytes_reallocated),
+        }
+    }
+}
+
+impl ops::SubAssign for Stats {
+    fn sub_assign(&mut self, earlier: Stats) {
+        *self = *self - earlier;
+    }
+}
+
+/// Measures the activity of a detector from a starting point on.
+pub struct Region<'a, T> {
+    detector: &'a LeakDetector<T>,
+    initial: Stats,
+}
+
+impl<'a, T> Region<'a, T> {
+    pub fn new(detector: &'a LeakDetector<T>) -> Self {
+        Region {
+            detector,
+            initial: detector.stats(),
+        }
+    }
+
+    pub fn initial(&self) -> Stats {
+        self.initial
+    }
+
+    /// The activity since the region started or was last reset.
+    pub fn change(&self) -> Stats {
+        self.detector.stats() - self.initial
+    }
+
+    pub fn change_and_reset(&mut self) -> Stats {
+        let now = self.detector.stats();
+        let change = now - self.initial;
+        self.initial = now;
+        change
+    }
+
+    pub fn reset(&mut self) {
+        self.initial = self.detector.stats();
+    }
+}
+
+impl<T> LeakDetector<T> {
+    pub fn stats(&self) -> Stats {
+        Stats {
+            allocations: self.counters.allocations(),
+            deallocations: self.counters.deallocations(),
+            reallocations: self.counters.reallocations(),
+            bytes_allocated: self.counters.bytes_allocated(),
+            bytes_deallocated: self.counters.bytes_deallocated(),
+            bytes_reallocated: self.counters.bytes_reallocated(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn vec_lifecycle() {
+        let detector = LeakDetector::system();
+        let mut region = Region::new(&detector);
+        let mut x = Vec::<u8, _>::with_capacity_in(1024, &detector);
+        assert_eq!(
+            region.change(),
+            Stats {
+                allocations: 1,
+                bytes_allocated: 1024,
+                ..Stats::default()
+            }
+        );
+
+        x.reserve_exact(2048);
+        x.shrink_to(512);
+        assert_eq!(
+            region.change_and_reset(),
+            Stats {
+                allocations: 1,
+                reallocations: 2,
+                bytes_allocated: 2048,
+                bytes_deallocated: 1536,
+                bytes_reallocated: 512 - 1024,
+                ..Stats::default()
+            }
+        );
+
+        drop(x);
+        assert_eq!(
+            region.change(),
+            Stats {
+                deallocations: 1,
+                bytes_deallocated: 512,
+                ..Stats::default()
+            }
+        );
+        region.reset();
+        assert_eq!(region.change(), Stats::default());
+        assert_eq!(region.initial(), detector.stats());
+    }
+
+    #[test]
+    fn detectors_are_independent() {
+        let detector = LeakDetector::builder(System).build();
+        let other = LeakDetector::system();
+        let region = Region::new(&detector);
+        drop(Box::new_in(0u64, &other));
+        assert_eq!(region.change(), Stats::default());
+    }
+}
@@ -0,0 +1,200 @@
+use std::{cmp::Reverse, collections::BTreeMap, fmt, panic::Location, time::Duration};
+
+use crate::{LeakReport, SortSites, StackId};
+
+/// A [`LeakReport`] folded into its biggest allocation sites, see
+/// [`LeakReport::summary`].
+pub struct Summary<'a> {
+    report: &'a LeakReport,
+    pub(crate) sites: Vec<Site>,
+    max_sites: usize,
+    max_frames: usize,
+}
+
+/// The live allocations sharing a callsite and stack.
+pub(crate) struct Site {
+    pub(crate) callsite: &'static Location<'static>,
+    pub(crate) stack: Option<StackId>,
+    pub(crate) bytes: usize,
+    pub(crate) allocations: usize,
+    /// Age of the site's oldest allocation.
+    oldest: Duration,
+}
+
+impl LeakReport {
+    /// The report grouped by callsite and stack, in the order of its
+    /// [`options`](LeakReport::options), showing at most `max_sites` sites
+    /// with `max_frames` frames each and summing up the rest.
+    pub fn summary(&self, max_sites: usize, max_frames: usize) -> Summary<'_> {
+        Summary {
+            report: self,
+            sites: self.sites(self.options().sort),
+            max_sites,
+            max_frames,
+        }
+    }
+
+    /// The allocations grouped by callsite and stack, ordered by `sort`.
+    pub(crate) fn sites(&self, sort: SortSites) -> Vec<Site> {
+        let mut sites: Vec<Site> = Vec::new();
+        // Indices into `sites`, which keeps them in the order first seen.
+        let mut by_key: BTreeMap<_, usize> = BTreeMap::new();
+        for allocation in self.allocations() {
+            match by_key.get(&(allocation.callsite, allocation.stack)) {
+                Some(&index) => {
+                    let site = &mut sites[index];
+                    site.bytes += self.estimate(allocation.size);
+                    site.allocations += 1;
+                    site.oldest = site.oldest.max(allocation.age);
+                }
+                None => {
+                    by_key.insert((allocation.callsite, allocation.stack), sites.len());
+                    sites.push(Site {
+                        callsite: allocation.callsite,
+                        stack: allocation.stack,
+                        bytes: self.estimate(allocation.size),
+                        allocations: 1,
+                        oldest: allocation.age,
+                    });
+                }
+            }
+        }
+        match sort {
+            SortSites::BySize => sites.sort_by_key(|site| Reverse(site.bytes)),
+            SortSites::ByCount => {
+                sites.sort_by_key(|site| (Reverse(site.allocations), Reverse(site.bytes)))
+            }
+            SortSites::ByAge => {
+                sites.sort_by_key(|site| (Reverse(site.oldest), Reverse(site.bytes)))
+            }
+        }
+        sites
+    }
+}
+
+/// The first `top` of `sites` with at least `min_bytes`, and the others.
+pub(crate) fn split_sites(
+    sites: &[Site],
+    top: usize,
+    min_bytes: usize,
+) -> (Vec<&Site>, Vec<&Site>) {
+    let mut shown = Vec::new();
+    let mut rest = Vec::new();
+    for site in sites {
+        if site.bytes >= min_bytes && shown.len() < top {
+            shown.push(site);
+        } else {
+            rest.push(site);
+        }
+    }
+    (shown, rest)
+}
+
+impl Site {
+    pub(crate) fn write(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        report: &LeakReport,
+        max_frames: usize,
+    ) -> fmt::Result {
+        if report.sampling().is_some() {
+            write!(
+                f,
+                "\n  ~{} bytes in {} sampled allocation(s) at {}",
+                self.bytes, self.allocations, self.callsite
+            )?;
+        } else {
+            write!(
+                f,
+                "\n  {} bytes in {} allocation(s) at {}",
+                self.bytes, self.allocations, self.callsite
+            )?;
+        }
+        report.write_frames(f, self.stack, max_frames)
+    }
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = self.report;
+        write!(
+            f,
+            "{} bytes live in {} allocation(s) from {} site(s)",
+            report.bytes(),
+            report.allocations().len(),
+            self.sites.len()
+        )?;
+        let (shown, rest) = split_sites(&self.sites, self.max_sites, report.options().min_bytes);
+        for site in shown {
+            site.write(f, report, self.max_frames)?;
+        }
+        if !rest.is_empty() {
+            write!(
+                f,
+                "\n  ... {} more site(s) with {} bytes in {} allocation(s)",
+                rest.len(),
+                rest.iter().map(|site| site.bytes).sum::<usize>(),
+                rest.iter().map(|site| site.allocations).sum::<usize>()
+            )?;
+        }
+        let mut patterns: Vec<(&str, usize, usize)> = Vec::new();
+        for suppressed in report.suppressed() {
+            let size = suppressed.allocation.size;
+            match patterns
+                .iter_mut()
+                .find(|(pattern, ..)| *pattern == suppressed.pattern)
+            {
+                Some((_, bytes, allocations)) => {
+                    *bytes += size;
+                    *allocations += 1;
+                }
+                None => patterns.push((&suppressed.pattern, size, 1)),
+            }
+        }
+        for (pattern, bytes, allocations) in patterns {
+            write!(
+                f,
+                "\n  {bytes} bytes in {allocations} allocation(s) suppressed by '{pattern}'"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout, System};
+
+    use crate::LeakDetector;
+
+    #[test]
+    fn caps_sites() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let small = Layout::new::<[u8; 8]>();
+        let big = Layout::new::<[u8; 100]>();
+        let other = Layout::new::<u16>();
+        let mut blocks = Vec::new();
+        for _ in 0..3 {
+            blocks.push((detector.allocate(small).unwrap(), small));
+        }
+        blocks.push((detector.allocate(big).unwrap(), big));
+        blocks.push((detector.allocate(other).unwrap(), other));
+
+        let report = detector.leak_report();
+        let text = report.summary(1, 4).to_string();
+        let mut lines = text.lines();
+        assert_eq!(
+            lines.next(),
+            Some("126 bytes live in 5 allocation(s) from 3 site(s)")
+        );
+        let site = format!("  100 bytes in 1 allocation(s) at {}:", file!());
+        assert!(lines.next().unwrap().starts_with(&site));
+        assert_eq!(
+            lines.next(),
+            Some("  ... 2 more site(s) with 26 bytes in 4 allocation(s)")
+        );
+        for (block, layout) in blocks {
+            unsafe { detector.deallocate(block.cast(), layout) };
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::{fmt, ops::RangeInclusive};
+
+use crate::{LeakDetector, LeakedAllocation, overhead::Internal};
+
+/// One way of excusing live allocations, kept in the order added.
+#[derive(Debug, Clone)]
+pub(crate) enum Suppression {
+    #[cfg(feature = "backtrace")]
+    Symbol(Pattern),
+    Callsite(Pattern),
+    Size(RangeInclusive<usize>),
+    Id(u64),
+}
+
+/// The text of a symbol or callsite pattern, in [`Internal`] memory like
+/// the list it's kept in.
+#[derive(Debug, Clone)]
+pub(crate) struct Pattern(Vec<u8, Internal>);
+
+impl Pattern {
+    pub(crate) fn new(pattern: &str) -> Self {
+        let mut bytes = Vec::new_in(Internal);
+        bytes.extend_from_slice(pattern.as_bytes());
+        Self(bytes)
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        unsafe { std::str::from_utf8_unchecked(&self.0) }
+    }
+}
+
+impl Suppression {
+    #[cfg(feature = "backtrace")]
+    pub(crate) fn symbol(&self) -> Option<&str> {
+        match self {
+            Suppression::Symbol(pattern) => Some(pattern.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Whether `allocation` is excused without looking at its stack.
+    pub(crate) fn matches_block(&self, allocation: &LeakedAllocation) -> bool {
+        match self {
+            #[cfg(feature = "backtrace")]
+            Suppression::Symbol(_) => false,
+            Suppression::Callsite(pattern) => {
+                allocation.callsite.to_string().contains(pattern.as_str())
+            }
+            Suppression::Size(sizes) => sizes.contains(&allocation.size),
+            Suppression::Id(id) => allocation.id == *id,
+        }
+    }
+}
+
+/// How the suppression shows up in [`SuppressedAllocation::pattern`]:
+/// symbol and callsite patterns as given, sizes as `16..=64`, ids as `#7`.
+///
+/// [`SuppressedAllocation::pattern`]: crate::SuppressedAllocation::pattern
+impl fmt::Display for Suppression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "backtrace")]
+            Suppression::Symbol(pattern) => f.write_str(pattern.as_str()),
+            Suppression::Callsite(pattern) => f.write_str(pattern.as_str()),
+            Suppression::Size(sizes) => write!(f, "{}..={}", sizes.start(), sizes.end()),
+            Suppression::Id(id) => write!(f, "#{id}"),
+        }
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Excuses every live allocation with a frame whose symbol contains
+    /// `pattern`, such as `once_cell` or `regex::`. Matching is a plain
+    /// substring search, not a regex, and needs the allocation's stack, so
+    /// unsampled allocations are never excused.
+    ///
+    /// Excused allocations are listed separately by [`leak_report`] and don't
+    /// count towards [`check`]. Stacks are only symbolized when a check would
+    /// otherwise fail or a report is taken.
+    ///
+    /// [`leak_report`]: LeakDetector::leak_report
+    /// [`check`]: LeakDetector::check
+    #[cfg(feature = "backtrace")]
+    pub fn add_symbol_suppression(&self, pattern: &str) {
+        self.add_suppression(Suppression::Symbol(Pattern::new(pattern)));
+    }
+
+    /// Excuses every live allocation whose callsite, as `file:line:column`,
+    /// contains `pattern`, like `src/cache.rs` or `src/cache.rs:42:`. Needs
+    /// the registry, like every suppression.
+    pub fn add_callsite_suppression(&self, pattern: &str) {
+        self.add_suppression(Suppression::Callsite(Pattern::new(pattern)));
+    }
+
+    /// Excuses every live allocation with a size in `sizes`.
+    pub fn add_size_suppression(&self, sizes: RangeInclusive<usize>) {
+        self.add_suppression(Suppression::Size(sizes));
+    }
+
+    /// Excuses the one allocation with this
+    /// [`id`](crate::LeakedAllocation::id), wherever it has moved since,
+    /// for a block kept on purpose that a callsite or size would match too
+    /// broadly.
+    pub fn expect_leak_by_id(&self, id: u64) {
+        self.add_suppression(Suppression::Id(id));
+    }
+
+    /// Keeps a copy of `suppression`, made under the lock so its pattern is
+    /// charged to the list.
+    pub(crate) fn add_suppression(&self, suppression: Suppression) {
+        let mut suppressions = self.suppressions.lock();
+        suppressions.push(suppression.clone());
+    }
+
+    pub(crate) fn with_suppressions<R>(&self, f: impl FnOnce(&[Suppression]) -> R) -> R {
+        f(&self.suppressions.lock())
+    }
+
+    /// Live bytes excused by a suppression.
+    pub(crate) fn suppressed_bytes(&self) -> usize {
+        if self.suppressions.lock().is_empty() {
+            return 0;
+        }
+        self.leak_report().suppressed_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[cfg(feature = "backtrace")]
+    #[inline(never)]
+    fn leak_from_cache(detector: &LeakDetector<System>) -> Box<[u8; 16], &LeakDetector<System>> {
+        Box::new_in([0; 16], detector)
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[inline(never)]
+    fn leak_from_request(detector: &LeakDetector<System>) -> Box<[u8; 8], &LeakDetector<System>> {
+        Box::new_in([0; 8], detector)
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[test]
+    fn excuses_matching_stacks() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .backtraces(true)
+            .build();
+        detector.add_symbol_suppression("leak_from_cache");
+        let cached = leak_from_cache(&detector);
+        let request = leak_from_request(&detector);
+
+        let report = detector.leak_report();
+        assert_eq!(report.allocations().len(), 1);
+        assert_eq!(report.bytes(), 8);
+        assert_eq!(report.suppressed().len(), 1);
+        assert_eq!(report.suppressed()[0].pattern, "leak_from_cache");
+        assert!(
+            report
+                .to_string()
+                .contains("16 bytes in 1 allocation(s) suppressed")
+        );
+        assert!(matches!(
+            detector.check(),
+            Err(crate::LeakError::Leaked { bytes: 8, .. })
+        ));
+
+        drop(request);
+        detector.check().unwrap();
+        drop(cached);
+    }
+
+    #[test]
+    fn excuses_callsites_and_sizes() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        detector.add_callsite_suppression(file!());
+        detector.add_size_suppression(100..=200);
+        let layout = std::alloc::Layout::from_size_align(8, 8).unwrap();
+        let here = std::alloc::Allocator::allocate(&detector, layout).unwrap();
+        let sized = Vec::<u8, _>::with_capacity_in(150, &detector);
+        let leaked = Vec::<u8, _>::with_capacity_in(32, &detector);
+
+        let report = detector.leak_report();
+        assert_eq!(report.bytes(), 32);
+        let patterns: Vec<&str> = report
+            .suppressed()
+            .iter()
+            .map(|suppressed| suppressed.pattern.as_str())
+            .collect();
+        assert_eq!(patterns.len(), 2);
+        assert!(patterns.contains(&file!()));
+        assert!(patterns.contains(&"100..=200"));
+        drop(leaked);
+        detector.check().unwrap();
+        drop(sized);
+        unsafe { std::alloc::Allocator::deallocate(&detector, here.cast(), layout) };
+    }
+
+    #[test]
+    fn excuses_ids() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let mut kept = Vec::<u8, _>::with_capacity_in(16, &detector);
+        let leaked = Box::new_in([0u8; 24], &detector);
+        let id = detector
+            .leak_report()
+            .allocations()
+            .iter()
+            .find(|allocation| allocation.address == kept.as_ptr() as usize)
+            .unwrap()
+            .id;
+        detector.expect_leak_by_id(id);
+        kept.reserve_exact(4096);
+
+        let report = detector.leak_report();
+        assert_eq!(report.bytes(), 24);
+        assert_eq!(report.suppressed().len(), 1);
+        assert_eq!(report.suppressed()[0].allocation.id, id);
+        assert_eq!(report.suppressed()[0].pattern, format!("#{id}"));
+        let text = report.to_string();
+        assert!(
+            text.contains(&format!("\n  allocation #{id}, 4096 bytes at 0x")),
+            "{text}"
+        );
+        drop(leaked);
+        detector.check().unwrap();
+        drop(kept);
+    }
+}
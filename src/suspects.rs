@@ -0,0 +1,193 @@
+//! A heuristic for long-running processes: blocks that outlive several
+//! checkpoints while usage keeps growing are probably leaking. It can't tell
+//! a leak from a cache that is still warming up, so its results are suspects
+//! to look at, not failures.
+
+use std::panic::Location;
+
+use crate::LeakDetector;
+
+/// Checkpoints kept; older ones are forgotten.
+const MAX_CHECKPOINTS: usize = 1024;
+
+/// Tuning for [`LeakDetector::leak_suspects_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuspectOptions {
+    /// How many checkpoints a block must survive to be suspicious, at least 2.
+    pub min_checkpoints: usize,
+    /// How much `used` must have grown over the last `min_checkpoints`
+    /// checkpoints for anything to be suspected at all.
+    pub min_growth: usize,
+}
+
+impl Default for SuspectOptions {
+    fn default() -> Self {
+        SuspectOptions {
+            min_checkpoints: 3,
+            min_growth: 0,
+        }
+    }
+}
+
+/// A callsite whose blocks look like a leak, see
+/// [`LeakDetector::leak_suspects`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuspectSite {
+    pub callsite: &'static Location<'static>,
+    /// Live bytes and blocks from the site that survived a checkpoint.
+    pub bytes: usize,
+    pub allocations: usize,
+    /// Checkpoints survived by the site's oldest block.
+    pub survived: usize,
+    /// Of the intervals between the last `min_checkpoints` checkpoints, how
+    /// many left behind at least one block from the site.
+    pub intervals_grown: usize,
+    /// `intervals_grown` over the number of intervals: 1.0 for a site that
+    /// left blocks behind every time, 0.0 for one that stopped.
+    pub confidence: f64,
+}
+
+impl<T> LeakDetector<T> {
+    /// Ends the current epoch and records how much was in use, for
+    /// [`leak_suspects`](LeakDetector::leak_suspects). Call it at a steady
+    /// point that recurs, like between requests or frames.
+    pub fn checkpoint(&self) {
+        let epoch = self.registry.advance_epoch();
+        let used = self.get_used();
+        let mut checkpoints = self.checkpoints.lock();
+        if checkpoints.len() == MAX_CHECKPOINTS {
+            checkpoints.remove(0);
+        }
+        checkpoints.push((epoch, used));
+    }
+
+    /// [`leak_suspects_with`](LeakDetector::leak_suspects_with) with the
+    /// default options.
+    pub fn leak_suspects(&self) -> Vec<SuspectSite> {
+        self.leak_suspects_with(SuspectOptions::default())
+    }
+
+    /// Heuristically likely leaks, most likely first: callsites with live
+    /// registry blocks that survived `min_checkpoints` checkpoints, over
+    /// which `used` grew by more than `min_growth`. Empty until that many
+    /// checkpoints were taken, or when usage didn't grow.
+    pub fn leak_suspects_with(&self, options: SuspectOptions) -> Vec<SuspectSite> {
+        let k = options.min_checkpoints.max(2);
+        let checkpoints = self.checkpoints.lock().to_vec();
+        if checkpoints.len() < k {
+            return Vec::new();
+        }
+        let window = &checkpoints[checkpoints.len() - k..];
+        let growth = window[k - 1].1.wrapping_sub(window[0].1) as isize;
+        if growth <= options.min_growth as isize {
+            return Vec::new();
+        }
+
+        struct Site {
+            callsite: &'static Location<'static>,
+            bytes: usize,
+            allocations: usize,
+            survived: usize,
+            grown: Vec<bool>,
+        }
+        let mut sites: Vec<Site> = Vec::new();
+        for (_, entry) in self.registry.entries(0) {
+            // The first checkpoint taken after the block was allocated.
+            let first = checkpoints.partition_point(|&(epoch, _)| epoch < entry.epoch);
+            let survived = checkpoints.len() - first;
+            if survived == 0 {
+                continue;
+            }
+            let site = match sites
+                .iter_mut()
+                .position(|site| site.callsite == entry.callsite)
+            {
+                Some(index) => &mut sites[index],
+                None => {
+                    sites.push(Site {
+                        callsite: entry.callsite,
+                        bytes: 0,
+                        allocations: 0,
+                        survived: 0,
+                        grown: vec![false; k - 1],
+                    });
+                    sites.last_mut().unwrap()
+                }
+            };
+            site.bytes += entry.size;
+            site.allocations += 1;
+            site.survived = site.survived.max(survived);
+            if let Some(interval) = first.checked_sub(checkpoints.len() - k + 1) {
+                site.grown[interval] = true;
+            }
+        }
+
+        let mut suspects: Vec<SuspectSite> = sites
+            .into_iter()
+            .filter(|site| site.survived >= k)
+            .map(|site| {
+                let intervals_grown = site.grown.iter().filter(|&&grown| grown).count();
+                SuspectSite {
+                    callsite: site.callsite,
+                    bytes: site.bytes,
+                    allocations: site.allocations,
+                    survived: site.survived,
+                    intervals_grown,
+                    confidence: intervals_grown as f64 / (k - 1) as f64,
+                }
+            })
+            .collect();
+        suspects.sort_by(|a, b| {
+            b.confidence
+                .total_cmp(&a.confidence)
+                .then(b.bytes.cmp(&a.bytes))
+        });
+        suspects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Allocator, Layout, System};
+
+    use super::*;
+
+    #[test]
+    fn ranks_accumulating_site_first() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let cache = Layout::new::<[u8; 4096]>();
+        let entry = Layout::new::<[u8; 64]>();
+        let scratch = Layout::new::<[u8; 256]>();
+        let warm = detector.allocate(cache).unwrap();
+        detector.checkpoint();
+        assert!(detector.leak_suspects().is_empty());
+
+        let mut leaked = Vec::new();
+        for _ in 0..5 {
+            leaked.push(detector.allocate(entry).unwrap());
+            let temporary = detector.allocate(scratch).unwrap();
+            unsafe { detector.deallocate(temporary.cast(), scratch) };
+            detector.checkpoint();
+        }
+
+        let suspects = detector.leak_suspects();
+        assert_eq!(suspects.len(), 2);
+        assert_eq!(suspects[0].bytes, 5 * 64);
+        assert_eq!(suspects[0].allocations, 5);
+        assert_eq!(suspects[0].survived, 5);
+        assert_eq!(suspects[0].confidence, 1.0);
+        assert_eq!(suspects[1].bytes, 4096);
+        assert_eq!(suspects[1].confidence, 0.0);
+
+        let strict = SuspectOptions {
+            min_checkpoints: 3,
+            min_growth: 1024,
+        };
+        assert!(detector.leak_suspects_with(strict).is_empty());
+
+        for block in leaked {
+            unsafe { detector.deallocate(block.cast(), entry) };
+        }
+        unsafe { detector.deallocate(warm.cast(), cache) };
+    }
+}
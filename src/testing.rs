@@ -0,0 +1,426 @@
+//! Randomized allocator workloads with a shadow model of what the detector
+//! should count, for checking an allocator wrapped in a [`LeakDetector`] or
+//! a [`LocalLeakDetector`]:
+//!
+//! ```ignore
+//! let detector = LeakDetector::builder(MyAllocator::new()).registry(true).build();
+//! for seed in 0..16 {
+//!     OpSequence::generate(seed, 1000).run(&detector).unwrap();
+//! }
+//! ```
+//!
+//! Every block is filled with a pattern that is checked when it's resized
+//! or freed, so an allocator that loses contents fails too.
+
+use std::{
+    alloc::{Allocator, Layout},
+    fmt,
+    ptr::NonNull,
+};
+
+use crate::{LeakDetector, LocalLeakDetector};
+
+/// A detector whose accounting [`OpSequence::run`] can check.
+pub trait Counted: Allocator {
+    fn get_used(&self) -> usize;
+}
+
+impl<A: Allocator> Counted for LeakDetector<A> {
+    fn get_used(&self) -> usize {
+        LeakDetector::get_used(self)
+    }
+}
+
+impl<A: Allocator> Counted for LocalLeakDetector<A> {
+    fn get_used(&self) -> usize {
+        LocalLeakDetector::get_used(self)
+    }
+}
+
+/// One step of an [`OpSequence`]. `slot` indexes the blocks live at that
+/// step, in allocation order, with a freed block's slot taken by the last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Allocate { layout: Layout },
+    Grow { slot: usize, new_size: usize },
+    GrowZeroed { slot: usize, new_size: usize },
+    Shrink { slot: usize, new_size: usize },
+    Deallocate { slot: usize },
+}
+
+/// What went wrong at a step of [`OpSequence::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchKind {
+    /// [`get_used`](LeakDetector::get_used) disagreed with the model.
+    Used { expected: usize, actual: usize },
+    /// A block lost its pattern, or a grown block wasn't zeroed.
+    Contents,
+    /// The allocator refused the request.
+    AllocError,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The index of the step; the sequence's length for the final frees.
+    pub step: usize,
+    /// `None` for the final frees.
+    pub op: Option<Op>,
+    pub kind: MismatchKind,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op {
+            Some(op) => write!(f, "step {} ({op:?}): ", self.step)?,
+            None => write!(f, "freeing what was left after step {}: ", self.step)?,
+        }
+        match self.kind {
+            MismatchKind::Used { expected, actual } => {
+                write!(f, "used is {actual} bytes, the model says {expected}")
+            }
+            MismatchKind::Contents => f.write_str("block contents were lost"),
+            MismatchKind::AllocError => f.write_str("the allocator failed"),
+        }
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// A reproducible sequence of valid allocator operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpSequence {
+    ops: Vec<Op>,
+}
+
+impl OpSequence {
+    /// `len` operations drawn from `seed`. Sizes go up to 4 KiB, alignments
+    /// up to 64, and zero sizes come up too.
+    pub fn generate(seed: u64, len: usize) -> Self {
+        let mut rng = SplitMix64(seed);
+        // The layout of each live block, as the executor's slots will be.
+        let mut live: Vec<Layout> = Vec::new();
+        let mut ops = Vec::with_capacity(len);
+        while ops.len() < len {
+            let slot = match live.len() {
+                0 => None,
+                n => Some(rng.below(n as u64) as usize),
+            };
+            let op = match (rng.below(8), slot) {
+                (0..3, _) | (_, None) => {
+                    let align = 1 << rng.below(7);
+                    let layout = Layout::from_size_align(rng.size(), align).unwrap();
+                    live.push(layout);
+                    Op::Allocate { layout }
+                }
+                (3 | 4, Some(slot)) => {
+                    let old = live[slot].size();
+                    let new_size = old + rng.below(old as u64 + 64) as usize;
+                    live[slot] = Layout::from_size_align(new_size, live[slot].align()).unwrap();
+                    if rng.below(2) == 0 {
+                        Op::Grow { slot, new_size }
+                    } else {
+                        Op::GrowZeroed { slot, new_size }
+                    }
+                }
+                (5, Some(slot)) => {
+                    let new_size = rng.below(live[slot].size() as u64 + 1) as usize;
+                    live[slot] = Layout::from_size_align(new_size, live[slot].align()).unwrap();
+                    Op::Shrink { slot, new_size }
+                }
+                (_, Some(slot)) => {
+                    live.swap_remove(slot);
+                    Op::Deallocate { slot }
+                }
+            };
+            ops.push(op);
+        }
+        Self { ops }
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Runs the sequence against `detector`, then frees whatever it left,
+    /// checking after every step that `get_used` moved by exactly what the
+    /// model says. Nothing else may allocate from `detector` meanwhile.
+    ///
+    /// # Panics
+    ///
+    /// If the sequence wasn't made by [`generate`](OpSequence::generate)
+    /// and refers to a slot that isn't live.
+    pub fn run<D: Counted>(&self, detector: &D) -> Result<(), Mismatch> {
+        let mut executor = Executor {
+            detector,
+            start: detector.get_used(),
+            used: 0,
+            slots: Vec::new(),
+            tags: 0,
+        };
+        let result = self.ops.iter().enumerate().try_for_each(|(step, &op)| {
+            executor
+                .apply(op)
+                .and_then(|()| executor.check_used())
+                .map_err(|kind| Mismatch {
+                    step,
+                    op: Some(op),
+                    kind,
+                })
+        });
+        let cleanup = executor.free_all().map_err(|kind| Mismatch {
+            step: self.ops.len(),
+            op: None,
+            kind,
+        });
+        result.and(cleanup)
+    }
+}
+
+struct Slot {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    /// The byte its contents are filled with.
+    tag: u8,
+}
+
+struct Executor<'a, D> {
+    detector: &'a D,
+    start: usize,
+    /// The model: bytes live from this sequence.
+    used: usize,
+    slots: Vec<Slot>,
+    tags: u8,
+}
+
+impl<D: Counted> Executor<'_, D> {
+    fn apply(&mut self, op: Op) -> Result<(), MismatchKind> {
+        match op {
+            Op::Allocate { layout } => {
+                let ptr = self
+                    .detector
+                    .allocate(layout)
+                    .map_err(|_| MismatchKind::AllocError)?
+                    .cast();
+                // Never 0, which a fresh block may hold by chance.
+                self.tags = self.tags % 255 + 1;
+                let slot = Slot {
+                    ptr,
+                    layout,
+                    tag: self.tags,
+                };
+                unsafe { fill(&slot, 0) };
+                self.used += layout.size();
+                self.slots.push(slot);
+            }
+            Op::Grow { slot, new_size }
+            | Op::GrowZeroed { slot, new_size }
+            | Op::Shrink { slot, new_size } => {
+                let slot = &mut self.slots[slot];
+                let old = slot.layout;
+                let new = Layout::from_size_align(new_size, old.align()).unwrap();
+                let result = unsafe {
+                    match op {
+                        Op::Grow { .. } => self.detector.grow(slot.ptr, old, new),
+                        Op::GrowZeroed { .. } => self.detector.grow_zeroed(slot.ptr, old, new),
+                        _ => self.detector.shrink(slot.ptr, old, new),
+                    }
+                };
+                slot.ptr = result.map_err(|_| MismatchKind::AllocError)?.cast();
+                slot.layout = new;
+                let kept = old.size().min(new_size);
+                let intact = unsafe { holds(slot, 0, kept) }
+                    && (!matches!(op, Op::GrowZeroed { .. })
+                        || unsafe { zeroed(slot.ptr, kept, new_size) });
+                unsafe { fill(slot, kept) };
+                self.used = self.used - old.size() + new_size;
+                if !intact {
+                    return Err(MismatchKind::Contents);
+                }
+            }
+            Op::Deallocate { slot } => {
+                let slot = self.slots.swap_remove(slot);
+                self.free(slot)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn free(&mut self, slot: Slot) -> Result<(), MismatchKind> {
+        let intact = unsafe { holds(&slot, 0, slot.layout.size()) };
+        unsafe { self.detector.deallocate(slot.ptr, slot.layout) };
+        self.used -= slot.layout.size();
+        if intact {
+            Ok(())
+        } else {
+            Err(MismatchKind::Contents)
+        }
+    }
+
+    fn check_used(&self) -> Result<(), MismatchKind> {
+        let expected = self.start + self.used;
+        let actual = self.detector.get_used();
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(MismatchKind::Used { expected, actual })
+        }
+    }
+
+    /// Frees every slot, even past a mismatch, so nothing leaks.
+    fn free_all(&mut self) -> Result<(), MismatchKind> {
+        let mut result = Ok(());
+        while let Some(slot) = self.slots.pop() {
+            result = result.and(self.free(slot));
+        }
+        result.and_then(|()| self.check_used())
+    }
+}
+
+/// Fills the slot's bytes from `from` on with its tag.
+unsafe fn fill(slot: &Slot, from: usize) {
+    let len = slot.layout.size() - from;
+    unsafe { slot.ptr.add(from).write_bytes(slot.tag, len) };
+}
+
+/// Whether the slot's bytes in `from..to` hold its tag.
+unsafe fn holds(slot: &Slot, from: usize, to: usize) -> bool {
+    let bytes = unsafe { std::slice::from_raw_parts(slot.ptr.as_ptr().add(from), to - from) };
+    bytes.iter().all(|&byte| byte == slot.tag)
+}
+
+unsafe fn zeroed(ptr: NonNull<u8>, from: usize, to: usize) -> bool {
+    let bytes = unsafe { std::slice::from_raw_parts(ptr.as_ptr().add(from), to - from) };
+    bytes.iter().all(|&byte| byte == 0)
+}
+
+/// Small and dependency-free; sequences only need to be reproducible.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Mostly small, sometimes empty, now and then up to 4 KiB.
+    fn size(&mut self) -> usize {
+        match self.below(16) {
+            0 => 0,
+            1 => self.below(4097) as usize,
+            _ => self.below(257) as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{AllocError, System};
+
+    use super::*;
+
+    #[test]
+    fn generates_the_same_sequence_from_a_seed() {
+        let ops = OpSequence::generate(7, 200);
+        assert_eq!(ops, OpSequence::generate(7, 200));
+        assert_ne!(ops, OpSequence::generate(8, 200));
+        assert_eq!(ops.ops().len(), 200);
+        let kinds: std::collections::HashSet<_> =
+            ops.ops().iter().map(std::mem::discriminant).collect();
+        assert_eq!(kinds.len(), 5);
+    }
+
+    #[test]
+    fn system_matches_the_model() {
+        for registry in [false, true] {
+            let detector = LeakDetector::builder(System).registry(registry).build();
+            for seed in 0..8 {
+                OpSequence::generate(seed, 500).run(&detector).unwrap();
+            }
+            assert_eq!(detector.get_used(), 0);
+            assert!(detector.diagnostics().is_empty());
+        }
+    }
+
+    #[test]
+    fn local_matches_the_model() {
+        for detector in [
+            LocalLeakDetector::new(System),
+            LocalLeakDetector::with_registry(System),
+        ] {
+            for seed in 0..8 {
+                OpSequence::generate(seed, 500).run(&detector).unwrap();
+            }
+            assert_eq!(detector.get_used(), 0);
+            assert_eq!(detector.live_allocations(), 0);
+        }
+    }
+
+    #[test]
+    fn global_as_allocator_matches_the_model() {
+        for registry in [false, true] {
+            let detector = LeakDetector::builder(crate::GlobalAsAllocator(System))
+                .registry(registry)
+                .build();
+            for seed in 0..8 {
+                OpSequence::generate(seed, 500).run(&detector).unwrap();
+            }
+            assert_eq!(detector.get_used(), 0);
+            assert!(detector.diagnostics().is_empty());
+        }
+    }
+
+    /// Moves blocks on grow without copying them.
+    struct Forgetful;
+
+    unsafe impl Allocator for Forgetful {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            System.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            unsafe { System.deallocate(ptr, layout) }
+        }
+
+        unsafe fn grow(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            let new = System.allocate(new_layout)?;
+            unsafe { System.deallocate(ptr, old_layout) };
+            Ok(new)
+        }
+    }
+
+    #[test]
+    fn reports_lost_contents() {
+        let detector = LeakDetector::new(Forgetful);
+        let ops = OpSequence {
+            ops: vec![
+                Op::Allocate {
+                    layout: Layout::from_size_align(32, 8).unwrap(),
+                },
+                Op::Shrink {
+                    slot: 0,
+                    new_size: 16,
+                },
+                Op::Grow {
+                    slot: 0,
+                    new_size: 4096,
+                },
+            ],
+        };
+        let mismatch = ops.run(&detector).unwrap_err();
+        assert_eq!(mismatch.step, 2);
+        assert_eq!(mismatch.kind, MismatchKind::Contents);
+        assert_eq!(detector.get_used(), 0);
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    alloc::System,
+    cell::RefCell,
+    fmt,
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread::{self, Thread, ThreadId},
+};
+
+use crate::{
+    LeakDetector,
+    overhead::{Account, Accounted, Internal},
+    registry,
+};
+
+/// What a detector built with [`check_on_thread_exit`] does when a thread
+/// exits with blocks it allocated still live. Each case is also kept for
+/// [`LeakDetector::thread_exit_reports`].
+///
+/// [`check_on_thread_exit`]: crate::LeakDetectorBuilder::check_on_thread_exit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnThreadExit {
+    Record,
+    Log,
+    /// The check runs in a thread-local destructor, where panicking aborts
+    /// the process.
+    Panic,
+}
+
+/// A thread that exited without freeing what it allocated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadExitReport {
+    pub thread_name: Option<String>,
+    pub thread_id: ThreadId,
+    pub bytes: usize,
+    pub allocations: usize,
+}
+
+impl fmt::Display for ThreadExitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.thread_name {
+            Some(name) => write!(f, "thread '{name}' ({:?})", self.thread_id)?,
+            None => write!(f, "thread {:?}", self.thread_id)?,
+        }
+        write!(
+            f,
+            " exited with {} bytes in {} allocations still live",
+            self.bytes, self.allocations
+        )
+    }
+}
+
+/// A [`ThreadExitReport`] as kept, its name in [`Internal`] memory.
+struct Recorded {
+    thread_name: Option<Vec<u8, Internal>>,
+    thread_id: ThreadId,
+    bytes: usize,
+    allocations: usize,
+}
+
+pub(crate) struct ThreadExit {
+    enabled: bool,
+    on_exit: OnThreadExit,
+    reports: Accounted<Vec<Recorded, Internal>>,
+}
+
+impl ThreadExit {
+    pub(crate) const fn new(enabled: bool, on_exit: OnThreadExit) -> Self {
+        Self {
+            enabled,
+            on_exit,
+            reports: Accounted::new(Vec::new_in(Internal)),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn account(&self) -> &Account {
+        self.reports.account()
+    }
+
+    fn record(&self, report: &ThreadExitReport) {
+        let mut reports = self.reports.lock();
+        let thread_name = report.thread_name.as_ref().map(|name| {
+            let mut bytes = Vec::new_in(Internal);
+            bytes.extend_from_slice(name.as_bytes());
+            bytes
+        });
+        reports.push(Recorded {
+            thread_name,
+            thread_id: report.thread_id,
+            bytes: report.bytes,
+            allocations: report.allocations,
+        });
+    }
+}
+
+/// Addresses of the detectors threads may still check on exit; a detector
+/// leaves when dropped, so no thread checks one that's gone.
+static LIVE: Mutex<Vec<usize, System>> = Mutex::new(Vec::new_in(System));
+
+fn live() -> MutexGuard<'static, Vec<usize, System>> {
+    LIVE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Watch {
+    detector: usize,
+    check: unsafe fn(usize, &Thread),
+    thread: Thread,
+}
+
+/// The detectors this thread allocated from, checked by its destructor.
+struct Watched(RefCell<Vec<Watch, System>>);
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        let live = live();
+        for watch in self.0.get_mut().drain(..) {
+            if live.contains(&watch.detector) {
+                unsafe { (watch.check)(watch.detector, &watch.thread) };
+            }
+        }
+    }
+}
+
+thread_local! {
+    static WATCHED: Watched = const { Watched(RefCell::new(Vec::new_in(System))) };
+}
+
+unsafe fn check<T>(detector: usize, thread: &Thread) {
+    let detector = unsafe { &*(detector as *const LeakDetector<T>) };
+    detector.check_thread_exit(thread);
+}
+
+impl<T> LeakDetector<T> {
+    /// Has this thread check the detector when it exits, the first time it
+    /// allocates from it. The detector mustn't move from then on, as one in
+    /// a `static` never does.
+    #[inline]
+    pub(crate) fn watch_thread(&self) {
+        if !self.thread_exit.is_enabled() || !self.registry.is_enabled() {
+            return;
+        }
+        let address = self as *const Self as usize;
+        let _ = WATCHED.try_with(|watched| {
+            // Taken while registering, in case `thread::current` allocates.
+            let Ok(mut watches) = watched.0.try_borrow_mut() else {
+                return;
+            };
+            if watches.iter().any(|watch| watch.detector == address) {
+                return;
+            }
+            let thread = thread::current();
+            let mut live = live();
+            if !live.contains(&address) {
+                live.push(address);
+            }
+            watches.push(Watch {
+                detector: address,
+                check: check::<T>,
+                thread,
+            });
+        });
+    }
+
+    /// Stops threads from checking the detector, before it's dropped.
+    pub(crate) fn unwatch_threads(&self) {
+        if self.thread_exit.is_enabled() {
+            let address = self as *const Self as usize;
+            live().retain(|&live| live != address);
+        }
+    }
+
+    fn check_thread_exit(&self, thread: &Thread) {
+        let tag = registry::thread_tag();
+        let (bytes, allocations) = self
+            .registry
+            .lock()
+            .values()
+            .filter(|entry| entry.thread == tag)
+            .fold((0, 0), |(bytes, count), entry| {
+                (bytes + entry.size, count + 1)
+            });
+        if allocations == 0 {
+            return;
+        }
+        let _internal = crate::pause::internal();
+        let report = ThreadExitReport {
+            thread_name: thread.name().map(str::to_owned),
+            thread_id: thread.id(),
+            bytes,
+            allocations,
+        };
+        match self.thread_exit.on_exit {
+            OnThreadExit::Record => {}
+            OnThreadExit::Log => eprintln!("{report}"),
+            OnThreadExit::Panic => panic!("{report}"),
+        }
+        self.thread_exit.record(&report);
+    }
+
+    /// The threads that exited with blocks they allocated still live, for a
+    /// detector built with
+    /// [`check_on_thread_exit`](crate::LeakDetectorBuilder::check_on_thread_exit).
+    pub fn thread_exit_reports(&self) -> Vec<ThreadExitReport> {
+        self.thread_exit
+            .reports
+            .lock()
+            .iter()
+            .map(|recorded| ThreadExitReport {
+                thread_name: recorded
+                    .thread_name
+                    .as_ref()
+                    .map(|name| String::from_utf8_lossy(name).into_owned()),
+                thread_id: recorded.thread_id,
+                bytes: recorded.bytes,
+                allocations: recorded.allocations,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn reports_threads_that_leak() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .check_on_thread_exit(true)
+            .on_thread_exit(OnThreadExit::Record)
+            .build();
+        thread::scope(|scope| {
+            let leaky = thread::Builder::new()
+                .name("leaky".to_owned())
+                .spawn_scoped(scope, || std::mem::forget(Box::new_in(7u64, &detector)))
+                .unwrap();
+            leaky.join().unwrap();
+        });
+        let reports = detector.thread_exit_reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].thread_name.as_deref(), Some("leaky"));
+        assert_eq!((reports[0].bytes, reports[0].allocations), (8, 1));
+        assert!(
+            reports[0]
+                .to_string()
+                .starts_with("thread 'leaky' (ThreadId(")
+        );
+    }
+
+    #[test]
+    fn clean_threads_and_handoffs_pass() {
+        let detector = LeakDetector::builder(System)
+            .registry(true)
+            .check_on_thread_exit(true)
+            .on_thread_exit(OnThreadExit::Record)
+            .build();
+        let detector = &detector;
+        thread::scope(|scope| {
+            let clean = scope.spawn(|| drop(Box::new_in([0u8; 32], detector)));
+            clean.join().unwrap();
+
+            let (send, receive) = mpsc::channel();
+            let (freed, wait) = mpsc::channel();
+            let sender = scope.spawn(move || {
+                send.send(Box::new_in(1u32, detector)).unwrap();
+                wait.recv().unwrap();
+            });
+            drop(receive.recv().unwrap());
+            freed.send(()).unwrap();
+            sender.join().unwrap();
+        });
+        assert!(detector.thread_exit_reports().is_empty());
+        detector.assert();
+    }
+}
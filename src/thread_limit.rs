@@ -0,0 +1,320 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    LeakDetector,
+    overhead::{Account, Accounted, Internal, Locked},
+    registry::{self, Entry},
+};
+
+/// Detectors limiting one thread beyond this many aren't enforced.
+const MAX_THREAD_LIMITS: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Limit {
+    detector: usize,
+    limit: usize,
+    used: usize,
+    /// The id the first block allocated under the limit got; older blocks
+    /// are neither charged nor credited.
+    since: u64,
+}
+
+/// The calling thread's limits, like the budgets a fixed array so the
+/// allocator reads it without allocating or locking.
+struct Limits {
+    len: usize,
+    limits: [Limit; MAX_THREAD_LIMITS],
+}
+
+thread_local! {
+    static LIMITS: RefCell<Limits> = const {
+        RefCell::new(Limits {
+            len: 0,
+            limits: [Limit {
+                detector: 0,
+                limit: 0,
+                used: 0,
+                since: 0,
+            }; MAX_THREAD_LIMITS],
+        })
+    };
+}
+
+/// Runs `f` on the calling thread's limit for `detector`, if it has one.
+fn with_limit<R>(detector: usize, f: impl FnOnce(&mut Limit) -> R) -> Option<R> {
+    LIMITS
+        .try_with(|limits| {
+            let mut limits = limits.try_borrow_mut().ok()?;
+            let len = limits.len;
+            limits.limits[..len]
+                .iter_mut()
+                .find(|limit| limit.detector == detector)
+                .map(f)
+        })
+        .ok()
+        .flatten()
+}
+
+/// What other threads freed or resized of a limited thread's blocks, taken
+/// into its usage when it next needs it.
+#[derive(Clone, Copy)]
+struct Credit {
+    since: u64,
+    bytes: isize,
+}
+
+/// The threads given a limit by [`LeakDetector::set_thread_limit`]. Each
+/// keeps its usage to itself; only frees from other threads go through the
+/// shared credits, keyed by thread tag. A detector without any limit pays
+/// one atomic load per allocation.
+pub(crate) struct ThreadLimits {
+    active: AtomicUsize,
+    credits: Accounted<BTreeMap<u64, Credit, Internal>>,
+}
+
+impl ThreadLimits {
+    pub(crate) const fn new() -> Self {
+        Self {
+            active: AtomicUsize::new(0),
+            credits: Accounted::new(BTreeMap::new_in(Internal)),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed) != 0
+    }
+
+    fn lock(&self) -> Locked<'_, BTreeMap<u64, Credit, Internal>> {
+        self.credits.lock()
+    }
+
+    pub(crate) fn account(&self) -> &Account {
+        self.credits.account()
+    }
+}
+
+impl<T> LeakDetector<T> {
+    fn address(&self) -> usize {
+        self as *const LeakDetector<T> as usize
+    }
+
+    /// Makes allocations on the calling thread fail once the bytes it
+    /// allocated and are still live would exceed `bytes`. Only blocks
+    /// allocated from now on count, whoever frees them. That takes the
+    /// registry: without it, the detector can't tell blocks apart, and
+    /// credits any free on the thread against its usage.
+    pub fn set_thread_limit(&self, bytes: usize) {
+        let detector = self.address();
+        let since = self.registry.peek_id();
+        let replaced = with_limit(detector, |limit| {
+            *limit = Limit {
+                detector,
+                limit: bytes,
+                used: 0,
+                since,
+            };
+        })
+        .is_some();
+        let added = !replaced
+            && LIMITS
+                .try_with(|limits| {
+                    let mut limits = limits.borrow_mut();
+                    let len = limits.len;
+                    if len == MAX_THREAD_LIMITS {
+                        return false;
+                    }
+                    limits.limits[len] = Limit {
+                        detector,
+                        limit: bytes,
+                        used: 0,
+                        since,
+                    };
+                    limits.len += 1;
+                    true
+                })
+                .unwrap_or(false);
+        if replaced || added {
+            self.thread_limits
+                .lock()
+                .insert(registry::thread_tag(), Credit { since, bytes: 0 });
+        }
+        if added {
+            self.thread_limits.active.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn clear_thread_limit(&self) {
+        let detector = self.address();
+        let removed = LIMITS
+            .try_with(|limits| {
+                let mut limits = limits.borrow_mut();
+                let len = limits.len;
+                let index = limits.limits[..len]
+                    .iter()
+                    .position(|limit| limit.detector == detector)?;
+                limits.limits.copy_within(index + 1..len, index);
+                limits.len -= 1;
+                Some(())
+            })
+            .ok()
+            .flatten()
+            .is_some();
+        if removed {
+            self.thread_limits.lock().remove(&registry::thread_tag());
+            self.thread_limits.active.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Live bytes the calling thread allocated since it was given a limit;
+    /// `None` without one.
+    pub fn thread_usage(&self) -> Option<usize> {
+        self.thread_limit().map(|limit| limit.used)
+    }
+
+    /// How much more the calling thread may allocate; `None` without a limit.
+    pub fn remaining_for_thread(&self) -> Option<usize> {
+        self.thread_limit()
+            .map(|limit| limit.limit.saturating_sub(limit.used))
+    }
+
+    fn thread_limit(&self) -> Option<Limit> {
+        if !self.thread_limits.is_active() {
+            return None;
+        }
+        self.take_credit();
+        with_limit(self.address(), |limit| *limit)
+    }
+
+    /// Takes what other threads freed of the calling thread's blocks into
+    /// its usage.
+    fn take_credit(&self) {
+        let credit = self
+            .thread_limits
+            .lock()
+            .get_mut(&registry::thread_tag())
+            .map_or(0, |credit| std::mem::take(&mut credit.bytes));
+        if credit != 0 {
+            with_limit(self.address(), |limit| {
+                limit.used = limit.used.saturating_add_signed(-credit);
+            });
+        }
+    }
+
+    #[inline]
+    pub(crate) fn within_thread_limit(&self, additional: usize) -> bool {
+        if !self.thread_limits.is_active() {
+            return true;
+        }
+        let fits = |limit: &mut Limit| limit.used.saturating_add(additional) <= limit.limit;
+        if with_limit(self.address(), fits).unwrap_or(true) {
+            return true;
+        }
+        self.take_credit();
+        with_limit(self.address(), fits).unwrap_or(true)
+    }
+
+    /// Moves the usage of the thread that allocated a block from `old` to
+    /// `new` bytes, as it's allocated, freed or resized. `owner` is the
+    /// block's registry entry; without one, the calling thread is charged
+    /// whatever the block's age.
+    #[inline]
+    pub(crate) fn charge_thread(&self, owner: Option<&Entry>, old: usize, new: usize) {
+        if !self.thread_limits.is_active() {
+            return;
+        }
+        let charge = |limit: &mut Limit| {
+            if owner.is_none_or(|owner| owner.id >= limit.since) {
+                limit.used = limit.used.saturating_sub(old).saturating_add(new);
+            }
+        };
+        match owner {
+            Some(owner) if owner.thread != registry::thread_tag() => {
+                if let Some(credit) = self.thread_limits.lock().get_mut(&owner.thread)
+                    && owner.id >= credit.since
+                {
+                    credit.bytes = credit
+                        .bytes
+                        .saturating_add_unsigned(old)
+                        .saturating_sub_unsigned(new);
+                }
+            }
+            _ => {
+                with_limit(self.address(), charge);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::System, sync::Barrier};
+
+    use super::*;
+
+    #[test]
+    fn limits_each_thread() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let both_limited = Barrier::new(2);
+        std::thread::scope(|s| {
+            let small = s.spawn(|| {
+                detector.set_thread_limit(1024);
+                both_limited.wait();
+                let kept = Vec::<u8, _>::with_capacity_in(1000, &detector);
+                assert_eq!(detector.thread_usage(), Some(1000));
+                assert_eq!(detector.remaining_for_thread(), Some(24));
+                assert!(Vec::<u8, _>::try_with_capacity_in(100, &detector).is_err());
+                kept
+            });
+            let large = s.spawn(|| {
+                detector.set_thread_limit(1 << 20);
+                both_limited.wait();
+                let kept = Vec::<u8, _>::try_with_capacity_in(64 << 10, &detector);
+                assert!(kept.is_ok());
+                assert_eq!(detector.thread_usage(), Some(64 << 10));
+                kept.unwrap()
+            });
+            let small_kept = small.join().unwrap();
+            let large_kept = large.join().unwrap();
+            assert_eq!(detector.thread_usage(), None);
+            // Freed here, but credited to the threads that allocated them.
+            drop((small_kept, large_kept));
+        });
+        detector.assert();
+    }
+
+    #[test]
+    fn freeing_credits_the_thread() {
+        let detector = LeakDetector::system();
+        detector.set_thread_limit(256);
+        let first = Box::new_in([0u8; 200], &detector);
+        assert!(Box::try_new_in([0u8; 100], &detector).is_err());
+        drop(first);
+        assert_eq!(detector.thread_usage(), Some(0));
+        let second = Box::try_new_in([0u8; 100], &detector).unwrap();
+        detector.clear_thread_limit();
+        assert_eq!(detector.remaining_for_thread(), None);
+        drop(second);
+    }
+
+    #[test]
+    fn older_blocks_earn_no_credit() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let old = Box::new_in([0u8; 200], &detector);
+        detector.set_thread_limit(256);
+        let new = Box::new_in([0u8; 200], &detector);
+        drop(old);
+        assert_eq!(detector.thread_usage(), Some(200));
+        assert!(Box::try_new_in([0u8; 100], &detector).is_err());
+
+        // Freed by another thread, it's credited once this one checks.
+        std::thread::scope(|s| s.spawn(|| drop(new)).join().unwrap());
+        let again = Box::try_new_in([0u8; 200], &detector).unwrap();
+        assert_eq!(detector.thread_usage(), Some(200));
+        drop(again);
+        detector.clear_thread_limit();
+    }
+}
@@ -0,0 +1,174 @@
+use std::panic::Location;
+
+use crate::{LeakDetector, LeakedAllocation, OnLeak, ScopeLeak, ledger::Ledger};
+
+/// A scope that can end on another thread than the one it was opened on, as
+/// a request accepted on one thread and completed on a worker does. Made by
+/// [`LeakDetector::open_scope`]; end it with [`close`](ScopeTicket::close).
+///
+/// With the registry, the ticket counts the blocks allocated while it was
+/// open, on any thread, that are still live when it closes. Without it, or
+/// when sampling, it falls back to how far `used` moved between opening and
+/// closing, which other threads allocating at the same time move too.
+#[must_use = "a ticket must be closed to check it"]
+pub struct ScopeTicket<T: 'static> {
+    detector: &'static LeakDetector<T>,
+    start: usize,
+    /// The registry epoch before the ticket opened.
+    epoch: u64,
+    name: Option<&'static str>,
+    location: &'static Location<'static>,
+    on_unclosed: Option<OnLeak>,
+    closed: bool,
+}
+
+impl<T> LeakDetector<T> {
+    /// Opens a scope that may be moved to and closed on another thread, see
+    /// [`ScopeTicket`].
+    #[track_caller]
+    pub fn open_scope(&'static self) -> ScopeTicket<T> {
+        ScopeTicket {
+            detector: self,
+            start: self.get_used(),
+            epoch: self.registry.advance_epoch(),
+            name: None,
+            location: Location::caller(),
+            on_unclosed: None,
+            closed: false,
+        }
+    }
+}
+
+impl<T> ScopeTicket<T> {
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// What happens when the ticket is dropped without being closed, instead
+    /// of the detector's [`OnLeak`] policy. It applies even when nothing
+    /// leaked.
+    pub fn on_unclosed(mut self, on_unclosed: OnLeak) -> Self {
+        self.on_unclosed = Some(on_unclosed);
+        self
+    }
+
+    /// Ends the scope on the calling thread and reports what it left live.
+    /// Never poisons the detector.
+    #[allow(clippy::result_large_err)]
+    pub fn close(mut self) -> Result<(), ScopeLeak> {
+        self.closed = true;
+        let leak = self.leak(false);
+        if leak.bytes == 0 { Ok(()) } else { Err(leak) }
+    }
+
+    fn leak(&self, unclosed: bool) -> ScopeLeak {
+        let detector = self.detector;
+        let (bytes, allocations) =
+            if detector.registry.is_enabled() && detector.sampling().is_none() {
+                (
+                    detector.registry.bytes_after(self.epoch) as isize,
+                    detector.allocations_after(self.epoch),
+                )
+            } else {
+                let delta = detector.get_used().wrapping_sub(self.start) as isize;
+                (delta, Vec::<LeakedAllocation>::new())
+            };
+        ScopeLeak {
+            scope_name: self.name,
+            location: self.location,
+            bytes,
+            max_delta: None,
+            expected_retention: None,
+            waited: None,
+            count: None,
+            poisoned_by: None,
+            enclosing_scopes: Vec::new(),
+            attribution: Vec::new(),
+            allocations,
+            unclosed,
+        }
+    }
+}
+
+impl<T> Drop for ScopeTicket<T> {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        let on_unclosed = self.on_unclosed.unwrap_or_else(|| self.detector.on_leak());
+        on_unclosed.apply(&self.leak(true), self.detector.failure_mode());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::System,
+        sync::{Mutex, mpsc},
+        thread,
+    };
+
+    use super::*;
+
+    /// Opens a ticket here, then has a worker allocate `kept` bytes it
+    /// doesn't free, plus a block it does, and close the ticket.
+    #[allow(clippy::result_large_err)]
+    fn hand_over(detector: &'static LeakDetector<System>, kept: usize) -> Result<(), ScopeLeak> {
+        let (send, receive) = mpsc::channel();
+        send.send(detector.open_scope().named("request")).unwrap();
+        thread::spawn(move || {
+            let ticket = receive.recv().unwrap();
+            drop(Vec::<u8, _>::with_capacity_in(64, detector));
+            std::mem::forget(Vec::<u8, _>::with_capacity_in(kept, detector));
+            ticket.close()
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn closes_on_another_thread() {
+        static DETECTOR: LeakDetector<System> =
+            LeakDetector::builder(System).registry(true).build();
+        hand_over(&DETECTOR, 0).unwrap();
+        let leak = hand_over(&DETECTOR, 24).unwrap_err();
+        assert_eq!(leak.bytes, 24);
+        assert_eq!(leak.allocations.len(), 1);
+        assert_eq!(leak.scope_name, Some("request"));
+        assert!(
+            leak.to_string()
+                .contains(" leaked 24 bytes\n  allocation #")
+        );
+    }
+
+    #[test]
+    fn counts_the_global_delta_without_the_registry() {
+        static DETECTOR: LeakDetector<System> = LeakDetector::system();
+        hand_over(&DETECTOR, 0).unwrap();
+        let leak = hand_over(&DETECTOR, 40).unwrap_err();
+        assert_eq!(leak.bytes, 40);
+        assert!(leak.allocations.is_empty());
+    }
+
+    static DROPPED: Mutex<Vec<(isize, bool)>> = Mutex::new(Vec::new());
+
+    #[test]
+    fn dropping_unclosed_applies_the_policy() {
+        static DETECTOR: LeakDetector<System> =
+            LeakDetector::builder(System).registry(true).build();
+        let record = OnLeak::Callback(|leak| {
+            DROPPED.lock().unwrap().push((leak.bytes, leak.unclosed));
+            assert!(
+                leak.to_string()
+                    .contains(" was dropped without being closed")
+            );
+        });
+        drop(DETECTOR.open_scope().on_unclosed(record));
+        let ticket = DETECTOR.open_scope().on_unclosed(record);
+        let leaked = Box::new_in(0u64, &DETECTOR);
+        drop(ticket);
+        assert_eq!(*DROPPED.lock().unwrap(), [(0, true), (8, true)]);
+        drop(leaked);
+    }
+}
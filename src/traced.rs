@@ -0,0 +1,196 @@
+//! `file:line` attribution without backtraces. The registry records the
+//! caller of each allocation, which for a collection growing itself is
+//! somewhere in `alloc`; allocating through [`Traced`] records the place
+//! that made the collection instead.
+
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::Cell,
+    fmt,
+    panic::Location,
+    ptr::NonNull,
+};
+
+use crate::LeakDetector;
+
+thread_local! {
+    /// The callsite [`Traced`] is allocating for on this thread, if any.
+    static CALLSITE: Cell<Option<&'static Location<'static>>> = const { Cell::new(None) };
+}
+
+/// Where the allocation being made was asked for: the callsite a [`Traced`]
+/// allocator set, or else the caller.
+#[track_caller]
+pub(crate) fn caller() -> &'static Location<'static> {
+    match CALLSITE.try_with(Cell::get) {
+        Ok(Some(callsite)) => callsite,
+        _ => Location::caller(),
+    }
+}
+
+fn at<R>(callsite: &'static Location<'static>, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<&'static Location<'static>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let _ = CALLSITE.try_with(|current| current.set(self.0));
+        }
+    }
+    let _restore = Restore(
+        CALLSITE
+            .try_with(|current| current.replace(Some(callsite)))
+            .ok()
+            .flatten(),
+    );
+    f()
+}
+
+/// A detector as an allocator that attributes everything allocated through
+/// it to one callsite, where it was made. Made by [`LeakDetector::traced`]
+/// and the `_traced` helpers.
+pub struct Traced<'a, T> {
+    detector: &'a LeakDetector<T>,
+    callsite: &'static Location<'static>,
+}
+
+impl<T> Clone for Traced<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Traced<'_, T> {}
+
+impl<T> fmt::Debug for Traced<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Traced")
+            .field("callsite", &self.callsite)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Traced<'_, T> {
+    pub fn callsite(&self) -> &'static Location<'static> {
+        self.callsite
+    }
+}
+
+unsafe impl<T: Allocator> Allocator for Traced<'_, T> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        at(self.callsite, || self.detector.allocate(layout))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        at(self.callsite, || self.detector.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.detector.deallocate(ptr, layout) }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        at(self.callsite, || unsafe {
+            self.detector.grow(ptr, old_layout, new_layout)
+        })
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        at(self.callsite, || unsafe {
+            self.detector.grow_zeroed(ptr, old_layout, new_layout)
+        })
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        at(self.callsite, || unsafe {
+            self.detector.shrink(ptr, old_layout, new_layout)
+        })
+    }
+}
+
+impl<T: Allocator> LeakDetector<T> {
+    /// The detector as an allocator whose blocks, with the registry, are
+    /// all attributed to the caller of this, however deep in a collection
+    /// they're allocated.
+    #[track_caller]
+    pub fn traced(&self) -> Traced<'_, T> {
+        Traced {
+            detector: self,
+            callsite: Location::caller(),
+        }
+    }
+
+    /// `self.allocate(layout)`, attributed to the caller.
+    #[track_caller]
+    pub fn allocate_traced(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.traced().allocate(layout)
+    }
+
+    /// `Box::new_in(value, self.traced())`.
+    #[track_caller]
+    pub fn boxed_traced<V>(&self, value: V) -> Box<V, Traced<'_, T>> {
+        Box::new_in(value, self.traced())
+    }
+
+    /// `Vec::new_in(self.traced())`: all of the vector's growth is
+    /// attributed to the caller.
+    #[track_caller]
+    pub fn vec_traced<V>(&self) -> Vec<V, Traced<'_, T>> {
+        Vec::new_in(self.traced())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn leaks_point_at_the_helpers() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let vec_line = line!() + 1;
+        let mut bytes = detector.vec_traced::<u8>();
+        bytes.extend(0..100);
+        bytes.extend(0..100);
+        let boxed_line = line!() + 1;
+        let boxed = detector.boxed_traced(7u64);
+        let layout = Layout::new::<u32>();
+        let raw_line = line!() + 1;
+        let raw = detector.allocate_traced(layout).unwrap();
+
+        let report = detector.leak_report();
+        let mut lines: Vec<_> = report
+            .allocations()
+            .iter()
+            .map(|allocation| {
+                assert_eq!(allocation.callsite.file(), file!());
+                (allocation.callsite.line(), allocation.size)
+            })
+            .collect();
+        lines.sort();
+        let mut expected = vec![(vec_line, bytes.capacity()), (boxed_line, 8), (raw_line, 4)];
+        expected.sort();
+        assert_eq!(lines, expected);
+        let text = report.to_string();
+        assert!(text.contains(&format!("{}:{vec_line}:", file!())), "{text}");
+
+        drop((bytes, boxed));
+        unsafe { detector.deallocate(raw.cast(), layout) };
+        detector.check().unwrap();
+        assert!(CALLSITE.with(Cell::get).is_none());
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::LeakDetector;
+
+enum Target {
+    /// Nothing leaked, as `check` counts it.
+    Zero,
+    /// `used` at most this.
+    Below(usize),
+}
+
+/// Resolves once usage drains, see [`LeakDetector::until_zero`]. Dropping
+/// it before then unregisters its waker.
+#[must_use = "futures do nothing unless polled"]
+pub struct Drained<'a, T> {
+    detector: &'a LeakDetector<T>,
+    target: Target,
+    wake_at: usize,
+    id: u64,
+}
+
+impl<T> LeakDetector<T> {
+    /// Resolves once nothing is leaked, as [`check`](LeakDetector::check)
+    /// counts it. The task is woken by every free while it waits, to check
+    /// again, without polling or needing a runtime, so bound it with the
+    /// runtime's timeout.
+    pub fn until_zero(&self) -> Drained<'_, T> {
+        // Suppressions and the registry's epochs let a check pass with
+        // `used` above any fixed mark, so no free can be skipped.
+        self.drained(Target::Zero, usize::MAX)
+    }
+
+    /// Resolves once `used` is at most `bytes`.
+    pub fn until_below(&self, bytes: usize) -> Drained<'_, T> {
+        self.drained(Target::Below(bytes), bytes)
+    }
+
+    fn drained(&self, target: Target, wake_at: usize) -> Drained<'_, T> {
+        Drained {
+            detector: self,
+            target,
+            wake_at,
+            id: self.waiters.next_id(),
+        }
+    }
+}
+
+impl<T> Drained<'_, T> {
+    fn is_done(&self) -> bool {
+        match self.target {
+            Target::Zero => self.detector.leaked_bytes() == 0,
+            Target::Below(bytes) => self.detector.get_used() <= bytes,
+        }
+    }
+}
+
+impl<T> Future for Drained<'_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let waiters = &self.detector.waiters;
+        if self.is_done() {
+            waiters.deregister(self.id);
+            return Poll::Ready(());
+        }
+        waiters.register(self.id, self.wake_at, cx.waker());
+        // A free between the check and registering would have found no
+        // waker to wake.
+        if self.is_done() {
+            waiters.deregister(self.id);
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Drained<'_, T> {
+    fn drop(&mut self) {
+        self.detector.waiters.deregister(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::System,
+        pin::pin,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        task::{Wake, Waker},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::executor::block_on;
+
+    #[derive(Default)]
+    struct CountWakes(AtomicUsize);
+
+    impl Wake for CountWakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn ready_at_once() {
+        let detector = LeakDetector::system();
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(pin!(detector.until_zero()).poll(&mut cx).is_ready());
+        let kept = detector.vec_with_capacity::<u8>(16);
+        assert!(pin!(detector.until_below(16)).poll(&mut cx).is_ready());
+        assert_eq!(detector.waiters.registered(), 0);
+        drop(kept);
+    }
+
+    #[test]
+    fn ready_after_free() {
+        let detector = LeakDetector::system();
+        let kept = detector.vec_with_capacity::<u8>(16);
+        thread::scope(|scope| {
+            let buffer = detector.vec_with_capacity::<u8>(64);
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                drop(buffer);
+            });
+            block_on(detector.until_below(16));
+        });
+        assert_eq!(detector.get_used(), 16);
+        drop(kept);
+        block_on(detector.until_zero());
+        assert_eq!(detector.waiters.registered(), 0);
+    }
+
+    #[test]
+    fn wakes_when_check_passes_above_the_baseline() {
+        let detector = LeakDetector::builder(System).registry(true).build();
+        let mut cache = detector.vec_with_capacity::<u8>(64);
+        detector.capture_baseline();
+        // Allocated before the baseline, so not leaked however it grows.
+        cache.reserve_exact(4096);
+        let buffer = detector.vec_with_capacity::<u8>(16);
+        let wakes = Arc::new(CountWakes::default());
+        let waker = Waker::from(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(detector.until_zero());
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        drop(buffer);
+        assert!(detector.get_used() > detector.baseline());
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert!(future.as_mut().poll(&mut cx).is_ready());
+        drop(cache);
+    }
+
+    #[test]
+    fn cancelled_future_unregisters() {
+        let detector = LeakDetector::system();
+        let buffer = detector.vec_with_capacity::<u8>(64);
+        let wakes = Arc::new(CountWakes::default());
+        let waker = Waker::from(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+        {
+            let mut future = pin!(detector.until_zero());
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(detector.waiters.registered(), 1);
+        }
+        assert_eq!(detector.waiters.registered(), 0);
+        drop(buffer);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 0);
+    }
+}
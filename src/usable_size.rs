@@ -0,0 +1,68 @@
+use std::ffi::c_void;
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" {
+    fn malloc_usable_size(ptr: *mut c_void) -> usize;
+}
+
+#[cfg(target_os = "macos")]
+unsafe extern "C" {
+    fn malloc_size(ptr: *const c_void) -> usize;
+}
+
+#[cfg(windows)]
+unsafe extern "C" {
+    fn _msize(ptr: *mut c_void) -> usize;
+}
+
+/// The usable size of a block from the C library's `malloc`, `None` where
+/// there's no way to ask.
+///
+/// # Safety
+///
+/// `ptr` must be a live block returned by `malloc` or one of its relatives.
+pub(crate) unsafe fn usable_size(ptr: *mut u8) -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    return Some(unsafe { malloc_usable_size(ptr.cast()) });
+    #[cfg(target_os = "macos")]
+    return Some(unsafe { malloc_size(ptr.cast_const().cast()) });
+    #[cfg(windows)]
+    return Some(unsafe { _msize(ptr.cast()) });
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = ptr;
+        None
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
+mod tests {
+    use std::alloc::System;
+
+    use crate::LeakDetector;
+
+    #[test]
+    fn actual_covers_requested() {
+        let detector = unsafe { LeakDetector::builder(System).usable_size(true) }
+            .registry(true)
+            .build();
+        let mut blocks: Vec<Vec<u8, _>> = [1, 3, 17, 33, 100, 1023, 4097]
+            .into_iter()
+            .map(|len| {
+                let mut block = Vec::with_capacity_in(len, &detector);
+                block.resize(len, 0);
+                block
+            })
+            .collect();
+        blocks[2].reserve_exact(50);
+        blocks[5].truncate(7);
+        blocks[5].shrink_to_fit();
+        assert!(detector.get_used_actual() >= detector.get_used());
+        assert!(detector.get_used_actual() > 0);
+        let report = detector.leak_report();
+        assert!(report.to_string().contains("resident in allocator"));
+        drop(blocks);
+        assert_eq!(detector.get_used(), 0);
+        assert_eq!(detector.get_used_actual(), 0);
+    }
+}
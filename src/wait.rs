@@ -0,0 +1,353 @@
+use std::{
+    alloc::System,
+    panic::Location,
+    sync::{
+        Condvar, Mutex, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    task::Waker,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    LeakDetector, LeakError,
+    overhead::{Account, Accounted, Internal, Locked},
+};
+
+/// Polls `done` with a short backoff until it holds or `timeout` runs out.
+/// Returns whether it held and how long that took.
+pub(crate) fn wait_until(timeout: Duration, mut done: impl FnMut() -> bool) -> (bool, Duration) {
+    const MAX_BACKOFF: Duration = Duration::from_millis(10);
+    let start = Instant::now();
+    let mut backoff = Duration::from_micros(100);
+    loop {
+        if done() {
+            return (true, start.elapsed());
+        }
+        let waited = start.elapsed();
+        if waited >= timeout {
+            return (false, waited);
+        }
+        std::thread::sleep(backoff.min(timeout - waited));
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+struct AsyncWaiter {
+    id: u64,
+    wake_at: usize,
+    waker: Waker,
+}
+
+/// Threads blocked in [`LeakDetector::wait_for_zero`] or
+/// [`LeakDetector::wait_for_below`], and tasks awaiting
+/// [`LeakDetector::until_zero`] or [`LeakDetector::until_below`]. Frees only
+/// look at `waiting` unless someone is, and only wake them once `used` is
+/// down to `wake_at`.
+pub(crate) struct Waiters {
+    waiting: AtomicUsize,
+    /// The highest `used` any waiter would accept.
+    wake_at: AtomicUsize,
+    lock: Mutex<()>,
+    freed: Condvar,
+    /// Kept in [`Internal`] so registering from inside a tracked allocation
+    /// can't recurse.
+    wakers: Accounted<Vec<AsyncWaiter, Internal>>,
+    next_id: AtomicU64,
+}
+
+impl Waiters {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waiting: AtomicUsize::new(0),
+            wake_at: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            freed: Condvar::new(),
+            wakers: Accounted::new(Vec::new_in(Internal)),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Called after a free left `used` bytes in use.
+    #[inline]
+    pub(crate) fn freed(&self, used: usize) {
+        if self.waiting.load(Ordering::SeqCst) != 0 && used <= self.wake_at.load(Ordering::SeqCst) {
+            self.wake(used);
+        }
+    }
+
+    /// Wakes the blocked threads, and the tasks that accept `used`. Their
+    /// wakers run after the list is unlocked, since they may allocate and
+    /// free.
+    #[cold]
+    fn wake(&self, used: usize) {
+        {
+            let _lock = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+            self.freed.notify_all();
+        }
+        let mut woken = Vec::new_in(System);
+        let mut wakers = self.lock_wakers();
+        let mut index = 0;
+        while index < wakers.len() {
+            if used <= wakers[index].wake_at {
+                woken.push(wakers.swap_remove(index).waker);
+            } else {
+                index += 1;
+            }
+        }
+        drop(wakers);
+        self.leave(woken.len());
+        woken.into_iter().for_each(Waker::wake);
+    }
+
+    fn join(&self, wake_at: usize) {
+        self.wake_at.fetch_max(wake_at, Ordering::SeqCst);
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn leave(&self, waiters: usize) {
+        if waiters != 0 && self.waiting.fetch_sub(waiters, Ordering::SeqCst) == waiters {
+            self.wake_at.store(0, Ordering::SeqCst);
+        }
+    }
+
+    fn lock_wakers(&self) -> Locked<'_, Vec<AsyncWaiter, Internal>> {
+        self.wakers.lock()
+    }
+
+    pub(crate) fn account(&self) -> &Account {
+        self.wakers.account()
+    }
+
+    pub(crate) fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Has `waker` woken once a free brings `used` down to `wake_at`,
+    /// replacing the one `id` registered before, if it is still waiting.
+    /// The replaced waker is dropped after unlocking, as that may free.
+    pub(crate) fn register(&self, id: u64, wake_at: usize, waker: &Waker) {
+        let mut wakers = self.lock_wakers();
+        let replaced = match wakers.iter_mut().find(|waiter| waiter.id == id) {
+            Some(waiter) => Some(std::mem::replace(&mut waiter.waker, waker.clone())),
+            None => {
+                wakers.push(AsyncWaiter {
+                    id,
+                    wake_at,
+                    waker: waker.clone(),
+                });
+                self.join(wake_at);
+                None
+            }
+        };
+        drop(wakers);
+        drop(replaced);
+    }
+
+    pub(crate) fn deregister(&self, id: u64) {
+        let mut wakers = self.lock_wakers();
+        if let Some(index) = wakers.iter().position(|waiter| waiter.id == id) {
+            let waiter = wakers.swap_remove(index);
+            drop(wakers);
+            self.leave(1);
+            drop(waiter);
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn registered(&self) -> usize {
+        self.lock_wakers().len()
+    }
+
+    /// Blocks until `done` holds or `timeout` runs out, woken by frees that
+    /// bring `used` down to `wake_at`. `done` is checked again after every
+    /// wakeup, since usage may have gone back up in the meantime, and at
+    /// least every 10ms in case a wakeup raced with the waiter registering.
+    fn wait(
+        &self,
+        wake_at: usize,
+        timeout: Duration,
+        mut done: impl FnMut() -> bool,
+    ) -> (bool, Duration) {
+        const RECHECK: Duration = Duration::from_millis(10);
+        let start = Instant::now();
+        let mut lock = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.join(wake_at);
+        let result = loop {
+            if done() {
+                break (true, start.elapsed());
+            }
+            let waited = start.elapsed();
+            if waited >= timeout {
+                break (false, waited);
+            }
+            lock = self
+                .freed
+                .wait_timeout(lock, (timeout - waited).min(RECHECK))
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        };
+        self.leave(1);
+        drop(lock);
+        result
+    }
+}
+
+impl<T> LeakDetector<T> {
+    /// Blocks until nothing is leaked, as [`check`](LeakDetector::check)
+    /// counts it, or `timeout` runs out. Unlike
+    /// [`check_eventually`](LeakDetector::check_eventually) it sleeps until
+    /// a free brings usage down instead of polling.
+    #[track_caller]
+    pub fn wait_for_zero(&self, timeout: Duration) -> Result<(), LeakError> {
+        let wake_at = self.baseline().saturating_add(self.tolerance());
+        let (freed, waited) = self
+            .waiters
+            .wait(wake_at, timeout, || self.leaked_bytes() == 0);
+        if freed {
+            return Ok(());
+        }
+        let bytes = self.leaked_bytes() as isize;
+        Err(LeakError::LeakedAfterWait {
+            bytes,
+            waited,
+            poisoned_by: self.record_failure(bytes, None, Location::caller()),
+        })
+    }
+
+    /// Blocks until `used` is at most `bytes` or `timeout` runs out.
+    pub fn wait_for_below(&self, bytes: usize, timeout: Duration) -> Result<(), LeakError> {
+        let (below, _) = self
+            .waiters
+            .wait(bytes, timeout, || self.get_used() <= bytes);
+        if below {
+            return Ok(());
+        }
+        Err(LeakError::UsedAbove {
+            used: self.get_used(),
+            max: bytes,
+        })
+    }
+
+    /// Like [`check`](LeakDetector::check), but gives memory freed a little
+    /// later, by thread pools, executors or channel receivers, up to `timeout`
+    /// to come back before failing.
+    #[track_caller]
+    pub fn check_eventually(&self, timeout: Duration) -> Result<(), LeakError> {
+        let (freed, waited) = wait_until(timeout, || self.leaked_bytes() == 0);
+        if freed {
+            return Ok(());
+        }
+        let bytes = self.leaked_bytes() as isize;
+        Err(LeakError::LeakedAfterWait {
+            bytes,
+            waited,
+            poisoned_by: self.record_failure(bytes, None, Location::caller()),
+        })
+    }
+
+    #[track_caller]
+    pub fn assert_eventually(&self, timeout: Duration) {
+        if let Err(err) = self.check_eventually(timeout) {
+            panic!("{err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::System, thread};
+
+    use super::*;
+
+    fn free_later<'scope, 'env>(
+        scope: &'scope thread::Scope<'scope, 'env>,
+        detector: &'env LeakDetector<System>,
+    ) {
+        let buffer = Vec::<u8, _>::with_capacity_in(64, detector);
+        scope.spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(buffer);
+        });
+    }
+
+    #[test]
+    fn deferred_free() {
+        let detector = LeakDetector::system();
+        thread::scope(|scope| {
+            free_later(scope, &detector);
+            detector
+                .check_eventually(Duration::from_millis(500))
+                .unwrap();
+        });
+        thread::scope(|scope| {
+            free_later(scope, &detector);
+            let err = detector
+                .check_eventually(Duration::from_millis(10))
+                .unwrap_err();
+            let LeakError::LeakedAfterWait {
+                bytes: 64, waited, ..
+            } = err
+            else {
+                panic!("unexpected {err:?}");
+            };
+            assert!(waited >= Duration::from_millis(10));
+        });
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(debug_assertions),
+        ignore = "scope checks only run with debug assertions"
+    )]
+    fn scope_grace_period() {
+        let detector = LeakDetector::system();
+        thread::scope(|scope| {
+            let _guard = detector
+                .scope()
+                .with_grace_period(Duration::from_millis(500));
+            free_later(scope, &detector);
+        });
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            thread::scope(|scope| {
+                let _guard = detector
+                    .scope()
+                    .with_grace_period(Duration::from_millis(10));
+                free_later(scope, &detector);
+            })
+        }))
+        .unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert!(
+            message.contains("leaked 64 bytes and 1 allocation after waiting "),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn waits_for_frees() {
+        let detector = LeakDetector::system();
+        thread::scope(|scope| {
+            free_later(scope, &detector);
+            detector.wait_for_zero(Duration::from_secs(5)).unwrap();
+        });
+        thread::scope(|scope| {
+            free_later(scope, &detector);
+            let err = detector
+                .wait_for_zero(Duration::from_millis(10))
+                .unwrap_err();
+            assert!(matches!(err, LeakError::LeakedAfterWait { bytes: 64, .. }));
+        });
+
+        let kept = Vec::<u8, _>::with_capacity_in(16, &detector);
+        thread::scope(|scope| {
+            free_later(scope, &detector);
+            detector.wait_for_below(16, Duration::from_secs(5)).unwrap();
+            assert_eq!(
+                detector.wait_for_below(8, Duration::from_millis(10)),
+                Err(LeakError::UsedAbove { used: 16, max: 8 })
+            );
+        });
+        drop(kept);
+    }
+}
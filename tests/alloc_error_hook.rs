@@ -0,0 +1,55 @@
+//! Runs itself as a child process that runs out of its scope budget, and
+//! checks what the alloc error hook printed before the abort.
+
+use std::{alloc::System, process::Command};
+
+use mem_leak_detector::LeakDetector;
+
+#[global_allocator]
+static GLOBAL: LeakDetector<System> = LeakDetector::builder(System).registry(true).build();
+
+fn child(case: &str) {
+    if case == "chained" {
+        GLOBAL.chain_alloc_error_hook();
+    } else {
+        GLOBAL.install_alloc_error_hook();
+    }
+    let _scope = GLOBAL.scope_with_budget(1024);
+    let kept = std::hint::black_box(vec![0u8; 512]);
+    let too_big = std::hint::black_box(Vec::<u8>::with_capacity(4096));
+    drop((kept, too_big));
+}
+
+fn run(case: &str) -> (bool, String) {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .arg(case)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+fn main() {
+    if let Some(case) = std::env::args().nth(1) {
+        return child(&case);
+    }
+
+    let (success, stderr) = run("replaced");
+    assert!(!success);
+    assert!(
+        stderr.starts_with("mem_leak_detector: allocation of 4096 bytes (align 1) failed; "),
+        "{stderr}"
+    );
+    assert!(stderr.contains(" allocation(s) at "), "{stderr}");
+    assert!(!stderr.contains("memory allocation of"), "{stderr}");
+
+    let (success, stderr) = run("chained");
+    assert!(!success);
+    assert!(stderr.starts_with("mem_leak_detector: allocation of 4096 bytes"));
+    assert!(
+        stderr.contains("memory allocation of 4096 bytes failed"),
+        "{stderr}"
+    );
+}
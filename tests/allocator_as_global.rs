@@ -0,0 +1,87 @@
+//! A nightly `Allocator` installed as the global allocator through
+//! `AllocatorAsGlobal`. Runs itself as a child process, once per case, and
+//! checks the exit report.
+
+#![feature(allocator_api)]
+
+use std::{
+    alloc::{AllocError, Allocator, Layout, System},
+    process::Command,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use mem_leak_detector::{AllocatorAsGlobal, ExitReport, LeakDetector};
+
+/// Stands in for a custom pool: `System`, counting what it hands out.
+struct Pool;
+
+static POOL_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl Allocator for Pool {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        POOL_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { System.deallocate(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: LeakDetector<AllocatorAsGlobal<Pool>> =
+    LeakDetector::builder(AllocatorAsGlobal(Pool))
+        .registry(true)
+        .build();
+
+fn child(case: &str) {
+    GLOBAL.report_at_exit(ExitReport {
+        abort: true,
+        ..ExitReport::default()
+    });
+    GLOBAL.capture_baseline();
+    let before = GLOBAL.get_used();
+    let mut words: Vec<String> = Vec::new();
+    for n in 0..200 {
+        words.push(n.to_string());
+    }
+    words.truncate(10);
+    words.shrink_to_fit();
+    let joined = words.concat();
+    assert!(joined.starts_with("0123"));
+    drop((words, joined));
+    assert_eq!(GLOBAL.get_used(), before);
+    assert!(POOL_ALLOCATIONS.load(Ordering::Relaxed) > 200);
+    if case == "leaking" {
+        std::mem::forget(std::hint::black_box(vec![0u8; 100]));
+    }
+}
+
+fn run(case: &str) -> (bool, String) {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .arg(case)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+fn main() {
+    if let Some(case) = std::env::args().nth(1) {
+        return child(&case);
+    }
+
+    let (success, stderr) = run("clean");
+    assert!(success, "{stderr}");
+    assert_eq!(stderr, "");
+
+    let (success, stderr) = run("leaking");
+    assert!(!success);
+    assert!(
+        stderr.starts_with("mem_leak_detector: 100 bytes leaked\n"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,53 @@
+//! The detector's own bookkeeping, kept while it is the global allocator,
+//! must not show up as leaks.
+
+use std::{alloc::System, thread};
+
+use mem_leak_detector::{LeakDetector, OnThreadExit};
+
+#[global_allocator]
+static GLOBAL: LeakDetector<System> = LeakDetector::builder(System)
+    .registry(true)
+    .check_on_thread_exit(true)
+    .on_thread_exit(OnThreadExit::Record)
+    .build();
+
+fn checkpoints() {
+    GLOBAL.capture_baseline();
+    for _ in 0..8 {
+        GLOBAL.checkpoint();
+        GLOBAL.sample_usage();
+    }
+    GLOBAL.check().unwrap();
+    assert!(GLOBAL.self_overhead().other >= 8 * size_of::<(u64, usize)>());
+}
+
+fn suppressions() {
+    GLOBAL.capture_baseline();
+    GLOBAL.add_callsite_suppression("src/generated/");
+    GLOBAL.add_size_suppression(1 << 20..=2 << 20);
+    GLOBAL.check().unwrap();
+}
+
+fn thread_exit() {
+    GLOBAL.capture_baseline();
+    let leaked = thread::Builder::new()
+        .name("worker".to_owned())
+        .spawn(|| Box::into_raw(Box::new([0u8; 48])) as usize)
+        .unwrap()
+        .join()
+        .unwrap();
+    drop(unsafe { Box::from_raw(leaked as *mut [u8; 48]) });
+    GLOBAL.check().unwrap();
+    let reports = GLOBAL.thread_exit_reports();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].thread_name.as_deref(), Some("worker"));
+    drop(reports);
+    GLOBAL.check().unwrap();
+}
+
+fn main() {
+    checkpoints();
+    suppressions();
+    thread_exit();
+}
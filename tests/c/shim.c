@@ -0,0 +1,19 @@
+/* C code for tests/c_intercept.rs: a cache that keeps its copy for good,
+ * and scratch space freed before returning. Built by build.rs and linked
+ * into the test alone. */
+
+#include <stdlib.h>
+#include <string.h>
+
+char *shim_cache(const char *text, size_t capacity) {
+    char *cached = malloc(capacity);
+    strncpy(cached, text, capacity - 1);
+    cached[capacity - 1] = '\0';
+    return cached;
+}
+
+void shim_scratch(size_t size) {
+    void *scratch = malloc(size);
+    memset(scratch, 0, size);
+    free(scratch);
+}
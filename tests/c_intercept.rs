@@ -0,0 +1,66 @@
+//! Leaks from C code, `strdup` in the C library and a cache in
+//! `tests/c/shim.c`, and from Rust, and checks that one report shows all
+//! three.
+
+use std::{
+    ffi::{CStr, c_char},
+    hint::black_box,
+};
+
+use mem_leak_detector::{
+    LeakDetector,
+    intercept::{Libc, c_heap},
+};
+
+#[global_allocator]
+static GLOBAL: LeakDetector<Libc> = LeakDetector::builder(Libc).registry(true).build();
+
+unsafe extern "C" {
+    fn strdup(string: *const c_char) -> *mut c_char;
+    /// From `tests/c/shim.c`, like `shim_scratch`.
+    fn shim_cache(text: *const c_char, capacity: usize) -> *mut c_char;
+    fn shim_scratch(size: usize);
+}
+
+fn main() {
+    c_heap(LeakDetector::capture_baseline);
+    GLOBAL.capture_baseline();
+
+    let copied = unsafe { strdup(c"leaked by the C library".as_ptr()) };
+    let cached = unsafe { shim_cache(c"kept by the C code".as_ptr(), 3000) };
+    unsafe { shim_scratch(5000) };
+    std::mem::forget(black_box(vec![0u8; 7000]));
+
+    let c_sizes: Vec<usize> = c_heap(|heap| {
+        heap.leak_report()
+            .allocations()
+            .iter()
+            .map(|allocation| allocation.size)
+            .collect()
+    });
+    assert!(c_sizes.iter().any(|&size| size >= 3000), "{c_sizes:?}");
+    assert!(!c_sizes.iter().any(|&size| size >= 5000), "{c_sizes:?}");
+    assert!(
+        c_sizes.iter().any(|&size| (24..64).contains(&size)),
+        "{c_sizes:?}"
+    );
+
+    let report = GLOBAL.combined_leak_report();
+    let sizes: Vec<usize> = report
+        .allocations()
+        .iter()
+        .map(|allocation| allocation.size)
+        .collect();
+    assert!(sizes.contains(&7000), "{sizes:?}");
+    assert!(sizes.iter().any(|&size| (3000..4000).contains(&size)));
+    let text = report.to_string();
+    assert!(text.contains("src/intercept.rs"), "{text}");
+    assert!(text.contains("tests/c_intercept.rs"), "{text}");
+
+    assert_eq!(
+        unsafe { CStr::from_ptr(copied) },
+        c"leaked by the C library"
+    );
+    assert_eq!(unsafe { CStr::from_ptr(cached) }, c"kept by the C code");
+    println!("c_intercept ok");
+}
@@ -0,0 +1,49 @@
+//! Runs itself as a child process that writes around a guarded block, and
+//! checks that only the write past its end kills it.
+
+use std::{alloc::System, hint::black_box, process::Command};
+
+use mem_leak_detector::{GuardPlacement, LeakDetector};
+
+#[global_allocator]
+static GLOBAL: LeakDetector<System> = LeakDetector::builder(System)
+    .registry(true)
+    .efence(64 << 10, GuardPlacement::After)
+    .build();
+
+fn child(case: &str) {
+    let mut buffer = vec![0u8; 100_000];
+    buffer.fill(1);
+    assert!(GLOBAL.get_used() >= 100_000);
+    println!("in bounds ok");
+    if case == "overrun" {
+        let end = black_box(buffer.as_mut_ptr()).wrapping_add(100_000);
+        unsafe { end.write_volatile(1) };
+        println!("overrun survived");
+    }
+}
+
+fn run(case: &str) -> (bool, String) {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .arg(case)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+fn main() {
+    if let Some(case) = std::env::args().nth(1) {
+        return child(&case);
+    }
+
+    let (success, stdout) = run("in-bounds");
+    assert!(success, "{stdout}");
+    assert_eq!(stdout, "in bounds ok\n");
+
+    let (success, stdout) = run("overrun");
+    assert!(!success);
+    assert_eq!(stdout, "in bounds ok\n");
+}
@@ -0,0 +1,110 @@
+//! Runs itself as a child process, once per case, and checks what the
+//! detector printed on stderr as the child exited.
+
+use std::{alloc::System, process::Command};
+
+use mem_leak_detector::{CiSummary, ExitReport, LeakDetector, ci::MergedSummary};
+
+#[global_allocator]
+static GLOBAL: LeakDetector<System> = LeakDetector::builder(System).registry(true).build();
+
+fn child(case: &str) {
+    GLOBAL.capture_baseline();
+    let abort = case == "abort";
+    GLOBAL.report_at_exit(ExitReport {
+        max_sites: 2,
+        abort,
+        ..ExitReport::default()
+    });
+    let scratch = vec![0u8; 256];
+    drop(scratch);
+    if case != "clean" {
+        std::mem::forget(std::hint::black_box(vec![0u8; 100]));
+    }
+}
+
+fn run(case: &str) -> (bool, String) {
+    run_with(Command::new(std::env::current_exe().unwrap()).arg(case))
+}
+
+fn run_with(command: &mut Command) -> (bool, String) {
+    let output = command.output().unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+fn main() {
+    if let Some(case) = std::env::args().nth(1) {
+        return child(&case);
+    }
+
+    let (success, stderr) = run("clean");
+    assert!(success);
+    assert_eq!(stderr, "");
+
+    let (success, stderr) = run("leaking");
+    assert!(success);
+    let mut lines = stderr.lines();
+    assert_eq!(lines.next(), Some("mem_leak_detector: 100 bytes leaked"));
+    let summary = lines.next().unwrap();
+    assert!(summary.contains(" bytes live in "), "{stderr}");
+    assert!(
+        stderr.contains("  100 bytes in 1 allocation(s) at "),
+        "{stderr}"
+    );
+
+    let (success, stderr) = run("abort");
+    assert!(!success);
+    assert!(stderr.starts_with("mem_leak_detector: 100 bytes leaked\n"));
+
+    let path = std::env::temp_dir().join(format!("exit_report-{}.json", std::process::id()));
+    for (case, leaked_bytes) in [("clean", 0), ("leaking", 100)] {
+        let (success, _) = run_with(
+            Command::new(std::env::current_exe().unwrap())
+                .arg(case)
+                .env("MEM_LEAK_DETECTOR_SUMMARY", &path),
+        );
+        assert!(success);
+        let json = std::fs::read_to_string(&path).unwrap();
+        let summary = CiSummary::from_json(&json).unwrap();
+        assert_eq!(summary.leaked_bytes, leaked_bytes, "{json}");
+        assert_eq!(summary.leaked_allocations, leaked_bytes / 100);
+        assert!(summary.peak >= 256);
+        assert!(!summary.poisoned);
+        assert_eq!(summary.sites.len(), leaked_bytes / 100);
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("exit_report-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for case in ["clean", "leaking", "leaking"] {
+        let (success, _) = run_with(
+            Command::new(std::env::current_exe().unwrap())
+                .arg(case)
+                .env("MEM_LEAK_DETECTOR_SUMMARY", &dir),
+        );
+        assert!(success);
+    }
+    let merged = MergedSummary::from_dir(&dir);
+    assert_eq!(merged.processes.len(), 3, "{merged}");
+    assert!(merged.unreadable.is_empty(), "{merged}");
+    assert_eq!(merged.total().leaked_bytes, 200);
+    assert_eq!(merged.exit_code(), 1);
+    assert!(merged.processes[0].name.starts_with("exit_report-"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let (success, stderr) = run_with(
+        Command::new(std::env::current_exe().unwrap())
+            .arg("clean")
+            .env("MEM_LEAK_DETECTOR_SUMMARY", "/nonexistent/summary.json"),
+    );
+    assert!(success);
+    assert!(
+        stderr.starts_with(
+            "mem_leak_detector: couldn't write summary to /nonexistent/summary.json: "
+        ),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,105 @@
+//! Runs itself as a child process, once per failure mode, and checks how
+//! the child ended and what it printed on stderr.
+
+#![feature(allocator_api)]
+
+use std::{
+    alloc::System,
+    process::{Command, ExitStatus},
+};
+
+use mem_leak_detector::{ExitReport, FailureMode, LeakDetector};
+
+static DETECTOR: LeakDetector<System> = LeakDetector::system();
+
+fn leak() {
+    std::mem::forget(Box::new_in(0u64, &DETECTOR));
+}
+
+fn child(case: &str) {
+    match case {
+        "panic" | "abort" | "trap" => {
+            let mode = match case {
+                "panic" => FailureMode::Panic,
+                "abort" => FailureMode::Abort,
+                _ => FailureMode::Trap,
+            };
+            DETECTOR.set_failure_mode(mode);
+            leak();
+            DETECTOR.assert();
+        }
+        "scope-while-unwinding" => {
+            let _scope = DETECTOR
+                .scope()
+                .named("job")
+                .failure_mode(FailureMode::Abort);
+            leak();
+            panic!("job failed");
+        }
+        "exit-trap" => {
+            DETECTOR.set_failure_mode(FailureMode::Trap);
+            DETECTOR.report_at_exit(ExitReport {
+                abort: true,
+                ..ExitReport::default()
+            });
+            leak();
+        }
+        _ => unreachable!("{case}"),
+    }
+}
+
+fn run(case: &str) -> (ExitStatus, String) {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .arg(case)
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .unwrap();
+    (output.status, String::from_utf8(output.stderr).unwrap())
+}
+
+fn aborted(status: ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal() == Some(6)
+    }
+    #[cfg(not(unix))]
+    {
+        !status.success() && status.code() != Some(101)
+    }
+}
+
+fn main() {
+    if let Some(case) = std::env::args().nth(1) {
+        return child(&case);
+    }
+
+    let (status, stderr) = run("panic");
+    assert_eq!(status.code(), Some(101), "{stderr}");
+    assert!(stderr.contains("panicked at "), "{stderr}");
+    assert!(stderr.contains("8 bytes leaked"), "{stderr}");
+
+    let (status, stderr) = run("abort");
+    assert!(aborted(status), "{status}: {stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+    assert!(stderr.contains("8 bytes leaked"), "{stderr}");
+
+    let (status, stderr) = run("trap");
+    assert!(aborted(status), "{status}: {stderr}");
+    assert_eq!(stderr, "");
+
+    if cfg!(debug_assertions) {
+        let (status, stderr) = run("scope-while-unwinding");
+        assert!(aborted(status), "{status}: {stderr}");
+        assert!(stderr.contains("job failed"), "{stderr}");
+        assert!(
+            stderr.contains("scope 'job' created at ")
+                && stderr.ends_with(" leaked 8 bytes and 1 allocation\n"),
+            "{stderr}"
+        );
+    }
+
+    let (status, stderr) = run("exit-trap");
+    assert!(aborted(status), "{status}: {stderr}");
+    assert_eq!(stderr, "");
+}
@@ -0,0 +1,27 @@
+//! What the detector hands back is the caller's memory: allocated and freed
+//! through the global detector like any other, so `used` ends where it
+//! started. Without the registry, a block allocated untracked and freed
+//! tracked would take it below.
+
+use std::alloc::System;
+
+use mem_leak_detector::LeakDetector;
+
+#[global_allocator]
+static GLOBAL: LeakDetector<System> = LeakDetector::system();
+
+fn frame_window() {
+    for _ in 0..4 {
+        let _frame = GLOBAL.frame_guard();
+        drop(std::hint::black_box(vec![0u8; 64]));
+    }
+    let used = GLOBAL.get_used();
+    let window = GLOBAL.frame_window();
+    assert_eq!(window.len(), 4);
+    drop(window);
+    assert_eq!(GLOBAL.get_used(), used);
+}
+
+fn main() {
+    frame_window();
+}
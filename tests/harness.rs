@@ -0,0 +1,83 @@
+//! Uses the leak-checking harness as this target's `main`. Run without
+//! arguments it runs itself as a child, once with only clean tests and once
+//! with a leaking one, and checks the child's output and exit status.
+
+use std::{alloc::System, process::Command};
+
+use mem_leak_detector::{
+    LeakDetector,
+    harness::{self, Trial},
+};
+
+#[global_allocator]
+static GLOBAL: LeakDetector<System> = LeakDetector::builder(System).registry(true).build();
+
+fn balanced() {
+    let buffer = vec![0u8; 4096];
+    assert_eq!(buffer.len(), 4096);
+}
+
+fn leaking() {
+    std::mem::forget(vec![0u8; 100]);
+}
+
+fn child(case: &str) {
+    let mut tests = vec![
+        Trial::test("balanced", || {
+            balanced();
+            Ok(())
+        }),
+        Trial::test("cached", || {
+            std::mem::forget(Box::new(0u64));
+            Ok(())
+        })
+        .with_tolerance(8),
+    ];
+    if case == "leaking" {
+        tests.push(Trial::test("leaking", || {
+            leaking();
+            Ok(())
+        }));
+    }
+    harness::run(&GLOBAL, tests).exit();
+}
+
+fn run(case: &str) -> (Option<i32>, String) {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .env("HARNESS_CASE", case)
+        .output()
+        .unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+fn main() {
+    if let Ok(case) = std::env::var("HARNESS_CASE") {
+        return child(&case);
+    }
+
+    let (code, stdout) = run("clean");
+    assert_eq!(code, Some(0), "{stdout}");
+    assert!(stdout.contains("test balanced ... ok\n"), "{stdout}");
+    assert!(stdout.contains("test cached ... ok\n"), "{stdout}");
+    let row = stdout
+        .lines()
+        .find(|line| line.starts_with("balanced "))
+        .unwrap();
+    let columns: Vec<&str> = row.split_whitespace().collect();
+    assert_eq!(columns[1], "0", "{row}");
+    assert!(columns[2].parse::<usize>().unwrap() >= 4096, "{row}");
+
+    let (code, stdout) = run("leaking");
+    assert_eq!(code, Some(101), "{stdout}");
+    assert!(
+        stdout.contains("test leaking ... FAILED\n  leaked 100 bytes\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("test result: FAILED. 2 passed; 1 failed (1 leaked)"),
+        "{stdout}"
+    );
+}
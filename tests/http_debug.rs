@@ -0,0 +1,77 @@
+//! Serves the global detector's debug endpoints and reads them back over a
+//! real socket.
+
+use std::{
+    alloc::System,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+};
+
+use mem_leak_detector::LeakDetector;
+
+#[global_allocator]
+static GLOBAL: LeakDetector<System> = LeakDetector::builder(System).registry(true).build();
+
+fn get(address: SocketAddr, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_owned(), body.to_owned())
+}
+
+#[test]
+fn serves_stats_report_and_metrics() {
+    GLOBAL.capture_baseline();
+    let server = GLOBAL.serve_debug("127.0.0.1:0").unwrap();
+    let address = server.local_addr();
+    std::mem::forget(vec![0u8; 100]);
+
+    let (head, body) = get(address, "/stats");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("Content-Type: application/json\r\n"));
+    assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+    assert!(body.starts_with("{\"used\":"), "{body}");
+    assert!(body.contains(",\"leaked_bytes\":"), "{body}");
+    assert!(body.ends_with(",\"poisoned\":false}\n"), "{body}");
+
+    let (head, body) = get(address, "/report");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    let (leaked, _) = body.split_once(" bytes leaked in ").unwrap();
+    assert!(leaked.parse::<usize>().unwrap() >= 100, "{body}");
+    assert!(body.contains(" allocation(s) at "), "{body}");
+
+    let (head, body) = get(address, "/prometheus");
+    assert!(head.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+    let used = body
+        .lines()
+        .find_map(|line| line.strip_prefix("mem_leak_detector_used "))
+        .unwrap();
+    assert!(used.parse::<usize>().unwrap() >= 100, "{body}");
+    assert!(body.contains("# TYPE mem_leak_detector_allocations_total counter\n"));
+
+    let (head, _) = get(address, "/missing");
+    assert!(head.starts_with("HTTP/1.1 404 Not Found\r\n"), "{head}");
+
+    server.stop();
+    assert!(TcpStream::connect(address).is_err());
+}
+
+#[test]
+fn other_threads_are_tracked_while_serving() {
+    let server = GLOBAL.serve_debug("127.0.0.1:0").unwrap();
+    // The server blocks reading a request that hasn't been sent yet.
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    let before = GLOBAL.snapshot();
+    let kept = std::hint::black_box(Box::new([0u8; 64]));
+    assert!(GLOBAL.snapshot().allocations > before.allocations);
+    drop(kept);
+
+    write!(stream, "GET /stats HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    server.stop();
+}
@@ -0,0 +1,158 @@
+//! `#[leak_checked]` on impl blocks, methods and free functions. Each test
+//! has its own detector, as scopes compare detector-wide usage.
+
+#![feature(allocator_api)]
+
+use std::{
+    alloc::System,
+    future::Future,
+    panic::{AssertUnwindSafe, catch_unwind},
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use mem_leak_detector::{LeakDetector, leak_checked};
+
+type Detector = LeakDetector<System>;
+
+static SYNC: Detector = LeakDetector::system();
+static ASYNC: Detector = LeakDetector::system();
+static FREE: Detector = LeakDetector::system();
+
+fn panic_message(f: impl FnOnce()) -> String {
+    let payload = catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().unwrap().to_string(),
+    }
+}
+
+/// Polls once; the futures here never wait.
+fn ready<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!(),
+    }
+}
+
+struct Store<T> {
+    kept: Vec<Box<T, &'static Detector>>,
+}
+
+#[leak_checked(detector = SYNC)]
+impl<T: Default> Store<T> {
+    const LIMIT: usize = 2;
+
+    fn len(&self) -> usize {
+        let scratch = Box::new_in(self.kept.len(), &SYNC);
+        *scratch
+    }
+
+    fn parse(&self, text: &str) -> Result<usize, std::num::ParseIntError> {
+        let scratch = Box::new_in(text.trim(), &SYNC);
+        let n: usize = scratch.parse()?;
+        if n > Self::LIMIT {
+            return Ok(Self::LIMIT);
+        }
+        Ok(n)
+    }
+
+    fn map<U, F: FnOnce(usize) -> U>(&self, f: F) -> U {
+        f(self.kept.len())
+    }
+
+    fn leak(&mut self) {
+        self.kept.push(Box::new_in(T::default(), &SYNC));
+    }
+
+    #[leak_checked(tolerance = 64)]
+    fn leak_a_little(&mut self) {
+        self.kept.push(Box::new_in(T::default(), &SYNC));
+    }
+
+    #[leak_checked(skip)]
+    fn push(&mut self) {
+        self.kept.push(Box::new_in(T::default(), &SYNC));
+    }
+}
+
+#[test]
+#[cfg_attr(
+    not(debug_assertions),
+    ignore = "scope checks only run with debug assertions"
+)]
+fn sync_methods() {
+    let mut store = Store::<u64> { kept: Vec::new() };
+    store.push();
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.parse(" 1 "), Ok(1));
+    assert_eq!(store.parse("7"), Ok(2));
+    assert!(store.parse("x").is_err());
+    assert_eq!(store.map(|n| n * 10), 10);
+    store.leak_a_little();
+
+    let message = panic_message(|| store.leak());
+    assert!(
+        message.starts_with("scope 'Store::leak' created at "),
+        "{message}"
+    );
+    assert!(
+        message.ends_with(" leaked 8 bytes and 1 allocation"),
+        "{message}"
+    );
+}
+
+struct Client;
+
+#[leak_checked(detector = ASYNC)]
+impl Client {
+    async fn fetch(&self, text: &str) -> Result<usize, std::num::ParseIntError> {
+        let buffer = Box::new_in(text.to_owned(), &ASYNC);
+        std::future::ready(()).await;
+        buffer.parse()
+    }
+
+    async fn leak(self) {
+        std::mem::forget(Box::new_in([0u8; 32], &ASYNC));
+    }
+}
+
+#[test]
+#[cfg_attr(
+    not(debug_assertions),
+    ignore = "scope checks only run with debug assertions"
+)]
+fn async_methods() {
+    let client = Client;
+    assert_eq!(ready(client.fetch("12")), Ok(12));
+    assert!(ready(client.fetch("twelve")).is_err());
+    let message = panic_message(|| ready(client.leak()));
+    assert!(message.starts_with("scope 'Client::leak' "), "{message}");
+    assert!(
+        message.ends_with(" leaked 32 bytes and 1 allocation"),
+        "{message}"
+    );
+}
+
+#[leak_checked(detector = FREE, name = "job")]
+fn run_job(leak: bool) -> usize {
+    let scratch = Box::new_in([0u8; 16], &FREE);
+    if leak {
+        std::mem::forget(scratch);
+        return 0;
+    }
+    scratch.len()
+}
+
+#[test]
+#[cfg_attr(
+    not(debug_assertions),
+    ignore = "scope checks only run with debug assertions"
+)]
+fn free_functions() {
+    assert_eq!(run_job(false), 16);
+    let message = panic_message(|| {
+        run_job(true);
+    });
+    assert!(message.starts_with("scope 'job' "), "{message}");
+}